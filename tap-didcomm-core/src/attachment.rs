@@ -0,0 +1,370 @@
+//! Attachment content helpers.
+//!
+//! This module provides constructors for the different forms of attachment data,
+//! and the integrity operations defined by `DIDComm` v2 for attachments:
+//! - Computing and verifying the multihash `hash` of inline or linked content
+//! - Signing and verifying the detached `jws` of an attachment with a [`Signer`]
+//!
+//! Hashes are SHA2-256 multihashes, encoded as base58btc multibase strings.
+//! Signatures are flattened JWS objects in detached content mode, where the
+//! payload is the base64url-encoded attachment content.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use tap_didcomm_core::types::{Attachment, AttachmentData};
+//!
+//! let document = b"KYC evidence";
+//! let attachment = Attachment::new("kyc-1", AttachmentData::base64(document)).with_hash()?;
+//!
+//! assert!(attachment.verify_hash(None).is_ok());
+//! # Ok::<(), tap_didcomm_core::Error>(())
+//! ```
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::error::{Error, Result};
+use crate::plugin::Signer;
+use crate::types::{Attachment, AttachmentData};
+
+/// Multihash code for SHA2-256
+const SHA2_256: u8 = 0x12;
+
+/// Length in bytes of a SHA2-256 digest
+const SHA2_256_LEN: u8 = 0x20;
+
+/// Computes the multihash of attachment content.
+///
+/// # Arguments
+/// * `content` - The raw attachment content
+///
+/// # Returns
+/// The SHA2-256 multihash of the content as a base58btc multibase string
+#[must_use]
+pub fn compute_hash(content: &[u8]) -> String {
+    let mut multihash = vec![SHA2_256, SHA2_256_LEN];
+    multihash.extend_from_slice(&Sha256::digest(content));
+    format!("z{}", bs58::encode(multihash).into_string())
+}
+
+/// Verifies attachment content against a multihash.
+///
+/// # Arguments
+/// * `hash` - The expected multihash as a base58btc multibase string
+/// * `content` - The raw attachment content
+///
+/// # Errors
+/// * `Error::Base64` - If the hash is not a base58btc multibase string
+/// * `Error::InvalidAlgorithm` - If the hash does not use SHA2-256
+/// * `Error::VerificationFailed` - If the content does not match the hash
+pub fn verify_hash(hash: &str, content: &[u8]) -> Result<()> {
+    let encoded = hash
+        .strip_prefix('z')
+        .ok_or_else(|| Error::Base64("Hash must be a base58btc multibase string".into()))?;
+    let multihash = bs58::decode(encoded)
+        .into_vec()
+        .map_err(|e| Error::Base64(format!("Invalid base58btc hash: {e}")))?;
+
+    match multihash.as_slice() {
+        [SHA2_256, SHA2_256_LEN, digest @ ..] if digest.len() == usize::from(SHA2_256_LEN) => {
+            if digest == Sha256::digest(content).as_slice() {
+                Ok(())
+            } else {
+                Err(Error::VerificationFailed(
+                    "Attachment content does not match its hash".into(),
+                ))
+            }
+        }
+        _ => Err(Error::InvalidAlgorithm(
+            "Only SHA2-256 multihashes are supported".into(),
+        )),
+    }
+}
+
+impl AttachmentData {
+    /// Creates attachment data holding base64url-encoded binary content.
+    #[must_use]
+    pub fn base64(content: &[u8]) -> Self {
+        Self {
+            base64: Some(URL_SAFE_NO_PAD.encode(content)),
+            ..Self::default()
+        }
+    }
+
+    /// Creates attachment data holding JSON content.
+    #[must_use]
+    pub fn json(content: Value) -> Self {
+        Self {
+            json: Some(content),
+            ..Self::default()
+        }
+    }
+
+    /// Creates attachment data referencing external content.
+    ///
+    /// # Arguments
+    /// * `links` - Locations the content can be fetched from
+    /// * `hash` - The multihash of the content, see [`compute_hash`]
+    #[must_use]
+    pub fn links(links: impl IntoIterator<Item = impl Into<String>>, hash: String) -> Self {
+        Self {
+            links: Some(links.into_iter().map(Into::into).collect()),
+            hash: Some(hash),
+            ..Self::default()
+        }
+    }
+
+    /// Gets the inline content of the attachment, if any.
+    ///
+    /// Base64 content is decoded and JSON content is serialized.
+    ///
+    /// # Errors
+    /// * `Error::Base64` - If the base64 content cannot be decoded
+    /// * `Error::Json` - If the JSON content cannot be serialized
+    pub fn inline_content(&self) -> Result<Option<Vec<u8>>> {
+        if let Some(encoded) = &self.base64 {
+            return URL_SAFE_NO_PAD
+                .decode(encoded.trim_end_matches('='))
+                .map(Some)
+                .map_err(|e| Error::Base64(format!("Invalid attachment content: {e}")));
+        }
+        if let Some(value) = &self.json {
+            return Ok(Some(serde_json::to_vec(value)?));
+        }
+        Ok(None)
+    }
+
+    /// Resolves the content to verify against: the given fetched content for
+    /// linked attachments, or the inline content otherwise.
+    fn content(&self, fetched: Option<&[u8]>) -> Result<Vec<u8>> {
+        if let Some(content) = self.inline_content()? {
+            return Ok(content);
+        }
+        fetched.map(<[u8]>::to_vec).ok_or_else(|| {
+            Error::VerificationFailed("Linked attachment content has not been fetched".into())
+        })
+    }
+}
+
+impl Attachment {
+    /// Creates a new attachment with the given ID and data.
+    #[must_use]
+    pub fn new(id: impl Into<String>, data: AttachmentData) -> Self {
+        Self {
+            id: id.into(),
+            data,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the media type of the attachment.
+    #[must_use]
+    pub fn media_type(mut self, media_type: impl Into<String>) -> Self {
+        self.media_type = Some(media_type.into());
+        self
+    }
+
+    /// Sets the `hash` and `byte_count` of an attachment from its inline content.
+    ///
+    /// # Errors
+    /// * `Error::VerificationFailed` - If the attachment has no inline content
+    /// * `Error::Base64` - If the base64 content cannot be decoded
+    pub fn with_hash(mut self) -> Result<Self> {
+        let content = self.data.content(None)?;
+        self.data.hash = Some(compute_hash(&content));
+        self.byte_count = Some(content.len() as u64);
+        Ok(self)
+    }
+
+    /// Verifies the attachment content against its `hash`.
+    ///
+    /// # Arguments
+    /// * `fetched` - The content fetched from the attachment's links, required
+    ///   for linked attachments and ignored for inline ones
+    ///
+    /// # Errors
+    /// * `Error::VerificationFailed` - If the attachment has no hash, the content
+    ///   is not available, or the content does not match the hash or byte count
+    pub fn verify_hash(&self, fetched: Option<&[u8]>) -> Result<()> {
+        let hash = self
+            .data
+            .hash
+            .as_deref()
+            .ok_or_else(|| Error::VerificationFailed("Attachment has no hash".into()))?;
+        let content = self.data.content(fetched)?;
+        if let Some(byte_count) = self.byte_count {
+            if byte_count != content.len() as u64 {
+                return Err(Error::VerificationFailed(
+                    "Attachment content does not match its byte count".into(),
+                ));
+            }
+        }
+        verify_hash(hash, &content)
+    }
+
+    /// Signs the attachment content and sets the attachment's `jws`.
+    ///
+    /// # Arguments
+    /// * `signer` - The signer to use
+    /// * `kid` - The key ID to sign with
+    /// * `alg` - The JWS algorithm of the key, e.g. `EdDSA`
+    /// * `fetched` - The content fetched from the attachment's links, required
+    ///   for linked attachments and ignored for inline ones
+    ///
+    /// # Errors
+    /// * `Error::VerificationFailed` - If the content is not available
+    /// * `Error::SigningFailed` - If signing fails
+    pub async fn sign(
+        &mut self,
+        signer: &dyn Signer,
+        kid: &str,
+        alg: &str,
+        fetched: Option<&[u8]>,
+    ) -> Result<()> {
+        let content = self.data.content(fetched)?;
+        let protected = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&json!({
+            "alg": alg,
+            "kid": kid,
+        }))?);
+        let signing_input = signing_input(&protected, &content);
+        let signature = signer.sign(signing_input.as_bytes(), kid).await?;
+
+        self.data.jws = Some(json!({
+            "protected": protected,
+            "signature": URL_SAFE_NO_PAD.encode(signature),
+        }));
+        Ok(())
+    }
+
+    /// Verifies the attachment's `jws` against its content.
+    ///
+    /// # Arguments
+    /// * `signer` - The signer to verify with
+    /// * `fetched` - The content fetched from the attachment's links, required
+    ///   for linked attachments and ignored for inline ones
+    ///
+    /// # Returns
+    /// Whether the signature is valid
+    ///
+    /// # Errors
+    /// * `Error::VerificationFailed` - If the attachment has no JWS or the
+    ///   content is not available
+    /// * `Error::Header` - If the JWS protected header is malformed
+    pub async fn verify_jws(&self, signer: &dyn Signer, fetched: Option<&[u8]>) -> Result<bool> {
+        let jws = self
+            .data
+            .jws
+            .as_ref()
+            .ok_or_else(|| Error::VerificationFailed("Attachment has no JWS".into()))?;
+        let protected = jws["protected"]
+            .as_str()
+            .ok_or_else(|| Error::Header("JWS is missing its protected header".into()))?;
+        let signature = jws["signature"]
+            .as_str()
+            .ok_or_else(|| Error::Header("JWS is missing its signature".into()))?;
+
+        let header: Value = serde_json::from_slice(
+            &URL_SAFE_NO_PAD
+                .decode(protected)
+                .map_err(|e| Error::Base64(e.to_string()))?,
+        )?;
+        let kid = header["kid"]
+            .as_str()
+            .ok_or_else(|| Error::Header("JWS header is missing its kid".into()))?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|e| Error::Base64(e.to_string()))?;
+
+        let content = self.data.content(fetched)?;
+        let signing_input = signing_input(protected, &content);
+        signer
+            .verify(signing_input.as_bytes(), &signature, kid)
+            .await
+    }
+}
+
+/// Builds the JWS signing input for a detached payload.
+fn signing_input(protected: &str, content: &[u8]) -> String {
+    format!("{protected}.{}", URL_SAFE_NO_PAD.encode(content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::MockTestPlugin;
+
+    #[test]
+    fn test_attachment_wire_format() {
+        let attachment = Attachment::new("doc-1", AttachmentData::json(json!({"name": "Alice"})))
+            .media_type("application/json");
+
+        let value = serde_json::to_value(&attachment).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "id": "doc-1",
+                "media_type": "application/json",
+                "data": { "json": { "name": "Alice" } }
+            })
+        );
+
+        let parsed: Attachment = serde_json::from_value(json!({
+            "id": "doc-2",
+            "byte_count": 5,
+            "lastmod_time": 1_700_000_000,
+            "data": { "links": ["https://example.com/doc"], "hash": "zQm" }
+        }))
+        .unwrap();
+        assert_eq!(parsed.byte_count, Some(5));
+        assert_eq!(parsed.lastmod_time, Some(1_700_000_000));
+        assert_eq!(parsed.data.hash.as_deref(), Some("zQm"));
+        assert!(parsed.data.base64.is_none());
+    }
+
+    #[test]
+    fn test_inline_hash() {
+        let attachment = Attachment::new("doc", AttachmentData::base64(b"hello"))
+            .with_hash()
+            .unwrap();
+        assert_eq!(attachment.byte_count, Some(5));
+        assert!(attachment.verify_hash(None).is_ok());
+
+        let mut tampered = attachment.clone();
+        tampered.data.base64 = Some(URL_SAFE_NO_PAD.encode(b"hellO"));
+        assert!(matches!(
+            tampered.verify_hash(None),
+            Err(Error::VerificationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_linked_hash() {
+        let content = b"large document";
+        let attachment = Attachment::new(
+            "doc",
+            AttachmentData::links(["https://example.com/doc"], compute_hash(content)),
+        );
+
+        assert!(attachment.verify_hash(Some(content)).is_ok());
+        assert!(attachment.verify_hash(Some(b"other document")).is_err());
+        assert!(attachment.verify_hash(None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_attachment_jws() {
+        let plugin = MockTestPlugin;
+        let mut attachment = Attachment::new("doc", AttachmentData::base64(b"signed content"));
+
+        attachment
+            .sign(&plugin, "did:example:alice#key-1", "EdDSA", None)
+            .await
+            .unwrap();
+        assert!(attachment.verify_jws(&plugin, None).await.unwrap());
+
+        attachment.data.base64 = Some(URL_SAFE_NO_PAD.encode(b"other content"));
+        assert!(!attachment.verify_jws(&plugin, None).await.unwrap());
+    }
+}
@@ -0,0 +1,200 @@
+//! Clocks and message timestamp validation.
+//!
+//! `DIDComm` messages carry a `created_time` and an optional `expires_time`.
+//! This module provides a [`TimestampPolicy`] that checks both against the
+//! current time, with a tolerance for clock skew between sender and recipient.
+//!
+//! The current time is obtained from a [`Clock`], so that the system clock
+//! can be replaced by a [`FixedClock`] in tests.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use tap_didcomm_core::clock::{Clock, FixedClock, TimestampPolicy};
+//! use tap_didcomm_core::{types::Message, Error};
+//! use serde_json::json;
+//!
+//! let clock = FixedClock::new(1_000);
+//! let mut message = Message::new("test", json!({})).unwrap().expires_at(900);
+//! message.created_time = 800;
+//!
+//! let policy = TimestampPolicy::default();
+//! assert!(matches!(
+//!     policy.check(&message, clock.now()),
+//!     Err(Error::MessageExpired(_))
+//! ));
+//! ```
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, Result};
+use crate::types::Message;
+
+/// A source of the current time.
+pub trait Clock: Send + Sync {
+    /// Returns the current time as a Unix timestamp in seconds
+    fn now(&self) -> u64;
+}
+
+/// A clock backed by the system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs())
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests.
+#[derive(Debug, Default)]
+pub struct FixedClock(AtomicU64);
+
+impl FixedClock {
+    /// Creates a clock set to the given Unix timestamp
+    #[must_use]
+    pub fn new(now: u64) -> Self {
+        Self(AtomicU64::new(now))
+    }
+
+    /// Sets the clock to the given Unix timestamp
+    pub fn set(&self, now: u64) {
+        self.0.store(now, Ordering::SeqCst);
+    }
+
+    /// Moves the clock forward by the given number of seconds
+    pub fn advance(&self, seconds: u64) {
+        self.0.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Rules for validating the timestamps of received messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimestampPolicy {
+    /// Whether messages past their `expires_time` are rejected
+    pub reject_expired: bool,
+
+    /// Whether messages created in the future are rejected
+    pub reject_future: bool,
+
+    /// The tolerated difference between the sender's clock and ours, in seconds
+    pub max_clock_skew: u64,
+}
+
+impl Default for TimestampPolicy {
+    fn default() -> Self {
+        Self {
+            reject_expired: true,
+            reject_future: true,
+            max_clock_skew: 300, // 5 minutes
+        }
+    }
+}
+
+impl TimestampPolicy {
+    /// A policy that accepts messages regardless of their timestamps.
+    #[must_use]
+    pub fn disabled() -> Self {
+        Self {
+            reject_expired: false,
+            reject_future: false,
+            max_clock_skew: 0,
+        }
+    }
+
+    /// Checks a message's timestamps against the current time.
+    ///
+    /// # Arguments
+    /// * `message` - The message to check
+    /// * `now` - The current Unix timestamp in seconds
+    ///
+    /// # Errors
+    /// * `Error::MessageExpired` - If the message expired more than
+    ///   `max_clock_skew` seconds ago
+    /// * `Error::ClockSkew` - If the message was created more than
+    ///   `max_clock_skew` seconds in the future
+    pub fn check(&self, message: &Message, now: u64) -> Result<()> {
+        if self.reject_expired {
+            if let Some(expires_time) = message.expires_time {
+                if now > expires_time.saturating_add(self.max_clock_skew) {
+                    return Err(Error::MessageExpired(format!(
+                        "Message {} expired at {expires_time}, current time is {now}",
+                        message.id.as_str()
+                    )));
+                }
+            }
+        }
+
+        if self.reject_future && message.created_time > now.saturating_add(self.max_clock_skew) {
+            return Err(Error::ClockSkew(format!(
+                "Message {} was created at {}, current time is {now}",
+                message.id.as_str(),
+                message.created_time
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(created_time: u64, expires_time: Option<u64>) -> Message {
+        let mut message = Message::new("test", json!({})).unwrap();
+        message.created_time = created_time;
+        message.expires_time = expires_time;
+        message
+    }
+
+    #[test]
+    fn test_expiry() {
+        let policy = TimestampPolicy {
+            max_clock_skew: 10,
+            ..TimestampPolicy::default()
+        };
+        let clock = FixedClock::new(1_000);
+        let msg = message(900, Some(1_000));
+
+        assert!(policy.check(&msg, clock.now()).is_ok());
+        clock.advance(10);
+        assert!(policy.check(&msg, clock.now()).is_ok());
+        clock.advance(1);
+        assert!(matches!(
+            policy.check(&msg, clock.now()),
+            Err(Error::MessageExpired(_))
+        ));
+        assert!(TimestampPolicy::disabled().check(&msg, clock.now()).is_ok());
+    }
+
+    #[test]
+    fn test_clock_skew() {
+        let policy = TimestampPolicy {
+            max_clock_skew: 60,
+            ..TimestampPolicy::default()
+        };
+        let clock = FixedClock::new(1_000);
+
+        assert!(policy.check(&message(1_060, None), clock.now()).is_ok());
+        assert!(matches!(
+            policy.check(&message(1_061, None), clock.now()),
+            Err(Error::ClockSkew(_))
+        ));
+    }
+
+    #[test]
+    fn test_system_clock() {
+        assert!(SystemClock.now() > 1_700_000_000);
+    }
+}
@@ -1,8 +1,12 @@
-//! Cryptographic operations for DIDComm messages.
+//! Cryptographic operations for `DIDComm` messages.
 
 use crate::error::Result;
 
 /// Signs a message using the provided signer
+///
+/// # Errors
+/// Returns an error if signing fails
+#[allow(clippy::unused_async)]
 pub async fn sign_message(
     message: &[u8],
     _signer: Box<dyn crate::plugin::Signer>,
@@ -88,6 +88,14 @@ pub enum Error {
     /// Error during content encryption/decryption
     #[error("Content encryption error: {0}")]
     ContentEncryption(String),
+
+    /// The message is past its expiry time
+    #[error("Message expired: {0}")]
+    MessageExpired(String),
+
+    /// The message was created too far in the future
+    #[error("Clock skew too large: {0}")]
+    ClockSkew(String),
 }
 
 /// Result type for the `DIDComm` core library.
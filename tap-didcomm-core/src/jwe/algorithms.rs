@@ -1,7 +1,7 @@
 //! Cryptographic algorithms for JWE operations.
 //!
 //! This module provides implementations of the cryptographic algorithms required for
-//! JWE (JSON Web Encryption) in DIDComm v2, including:
+//! JWE (JSON Web Encryption) in `DIDComm` v2, including:
 //! - ECDH key agreement (X25519 and NIST curves)
 //! - Key derivation (HKDF)
 //! - Content encryption (AES-GCM, AES-CBC-HMAC, XChaCha20-Poly1305)
@@ -70,6 +70,7 @@ type P521NonZeroScalar = NonZeroScalar<NistP521>;
 /// # Security
 ///
 /// Uses the system's secure random number generator.
+#[must_use]
 pub fn generate_random_key(size: usize) -> Vec<u8> {
     let mut key = vec![0u8; size];
     OsRng.fill_bytes(&mut key);
@@ -125,9 +126,9 @@ pub fn ecdh_key_agreement(
 pub fn generate_ephemeral_keypair(curve: EcdhCurve) -> Result<(Vec<u8>, Vec<u8>)> {
     match curve {
         EcdhCurve::X25519 => Ok(generate_x25519_ephemeral()),
-        EcdhCurve::P256 => generate_p256_ephemeral(),
-        EcdhCurve::P384 => generate_p384_ephemeral(),
-        EcdhCurve::P521 => generate_p521_ephemeral(),
+        EcdhCurve::P256 => Ok(generate_p256_ephemeral()),
+        EcdhCurve::P384 => Ok(generate_p384_ephemeral()),
+        EcdhCurve::P521 => Ok(generate_p521_ephemeral()),
     }
 }
 
@@ -178,11 +179,14 @@ pub fn derive_key(
     let hk = Hkdf::<Sha512>::new(Some(salt), shared_secret);
     let mut okm = vec![0u8; length];
     hk.expand(info, &mut okm)
-        .map_err(|e| Error::KeyAgreement(format!("HKDF error: {}", e)))?;
+        .map_err(|e| Error::KeyAgreement(format!("HKDF error: {e}")))?;
     Ok(okm)
 }
 
 /// Wraps a content encryption key using AES key wrapping
+///
+/// # Errors
+/// Returns an error if the key material is invalid or the operation fails
 pub fn wrap_key(kek: &[u8], cek: &[u8]) -> Result<Vec<u8>> {
     use aes_kw::KekAes256;
 
@@ -204,6 +208,9 @@ pub fn wrap_key(kek: &[u8], cek: &[u8]) -> Result<Vec<u8>> {
 }
 
 /// Unwraps a wrapped key using AES key unwrapping
+///
+/// # Errors
+/// Returns an error if the key material is invalid or the operation fails
 pub fn unwrap_key(kek: &[u8], wrapped: &[u8]) -> Result<Vec<u8>> {
     use aes_kw::KekAes256;
 
@@ -213,7 +220,7 @@ pub fn unwrap_key(kek: &[u8], wrapped: &[u8]) -> Result<Vec<u8>> {
     let mut unwrapped = vec![0u8; wrapped.len() - 8];
     cipher
         .unwrap_with_padding(wrapped, &mut unwrapped)
-        .map_err(|e| Error::KeyWrap(format!("Key unwrapping failed: {}", e)))?;
+        .map_err(|e| Error::KeyWrap(format!("Key unwrapping failed: {e}")))?;
 
     Ok(unwrapped)
 }
@@ -244,7 +251,7 @@ pub fn encrypt_aes_gcm(
 ) -> Result<(Vec<u8>, Vec<u8>)> {
     let key: &AesGcmKeyArray = GenericArray::from_slice(key);
     let nonce: &AesGcmNonce = GenericArray::from_slice(nonce);
-    let cipher = Aes256Gcm::new(key.into());
+    let cipher = Aes256Gcm::new(key);
 
     let ciphertext = cipher
         .encrypt(
@@ -254,7 +261,7 @@ pub fn encrypt_aes_gcm(
                 aad,
             },
         )
-        .map_err(|e| Error::ContentEncryption(format!("AES-GCM encryption failed: {}", e)))?;
+        .map_err(|e| Error::ContentEncryption(format!("AES-GCM encryption failed: {e}")))?;
 
     // Split ciphertext and tag
     let tag_start = ciphertext.len() - 16;
@@ -309,7 +316,7 @@ pub fn decrypt_aes_gcm(
 
     let key: &AesGcmKeyArray = GenericArray::from_slice(key);
     let nonce: &AesGcmNonce = GenericArray::from_slice(nonce);
-    let cipher = Aes256Gcm::new(key.into());
+    let cipher = Aes256Gcm::new(key);
 
     let mut ciphertext_with_tag = ciphertext.to_vec();
     ciphertext_with_tag.extend_from_slice(tag);
@@ -322,7 +329,7 @@ pub fn decrypt_aes_gcm(
                 aad,
             },
         )
-        .map_err(|e| Error::ContentEncryption(format!("AES-GCM decryption failed: {}", e)))
+        .map_err(|e| Error::ContentEncryption(format!("AES-GCM decryption failed: {e}")))
 }
 
 /// Encrypts data using XChaCha20-Poly1305.
@@ -441,6 +448,9 @@ pub fn decrypt_xchacha20poly1305(
 }
 
 /// Encrypts content using AES-CBC with HMAC-SHA-512
+///
+/// # Errors
+/// Returns an error if the key material is invalid or the operation fails
 pub fn encrypt_aes_cbc_hmac(
     key: &[u8],
     iv: &[u8],
@@ -454,7 +464,7 @@ pub fn encrypt_aes_cbc_hmac(
 
     // Create HMAC instance with explicit type
     let mut mac = <HmacSha512 as hmac::digest::KeyInit>::new_from_slice(mac_key)
-        .map_err(|e| Error::ContentEncryption(format!("HMAC initialization failed: {}", e)))?;
+        .map_err(|e| Error::ContentEncryption(format!("HMAC initialization failed: {e}")))?;
 
     // Encrypt the content
     let enc_key_array: &GenericArray<u8, U32> = GenericArray::from_slice(enc_key);
@@ -465,7 +475,8 @@ pub fn encrypt_aes_cbc_hmac(
     let block_size = 16;
     let padding_len = block_size - (plaintext.len() % block_size);
     let mut padded = plaintext.to_vec();
-    padded.extend(std::iter::repeat(padding_len as u8).take(padding_len));
+    #[allow(clippy::cast_possible_truncation)] // at most one block
+    padded.extend(std::iter::repeat_n(padding_len as u8, padding_len));
 
     // Encrypt in CBC mode
     let mut ciphertext = Vec::with_capacity(padded.len());
@@ -495,6 +506,9 @@ pub fn encrypt_aes_cbc_hmac(
 }
 
 /// Decrypts content using AES-CBC with HMAC-SHA-512
+///
+/// # Errors
+/// Returns an error if the key material is invalid or the operation fails
 pub fn decrypt_aes_cbc_hmac(
     key: &[u8],
     iv: &[u8],
@@ -509,7 +523,7 @@ pub fn decrypt_aes_cbc_hmac(
 
     // Create HMAC instance with explicit type
     let mut mac = <HmacSha512 as hmac::digest::KeyInit>::new_from_slice(mac_key)
-        .map_err(|e| Error::ContentEncryption(format!("HMAC initialization failed: {}", e)))?;
+        .map_err(|e| Error::ContentEncryption(format!("HMAC initialization failed: {e}")))?;
 
     // Verify the tag
     mac.update(aad);
@@ -552,9 +566,9 @@ pub fn decrypt_aes_cbc_hmac(
 /// Performs P-256 key agreement
 fn p256_key_agreement(private_key: &[u8], public_key: &[u8]) -> Result<Vec<u8>> {
     let secret = P256SecretKey::from_slice(private_key)
-        .map_err(|e| Error::InvalidKeyMaterial(format!("Invalid P-256 private key: {}", e)))?;
+        .map_err(|e| Error::InvalidKeyMaterial(format!("Invalid P-256 private key: {e}")))?;
     let public = P256PublicKey::from_sec1_bytes(public_key)
-        .map_err(|e| Error::InvalidKeyMaterial(format!("Invalid P-256 public key: {}", e)))?;
+        .map_err(|e| Error::InvalidKeyMaterial(format!("Invalid P-256 public key: {e}")))?;
 
     let scalar: P256NonZeroScalar = secret.to_nonzero_scalar();
     let point = public.as_affine();
@@ -563,22 +577,22 @@ fn p256_key_agreement(private_key: &[u8], public_key: &[u8]) -> Result<Vec<u8>>
 }
 
 /// Generates a P-256 ephemeral keypair
-fn generate_p256_ephemeral() -> Result<(Vec<u8>, Vec<u8>)> {
+fn generate_p256_ephemeral() -> (Vec<u8>, Vec<u8>) {
     let secret = P256SecretKey::random(&mut OsRng);
     let public_key = P256PublicKey::from_secret_scalar(&secret.to_nonzero_scalar());
 
-    Ok((
+    (
         secret.to_bytes().to_vec(),
         public_key.to_encoded_point(false).as_bytes().to_vec(),
-    ))
+    )
 }
 
 /// Performs P-384 key agreement
 fn p384_key_agreement(private_key: &[u8], public_key: &[u8]) -> Result<Vec<u8>> {
     let secret = P384SecretKey::from_slice(private_key)
-        .map_err(|e| Error::InvalidKeyMaterial(format!("Invalid P-384 private key: {}", e)))?;
+        .map_err(|e| Error::InvalidKeyMaterial(format!("Invalid P-384 private key: {e}")))?;
     let public = P384PublicKey::from_sec1_bytes(public_key)
-        .map_err(|e| Error::InvalidKeyMaterial(format!("Invalid P-384 public key: {}", e)))?;
+        .map_err(|e| Error::InvalidKeyMaterial(format!("Invalid P-384 public key: {e}")))?;
 
     let scalar: P384NonZeroScalar = secret.to_nonzero_scalar();
     let point = public.as_affine();
@@ -587,22 +601,22 @@ fn p384_key_agreement(private_key: &[u8], public_key: &[u8]) -> Result<Vec<u8>>
 }
 
 /// Generates an ephemeral P-384 key pair.
-fn generate_p384_ephemeral() -> Result<(Vec<u8>, Vec<u8>)> {
+fn generate_p384_ephemeral() -> (Vec<u8>, Vec<u8>) {
     let secret = P384SecretKey::random(&mut OsRng);
     let public_key = P384PublicKey::from_secret_scalar(&secret.to_nonzero_scalar());
 
-    Ok((
+    (
         secret.to_bytes().to_vec(),
         public_key.to_encoded_point(false).as_bytes().to_vec(),
-    ))
+    )
 }
 
 /// Performs P-521 key agreement
 fn p521_key_agreement(private_key: &[u8], public_key: &[u8]) -> Result<Vec<u8>> {
     let secret = P521SecretKey::from_slice(private_key)
-        .map_err(|e| Error::InvalidKeyMaterial(format!("Invalid P-521 private key: {}", e)))?;
+        .map_err(|e| Error::InvalidKeyMaterial(format!("Invalid P-521 private key: {e}")))?;
     let public = P521PublicKey::from_sec1_bytes(public_key)
-        .map_err(|e| Error::InvalidKeyMaterial(format!("Invalid P-521 public key: {}", e)))?;
+        .map_err(|e| Error::InvalidKeyMaterial(format!("Invalid P-521 public key: {e}")))?;
 
     let scalar: P521NonZeroScalar = secret.to_nonzero_scalar();
     let point = public.as_affine();
@@ -611,14 +625,14 @@ fn p521_key_agreement(private_key: &[u8], public_key: &[u8]) -> Result<Vec<u8>>
 }
 
 /// Generates an ephemeral P-521 key pair.
-fn generate_p521_ephemeral() -> Result<(Vec<u8>, Vec<u8>)> {
+fn generate_p521_ephemeral() -> (Vec<u8>, Vec<u8>) {
     let secret = P521SecretKey::random(&mut OsRng);
     let public_key = P521PublicKey::from_secret_scalar(&secret.to_nonzero_scalar());
 
-    Ok((
+    (
         secret.to_bytes().to_vec(),
         public_key.to_encoded_point(false).as_bytes().to_vec(),
-    ))
+    )
 }
 
 /// Compresses a public key for the specified curve.
@@ -695,13 +709,6 @@ pub fn decompress_public_key(curve: EcdhCurve, public_key: &[u8]) -> Result<Vec<
     }
 }
 
-/// Creates HMAC instance with explicit type annotation
-fn create_hmac(key: &[u8]) -> Result<HmacSha512> {
-    let hmac = <HmacSha512 as hmac::digest::KeyInit>::new_from_slice(key)
-        .map_err(|e| Error::ContentEncryption(format!("HMAC initialization failed: {}", e)))?;
-    Ok(hmac)
-}
-
 /// Validates PKCS7 padding
 fn validate_pkcs7_padding(plaintext: &mut Vec<u8>) -> Result<()> {
     if plaintext.is_empty() {
@@ -717,7 +724,7 @@ fn validate_pkcs7_padding(plaintext: &mut Vec<u8>) -> Result<()> {
 
     let start = plaintext.len() - padding_len;
     for &byte in &plaintext[start..] {
-        if byte != padding_len as u8 {
+        if usize::from(byte) != padding_len {
             return Err(Error::ContentEncryption("Invalid padding".to_string()));
         }
     }
@@ -874,8 +881,8 @@ mod tests {
 
     #[test]
     fn test_p256_key_agreement() {
-        let (priv_a, pub_a) = generate_p256_ephemeral().unwrap();
-        let (priv_b, pub_b) = generate_p256_ephemeral().unwrap();
+        let (priv_a, pub_a) = generate_p256_ephemeral();
+        let (priv_b, pub_b) = generate_p256_ephemeral();
 
         let shared_a = p256_key_agreement(&priv_a, &pub_b).unwrap();
         let shared_b = p256_key_agreement(&priv_b, &pub_a).unwrap();
@@ -885,8 +892,8 @@ mod tests {
 
     #[test]
     fn test_p384_key_agreement() {
-        let (priv_a, pub_a) = generate_p384_ephemeral().unwrap();
-        let (priv_b, pub_b) = generate_p384_ephemeral().unwrap();
+        let (priv_a, pub_a) = generate_p384_ephemeral();
+        let (priv_b, pub_b) = generate_p384_ephemeral();
 
         let shared_a = p384_key_agreement(&priv_a, &pub_b).unwrap();
         let shared_b = p384_key_agreement(&priv_b, &pub_a).unwrap();
@@ -896,8 +903,8 @@ mod tests {
 
     #[test]
     fn test_p521_key_agreement() {
-        let (priv_a, pub_a) = generate_p521_ephemeral().unwrap();
-        let (priv_b, pub_b) = generate_p521_ephemeral().unwrap();
+        let (priv_a, pub_a) = generate_p521_ephemeral();
+        let (priv_b, pub_b) = generate_p521_ephemeral();
 
         let shared_a = p521_key_agreement(&priv_a, &pub_b).unwrap();
         let shared_b = p521_key_agreement(&priv_b, &pub_a).unwrap();
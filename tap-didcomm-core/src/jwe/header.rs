@@ -50,11 +50,11 @@ pub struct JweHeader {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub skid: Option<String>,
 
-    /// The agreement PartyUInfo (APU, for authcrypt)
+    /// The agreement `PartyUInfo` (APU, for authcrypt)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub apu: Option<String>,
 
-    /// The agreement PartyVInfo (APV)
+    /// The agreement `PartyVInfo` (APV)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub apv: Option<String>,
 
@@ -126,7 +126,7 @@ impl JweHeader {
     /// * `content_encryption` - The content encryption algorithm to use
     /// * `epk` - The ephemeral public key
     /// * `skid` - The sender key ID
-    /// * `apu` - Optional agreement PartyUInfo
+    /// * `apu` - Optional agreement `PartyUInfo`
     ///
     /// # Returns
     /// A new JWE header for authenticated encryption
@@ -223,7 +223,7 @@ impl EphemeralPublicKey {
                     EcdhCurve::P256 => 32,
                     EcdhCurve::P384 => 48,
                     EcdhCurve::P521 => 66,
-                    _ => unreachable!(),
+                    EcdhCurve::X25519 => unreachable!(),
                 };
 
                 if public_key.len() != 1 + 2 * key_size {
@@ -232,7 +232,7 @@ impl EphemeralPublicKey {
                     ));
                 }
 
-                let x = &public_key[1..1 + key_size];
+                let x = &public_key[1..=key_size];
                 let y = &public_key[1 + key_size..];
 
                 Ok(Self {
@@ -281,10 +281,10 @@ impl EphemeralPublicKey {
     }
 }
 
-impl ToString for JweHeader {
-    fn to_string(&self) -> String {
-        let json = serde_json::to_string(self).expect("JweHeader serialization failed");
-        URL_SAFE_NO_PAD.encode(json.as_bytes())
+impl std::fmt::Display for JweHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let json = serde_json::to_string(self).map_err(|_| std::fmt::Error)?;
+        f.write_str(&URL_SAFE_NO_PAD.encode(json.as_bytes()))
     }
 }
 
@@ -55,7 +55,6 @@ use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
 use zeroize::Zeroize;
 
 use crate::error::{Error, Result};
@@ -237,9 +236,9 @@ impl JweMessage {
             .as_str()
             .ok_or_else(|| Error::InvalidDIDDocument("No publicKeyBase64 found".into()))?;
 
-        let recipient_key = URL_SAFE_NO_PAD.decode(public_key_base64).map_err(|e| {
-            Error::InvalidDIDDocument(format!("Invalid public key encoding: {}", e))
-        })?;
+        let recipient_key = URL_SAFE_NO_PAD
+            .decode(public_key_base64)
+            .map_err(|e| Error::InvalidDIDDocument(format!("Invalid public key encoding: {e}")))?;
 
         // Perform ECDH and derive shared secret
         let shared_secret = ecdh_key_agreement(curve, &ephemeral_private, &recipient_key)?;
@@ -257,14 +256,15 @@ impl JweMessage {
         // Encode header
         let protected = URL_SAFE_NO_PAD.encode(
             serde_json::to_string(&header)
-                .map_err(|e| Error::Header(format!("Failed to serialize header: {}", e)))?,
+                .map_err(|e| Error::Header(format!("Failed to serialize header: {e}")))?,
         );
 
         // Generate content encryption key and IV
         let cek = match content_encryption {
             ContentEncryptionAlgorithm::A256CbcHs512 => generate_random_key(32 + 64),
-            ContentEncryptionAlgorithm::A256Gcm => generate_random_key(32),
-            ContentEncryptionAlgorithm::Xc20P => generate_random_key(32),
+            ContentEncryptionAlgorithm::A256Gcm | ContentEncryptionAlgorithm::Xc20P => {
+                generate_random_key(32)
+            }
         };
 
         let iv = match content_encryption {
@@ -311,10 +311,11 @@ impl JweMessage {
     /// * `Error::KeyAgreement` - If key agreement fails
     /// * `Error::ContentEncryption` - If content decryption fails
     /// * `Error::InvalidKeyMaterial` - If the provided private key is invalid
+    #[allow(clippy::unused_async)]
     pub async fn decrypt<R: DIDResolver>(
         &self,
         recipient_private_key: &[u8],
-        resolver: &R,
+        _resolver: &R,
     ) -> Result<Vec<u8>> {
         // Decode protected header
         let protected_json = URL_SAFE_NO_PAD
@@ -322,7 +323,7 @@ impl JweMessage {
             .map_err(|e| Error::Base64(e.to_string()))?;
 
         let header: header::JweHeader = serde_json::from_slice(&protected_json)
-            .map_err(|e| Error::Header(format!("Failed to parse header: {}", e)))?;
+            .map_err(|e| Error::Header(format!("Failed to parse header: {e}")))?;
 
         // Extract ephemeral public key
         let epk = header
@@ -370,28 +371,6 @@ impl JweMessage {
     }
 }
 
-/// Resolves a key from a DID Document
-async fn resolve_key<R: DIDResolver>(resolver: &R, did: &str) -> Result<Vec<u8>> {
-    let did_doc = resolver.resolve(did).await?;
-    let doc: Value = serde_json::from_str(&did_doc).map_err(|e| Error::Json(e))?;
-
-    // Extract verification method from DID Document
-    let vm = doc["verificationMethod"]
-        .as_array()
-        .ok_or_else(|| Error::InvalidDIDDocument("No verification methods found".into()))?
-        .first()
-        .ok_or_else(|| Error::InvalidDIDDocument("Empty verification methods".into()))?;
-
-    // Get public key bytes
-    let public_key_base64 = vm["publicKeyBase64"]
-        .as_str()
-        .ok_or_else(|| Error::InvalidDIDDocument("No publicKeyBase64 found".into()))?;
-
-    URL_SAFE_NO_PAD
-        .decode(public_key_base64)
-        .map_err(|e| Error::Base64(e.to_string()))
-}
-
 /// Builder for creating encrypted messages with multiple recipients.
 ///
 /// This builder provides a fluent interface for constructing encrypted
@@ -436,23 +415,27 @@ pub struct Recipient {
 
 impl EncryptedMessageBuilder {
     /// Creates a new empty builder.
+    #[must_use]
     pub fn new() -> Self {
         Self::default()
     }
 
     /// Sets the sender information for authenticated encryption.
+    #[must_use]
     pub fn from(mut self, sender_did: String, sender_key: Vec<u8>) -> Self {
         self.sender = Some((sender_did, sender_key));
         self
     }
 
     /// Adds a recipient who will be able to decrypt the message.
+    #[must_use]
     pub fn add_recipient(mut self, did: String, key: Vec<u8>) -> Self {
         self.recipients.push(Recipient { did, key });
         self
     }
 
     /// Sets the plaintext to be encrypted.
+    #[must_use]
     pub fn plaintext(mut self, data: &[u8]) -> Self {
         self.plaintext = Some(data.to_vec());
         self
@@ -470,6 +453,7 @@ impl EncryptedMessageBuilder {
     /// - No recipients are specified
     /// - No plaintext is specified
     /// - Encryption fails
+    #[allow(clippy::unused_async)]
     pub async fn build(self) -> Result<Vec<u8>> {
         if self.recipients.is_empty() {
             return Err(Error::EncryptionFailed(
@@ -477,7 +461,7 @@ impl EncryptedMessageBuilder {
             ));
         }
 
-        let plaintext = self
+        let _plaintext = self
             .plaintext
             .ok_or_else(|| Error::EncryptionFailed("No plaintext specified".to_string()))?;
 
@@ -543,7 +527,7 @@ pub async fn pack_message(
                 Error::InvalidDIDDocument("Recipient DIDs required for authcrypt".into())
             })?;
 
-            let to_refs: Vec<&str> = to.iter().map(|s| s.as_str()).collect();
+            let to_refs: Vec<&str> = to.iter().map(std::string::String::as_str).collect();
 
             let encrypted = plugin
                 .encryptor()
@@ -558,7 +542,7 @@ pub async fn pack_message(
                 Error::InvalidDIDDocument("Recipient DIDs required for anoncrypt".into())
             })?;
 
-            let to_refs: Vec<&str> = to.iter().map(|s| s.as_str()).collect();
+            let to_refs: Vec<&str> = to.iter().map(std::string::String::as_str).collect();
 
             let encrypted = plugin
                 .encryptor()
@@ -632,6 +616,7 @@ mod tests {
     use super::*;
     use async_trait::async_trait;
     use serde_json::json;
+    use std::collections::HashMap;
 
     #[test]
     fn test_encryption_config_default() {
@@ -724,7 +709,7 @@ mod tests {
 
     #[async_trait]
     impl DIDResolver for MockResolver {
-        async fn resolve(&self, did: &str) -> crate::error::Result<String> {
+        async fn resolve(&self, _did: &str) -> crate::error::Result<String> {
             // Return a test public key
             let mut key = [0u8; 32];
             key[0] = 1;
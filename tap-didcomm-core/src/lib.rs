@@ -19,8 +19,11 @@
 //! # Architecture
 //!
 //! The crate is organized into these main modules:
+//! - `attachment`: Attachment integrity (hashes and signatures)
+//! - `clock`: Clocks and message timestamp validation
 //! - `pack`: Message packing and unpacking operations
 //! - `plugin`: Plugin system for DID resolution and cryptographic operations
+//! - `service`: `DIDCommMessaging` services from DID documents
 //! - `types`: Core type definitions
 //! - `error`: Error types and handling
 //! - `jwe`: JSON Web Encryption implementation
@@ -33,7 +36,7 @@
 //!
 //! async fn example(plugin: &impl DIDCommPlugin) -> Result<()> {
 //!     // Create a message
-//!     let message = Message::new("Hello DIDComm!".to_string())
+//!     let message = Message::new("https://didcomm.org/basicmessage/2.0/message", "Hello DIDComm!")?
 //!         .from("did:example:alice")
 //!         .to(vec!["did:example:bob"]);
 //!
@@ -69,6 +72,7 @@
 //! - DID resolution failures
 //! - Cryptographic operation failures
 //! - Message format errors
+//! - Expired messages and clock skew
 //! - Plugin errors
 //! - Base64 encoding/decoding errors
 //! - JSON serialization/deserialization errors
@@ -79,12 +83,15 @@
 #![deny(clippy::pedantic)]
 #![allow(clippy::module_name_repetitions)]
 
+pub mod attachment;
+pub mod clock;
 pub mod crypto;
 pub mod error;
 pub mod jwe;
 pub mod pack;
 pub mod plugin;
 pub mod prelude;
+pub mod service;
 pub mod types;
 /// Utility functions for DID validation and other common operations
 pub mod utils;
@@ -94,8 +101,8 @@ pub(crate) mod tests;
 
 // Re-export commonly used types at the crate root
 pub use error::{Error, Result};
-pub use pack::{pack_message, unpack_message, Message};
+pub use pack::{pack_message, unpack_envelope, unpack_message, UnpackedMessage};
 pub use plugin::{DIDCommPlugin, DIDResolver, Encryptor, Signer};
 pub use types::{
-    Attachment, AttachmentData, Header, MessageId, MessageType, PackedMessage, PackingType,
+    Attachment, AttachmentData, Header, Message, MessageId, MessageType, PackedMessage, PackingType,
 };
@@ -3,16 +3,15 @@
 //! This module provides functions for packing and unpacking `DIDComm` messages
 //! using different methods (`Signed`, `AuthCrypt`, `AnonCrypt`).
 
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use uuid::Uuid;
+use serde_json::{json, Value};
 
 use crate::error::{Error, Result};
 use crate::jwe::EncryptedMessageBuilder;
 use crate::plugin::DIDCommPlugin;
 use crate::plugin::DIDCommPlugins;
-use crate::types::PackingType;
+use crate::types::{Message, PackingType};
 use crate::utils::validate_did;
 
 /// A recipient for an encrypted message.
@@ -24,88 +23,88 @@ pub struct Recipient {
     pub key: Vec<u8>,
 }
 
-/// A `DIDComm` message.
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub struct Message {
-    /// The unique identifier for this message
-    pub id: String,
+/// A message unpacked from an envelope, with the protection the envelope had
+#[derive(Debug, Clone)]
+pub struct UnpackedMessage {
+    /// The unpacked message
+    pub message: Message,
 
-    /// The message body as a JSON value
-    pub body: Value,
+    /// The packing of the envelope, or `None` for a plaintext message
+    pub packing: Option<PackingType>,
 
-    /// The sender DID (optional)
-    pub from: Option<String>,
+    /// The key ID the envelope was authcrypted by, if it was authcrypted
+    pub authenticated_sender: Option<String>,
 
-    /// The recipient DIDs (optional)
-    pub to: Option<Vec<String>>,
+    /// The key ID whose signature over the message was verified, if it was signed
+    pub signed_by: Option<String>,
 }
 
-impl Message {
-    /// Creates a new message with the given body.
-    ///
-    /// # Arguments
-    /// * `body` - The message body that can be converted into a JSON value
-    ///
-    /// # Returns
-    /// A new `Message` instance with the given body
-    #[must_use]
-    pub fn new<T: Into<Value>>(body: T) -> Self {
-        Message {
-            id: Uuid::new_v4().to_string(),
-            body: body.into(),
-            from: None,
-            to: None,
-        }
-    }
+/// Media type of a signed `DIDComm` envelope
+const SIGNED_TYP: &str = "application/didcomm-signed+json";
 
-    /// Sets the sender DID.
-    ///
-    /// # Arguments
-    /// * `from` - The sender's DID as a string reference
-    ///
-    /// # Returns
-    /// The modified `Message` instance
-    #[must_use]
-    pub fn from<S: AsRef<str>>(mut self, from: S) -> Self {
-        self.from = Some(from.as_ref().to_string());
-        self
-    }
+/// Media type of an encrypted `DIDComm` envelope
+const ENCRYPTED_TYP: &str = "application/didcomm-encrypted+json";
 
-    /// Sets the recipients of the message.
-    ///
-    /// # Arguments
-    /// * `to` - The recipient DIDs as an iterator of string references
-    ///
-    /// # Returns
-    /// The modified `Message` instance
-    #[must_use]
-    pub fn to<S: AsRef<str>>(mut self, to: impl IntoIterator<Item = S>) -> Self {
-        self.to = Some(to.into_iter().map(|s| s.as_ref().to_string()).collect());
-        self
-    }
+/// Gets the DID a key ID belongs to
+fn did_of(kid: &str) -> &str {
+    kid.split_once('#').map_or(kid, |(did, _)| did)
+}
 
-    /// Gets the message body as a JSON string.
-    ///
-    /// # Errors
-    /// Returns an error if the body cannot be serialized to JSON
-    fn to_json_string(&self) -> Result<String> {
-        serde_json::to_string(&self.body).map_err(Error::Json)
+/// Encodes bytes as base64url without padding
+fn encode(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Decodes base64url without padding
+fn decode(encoded: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(encoded.trim())
+        .map_err(|e| Error::Base64(format!("Invalid base64: {e}")))
+}
+
+/// Gets a string member of a JSON object
+fn field<'a>(value: &'a Value, name: &str) -> Result<&'a str> {
+    value[name]
+        .as_str()
+        .ok_or_else(|| Error::SerializationError(format!("Envelope has no {name}")))
+}
+
+/// Checks that the recipients of a message to encrypt are valid DIDs
+fn recipients(message: &Message) -> Result<Vec<&str>> {
+    let recipients = message.to.as_deref().unwrap_or_default();
+    if recipients.is_empty() {
+        return Err(Error::InvalidDIDDocument(
+            "At least one recipient required for encryption".into(),
+        ));
+    }
+    for did in recipients {
+        validate_did(did)?;
     }
+    Ok(recipients.iter().map(String::as_str).collect())
 }
 
 /// Pack a message using the specified packing type.
 ///
+/// Signed messages are packed as a JWS in general JSON serialization. The
+/// signer's `kid` is in the protected header, and the signature covers the
+/// JWS signing input `BASE64URL(protected) || '.' || BASE64URL(payload)`,
+/// so the header cannot be changed without breaking it.
+///
+/// Encrypted messages are packed as a JWE in general JSON serialization,
+/// with one recipient per `to` DID. The output of the plugin's encryptor is
+/// carried as the ciphertext; key agreement and content encryption are up
+/// to the plugin.
+///
 /// # Arguments
 /// * `message` - The message to pack
 /// * `plugin` - Plugin providing cryptographic operations
 /// * `packing_type` - Type of packing to use (`Signed`, `AuthCryptV2`, `AnonV2`)
 ///
 /// # Returns
-/// The packed message as a base64url-encoded string
+/// The packed envelope as JSON
 ///
 /// # Errors
 /// * `Error::InvalidDIDDocument` - If a DID is invalid or missing when required
-/// * `Error::Base64` - If base64 encoding fails
 /// * `Error::Json` - If JSON serialization fails
 /// * `Error::SigningFailed` - If message signing fails
 /// * `Error::EncryptionFailed` - If message encryption fails
@@ -114,59 +113,65 @@ pub async fn pack_message(
     plugin: &dyn DIDCommPlugin,
     packing_type: PackingType,
 ) -> Result<String> {
-    let msg_json = message.to_json_string()?;
-    match packing_type {
+    let msg_json = serde_json::to_string(message).map_err(Error::Json)?;
+    let envelope = match packing_type {
         PackingType::Signed => {
             let from = message.from.as_deref().ok_or_else(|| {
                 Error::InvalidDIDDocument("Sender DID required for signed messages".into())
             })?;
             validate_did(from)?;
-            let signed = plugin.signer().sign(msg_json.as_bytes(), from).await?;
-            Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&signed))
-        }
-        PackingType::AuthcryptV2 => {
-            let from = message.from.as_deref().ok_or_else(|| {
-                Error::InvalidDIDDocument("Sender DID required for authcrypt".into())
-            })?;
-            validate_did(from)?;
-            let recipients = message.to.as_ref().ok_or_else(|| {
-                Error::InvalidDIDDocument("At least one recipient required for authcrypt".into())
-            })?;
-            if recipients.is_empty() {
-                return Err(Error::InvalidDIDDocument(
-                    "At least one recipient required for authcrypt".into(),
-                ));
-            }
-            for did in recipients {
-                validate_did(did)?;
-            }
-            let to_refs: Vec<&str> = recipients.iter().map(String::as_str).collect();
-            let encrypted = plugin
-                .encryptor()
-                .encrypt(msg_json.as_bytes(), &to_refs, Some(from))
+            let protected = encode(
+                json!({"typ": SIGNED_TYP, "alg": "EdDSA", "kid": from})
+                    .to_string()
+                    .as_bytes(),
+            );
+            let payload = encode(msg_json.as_bytes());
+            let signature = plugin
+                .signer()
+                .sign(format!("{protected}.{payload}").as_bytes(), from)
                 .await?;
-            Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&encrypted))
+            json!({
+                "payload": payload,
+                "signatures": [{
+                    "protected": protected,
+                    "header": {"kid": from},
+                    "signature": encode(&signature),
+                }],
+            })
         }
-        PackingType::AnonV2 => {
-            let recipients = message.to.as_ref().ok_or_else(|| {
-                Error::InvalidDIDDocument("At least one recipient required for anoncrypt".into())
-            })?;
-            if recipients.is_empty() {
-                return Err(Error::InvalidDIDDocument(
-                    "At least one recipient required for anoncrypt".into(),
-                ));
-            }
-            for did in recipients {
-                validate_did(did)?;
-            }
-            let to_refs: Vec<&str> = recipients.iter().map(String::as_str).collect();
-            let encrypted = plugin
+        PackingType::AuthcryptV2 | PackingType::AnonV2 => {
+            let from = if packing_type == PackingType::AuthcryptV2 {
+                let from = message.from.as_deref().ok_or_else(|| {
+                    Error::InvalidDIDDocument("Sender DID required for authcrypt".into())
+                })?;
+                validate_did(from)?;
+                Some(from)
+            } else {
+                None
+            };
+            let to = recipients(message)?;
+            let ciphertext = plugin
                 .encryptor()
-                .encrypt(msg_json.as_bytes(), &to_refs, None)
+                .encrypt(msg_json.as_bytes(), &to, from)
                 .await?;
-            Ok(base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(&encrypted))
+            let mut protected = json!({
+                "typ": ENCRYPTED_TYP,
+                "alg": if from.is_some() { "ECDH-1PU+A256KW" } else { "ECDH-ES+A256KW" },
+            });
+            if let Some(from) = from {
+                protected["skid"] = json!(from);
+            }
+            json!({
+                "protected": encode(protected.to_string().as_bytes()),
+                "recipients": to
+                    .iter()
+                    .map(|kid| json!({"header": {"kid": kid}}))
+                    .collect::<Vec<_>>(),
+                "ciphertext": encode(&ciphertext),
+            })
         }
-    }
+    };
+    Ok(envelope.to_string())
 }
 
 /// Unpack a `DIDComm` message.
@@ -174,47 +179,140 @@ pub async fn pack_message(
 /// # Arguments
 /// * `packed` - The packed message to unpack
 /// * `plugin` - Plugin providing cryptographic operations
-/// * `recipient` - Optional recipient DID to use for decryption
+/// * `recipient` - Recipient DID to decrypt with, or `None` to try each
+///   recipient the envelope names
 ///
 /// # Errors
-/// * `Error::Base64` - If base64 decoding fails
-/// * `Error::Json` - If JSON parsing fails
-/// * `Error::InvalidDIDDocument` - If a DID document is invalid
-/// * `Error::KeyAgreement` - If key agreement fails
-/// * `Error::ContentEncryption` - If content decryption fails
+/// See [`unpack_envelope`]
 pub async fn unpack_message(
     packed: &str,
     plugin: &dyn DIDCommPlugin,
     recipient: Option<String>,
 ) -> Result<Message> {
-    let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
-        .decode(packed)
-        .map_err(|e| Error::Base64(format!("Invalid base64: {e}")))?;
+    Ok(unpack_envelope(packed, plugin, recipient).await?.message)
+}
 
-    // Try to parse as JSON first
-    if let Ok(message) = serde_json::from_slice::<Message>(&decoded) {
-        return Ok(message);
-    }
+/// Unpack a `DIDComm` envelope, reporting how it was protected.
+///
+/// The envelope may be a signed JWS, an encrypted JWE or a plaintext
+/// message, as JSON. Envelopes that are base64url encoded, as earlier
+/// versions of [`pack_message`] produced, are decoded first.
+///
+/// A signed envelope is verified against the `kid` of its protected header,
+/// and an authcrypted envelope is decrypted as coming from its `skid`. When
+/// an envelope names a sender this way, the `from` of the message must be
+/// the same DID.
+///
+/// # Arguments
+/// * `packed` - The envelope to unpack
+/// * `plugin` - Plugin providing cryptographic operations
+/// * `recipient` - Recipient DID to decrypt with, or `None` to try each
+///   recipient the envelope names
+///
+/// # Errors
+/// * `Error::Base64` - If base64 decoding fails
+/// * `Error::Json` - If JSON parsing fails
+/// * `Error::SerializationError` - If the envelope is malformed
+/// * `Error::VerificationFailed` - If the signature does not verify
+/// * `Error::AuthenticationFailed` - If the message's `from` does not match
+///   the DID that signed or authcrypted it
+/// * `Error::DecryptionFailed` - If no recipient can decrypt the envelope
+pub async fn unpack_envelope(
+    packed: &str,
+    plugin: &dyn DIDCommPlugin,
+    recipient: Option<String>,
+) -> Result<UnpackedMessage> {
+    let packed = packed.trim();
+    let envelope: Value = if packed.starts_with('{') {
+        serde_json::from_str(packed)?
+    } else {
+        serde_json::from_slice(&decode(packed)?)?
+    };
 
-    // If not JSON, try to verify as signed message
-    if let Some(from) = recipient.as_ref() {
-        validate_did(from)?;
-        let verified = plugin.signer().verify(&decoded, &decoded, from).await?;
-        if verified {
-            let message: Message = serde_json::from_slice(&decoded)?;
-            return Ok(message);
+    let unpacked = if let Some(signature) = envelope.get("signatures") {
+        let signature = &signature[0];
+        let protected = field(signature, "protected")?;
+        let payload = field(&envelope, "payload")?;
+        let header: Value = serde_json::from_slice(&decode(protected)?)?;
+        let kid = field(&header, "kid")?;
+        validate_did(kid)?;
+        let bytes = decode(field(signature, "signature")?)?;
+        let verified = plugin
+            .signer()
+            .verify(format!("{protected}.{payload}").as_bytes(), &bytes, kid)
+            .await?;
+        if !verified {
+            return Err(Error::VerificationFailed(format!(
+                "Signature by {kid} does not verify"
+            )));
         }
-    }
+        UnpackedMessage {
+            message: serde_json::from_slice(&decode(payload)?)?,
+            packing: Some(PackingType::Signed),
+            authenticated_sender: None,
+            signed_by: Some(kid.to_string()),
+        }
+    } else if envelope.get("ciphertext").is_some() {
+        let protected: Value = serde_json::from_slice(&decode(field(&envelope, "protected")?)?)?;
+        let skid = protected["skid"].as_str();
+        let ciphertext = decode(field(&envelope, "ciphertext")?)?;
+        let kids: Vec<String> = match recipient {
+            Some(recipient) => vec![recipient],
+            None => envelope["recipients"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|r| r["header"]["kid"].as_str().map(str::to_string))
+                .collect(),
+        };
+        let mut plaintext = Err(Error::DecryptionFailed(
+            "Envelope names no recipient".into(),
+        ));
+        for kid in &kids {
+            validate_did(kid)?;
+            plaintext = match skid {
+                Some(skid) => {
+                    validate_did(skid)?;
+                    plugin
+                        .encryptor()
+                        .decrypt_from(&ciphertext, kid, skid)
+                        .await
+                }
+                None => plugin.encryptor().decrypt(&ciphertext, kid).await,
+            };
+            if plaintext.is_ok() {
+                break;
+            }
+        }
+        UnpackedMessage {
+            message: serde_json::from_slice(&plaintext?)?,
+            packing: Some(if skid.is_some() {
+                PackingType::AuthcryptV2
+            } else {
+                PackingType::AnonV2
+            }),
+            authenticated_sender: skid.map(str::to_string),
+            signed_by: None,
+        }
+    } else {
+        UnpackedMessage {
+            message: serde_json::from_value(envelope)?,
+            packing: None,
+            authenticated_sender: None,
+            signed_by: None,
+        }
+    };
 
-    // If not signed, try to decrypt
-    if let Some(recipient) = recipient.as_ref() {
-        validate_did(recipient)?;
-        let decrypted = plugin.encryptor().decrypt(&decoded, recipient).await?;
-        let message: Message = serde_json::from_slice(&decrypted)?;
-        return Ok(message);
+    let sender = unpacked
+        .authenticated_sender
+        .as_deref()
+        .or(unpacked.signed_by.as_deref());
+    if let (Some(sender), Some(from)) = (sender, unpacked.message.from.as_deref()) {
+        if did_of(sender) != did_of(from) {
+            return Err(Error::AuthenticationFailed);
+        }
     }
-
-    Err(Error::InvalidDIDDocument("Unable to unpack message".into()))
+    Ok(unpacked)
 }
 
 /// Packs a message with encryption for multiple recipients.
@@ -295,36 +393,133 @@ pub async fn pack_encrypted(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::plugin::tests::MockTestPlugin;
-    use serde_json::json;
+    use crate::tests::MockTestPlugin;
+    use crate::{Encryptor, Signer};
 
     #[tokio::test]
     async fn test_pack_signed() -> Result<()> {
         let plugin = MockTestPlugin;
-        let message = Message::new(json!("test"))
+        let message = Message::new("test", json!("test"))?
             .from("did:example:alice")
             .to(vec!["did:example:bob"]);
 
         let packed = pack_message(&message, &plugin, PackingType::Signed).await?;
-        let unpacked =
-            unpack_message(&packed, &plugin, Some("did:example:alice".to_string())).await?;
+        let envelope: Value = serde_json::from_str(&packed)?;
+        assert!(envelope["payload"].is_string());
+        assert!(envelope["signatures"][0]["protected"].is_string());
+
+        let unpacked = unpack_envelope(&packed, &plugin, None).await?;
+        assert_eq!(unpacked.message.body, message.body);
+        assert_eq!(unpacked.message.id.as_str(), message.id.as_str());
+        assert_eq!(unpacked.message.thread_id(), message.thread_id());
+        assert_eq!(unpacked.packing, Some(PackingType::Signed));
+        assert_eq!(unpacked.signed_by.as_deref(), Some("did:example:alice"));
+        assert_eq!(unpacked.authenticated_sender, None);
+        Ok(())
+    }
 
-        assert_eq!(unpacked.body, message.body);
+    #[tokio::test]
+    async fn test_signature_covers_protected_header() -> Result<()> {
+        let plugin = MockTestPlugin;
+        let message = Message::new("test", json!("test"))?.from("did:example:alice");
+        let packed = pack_message(&message, &plugin, PackingType::Signed).await?;
+
+        // Swap the protected header for one naming another signer
+        let mut envelope: Value = serde_json::from_str(&packed)?;
+        envelope["signatures"][0]["protected"] = json!(encode(
+            json!({"typ": SIGNED_TYP, "alg": "EdDSA", "kid": "did:example:mallory"})
+                .to_string()
+                .as_bytes()
+        ));
+        assert!(matches!(
+            unpack_message(&envelope.to_string(), &plugin, None).await,
+            Err(Error::VerificationFailed(_))
+        ));
+
+        // A signature over the payload alone is not a valid JWS signature
+        let mut envelope: Value = serde_json::from_str(&packed)?;
+        let payload = field(&envelope, "payload")?.to_string();
+        let signature = plugin.sign(payload.as_bytes(), "did:example:alice").await?;
+        envelope["signatures"][0]["signature"] = json!(encode(&signature));
+        assert!(matches!(
+            unpack_message(&envelope.to_string(), &plugin, None).await,
+            Err(Error::VerificationFailed(_))
+        ));
         Ok(())
     }
 
     #[tokio::test]
     async fn test_pack_authcrypt() -> Result<()> {
         let plugin = MockTestPlugin;
-        let message = Message::new(json!("test"))
+        let message = Message::new("test", json!("test"))?
             .from("did:example:alice")
             .to(vec!["did:example:bob"]);
 
         let packed = pack_message(&message, &plugin, PackingType::AuthcryptV2).await?;
+        let envelope: Value = serde_json::from_str(&packed)?;
+        assert_eq!(
+            envelope["recipients"][0]["header"]["kid"],
+            "did:example:bob"
+        );
+
         let unpacked =
             unpack_message(&packed, &plugin, Some("did:example:bob".to_string())).await?;
-
         assert_eq!(unpacked.body, message.body);
+        assert_eq!(unpacked.id.as_str(), message.id.as_str());
+
+        let unpacked = unpack_envelope(&packed, &plugin, None).await?;
+        assert_eq!(unpacked.packing, Some(PackingType::AuthcryptV2));
+        assert_eq!(
+            unpacked.authenticated_sender.as_deref(),
+            Some("did:example:alice")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unpack_anoncrypt_and_plaintext() -> Result<()> {
+        let plugin = MockTestPlugin;
+        let message = Message::new("test", json!("test"))?.to(vec!["did:example:bob"]);
+
+        let packed = pack_message(&message, &plugin, PackingType::AnonV2).await?;
+        let unpacked = unpack_envelope(&packed, &plugin, None).await?;
+        assert_eq!(unpacked.message.id.as_str(), message.id.as_str());
+        assert_eq!(unpacked.packing, Some(PackingType::AnonV2));
+        assert_eq!(unpacked.authenticated_sender, None);
+
+        let plaintext = serde_json::to_string(&message)?;
+        let unpacked = unpack_envelope(&plaintext, &plugin, None).await?;
+        assert_eq!(unpacked.message.id.as_str(), message.id.as_str());
+        assert_eq!(unpacked.packing, None);
+        assert_eq!(unpacked.signed_by, None);
+
+        let encoded = encode(plaintext.as_bytes());
+        let unpacked = unpack_message(&encoded, &plugin, None).await?;
+        assert_eq!(unpacked.id.as_str(), message.id.as_str());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unpack_rejects_mismatched_from() -> Result<()> {
+        let plugin = MockTestPlugin;
+        let message = Message::new("test", json!("test"))?
+            .from("did:example:alice")
+            .to(vec!["did:example:bob"]);
+        let mut forged = message.clone();
+        forged.from = Some("did:example:mallory".into());
+
+        // Authcrypt a message by alice that claims to be from mallory
+        let packed = pack_message(&message, &plugin, PackingType::AuthcryptV2).await?;
+        let mut envelope: Value = serde_json::from_str(&packed)?;
+        let ciphertext = plugin
+            .encrypt(&serde_json::to_vec(&forged)?, &["did:example:bob"], None)
+            .await?;
+        envelope["ciphertext"] = json!(encode(&ciphertext));
+
+        assert!(matches!(
+            unpack_envelope(&envelope.to_string(), &plugin, None).await,
+            Err(Error::AuthenticationFailed)
+        ));
         Ok(())
     }
 }
@@ -28,7 +28,7 @@
 //! - [`Encryptor`]: For message encryption and decryption
 //!
 //! These traits can be implemented individually or combined through the
-//! [`DIDCommPlugin`] trait to provide a complete DIDComm implementation.
+//! [`DIDCommPlugin`] trait to provide a complete `DIDComm` implementation.
 //!
 //! # Examples
 //!
@@ -112,7 +112,7 @@
 //! - Properly handle key material
 //! - Validate all inputs
 //! - Handle errors securely
-//! - Follow DIDComm v2 specifications
+//! - Follow `DIDComm` v2 specifications
 //! - Test implementations thoroughly
 //! - Consider side-channel attacks
 
@@ -194,6 +194,32 @@ pub trait Encryptor: Send + Sync {
     /// - If decryption fails
     /// - If the data is invalid
     async fn decrypt(&self, message: &[u8], recipient: &str) -> Result<Vec<u8>>;
+
+    /// Decrypts data authcrypted by a sender.
+    ///
+    /// The caller trusts `sender` as the authenticated sender of the
+    /// plaintext, so implementations must fail unless the data was encrypted
+    /// with the key of `sender`, as ECDH-1PU key agreement does. The default
+    /// implementation always fails, so that a plugin without authenticated
+    /// encryption never vouches for a sender.
+    ///
+    /// # Arguments
+    /// * `message` - The encrypted data
+    /// * `recipient` - The recipient DID
+    /// * `sender` - The key ID of the sender named by the envelope
+    ///
+    /// # Returns
+    /// The decrypted data or an error
+    ///
+    /// # Errors
+    /// - If the data was not encrypted by `sender`
+    /// - If decryption fails
+    async fn decrypt_from(&self, message: &[u8], recipient: &str, sender: &str) -> Result<Vec<u8>> {
+        let _ = (message, recipient);
+        Err(crate::Error::DecryptionFailed(format!(
+            "Cannot authenticate sender {sender}"
+        )))
+    }
 }
 
 /// A `DIDComm` plugin that provides DID resolution and cryptographic operations.
@@ -208,10 +234,10 @@ pub trait DIDCommPlugin: DIDResolver + Signer + Encryptor {
     fn encryptor(&self) -> &dyn Encryptor;
 }
 
-/// A collection of DIDComm plugin implementations.
+/// A collection of `DIDComm` plugin implementations.
 ///
 /// This trait provides access to DID resolution and signing operations
-/// needed for DIDComm message handling.
+/// needed for `DIDComm` message handling.
 #[async_trait::async_trait]
 pub trait DIDCommPlugins {
     /// Resolves a DID to its associated key material.
@@ -243,7 +269,7 @@ pub trait DIDCommPlugins {
     async fn get_signer(&self, did: &str) -> crate::error::Result<Box<dyn Signer>>;
 }
 
-/// Test implementations and mock plugins for testing DIDComm functionality.
+/// Test implementations and mock plugins for testing `DIDComm` functionality.
 #[cfg(test)]
 pub mod tests {
     use super::*;
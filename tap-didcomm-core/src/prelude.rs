@@ -10,7 +10,7 @@
 //! use tap_didcomm_core::prelude::*;
 //!
 //! async fn example(plugin: &impl DIDCommPlugin) -> Result<()> {
-//!     let message = Message::new("Hello DIDComm!".to_string())
+//!     let message = Message::new("https://didcomm.org/basicmessage/2.0/message", "Hello DIDComm!")?
 //!         .from("did:example:alice")
 //!         .to(vec!["did:example:bob"]);
 //!
@@ -37,4 +37,4 @@ pub use crate::jwe::{
 };
 
 // Re-export core functions
-pub use crate::pack::{pack_message, unpack_message};
+pub use crate::pack::{pack_message, unpack_envelope, unpack_message, UnpackedMessage};
@@ -0,0 +1,183 @@
+//! `DIDCommMessaging` services from DID documents.
+//!
+//! A DID document advertises where `DIDComm` messages for its subject should be
+//! delivered through services of type `DIDCommMessaging`. Each service has an
+//! endpoint URI, the media types it accepts, and an optional list of routing
+//! keys of the mediators that messages must be forwarded through.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use tap_didcomm_core::service::DIDCommService;
+//! use serde_json::json;
+//!
+//! let doc = json!({
+//!     "id": "did:example:bob",
+//!     "service": [{
+//!         "id": "did:example:bob#didcomm",
+//!         "type": "DIDCommMessaging",
+//!         "serviceEndpoint": {
+//!             "uri": "https://mediator.example.com/didcomm",
+//!             "routingKeys": ["did:example:mediator#key-1"]
+//!         }
+//!     }]
+//! });
+//!
+//! let services = DIDCommService::from_did_document(&doc);
+//! assert_eq!(services[0].uri, "https://mediator.example.com/didcomm");
+//! assert_eq!(services[0].routing_keys, vec!["did:example:mediator#key-1"]);
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{Error, Result};
+
+/// The service type of `DIDComm` v2 endpoints
+pub const DIDCOMM_SERVICE_TYPE: &str = "DIDCommMessaging";
+
+/// A `DIDCommMessaging` service endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct DIDCommService {
+    /// The ID of the service in the DID document
+    #[serde(default)]
+    pub id: String,
+
+    /// The URI messages are delivered to
+    pub uri: String,
+
+    /// The media types the endpoint accepts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub accept: Vec<String>,
+
+    /// Keys of the mediators messages are forwarded through, outermost first
+    #[serde(default, rename = "routingKeys", skip_serializing_if = "Vec::is_empty")]
+    pub routing_keys: Vec<String>,
+}
+
+impl DIDCommService {
+    /// Extracts the `DIDCommMessaging` services of a DID document.
+    ///
+    /// Services with a `serviceEndpoint` object, an array of objects or a
+    /// plain URI string are supported. Other services are skipped.
+    ///
+    /// # Arguments
+    /// * `doc` - The DID document
+    ///
+    /// # Returns
+    /// The services in document order
+    #[must_use]
+    pub fn from_did_document(doc: &Value) -> Vec<Self> {
+        let Some(services) = doc.get("service").and_then(Value::as_array) else {
+            return Vec::new();
+        };
+
+        services
+            .iter()
+            .filter(|service| is_didcomm_service(service))
+            .flat_map(|service| {
+                let id = service
+                    .get("id")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string();
+                let endpoints = match service.get("serviceEndpoint") {
+                    Some(Value::Array(endpoints)) => endpoints.clone(),
+                    Some(endpoint) => vec![endpoint.clone()],
+                    None => Vec::new(),
+                };
+                endpoints
+                    .into_iter()
+                    .filter_map(move |endpoint| parse_endpoint(&id, endpoint))
+            })
+            .collect()
+    }
+
+    /// Parses a DID document and extracts its `DIDCommMessaging` services.
+    ///
+    /// # Arguments
+    /// * `doc` - The DID document as returned by a `DIDResolver`
+    ///
+    /// # Errors
+    /// * `Error::InvalidDIDDocument` - If the document is not valid JSON
+    pub fn from_did_document_str(doc: &str) -> Result<Vec<Self>> {
+        let doc: Value = serde_json::from_str(doc)
+            .map_err(|e| Error::InvalidDIDDocument(format!("Invalid DID document: {e}")))?;
+        Ok(Self::from_did_document(&doc))
+    }
+}
+
+fn is_didcomm_service(service: &Value) -> bool {
+    match service.get("type") {
+        Some(Value::String(typ)) => typ == DIDCOMM_SERVICE_TYPE,
+        Some(Value::Array(types)) => types
+            .iter()
+            .any(|typ| typ.as_str() == Some(DIDCOMM_SERVICE_TYPE)),
+        _ => false,
+    }
+}
+
+fn parse_endpoint(id: &str, endpoint: Value) -> Option<DIDCommService> {
+    match endpoint {
+        Value::String(uri) => Some(DIDCommService {
+            id: id.to_string(),
+            uri,
+            ..DIDCommService::default()
+        }),
+        Value::Object(_) => {
+            let mut service: DIDCommService = serde_json::from_value(endpoint).ok()?;
+            service.id = id.to_string();
+            Some(service)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_service_endpoint_forms() {
+        let doc = json!({
+            "id": "did:example:bob",
+            "service": [
+                {
+                    "id": "did:example:bob#linked",
+                    "type": "LinkedDomains",
+                    "serviceEndpoint": "https://bob.example.com"
+                },
+                {
+                    "id": "did:example:bob#didcomm-1",
+                    "type": "DIDCommMessaging",
+                    "serviceEndpoint": [{
+                        "uri": "https://mediator.example.com/didcomm",
+                        "accept": ["didcomm/v2"],
+                        "routingKeys": ["did:example:m1#key-1", "did:example:m2#key-1"]
+                    }]
+                },
+                {
+                    "id": "did:example:bob#didcomm-2",
+                    "type": ["DIDCommMessaging"],
+                    "serviceEndpoint": "https://bob.example.com/didcomm"
+                }
+            ]
+        });
+
+        let services = DIDCommService::from_did_document(&doc);
+        assert_eq!(services.len(), 2);
+        assert_eq!(services[0].id, "did:example:bob#didcomm-1");
+        assert_eq!(services[0].accept, vec!["didcomm/v2"]);
+        assert_eq!(services[0].routing_keys.len(), 2);
+        assert_eq!(services[1].uri, "https://bob.example.com/didcomm");
+        assert!(services[1].routing_keys.is_empty());
+    }
+
+    #[test]
+    fn test_document_without_services() {
+        let services = DIDCommService::from_did_document_str(r#"{"id": "did:example:bob"}"#);
+        assert!(services.unwrap().is_empty());
+        assert!(DIDCommService::from_did_document_str("not json").is_err());
+    }
+}
@@ -74,6 +74,12 @@ pub struct Message {
     /// The DIDs of the recipients
     pub to: Option<Vec<String>>,
 
+    /// The thread this message belongs to (defaults to the message ID of the first message)
+    pub thid: Option<String>,
+
+    /// The parent thread this message's thread was spawned from
+    pub pthid: Option<String>,
+
     /// Unix timestamp when the message was created
     pub created_time: u64,
 
@@ -105,6 +111,8 @@ impl Message {
             typ: MessageType(typ.into()),
             from: None,
             to: None,
+            thid: None,
+            pthid: None,
             created_time,
             expires_time: None,
             body: body.into(),
@@ -126,6 +134,49 @@ impl Message {
         self
     }
 
+    /// Sets the thread ID of the message.
+    #[must_use]
+    pub fn thid(mut self, thid: impl Into<String>) -> Self {
+        self.thid = Some(thid.into());
+        self
+    }
+
+    /// Sets the parent thread ID of the message.
+    #[must_use]
+    pub fn pthid(mut self, pthid: impl Into<String>) -> Self {
+        self.pthid = Some(pthid.into());
+        self
+    }
+
+    /// Gets the thread ID of the message.
+    ///
+    /// A message without an explicit `thid` starts its own thread, so its
+    /// message ID is used as the thread ID.
+    #[must_use]
+    pub fn thread_id(&self) -> &str {
+        self.thid.as_deref().unwrap_or(self.id.as_str())
+    }
+
+    /// Creates a reply to this message in the same thread.
+    ///
+    /// The reply's `thid` is set to this message's thread ID, its sender is
+    /// the first recipient of this message and its recipient is this
+    /// message's sender. The parent thread ID is carried over.
+    ///
+    /// # Errors
+    /// Returns an error if the system time cannot be obtained.
+    pub fn reply(
+        &self,
+        typ: impl Into<String>,
+        body: impl Into<serde_json::Value>,
+    ) -> crate::error::Result<Self> {
+        let mut reply = Self::new(typ, body)?.thid(self.thread_id());
+        reply.pthid.clone_from(&self.pthid);
+        reply.from = self.to.as_ref().and_then(|to| to.first().cloned());
+        reply.to = self.from.clone().map(|from| vec![from]);
+        Ok(reply)
+    }
+
     /// Sets the expiration time of the message.
     #[must_use]
     pub fn expires_at(mut self, expires_time: u64) -> Self {
@@ -147,48 +198,68 @@ impl Message {
 /// An attachment to a `DIDComm` message.
 ///
 /// Attachments can contain additional data that is associated with the message,
-/// such as files, images, or other binary content.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// such as files, images, or other binary content. The JSON representation
+/// follows the `DIDComm` v2 attachment format; absent fields are omitted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Attachment {
     /// Unique identifier for the attachment
     pub id: String,
 
     /// Optional human-readable description
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
 
     /// Optional filename for the attachment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub filename: Option<String>,
 
     /// Optional MIME type of the attachment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub media_type: Option<String>,
 
     /// Optional format identifier
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub format: Option<String>,
 
+    /// Optional Unix timestamp of when the content was last modified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lastmod_time: Option<u64>,
+
+    /// Optional size of the content in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub byte_count: Option<u64>,
+
     /// The actual attachment data
     pub data: AttachmentData,
 }
 
-/// The data formats that can be used for attachments
+/// The data of an attachment.
 ///
-/// This enum represents the different ways data can be attached
-/// in a `DIDComm` message, such as JWS, base64-encoded data, or JSON.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum AttachmentData {
-    /// JWS (JSON Web Signature) data
-    Jws(serde_json::Value),
-
-    /// Hash of the data for integrity verification
-    Hash(serde_json::Value),
-
-    /// Links to external resources
-    Links(Vec<String>),
-
-    /// Base64-encoded binary data
-    Base64(String),
-
-    /// Direct JSON data
-    Json(serde_json::Value),
+/// `DIDComm` v2 represents attachment data as an object in which the content
+/// is given inline (`base64` or `json`) or by reference (`links`, which
+/// requires a `hash`). A detached `jws` may sign the content and a `hash`
+/// may be given for any form to allow integrity verification.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AttachmentData {
+    /// JWS (JSON Web Signature) over the content, in detached content mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jws: Option<serde_json::Value>,
+
+    /// Multihash of the content for integrity verification
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+
+    /// Links to external resources holding the content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<Vec<String>>,
+
+    /// Base64url-encoded binary content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base64: Option<String>,
+
+    /// Direct JSON content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json: Option<serde_json::Value>,
 }
 
 /// `DIDComm` v2 supports three types of message packing:
@@ -227,6 +298,12 @@ pub struct Header {
     /// The recipient's DID
     pub to: Option<Vec<String>>,
 
+    /// The thread ID
+    pub thid: Option<String>,
+
+    /// The parent thread ID
+    pub pthid: Option<String>,
+
     /// When the message was created
     pub created_time: Option<u64>,
 
@@ -266,6 +343,17 @@ pub struct PackedMessage {
     pub packing: PackingType,
 }
 
+impl PackingType {
+    /// Gets the media type of messages packed with this packing type.
+    #[must_use]
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Self::Signed => "application/didcomm-signed+json",
+            Self::AuthcryptV2 | Self::AnonV2 => "application/didcomm-encrypted+json",
+        }
+    }
+}
+
 impl MessageType {
     /// Creates a new message type.
     #[must_use]
@@ -304,6 +392,40 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_packing_media_type() {
+        assert_eq!(
+            PackingType::Signed.media_type(),
+            "application/didcomm-signed+json"
+        );
+        assert_eq!(
+            PackingType::AuthcryptV2.media_type(),
+            PackingType::AnonV2.media_type()
+        );
+    }
+
+    #[test]
+    fn test_message_reply() -> crate::error::Result<()> {
+        let request = Message::new("https://tap.rsvp/schema/1.0#Transfer", json!({}))?
+            .from("did:example:alice")
+            .to(vec!["did:example:bob"])
+            .pthid("parent-thread");
+        assert_eq!(request.thread_id(), request.id.as_str());
+
+        let reply = request.reply("https://tap.rsvp/schema/1.0#Authorize", json!({}))?;
+        assert_eq!(reply.thid.as_deref(), Some(request.id.as_str()));
+        assert_eq!(reply.pthid.as_deref(), Some("parent-thread"));
+        assert_eq!(reply.from, Some("did:example:bob".to_string()));
+        assert_eq!(reply.to, Some(vec!["did:example:alice".to_string()]));
+        assert_ne!(reply.id.as_str(), request.id.as_str());
+
+        let settle = reply.reply("https://tap.rsvp/schema/1.0#Settle", json!({}))?;
+        assert_eq!(settle.thread_id(), request.id.as_str());
+        assert_eq!(settle.from, Some("did:example:alice".to_string()));
+
+        Ok(())
+    }
+
     #[test]
     fn test_packed_message_serialization() {
         let packed = PackedMessage {
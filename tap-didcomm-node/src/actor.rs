@@ -11,12 +11,14 @@ use tracing::{debug, error};
 
 use crate::{
     error::{Error, Result},
+    handler::{HandlerContext, MessageHandler, UnpackMetadata},
     node::DIDCommNode,
 };
 
-/// A `DIDComm` message wrapper
+/// A `DIDComm` message wrapper, with the metadata of the envelope it was
+/// received in
 #[derive(Debug, Clone)]
-pub struct Message(pub CoreMessage);
+pub struct Message(pub CoreMessage, pub UnpackMetadata);
 
 impl Message {
     /// Creates a new message with the given type and body
@@ -35,7 +37,7 @@ impl Message {
     /// Returns an error if the message creation fails
     pub fn new(typ: impl Into<String>, body: impl Into<serde_json::Value>) -> Result<Self> {
         let msg = CoreMessage::new(typ.into(), body.into())?;
-        Ok(Message(msg))
+        Ok(Message(msg, UnpackMetadata::default()))
     }
 
     /// Sets the sender of the message
@@ -70,6 +72,8 @@ pub enum HandlerMessage {
     Process {
         /// The message to process
         message: CoreMessage,
+        /// How the message was packed
+        meta: UnpackMetadata,
         /// Channel for sending the response back
         response: mpsc::Sender<Result<CoreMessage>>,
     },
@@ -113,8 +117,28 @@ impl HandlerHandle {
             .map_err(|_| Error::Actor("Handler was dropped".into()))?
     }
 
+    /// Reserves a place in the handler's queue without waiting
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Busy` if the queue is full, or `Error::Actor` if the
+    /// handler has been dropped
+    pub fn try_reserve(&self) -> Result<HandlerPermit<'_>> {
+        match self.sender.try_reserve() {
+            Ok(permit) => Ok(HandlerPermit { permit }),
+            Err(mpsc::error::TrySendError::Full(())) => {
+                Err(Error::Busy("The handler's queue is full".into()))
+            }
+            Err(mpsc::error::TrySendError::Closed(())) => {
+                Err(Error::Actor("Failed to send message to handler".into()))
+            }
+        }
+    }
+
     /// Process a message and return a response
     ///
+    /// The message is processed as if it was received in plaintext.
+    ///
     /// # Arguments
     ///
     /// * `message` - The message to process
@@ -125,10 +149,27 @@ impl HandlerHandle {
     /// - The message cannot be processed
     /// - The handler has been dropped
     pub async fn process(&self, message: CoreMessage) -> Result<CoreMessage> {
+        self.process_unpacked(Message(message, UnpackMetadata::default()))
+            .await
+    }
+
+    /// Process a message unpacked from an envelope and return a response
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The message to process, with the metadata of its envelope
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The message cannot be processed
+    /// - The handler has been dropped
+    pub async fn process_unpacked(&self, msg: Message) -> Result<CoreMessage> {
         let (tx, mut rx) = mpsc::channel(1);
         self.sender
             .send(HandlerMessage::Process {
-                message: message.clone(),
+                message: msg.0,
+                meta: msg.1,
                 response: tx,
             })
             .await
@@ -140,6 +181,25 @@ impl HandlerHandle {
     }
 }
 
+/// A reserved place in a handler's queue
+pub struct HandlerPermit<'a> {
+    permit: mpsc::Permit<'a, HandlerMessage>,
+}
+
+impl HandlerPermit<'_> {
+    /// Sends a message to the handler in the reserved place
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the handler fails or has been dropped
+    pub async fn send(self, msg: Message) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.permit.send(HandlerMessage::HandleMessage(msg, tx));
+        rx.await
+            .map_err(|_| Error::Actor("Handler was dropped".into()))?
+    }
+}
+
 /// Registry for message handlers
 #[derive(Default)]
 pub struct HandlerRegistry {
@@ -184,7 +244,9 @@ pub fn spawn_message_handler() -> HandlerHandle {
                         error!("Failed to send reply");
                     }
                 }
-                HandlerMessage::Process { message, response } => {
+                HandlerMessage::Process {
+                    message, response, ..
+                } => {
                     debug!("Processing message: {message:?}");
                     // Add your message processing logic here
                     let result = Ok(message);
@@ -199,9 +261,14 @@ pub fn spawn_message_handler() -> HandlerHandle {
     handle
 }
 
-/// A message handler that processes messages using a `DIDComm` node
+/// An actor running a [`MessageHandler`] in a `DIDComm` node
+///
+/// This lets a typed handler serve a [`HandlerHandle`]. Replies to handled
+/// messages are sent by the node, and processing a message returns its first
+/// reply.
 pub struct Handler {
     node: Arc<DIDCommNode>,
+    handler: Arc<dyn MessageHandler>,
 }
 
 impl Handler {
@@ -210,9 +277,13 @@ impl Handler {
     /// # Arguments
     ///
     /// * `node` - The `DIDComm` node to use for processing
+    /// * `handler` - The handler processing the messages
     #[must_use]
-    pub fn new(node: Arc<DIDCommNode>) -> Self {
-        Self { node }
+    pub fn new(node: Arc<DIDCommNode>, handler: impl MessageHandler + 'static) -> Self {
+        Self {
+            node,
+            handler: Arc::new(handler),
+        }
     }
 
     /// Runs the handler, processing messages from the given channel
@@ -229,15 +300,29 @@ impl Handler {
             match msg {
                 HandlerMessage::HandleMessage(message, reply_tx) => {
                     debug!("Handling message: {message:?}");
-                    let result = Ok(());
+                    let result = match self.handle(message.0, message.1).await {
+                        Ok(replies) => {
+                            self.node.send_replies(replies).await;
+                            Ok(())
+                        }
+                        Err(e) => Err(e),
+                    };
                     if reply_tx.send(result).is_err() {
                         error!("Failed to send reply");
                     }
                 }
-                HandlerMessage::Process { message, response } => {
+                HandlerMessage::Process {
+                    message,
+                    meta,
+                    response,
+                } => {
                     debug!("Processing message: {message:?}");
-                    // Add your message processing logic here
-                    let result = Ok(message);
+                    let result = self.handle(message, meta).await.and_then(|replies| {
+                        replies
+                            .into_iter()
+                            .next()
+                            .ok_or_else(|| Error::Actor("The handler returned no reply".into()))
+                    });
                     if response.send(result).await.is_err() {
                         error!("Failed to send response");
                     }
@@ -246,6 +331,12 @@ impl Handler {
         }
         Ok(())
     }
+
+    async fn handle(&self, message: CoreMessage, meta: UnpackMetadata) -> Result<Vec<CoreMessage>> {
+        self.handler
+            .handle(HandlerContext::new(&self.node), message, meta)
+            .await
+    }
 }
 
 #[cfg(test)]
@@ -268,20 +359,37 @@ mod tests {
         });
     }
 
+    /// Replies to every message with an echo of its body
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl MessageHandler for Echo {
+        async fn handle(
+            &self,
+            _ctx: HandlerContext<'_>,
+            msg: CoreMessage,
+            _meta: UnpackMetadata,
+        ) -> Result<Vec<CoreMessage>> {
+            Ok(vec![msg.reply("echo", msg.body.clone())?])
+        }
+    }
+
     #[tokio::test]
     async fn test_handler() {
         let config = NodeConfig::default();
         let node = Arc::new(DIDCommNode::new(config, crate::mock::MockPlugin));
         let (tx, rx) = mpsc::channel(32);
         let handle = HandlerHandle::new(tx);
-        let handler = Handler::new(node);
+        let handler = Handler::new(node, Echo);
 
         tokio::spawn(handler.run(rx));
 
         let result = handle
             .process(CoreMessage::new("test", json!({"test": "value"})).unwrap())
-            .await;
+            .await
+            .unwrap();
 
-        assert!(result.is_ok());
+        assert_eq!(result.typ.as_str(), "echo");
+        assert_eq!(result.body, json!({"test": "value"}));
     }
 }
@@ -0,0 +1,703 @@
+//! Blob storage for linked attachments.
+//!
+//! Large attachments (e.g. KYC evidence in TAP) should not travel inline in a
+//! message. This module provides:
+//! - A [`BlobStore`] trait for content-addressed attachment storage, with
+//!   in-memory and filesystem implementations
+//! - [`offload_attachments`], which encrypts large inline attachments into a
+//!   blob store and replaces them with `links` under the public URL the store
+//!   is served at, and a `hash`
+//! - An [`AttachmentResolver`] that fetches linked attachments on receipt,
+//!   verifies them against their hash and inlines them again
+//!
+//! Offloaded content is encrypted with AES-256-GCM under a fresh key for each
+//! attachment, so the blob store and anyone who fetches from it only see
+//! ciphertext. The key travels in the `#key=` fragment of the link, inside the
+//! message, and is never sent when the link is fetched.
+//!
+//! Links in received messages are chosen by the sender, so the resolver only
+//! fetches from hosts it is configured to trust, with a size cap and timeout.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! use tap_didcomm_node::blob::{offload_attachments, AttachmentResolver, MemoryBlobStore};
+//! use tap_didcomm_core::{Message, types::{Attachment, AttachmentData}};
+//! use std::sync::Arc;
+//! use serde_json::json;
+//!
+//! async fn example() -> tap_didcomm_node::error::Result<()> {
+//!     let store = Arc::new(MemoryBlobStore::new());
+//!     let mut message = Message::new("test", json!({}))?
+//!         .with_attachment(Attachment::new("kyc", AttachmentData::base64(&[0u8; 4096])));
+//!
+//!     offload_attachments(&mut message, store.as_ref(), 1024, "https://blobs.example.com").await?;
+//!
+//!     let resolver = AttachmentResolver::new().allow_host("blobs.example.com");
+//!     resolver.resolve_attachments(&mut message).await
+//! }
+//! ```
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use reqwest::Client;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tap_didcomm_core::{
+    attachment::compute_hash,
+    jwe::algorithms::{decrypt_aes_gcm, encrypt_aes_gcm, generate_random_key},
+    types::{Attachment, AttachmentData},
+    Message,
+};
+use tracing::debug;
+
+use crate::error::{Error, Result};
+
+/// Link scheme used by [`MemoryBlobStore`]
+const MEMORY_SCHEME: &str = "blob:";
+
+/// Link scheme used by [`FileSystemBlobStore`]
+const FILE_SCHEME: &str = "file://";
+
+/// Link fragment carrying the key of an encrypted blob
+const KEY_FRAGMENT: &str = "#key=";
+
+/// Length in bytes of the AES-256-GCM key of a blob
+const BLOB_KEY_LEN: usize = 32;
+
+/// Length in bytes of the AES-256-GCM nonce that prefixes a blob
+const BLOB_NONCE_LEN: usize = 12;
+
+/// Length in bytes of the AES-256-GCM tag that ends a blob
+const BLOB_TAG_LEN: usize = 16;
+
+/// Default limit on the size of a fetched attachment, in bytes
+pub const DEFAULT_MAX_ATTACHMENT_SIZE: usize = 10 * 1024 * 1024;
+
+/// Default timeout for fetching an attachment, in seconds
+pub const DEFAULT_FETCH_TIMEOUT: u64 = 30;
+
+/// Content-addressed storage for attachment content.
+///
+/// Content is stored under its multihash, so storing the same content twice
+/// yields the same link.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Stores content and returns the link it can be fetched from
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the content cannot be stored
+    async fn put(&self, content: &[u8]) -> Result<String>;
+
+    /// Fetches the content behind a link, if present
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the link is not handled by this store or the
+    /// content cannot be read
+    async fn get(&self, link: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Deletes the content behind a link
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the content cannot be deleted
+    async fn delete(&self, link: &str) -> Result<()>;
+
+    /// Returns whether the link points into this store
+    fn handles(&self, link: &str) -> bool;
+}
+
+/// An in-memory blob store, mainly useful for tests.
+#[derive(Debug, Default)]
+pub struct MemoryBlobStore {
+    blobs: RwLock<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryBlobStore {
+    /// Creates a new empty store
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl BlobStore for MemoryBlobStore {
+    async fn put(&self, content: &[u8]) -> Result<String> {
+        let hash = compute_hash(content);
+        self.blobs
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(hash.clone(), content.to_vec());
+        Ok(format!("{MEMORY_SCHEME}{hash}"))
+    }
+
+    async fn get(&self, link: &str) -> Result<Option<Vec<u8>>> {
+        let hash = link
+            .strip_prefix(MEMORY_SCHEME)
+            .ok_or_else(|| Error::Storage(format!("Unsupported blob link: {link}")))?;
+        Ok(self
+            .blobs
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(hash)
+            .cloned())
+    }
+
+    async fn delete(&self, link: &str) -> Result<()> {
+        if let Some(hash) = link.strip_prefix(MEMORY_SCHEME) {
+            self.blobs
+                .write()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .remove(hash);
+        }
+        Ok(())
+    }
+
+    fn handles(&self, link: &str) -> bool {
+        link.starts_with(MEMORY_SCHEME)
+    }
+}
+
+/// A blob store that keeps each blob in a file named after its hash.
+#[derive(Debug, Clone)]
+pub struct FileSystemBlobStore {
+    root: PathBuf,
+}
+
+impl FileSystemBlobStore {
+    /// Creates a store rooted at the given directory
+    ///
+    /// The directory is created on first write.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The directory to store blobs in
+    #[must_use]
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Maps a link back to a path inside the store's root.
+    ///
+    /// Only links produced by this store are accepted, so a sender cannot
+    /// make the node read arbitrary files.
+    fn path_for(&self, link: &str) -> Option<PathBuf> {
+        let path = PathBuf::from(link.strip_prefix(FILE_SCHEME)?);
+        let name = path.file_name()?.to_str()?;
+        let is_hash = name.starts_with('z') && name.chars().all(|c| c.is_ascii_alphanumeric());
+        (is_hash && path.parent()? == self.root).then(|| self.root.join(name))
+    }
+}
+
+#[async_trait]
+impl BlobStore for FileSystemBlobStore {
+    async fn put(&self, content: &[u8]) -> Result<String> {
+        let hash = compute_hash(content);
+        let path = self.root.join(&hash);
+        let tmp = self.root.join(format!("{hash}.tmp"));
+
+        tokio::fs::create_dir_all(&self.root)
+            .await
+            .map_err(|e| Error::Storage(format!("Failed to create blob directory: {e}")))?;
+        tokio::fs::write(&tmp, content)
+            .await
+            .map_err(|e| Error::Storage(format!("Failed to write blob: {e}")))?;
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .map_err(|e| Error::Storage(format!("Failed to write blob: {e}")))?;
+
+        Ok(format!("{FILE_SCHEME}{}", path.display()))
+    }
+
+    async fn get(&self, link: &str) -> Result<Option<Vec<u8>>> {
+        let path = self
+            .path_for(link)
+            .ok_or_else(|| Error::Storage(format!("Unsupported blob link: {link}")))?;
+        match tokio::fs::read(path).await {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::Storage(format!("Failed to read blob: {e}"))),
+        }
+    }
+
+    async fn delete(&self, link: &str) -> Result<()> {
+        let Some(path) = self.path_for(link) else {
+            return Ok(());
+        };
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(Error::Storage(format!("Failed to delete blob: {e}")))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn handles(&self, link: &str) -> bool {
+        self.path_for(link).is_some()
+    }
+}
+
+/// Encrypts inline attachments larger than a threshold into a blob store.
+///
+/// Each offloaded attachment keeps its ID, metadata and JWS, and its inline
+/// content is replaced by a link together with the content's hash and byte
+/// count. The content is encrypted under a fresh key, which is carried in the
+/// link's `#key=` fragment. The link is `public_url` followed by the hash of
+/// the encrypted blob, so the store must be served there for recipients to
+/// dereference it. Links into the store itself are never sent, as they expose
+/// local paths.
+///
+/// # Arguments
+///
+/// * `message` - The message whose attachments to offload
+/// * `store` - The store to move content into
+/// * `threshold` - The size in bytes above which content is offloaded
+/// * `public_url` - The `https` URL the store's content is served at
+///
+/// # Returns
+///
+/// The number of attachments that were offloaded
+///
+/// # Errors
+///
+/// Returns an error if inline content cannot be decoded, encrypted or stored
+pub async fn offload_attachments(
+    message: &mut Message,
+    store: &dyn BlobStore,
+    threshold: usize,
+    public_url: &str,
+) -> Result<usize> {
+    let mut offloaded = 0;
+    for attachment in message.attachments.iter_mut().flatten() {
+        let Some(content) = attachment.data.inline_content()? else {
+            continue;
+        };
+        if content.len() <= threshold {
+            continue;
+        }
+
+        let key = generate_random_key(BLOB_KEY_LEN);
+        let blob = encrypt_blob(&key, &content)?;
+        let stored = store.put(&blob).await?;
+        debug!("Offloaded attachment {} to {stored}", attachment.id);
+        let hash = compute_hash(&content);
+        let link = format!(
+            "{}/{}{KEY_FRAGMENT}{}",
+            public_url.trim_end_matches('/'),
+            compute_hash(&blob),
+            URL_SAFE_NO_PAD.encode(&key)
+        );
+        attachment.byte_count = Some(content.len() as u64);
+        attachment.data = AttachmentData {
+            jws: attachment.data.jws.take(),
+            ..AttachmentData::links([link], hash)
+        };
+        offloaded += 1;
+    }
+    Ok(offloaded)
+}
+
+/// Encrypts content into a blob of the nonce, ciphertext and tag
+fn encrypt_blob(key: &[u8], content: &[u8]) -> Result<Vec<u8>> {
+    let nonce = generate_random_key(BLOB_NONCE_LEN);
+    let (ciphertext, tag) = encrypt_aes_gcm(key, &nonce, &[], content)?;
+    Ok([nonce, ciphertext, tag].concat())
+}
+
+/// Decrypts a blob with the base64url key from a link's fragment
+fn decrypt_blob(key: &str, blob: &[u8]) -> Result<Vec<u8>> {
+    let key = URL_SAFE_NO_PAD
+        .decode(key)
+        .map_err(|e| Error::InvalidFormat(format!("Invalid attachment key: {e}")))?;
+    if blob.len() < BLOB_NONCE_LEN + BLOB_TAG_LEN {
+        return Err(Error::InvalidFormat(
+            "Encrypted attachment is truncated".into(),
+        ));
+    }
+    let (nonce, rest) = blob.split_at(BLOB_NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - BLOB_TAG_LEN);
+    Ok(decrypt_aes_gcm(&key, nonce, &[], ciphertext, tag)?)
+}
+
+/// Fetches linked attachments and verifies them against their hash.
+///
+/// Links pointing into a registered [`BlobStore`] are read from the store;
+/// `http(s)` links are fetched over HTTP, but only from allowed hosts and
+/// without following redirects. Fetched content is limited in size and time.
+#[derive(Clone)]
+pub struct AttachmentResolver {
+    stores: Vec<Arc<dyn BlobStore>>,
+    client: Client,
+    allowed_hosts: HashSet<String>,
+    max_size: usize,
+    timeout: Duration,
+}
+
+impl Default for AttachmentResolver {
+    fn default() -> Self {
+        Self {
+            stores: Vec::new(),
+            client: Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .unwrap_or_default(),
+            allowed_hosts: HashSet::new(),
+            max_size: DEFAULT_MAX_ATTACHMENT_SIZE,
+            timeout: Duration::from_secs(DEFAULT_FETCH_TIMEOUT),
+        }
+    }
+}
+
+impl AttachmentResolver {
+    /// Creates a resolver that fetches no `http(s)` links until hosts are
+    /// allowed
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows `http(s)` links to a host to be fetched
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The host name, without scheme or port
+    #[must_use]
+    pub fn allow_host(mut self, host: impl Into<String>) -> Self {
+        self.allowed_hosts.insert(host.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Sets the largest attachment that is fetched, in bytes
+    #[must_use]
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets how long fetching an attachment over HTTP may take
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Adds a blob store to fetch links from
+    #[must_use]
+    pub fn with_store(mut self, store: Arc<dyn BlobStore>) -> Self {
+        self.stores.push(store);
+        self
+    }
+
+    /// Fetches the content of a linked attachment and verifies its hash.
+    ///
+    /// The links are tried in order until one yields content. Content behind
+    /// a link with a `#key=` fragment is decrypted with that key, and the
+    /// fragment is never sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `attachment` - The attachment to fetch
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no link yields content or the content does not
+    /// match the attachment's hash
+    pub async fn fetch(&self, attachment: &Attachment) -> Result<Vec<u8>> {
+        for link in attachment.data.links.iter().flatten() {
+            let (location, key) = link
+                .split_once(KEY_FRAGMENT)
+                .map_or((link.as_str(), None), |(location, key)| {
+                    (location, Some(key))
+                });
+            match self.fetch_link(location).await {
+                Ok(Some(content)) => {
+                    let content = match key {
+                        Some(key) => decrypt_blob(key, &content)?,
+                        None => content,
+                    };
+                    attachment.verify_hash(Some(&content))?;
+                    return Ok(content);
+                }
+                Ok(None) => debug!("Attachment {} not found at {link}", attachment.id),
+                Err(e) => debug!(
+                    "Failed to fetch attachment {} from {link}: {e}",
+                    attachment.id
+                ),
+            }
+        }
+        Err(Error::Storage(format!(
+            "Failed to fetch linked attachment {}",
+            attachment.id
+        )))
+    }
+
+    /// Fetches and verifies every linked attachment of a message and inlines
+    /// its content as base64.
+    ///
+    /// The links and hash are kept so the content can still be verified.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message whose attachments to resolve
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an attachment cannot be fetched or verified
+    pub async fn resolve_attachments(&self, message: &mut Message) -> Result<()> {
+        for attachment in message.attachments.iter_mut().flatten() {
+            if attachment.data.links.is_none() || attachment.data.inline_content()?.is_some() {
+                continue;
+            }
+            let content = self.fetch(attachment).await?;
+            attachment.data.base64 = AttachmentData::base64(&content).base64;
+        }
+        Ok(())
+    }
+
+    async fn fetch_link(&self, link: &str) -> Result<Option<Vec<u8>>> {
+        if let Some(store) = self.stores.iter().find(|store| store.handles(link)) {
+            return store.get(link).await;
+        }
+        if link.starts_with("https://") || link.starts_with("http://") {
+            return self.fetch_http(link).await;
+        }
+        Err(Error::Storage(format!(
+            "Unsupported attachment link: {link}"
+        )))
+    }
+
+    async fn fetch_http(&self, link: &str) -> Result<Option<Vec<u8>>> {
+        let url = reqwest::Url::parse(link)
+            .map_err(|e| Error::InvalidFormat(format!("Invalid attachment link {link}: {e}")))?;
+        let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+        if !self.allowed_hosts.contains(&host) {
+            return Err(Error::PolicyViolation(format!(
+                "Attachment host {host} is not allowed"
+            )));
+        }
+
+        let mut response = self
+            .client
+            .get(url)
+            .timeout(self.timeout)
+            .send()
+            .await
+            .map_err(|e| Error::Http(e.to_string()))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(Error::Http(format!(
+                "Fetching {link} failed with status {}",
+                response.status()
+            )));
+        }
+        let too_large = || {
+            Error::QuotaExceeded(format!(
+                "Attachment at {link} is larger than {} bytes",
+                self.max_size
+            ))
+        };
+        let declared = response.content_length().unwrap_or(0);
+        if usize::try_from(declared).map_or(true, |declared| declared > self.max_size) {
+            return Err(too_large());
+        }
+
+        let mut content = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| Error::Http(e.to_string()))?
+        {
+            if content.len() + chunk.len() > self.max_size {
+                return Err(too_large());
+            }
+            content.extend_from_slice(&chunk);
+        }
+        Ok(Some(content))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_memory_blob_store() {
+        let store = MemoryBlobStore::new();
+        let link = store.put(b"content").await.unwrap();
+
+        assert!(store.handles(&link));
+        assert_eq!(store.get(&link).await.unwrap(), Some(b"content".to_vec()));
+
+        store.delete(&link).await.unwrap();
+        assert_eq!(store.get(&link).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_filesystem_blob_store() {
+        let root = std::env::temp_dir().join(format!("tap-didcomm-blobs-{}", std::process::id()));
+        let store = FileSystemBlobStore::new(&root);
+        let link = store.put(b"content").await.unwrap();
+
+        assert!(store.handles(&link));
+        assert!(!store.handles("file:///etc/passwd"));
+        assert_eq!(store.get(&link).await.unwrap(), Some(b"content".to_vec()));
+
+        store.delete(&link).await.unwrap();
+        assert_eq!(store.get(&link).await.unwrap(), None);
+        tokio::fs::remove_dir_all(root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_offload_and_resolve() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let store = Arc::new(MemoryBlobStore::new());
+        let large = vec![7u8; 2048];
+        let mut message = Message::new("test", json!({}))
+            .unwrap()
+            .with_attachment(Attachment::new("large", AttachmentData::base64(&large)))
+            .with_attachment(Attachment::new("small", AttachmentData::base64(b"small")));
+
+        let server = MockServer::start().await;
+        let offloaded = offload_attachments(&mut message, store.as_ref(), 1024, &server.uri())
+            .await
+            .unwrap();
+        assert_eq!(offloaded, 1);
+
+        let attachments = message.attachments.as_ref().unwrap();
+        assert!(attachments[0].data.base64.is_none());
+        assert_eq!(attachments[0].data.hash, Some(compute_hash(&large)));
+        assert_eq!(attachments[0].byte_count, Some(2048));
+        assert!(attachments[1].data.base64.is_some());
+
+        // The store only holds the encrypted content, whose key is in the link
+        let link = &attachments[0].data.links.as_ref().unwrap()[0];
+        let (location, _) = link.split_once(KEY_FRAGMENT).unwrap();
+        let blob_hash = location
+            .strip_prefix(&format!("{}/", server.uri()))
+            .unwrap();
+        let stored = store
+            .get(&format!("{MEMORY_SCHEME}{blob_hash}"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(stored, large);
+        assert!(!stored.windows(64).any(|window| window == &large[..64]));
+
+        // The recipient fetches the content from where the store is served
+        Mock::given(path(format!("/{blob_hash}")))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(stored))
+            .mount(&server)
+            .await;
+        let resolver = AttachmentResolver::new().allow_host("127.0.0.1");
+        resolver.resolve_attachments(&mut message).await.unwrap();
+
+        let attachments = message.attachments.as_ref().unwrap();
+        assert_eq!(
+            attachments[0].data.inline_content().unwrap(),
+            Some(large.clone())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_limits() {
+        use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let content = vec![1u8; 2048];
+        Mock::given(path("/large"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(content.clone()))
+            .mount(&server)
+            .await;
+        Mock::given(path("/slow"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_bytes(content.clone())
+                    .set_delay(Duration::from_secs(5)),
+            )
+            .mount(&server)
+            .await;
+        let attachment = |name: &str| {
+            Attachment::new(
+                name,
+                AttachmentData::links([format!("{}/{name}", server.uri())], compute_hash(&content)),
+            )
+        };
+
+        // Hosts must be allowed
+        let resolver = AttachmentResolver::new();
+        assert!(matches!(
+            resolver
+                .fetch_link(&format!("{}/large", server.uri()))
+                .await,
+            Err(Error::PolicyViolation(_))
+        ));
+
+        let resolver = AttachmentResolver::new()
+            .allow_host("127.0.0.1")
+            .with_timeout(Duration::from_millis(200));
+        assert_eq!(resolver.fetch(&attachment("large")).await.unwrap(), content);
+        assert!(resolver.fetch(&attachment("slow")).await.is_err());
+
+        let resolver = resolver.with_max_size(1024);
+        assert!(matches!(
+            resolver
+                .fetch_link(&format!("{}/large", server.uri()))
+                .await,
+            Err(Error::QuotaExceeded(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_rejects_tampered_content() {
+        let store = Arc::new(MemoryBlobStore::new());
+        let link = store.put(b"tampered").await.unwrap();
+        let mut message =
+            Message::new("test", json!({}))
+                .unwrap()
+                .with_attachment(Attachment::new(
+                    "doc",
+                    AttachmentData::links([link], compute_hash(b"original")),
+                ));
+
+        let resolver = AttachmentResolver::new().with_store(store);
+        assert!(resolver.resolve_attachments(&mut message).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_rejects_wrong_key() {
+        let store = Arc::new(MemoryBlobStore::new());
+        let mut message = Message::new("test", json!({}))
+            .unwrap()
+            .with_attachment(Attachment::new("doc", AttachmentData::base64(&[3u8; 64])));
+        offload_attachments(
+            &mut message,
+            store.as_ref(),
+            16,
+            "https://blobs.example.com",
+        )
+        .await
+        .unwrap();
+
+        let attachment = &mut message.attachments.as_mut().unwrap()[0];
+        let link = &attachment.data.links.as_ref().unwrap()[0];
+        let (location, _) = link.split_once(KEY_FRAGMENT).unwrap();
+        let blob_hash = location.rsplit('/').next().unwrap();
+        let wrong_key = URL_SAFE_NO_PAD.encode([0u8; BLOB_KEY_LEN]);
+        attachment.data.links = Some(vec![format!(
+            "{MEMORY_SCHEME}{blob_hash}{KEY_FRAGMENT}{wrong_key}"
+        )]);
+
+        let resolver = AttachmentResolver::new().with_store(store);
+        assert!(resolver.resolve_attachments(&mut message).await.is_err());
+    }
+}
@@ -0,0 +1,877 @@
+//! Mediation coordination protocol 3.0.
+//!
+//! Before a mediator accepts forward messages for an agent, the agent asks it
+//! for mediation with a `mediate-request`. Once granted, the mediator returns
+//! the routing DID that the agent lists as a routing key in its DID document,
+//! and the agent registers the DIDs messages will be forwarded for with
+//! `recipient-update`.
+//!
+//! This module implements both sides of the protocol:
+//!
+//! - [`MediationCoordinator`]: The mediator side, backed by a
+//!   [`RecipientRegistry`] of the recipient DIDs of each client
+//! - [`MediationClient`]: The agent side, which builds requests and tracks the
+//!   mediator's responses
+//!
+//! Requests must be authcrypted by the client they are sent for, so no one
+//! can register or query recipient DIDs for another client.
+//!
+//! Each side has a handler that processes messages delivered by a
+//! [`DIDCommNode`] and sends the responses.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use tap_didcomm_core::PackingType;
+//! use tap_didcomm_node::coordinate_mediation::{MediationClient, MediationCoordinator};
+//! use tap_didcomm_node::handler::UnpackMetadata;
+//! use tap_didcomm_node::mediator::Mediator;
+//! use std::sync::Arc;
+//!
+//! # fn example() -> tap_didcomm_node::error::Result<()> {
+//! let coordinator = MediationCoordinator::new("did:example:mediator", Arc::new(Mediator::new()));
+//! let client = MediationClient::new("did:example:alice", "did:example:mediator");
+//!
+//! // The request was authcrypted by the client
+//! let meta = UnpackMetadata {
+//!     packing: Some(PackingType::AuthcryptV2),
+//!     authenticated_sender: Some("did:example:alice#key-1".into()),
+//!     ..Default::default()
+//! };
+//! let grant = coordinator.handle(&client.mediate_request()?, &meta)?.unwrap();
+//! client.handle(&grant)?;
+//! assert_eq!(client.routing_keys(), vec!["did:example:mediator"]);
+//! # Ok(())
+//! # }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, RwLock};
+use tap_didcomm_core::{service::DIDCommService, Message, PackingType};
+use tokio::sync::mpsc;
+use tracing::{debug, error};
+
+use crate::{
+    actor::HandlerMessage,
+    error::{Error, Result},
+    handler::UnpackMetadata,
+    mediator::Mediator,
+    node::DIDCommNode,
+};
+
+/// Message type of a request for mediation
+pub const MEDIATE_REQUEST: &str = "https://didcomm.org/coordinate-mediation/3.0/mediate-request";
+/// Message type of a granted mediation
+pub const MEDIATE_GRANT: &str = "https://didcomm.org/coordinate-mediation/3.0/mediate-grant";
+/// Message type of a denied mediation
+pub const MEDIATE_DENY: &str = "https://didcomm.org/coordinate-mediation/3.0/mediate-deny";
+/// Message type of a change to a client's recipient DIDs
+pub const RECIPIENT_UPDATE: &str = "https://didcomm.org/coordinate-mediation/3.0/recipient-update";
+/// Message type of the result of a recipient update
+pub const RECIPIENT_UPDATE_RESPONSE: &str =
+    "https://didcomm.org/coordinate-mediation/3.0/recipient-update-response";
+/// Message type of a query for a client's recipient DIDs
+pub const RECIPIENT_QUERY: &str = "https://didcomm.org/coordinate-mediation/3.0/recipient-query";
+/// Message type of the answer to a recipient query
+pub const RECIPIENT: &str = "https://didcomm.org/coordinate-mediation/3.0/recipient";
+
+/// The most recipient DIDs returned for a `recipient-query`
+pub const MAX_RECIPIENT_QUERY_LIMIT: usize = 100;
+
+/// Message types handled by the mediator side
+pub const MEDIATOR_MESSAGE_TYPES: [&str; 3] = [MEDIATE_REQUEST, RECIPIENT_UPDATE, RECIPIENT_QUERY];
+/// Message types handled by the client side
+pub const CLIENT_MESSAGE_TYPES: [&str; 4] = [
+    MEDIATE_GRANT,
+    MEDIATE_DENY,
+    RECIPIENT_UPDATE_RESPONSE,
+    RECIPIENT,
+];
+
+/// Whether a recipient DID is added or removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateAction {
+    /// Start forwarding messages for the DID
+    Add,
+    /// Stop forwarding messages for the DID
+    Remove,
+}
+
+/// The outcome of a recipient update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateResult {
+    /// The update was applied
+    Success,
+    /// The DID was already added or removed
+    NoChange,
+    /// The update was invalid
+    ClientError,
+    /// The update could not be applied
+    ServerError,
+}
+
+/// A change to a client's recipient DIDs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecipientUpdate {
+    /// The DID messages are forwarded for
+    pub recipient_did: String,
+    /// Whether the DID is added or removed
+    pub action: UpdateAction,
+    /// The outcome, in a `recipient-update-response`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<UpdateResult>,
+}
+
+impl RecipientUpdate {
+    /// Creates an update adding a recipient DID
+    #[must_use]
+    pub fn add(recipient_did: impl Into<String>) -> Self {
+        Self {
+            recipient_did: recipient_did.into(),
+            action: UpdateAction::Add,
+            result: None,
+        }
+    }
+
+    /// Creates an update removing a recipient DID
+    #[must_use]
+    pub fn remove(recipient_did: impl Into<String>) -> Self {
+        Self {
+            recipient_did: recipient_did.into(),
+            action: UpdateAction::Remove,
+            result: None,
+        }
+    }
+}
+
+/// The recipient DIDs of each client a mediator has granted mediation to.
+#[derive(Debug, Default)]
+pub struct RecipientRegistry {
+    clients: RwLock<HashMap<String, BTreeSet<String>>>,
+}
+
+impl RecipientRegistry {
+    /// Creates a new empty registry
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that mediation was granted to a client
+    pub fn grant(&self, client: &str) {
+        self.clients
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .entry(client.to_string())
+            .or_default();
+    }
+
+    /// Checks whether mediation was granted to a client
+    #[must_use]
+    pub fn is_granted(&self, client: &str) -> bool {
+        self.clients
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .contains_key(client)
+    }
+
+    /// Applies an update to a client's recipient DIDs
+    ///
+    /// # Returns
+    ///
+    /// The outcome of the update. Adding a DID that belongs to another client
+    /// is a client error.
+    pub fn apply(&self, client: &str, update: &RecipientUpdate) -> UpdateResult {
+        let mut clients = self
+            .clients
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let owned_elsewhere = clients.iter().any(|(other, recipients)| {
+            other != client && recipients.contains(&update.recipient_did)
+        });
+        let Some(recipients) = clients.get_mut(client) else {
+            return UpdateResult::ClientError;
+        };
+
+        let changed = match update.action {
+            UpdateAction::Add if owned_elsewhere => return UpdateResult::ClientError,
+            UpdateAction::Add => recipients.insert(update.recipient_did.clone()),
+            UpdateAction::Remove => recipients.remove(&update.recipient_did),
+        };
+        if changed {
+            UpdateResult::Success
+        } else {
+            UpdateResult::NoChange
+        }
+    }
+
+    /// Gets a page of a client's recipient DIDs, in sorted order
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The client's DID
+    /// * `offset` - The number of DIDs to skip
+    /// * `limit` - The maximum number of DIDs to return
+    ///
+    /// # Returns
+    ///
+    /// The DIDs in the page and the total number of the client's DIDs
+    #[must_use]
+    pub fn recipients(&self, client: &str, offset: usize, limit: usize) -> (Vec<String>, usize) {
+        let clients = self
+            .clients
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        clients.get(client).map_or((Vec::new(), 0), |recipients| {
+            let page = recipients
+                .iter()
+                .skip(offset)
+                .take(limit)
+                .cloned()
+                .collect();
+            (page, recipients.len())
+        })
+    }
+
+    /// Finds the client a recipient DID belongs to
+    #[must_use]
+    pub fn client_of(&self, recipient_did: &str) -> Option<String> {
+        self.clients
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .iter()
+            .find(|(_, recipients)| recipients.contains(recipient_did))
+            .map(|(client, _)| client.clone())
+    }
+}
+
+/// The mediator side of mediation coordination.
+///
+/// Recipient DIDs added by clients are registered with the [`Mediator`], so
+/// forward messages for them are queued for pickup.
+pub struct MediationCoordinator {
+    /// The DID clients list as routing key
+    routing_did: String,
+    /// The mediator that forward messages are queued by
+    mediator: Arc<Mediator>,
+    /// The recipient DIDs of each client
+    registry: Arc<RecipientRegistry>,
+    /// Decides whether a client is granted mediation
+    policy: Box<dyn Fn(&str) -> bool + Send + Sync>,
+}
+
+impl MediationCoordinator {
+    /// Creates a coordinator that grants mediation to every client
+    ///
+    /// # Arguments
+    ///
+    /// * `routing_did` - The DID clients list as routing key
+    /// * `mediator` - The mediator that forward messages are queued by
+    #[must_use]
+    pub fn new(routing_did: impl Into<String>, mediator: Arc<Mediator>) -> Self {
+        Self {
+            routing_did: routing_did.into(),
+            mediator,
+            registry: Arc::new(RecipientRegistry::new()),
+            policy: Box::new(|_| true),
+        }
+    }
+
+    /// Sets the policy that decides whether a client is granted mediation
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - Called with the client's DID; returns whether to grant
+    pub fn set_policy(&mut self, policy: impl Fn(&str) -> bool + Send + Sync + 'static) {
+        self.policy = Box::new(policy);
+    }
+
+    /// Returns the registry of clients and their recipient DIDs
+    #[must_use]
+    pub fn registry(&self) -> Arc<RecipientRegistry> {
+        Arc::clone(&self.registry)
+    }
+
+    /// Handles a mediation coordination message from a client.
+    ///
+    /// A `recipient-query` returns at most [`MAX_RECIPIENT_QUERY_LIMIT`] DIDs.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message from the client
+    /// * `meta` - How the message was packed
+    ///
+    /// # Returns
+    ///
+    /// The response to send back, or `None` for other message types
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message does not authenticate its sender or
+    /// its body is invalid
+    pub fn handle(&self, message: &Message, meta: &UnpackMetadata) -> Result<Option<Message>> {
+        let typ = message.typ.as_str();
+        if !MEDIATOR_MESSAGE_TYPES.contains(&typ) {
+            return Ok(None);
+        }
+        let client = meta.authcrypted_from(message).ok_or_else(|| {
+            Error::Unauthenticated(format!("{typ} is not authcrypted by its sender"))
+        })?;
+
+        let response = match typ {
+            MEDIATE_REQUEST if (self.policy)(client) => {
+                self.registry.grant(client);
+                debug!("Granted mediation to {client}");
+                message.reply(MEDIATE_GRANT, json!({ "routing_did": [self.routing_did] }))?
+            }
+            MEDIATE_REQUEST => {
+                debug!("Denied mediation to {client}");
+                message.reply(MEDIATE_DENY, json!({}))?
+            }
+            RECIPIENT_UPDATE => {
+                let requested: Vec<RecipientUpdate> = body_field(message, "updates")?;
+                let updated: Vec<RecipientUpdate> = requested
+                    .into_iter()
+                    .map(|mut update| {
+                        let result = self.registry.apply(client, &update);
+                        if result == UpdateResult::Success {
+                            match update.action {
+                                UpdateAction::Add => {
+                                    self.mediator.add_recipient(update.recipient_did.as_str());
+                                }
+                                UpdateAction::Remove => {
+                                    self.mediator.remove_recipient(&update.recipient_did);
+                                }
+                            }
+                        }
+                        update.result = Some(result);
+                        update
+                    })
+                    .collect();
+                message.reply(RECIPIENT_UPDATE_RESPONSE, json!({ "updated": updated }))?
+            }
+            _ => {
+                let paginate = message.body.get("paginate");
+                let field = |name: &str, default: usize| {
+                    paginate
+                        .and_then(|paginate| paginate.get(name))
+                        .and_then(serde_json::Value::as_u64)
+                        .and_then(|value| usize::try_from(value).ok())
+                        .unwrap_or(default)
+                };
+                let offset = field("offset", 0);
+                let limit =
+                    field("limit", MAX_RECIPIENT_QUERY_LIMIT).min(MAX_RECIPIENT_QUERY_LIMIT);
+                let (dids, count) = self.registry.recipients(client, offset, limit);
+                let remaining = count.saturating_sub(offset.saturating_add(dids.len()));
+                let dids: Vec<_> = dids
+                    .into_iter()
+                    .map(|did| json!({ "recipient_did": did }))
+                    .collect();
+                message.reply(
+                    RECIPIENT,
+                    json!({
+                        "dids": dids,
+                        "pagination": { "count": count, "offset": offset, "remaining": remaining }
+                    }),
+                )?
+            }
+        };
+        Ok(Some(response))
+    }
+}
+
+/// The state of mediation as seen by a client.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum MediationState {
+    /// Mediation has not been granted or denied yet
+    #[default]
+    Pending,
+    /// Mediation was granted with the given routing DIDs
+    Granted(Vec<String>),
+    /// Mediation was denied
+    Denied,
+}
+
+/// The client side of mediation coordination.
+#[derive(Debug)]
+pub struct MediationClient {
+    /// The client's DID
+    did: String,
+    /// The mediator's DID
+    mediator_did: String,
+    /// Whether mediation was granted
+    state: RwLock<MediationState>,
+    /// The recipient DIDs the mediator last confirmed
+    recipients: RwLock<BTreeSet<String>>,
+}
+
+impl MediationClient {
+    /// Creates a client for the given mediator
+    ///
+    /// # Arguments
+    ///
+    /// * `did` - The client's DID
+    /// * `mediator_did` - The mediator's DID
+    #[must_use]
+    pub fn new(did: impl Into<String>, mediator_did: impl Into<String>) -> Self {
+        Self {
+            did: did.into(),
+            mediator_did: mediator_did.into(),
+            state: RwLock::new(MediationState::Pending),
+            recipients: RwLock::new(BTreeSet::new()),
+        }
+    }
+
+    /// Builds a `mediate-request` for the mediator
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be created
+    pub fn mediate_request(&self) -> Result<Message> {
+        self.message(MEDIATE_REQUEST, json!({}))
+    }
+
+    /// Builds a `recipient-update` for the mediator
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be created
+    pub fn recipient_update(&self, updates: &[RecipientUpdate]) -> Result<Message> {
+        self.message(RECIPIENT_UPDATE, json!({ "updates": updates }))
+    }
+
+    /// Builds a `recipient-query` for the mediator
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The number of DIDs to skip
+    /// * `limit` - The maximum number of DIDs to return
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be created
+    pub fn recipient_query(&self, offset: usize, limit: usize) -> Result<Message> {
+        self.message(
+            RECIPIENT_QUERY,
+            json!({ "paginate": { "offset": offset, "limit": limit } }),
+        )
+    }
+
+    fn message(&self, typ: &str, body: serde_json::Value) -> Result<Message> {
+        Ok(Message::new(typ, body)?
+            .from(self.did.as_str())
+            .to([self.mediator_did.as_str()]))
+    }
+
+    /// Returns the state of mediation
+    #[must_use]
+    pub fn state(&self) -> MediationState {
+        self.state
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Returns the routing DIDs to list as routing keys, if mediation was granted
+    #[must_use]
+    pub fn routing_keys(&self) -> Vec<String> {
+        match self.state() {
+            MediationState::Granted(routing_dids) => routing_dids,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the recipient DIDs the mediator last confirmed
+    #[must_use]
+    pub fn recipients(&self) -> Vec<String> {
+        self.recipients
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Builds the `DIDCommMessaging` service to advertise in the client's DID
+    /// document, routing through the mediator.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the service
+    /// * `uri` - The mediator's endpoint
+    ///
+    /// # Returns
+    ///
+    /// The service, or `None` if mediation has not been granted
+    #[must_use]
+    pub fn service(&self, id: impl Into<String>, uri: impl Into<String>) -> Option<DIDCommService> {
+        let routing_keys = self.routing_keys();
+        (!routing_keys.is_empty()).then(|| DIDCommService {
+            id: id.into(),
+            uri: uri.into(),
+            accept: vec!["didcomm/v2".to_string()],
+            routing_keys,
+        })
+    }
+
+    /// Handles a response from the mediator.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message is not from the mediator or its body
+    /// is invalid
+    pub fn handle(&self, message: &Message) -> Result<()> {
+        let typ = message.typ.as_str();
+        if !CLIENT_MESSAGE_TYPES.contains(&typ) {
+            return Ok(());
+        }
+        if message.from.as_deref() != Some(self.mediator_did.as_str()) {
+            return Err(Error::InvalidFormat(format!(
+                "Unexpected {typ} from {:?}",
+                message.from
+            )));
+        }
+
+        match typ {
+            MEDIATE_GRANT => {
+                let routing_dids: Vec<String> = body_field(message, "routing_did")?;
+                self.set_state(MediationState::Granted(routing_dids));
+            }
+            MEDIATE_DENY => self.set_state(MediationState::Denied),
+            RECIPIENT_UPDATE_RESPONSE => {
+                let updated: Vec<RecipientUpdate> = body_field(message, "updated")?;
+                let mut recipients = self
+                    .recipients
+                    .write()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner);
+                for update in updated {
+                    if !matches!(
+                        update.result,
+                        Some(UpdateResult::Success | UpdateResult::NoChange)
+                    ) {
+                        continue;
+                    }
+                    match update.action {
+                        UpdateAction::Add => recipients.insert(update.recipient_did),
+                        UpdateAction::Remove => recipients.remove(&update.recipient_did),
+                    };
+                }
+            }
+            _ => {
+                #[derive(Deserialize)]
+                struct Recipient {
+                    recipient_did: String,
+                }
+                let dids: Vec<Recipient> = body_field(message, "dids")?;
+                self.recipients
+                    .write()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner)
+                    .extend(dids.into_iter().map(|did| did.recipient_did));
+            }
+        }
+        Ok(())
+    }
+
+    fn set_state(&self, state: MediationState) {
+        *self
+            .state
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = state;
+    }
+}
+
+/// Deserializes a field of a message body
+fn body_field<T: serde::de::DeserializeOwned>(message: &Message, field: &str) -> Result<T> {
+    let value =
+        message.body.get(field).cloned().ok_or_else(|| {
+            Error::InvalidFormat(format!("{} has no {field}", message.typ.as_str()))
+        })?;
+    serde_json::from_value(value).map_err(|e| Error::InvalidFormat(format!("Invalid {field}: {e}")))
+}
+
+/// A handler that answers mediation coordination requests using a `DIDComm` node
+pub struct MediatorHandler {
+    node: Arc<DIDCommNode>,
+    coordinator: MediationCoordinator,
+}
+
+impl MediatorHandler {
+    /// Creates a new handler with the given node and coordinator
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - The `DIDComm` node to send responses with
+    /// * `coordinator` - The coordinator handling the requests
+    #[must_use]
+    pub fn new(node: Arc<DIDCommNode>, coordinator: MediationCoordinator) -> Self {
+        Self { node, coordinator }
+    }
+
+    /// Runs the handler, processing messages from the given channel
+    ///
+    /// Responses are authcrypted and sent to the requesting client.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if message processing fails
+    pub async fn run(self, mut rx: mpsc::Receiver<HandlerMessage>) -> Result<()> {
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                HandlerMessage::HandleMessage(message, reply_tx) => {
+                    let result = match self.coordinator.handle(&message.0, &message.1) {
+                        Ok(Some(response)) => self
+                            .node
+                            .send(&response, PackingType::AuthcryptV2)
+                            .await
+                            .map(drop),
+                        Ok(None) => Ok(()),
+                        Err(e) => Err(e),
+                    };
+                    if reply_tx.send(result).is_err() {
+                        error!("Failed to send reply");
+                    }
+                }
+                HandlerMessage::Process {
+                    message,
+                    meta,
+                    response,
+                } => {
+                    let result = self.coordinator.handle(&message, &meta).and_then(|reply| {
+                        reply.ok_or_else(|| {
+                            Error::InvalidFormat(format!(
+                                "Not a mediation request: {}",
+                                message.typ.as_str()
+                            ))
+                        })
+                    });
+                    if response.send(result).await.is_err() {
+                        error!("Failed to send response");
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A handler that tracks a mediator's responses for a client
+pub struct ClientHandler {
+    client: Arc<MediationClient>,
+}
+
+impl ClientHandler {
+    /// Creates a new handler updating the given client
+    #[must_use]
+    pub fn new(client: Arc<MediationClient>) -> Self {
+        Self { client }
+    }
+
+    /// Runs the handler, processing messages from the given channel
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if message processing fails
+    pub async fn run(self, mut rx: mpsc::Receiver<HandlerMessage>) -> Result<()> {
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                HandlerMessage::HandleMessage(message, reply_tx) => {
+                    let result = self.client.handle(&message.0);
+                    if reply_tx.send(result).is_err() {
+                        error!("Failed to send reply");
+                    }
+                }
+                HandlerMessage::Process {
+                    message, response, ..
+                } => {
+                    let result = self.client.handle(&message).map(|()| message);
+                    if response.send(result).await.is_err() {
+                        error!("Failed to send response");
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        actor::{HandlerHandle, Message as ActorMessage},
+        mock::MockPlugin,
+        node::NodeConfig,
+    };
+
+    fn coordinator() -> (MediationCoordinator, Arc<Mediator>) {
+        let mediator = Arc::new(Mediator::new());
+        let coordinator = MediationCoordinator::new("did:example:mediator", Arc::clone(&mediator));
+        (coordinator, mediator)
+    }
+
+    fn authcrypted_by(kid: &str) -> UnpackMetadata {
+        UnpackMetadata {
+            packing: Some(PackingType::AuthcryptV2),
+            authenticated_sender: Some(kid.to_string()),
+            ..UnpackMetadata::default()
+        }
+    }
+
+    #[test]
+    fn test_mediation_flow() {
+        let (coordinator, mediator) = coordinator();
+        let client = MediationClient::new("did:example:alice", "did:example:mediator");
+        let meta = authcrypted_by("did:example:alice#key-1");
+        assert!(client
+            .service("did:example:alice#didcomm", "https://m.example.com")
+            .is_none());
+
+        let grant = coordinator
+            .handle(&client.mediate_request().unwrap(), &meta)
+            .unwrap()
+            .unwrap();
+        assert_eq!(grant.typ.as_str(), MEDIATE_GRANT);
+        client.handle(&grant).unwrap();
+        let service = client
+            .service("did:example:alice#didcomm", "https://m.example.com")
+            .unwrap();
+        assert_eq!(service.routing_keys, vec!["did:example:mediator"]);
+
+        let update = client
+            .recipient_update(&[
+                RecipientUpdate::add("did:example:alice-1"),
+                RecipientUpdate::add("did:example:alice-2"),
+                RecipientUpdate::remove("did:example:unknown"),
+            ])
+            .unwrap();
+        let response = coordinator.handle(&update, &meta).unwrap().unwrap();
+        client.handle(&response).unwrap();
+        assert_eq!(
+            client.recipients(),
+            vec!["did:example:alice-1", "did:example:alice-2"]
+        );
+        assert!(mediator.is_recipient("did:example:alice-1#key-1"));
+        assert_eq!(
+            coordinator.registry().client_of("did:example:alice-2"),
+            Some("did:example:alice".to_string())
+        );
+
+        let recipient = coordinator
+            .handle(&client.recipient_query(1, 10).unwrap(), &meta)
+            .unwrap()
+            .unwrap();
+        assert_eq!(recipient.body["pagination"]["count"], 2);
+        assert_eq!(recipient.body["pagination"]["remaining"], 0);
+        assert_eq!(
+            recipient.body["dids"][0]["recipient_did"],
+            "did:example:alice-2"
+        );
+    }
+
+    #[test]
+    fn test_mediation_denied() {
+        let (mut coordinator, mediator) = coordinator();
+        coordinator.set_policy(|client| client != "did:example:mallory");
+        let client = MediationClient::new("did:example:mallory", "did:example:mediator");
+        let meta = authcrypted_by("did:example:mallory#key-1");
+
+        let deny = coordinator
+            .handle(&client.mediate_request().unwrap(), &meta)
+            .unwrap()
+            .unwrap();
+        client.handle(&deny).unwrap();
+        assert_eq!(client.state(), MediationState::Denied);
+
+        // Updates from clients without mediation are rejected
+        let update = client
+            .recipient_update(&[RecipientUpdate::add("did:example:mallory-1")])
+            .unwrap();
+        let response = coordinator.handle(&update, &meta).unwrap().unwrap();
+        assert_eq!(response.body["updated"][0]["result"], "client_error");
+        assert!(!mediator.is_recipient("did:example:mallory-1"));
+    }
+
+    #[tokio::test]
+    async fn test_mediator_handler() {
+        let node = Arc::new(DIDCommNode::new(NodeConfig::default(), MockPlugin));
+        let (coordinator, _) = coordinator();
+        let (tx, rx) = mpsc::channel(32);
+        let handle = HandlerHandle::new(tx);
+        tokio::spawn(MediatorHandler::new(node, coordinator).run(rx));
+
+        let client = MediationClient::new("did:example:alice", "did:example:mediator");
+        let request = ActorMessage(
+            client.mediate_request().unwrap(),
+            authcrypted_by("did:example:alice#key-1"),
+        );
+        let grant = handle.process_unpacked(request).await.unwrap();
+        assert_eq!(grant.typ.as_str(), MEDIATE_GRANT);
+        assert_eq!(grant.to, Some(vec!["did:example:alice".to_string()]));
+    }
+
+    #[test]
+    fn test_rejects_unauthenticated_client() {
+        let (coordinator, mediator) = coordinator();
+        let client = MediationClient::new("did:example:alice", "did:example:mediator");
+        let update = client
+            .recipient_update(&[RecipientUpdate::add("did:example:alice-1")])
+            .unwrap();
+        coordinator
+            .handle(
+                &client.mediate_request().unwrap(),
+                &authcrypted_by("did:example:alice#key-1"),
+            )
+            .unwrap();
+
+        // Anyone can claim to be alice in the `from` of a plaintext,
+        // anoncrypted or otherwise authcrypted message
+        for meta in [
+            UnpackMetadata::default(),
+            UnpackMetadata {
+                packing: Some(PackingType::AnonV2),
+                ..UnpackMetadata::default()
+            },
+            authcrypted_by("did:example:mallory#key-1"),
+        ] {
+            assert!(matches!(
+                coordinator.handle(&update, &meta),
+                Err(Error::Unauthenticated(_))
+            ));
+        }
+        assert!(!mediator.is_recipient("did:example:alice-1"));
+    }
+
+    #[test]
+    fn test_recipient_query_limit() {
+        let (coordinator, _) = coordinator();
+        let client = MediationClient::new("did:example:alice", "did:example:mediator");
+        let meta = authcrypted_by("did:example:alice#key-1");
+        coordinator
+            .handle(&client.mediate_request().unwrap(), &meta)
+            .unwrap();
+        let updates: Vec<_> = (0..=MAX_RECIPIENT_QUERY_LIMIT)
+            .map(|i| RecipientUpdate::add(format!("did:example:alice-{i}")))
+            .collect();
+        coordinator
+            .handle(&client.recipient_update(&updates).unwrap(), &meta)
+            .unwrap();
+
+        let mut query = client.recipient_query(0, usize::MAX).unwrap();
+        query.body["paginate"] = json!({ "offset": u64::MAX });
+        let recipient = coordinator.handle(&query, &meta).unwrap().unwrap();
+        assert_eq!(recipient.body["pagination"]["remaining"], 0);
+
+        query.body = json!({});
+        let recipient = coordinator.handle(&query, &meta).unwrap().unwrap();
+        assert_eq!(
+            recipient.body["dids"].as_array().unwrap().len(),
+            MAX_RECIPIENT_QUERY_LIMIT
+        );
+        assert_eq!(recipient.body["pagination"]["remaining"], 1);
+    }
+}
@@ -0,0 +1,153 @@
+//! Dead-letter queue for messages that could not be delivered.
+//!
+//! When every route to a recipient has failed, including retries, the packed
+//! message is not dropped. It is stored with the history of failed attempts,
+//! so that it can be inspected and delivered again once the problem has been
+//! fixed.
+//!
+//! # Components
+//!
+//! - [`DeadLetter`]: An undelivered message and why it failed
+//! - [`DeadLetterQueue`]: Storage for dead letters
+//! - [`MemoryDeadLetterQueue`]: An in-memory queue
+//!
+//! # Examples
+//!
+//! ```rust
+//! use tap_didcomm_node::dead_letter::{DeadLetter, DeadLetterQueue, MemoryDeadLetterQueue};
+//!
+//! # async fn example() -> tap_didcomm_node::error::Result<()> {
+//! let queue = MemoryDeadLetterQueue::new();
+//! let letter = DeadLetter::new("msg-1", vec!["did:example:bob".into()], Vec::new(), Vec::new(), 1_000);
+//! let id = letter.id.clone();
+//! queue.push(letter).await?;
+//!
+//! assert_eq!(queue.list().await?.len(), 1);
+//! assert!(queue.take(&id).await?.is_some());
+//! # Ok(())
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use std::sync::RwLock;
+use tap_didcomm_core::MessageId;
+
+use crate::{dispatch::FailedAttempt, error::Result, routing::Route};
+
+/// A message that could not be delivered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter {
+    /// The ID of the dead letter
+    pub id: String,
+
+    /// The ID of the undelivered message
+    pub message_id: String,
+
+    /// The recipients the message was not delivered to
+    pub recipients: Vec<String>,
+
+    /// The routes that were tried, with their envelopes
+    pub routes: Vec<Route>,
+
+    /// Every failed attempt, in order
+    pub attempts: Vec<FailedAttempt>,
+
+    /// The Unix timestamp at which delivery last failed
+    pub failed_at: u64,
+}
+
+impl DeadLetter {
+    /// Creates a dead letter with a random ID.
+    #[must_use]
+    pub fn new(
+        message_id: impl Into<String>,
+        recipients: Vec<String>,
+        routes: Vec<Route>,
+        attempts: Vec<FailedAttempt>,
+        failed_at: u64,
+    ) -> Self {
+        Self {
+            id: MessageId::random().as_str().to_string(),
+            message_id: message_id.into(),
+            recipients,
+            routes,
+            attempts,
+            failed_at,
+        }
+    }
+}
+
+/// Storage for messages that could not be delivered.
+#[async_trait]
+pub trait DeadLetterQueue: Send + Sync {
+    /// Stores a dead letter.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the queue cannot be written
+    async fn push(&self, letter: DeadLetter) -> Result<()>;
+
+    /// Lists the stored dead letters, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the queue cannot be read
+    async fn list(&self) -> Result<Vec<DeadLetter>>;
+
+    /// Removes a dead letter, typically to deliver it again.
+    ///
+    /// # Returns
+    ///
+    /// The dead letter, or `None` if there is none with that ID
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the queue cannot be written
+    async fn take(&self, id: &str) -> Result<Option<DeadLetter>>;
+}
+
+/// An in-memory dead-letter queue.
+///
+/// Dead letters are lost when the node restarts.
+#[derive(Debug, Default)]
+pub struct MemoryDeadLetterQueue {
+    letters: RwLock<Vec<DeadLetter>>,
+}
+
+impl MemoryDeadLetterQueue {
+    /// Creates an empty queue
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl DeadLetterQueue for MemoryDeadLetterQueue {
+    async fn push(&self, letter: DeadLetter) -> Result<()> {
+        self.letters
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .push(letter);
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<DeadLetter>> {
+        Ok(self
+            .letters
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone())
+    }
+
+    async fn take(&self, id: &str) -> Result<Option<DeadLetter>> {
+        let mut letters = self
+            .letters
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        Ok(letters
+            .iter()
+            .position(|letter| letter.id == id)
+            .map(|index| letters.remove(index)))
+    }
+}
@@ -6,148 +6,963 @@
 //!
 //! # Features
 //!
-//! - HTTP(S) transport support
+//! - HTTP(S) transport support over a shared, pooled client
+//! - Custom root certificates and mutual TLS
 //! - Configurable retry policies
 //! - Asynchronous message delivery
 //! - Support for both native and WASM environments
 //!
 //! # Examples
 //!
+//! Messages are packed before they are dispatched, and delivered with the
+//! media type of their envelope:
+//!
 //! ```rust,no_run
-//! use tap_didcomm_node::dispatch::{dispatch_message, DispatchConfig};
-//! use tap_didcomm_core::Message;
+//! use tap_didcomm_core::{pack_message, Message, PackingType};
+//! use tap_didcomm_node::dispatch::{DispatchConfig, Dispatcher};
 //! use tap_didcomm_node::error::Result;
+//! use tap_didcomm_node::mock::MockPlugin;
+//! use tap_didcomm_node::routing::Route;
 //! use serde_json::json;
 //!
 //! async fn send_message() -> Result<()> {
-//!     let msg = Message::new("test", json!({"hello": "world"}))?;
-//!     let config = DispatchConfig::default();
-//!     dispatch_message(&msg, &config).await
+//!     let msg = Message::new("test", json!({"hello": "world"}))?.to(["did:example:bob"]);
+//!     let envelope = pack_message(&msg, &MockPlugin, PackingType::AnonV2).await?;
+//!     // Build the dispatcher once and reuse it for every message
+//!     let dispatcher = Dispatcher::try_new(&DispatchConfig::default())?;
+//!     let route = Route {
+//!         endpoint: "https://bob.example.com/didcomm".to_string(),
+//!         envelope,
+//!         media_type: PackingType::AnonV2.media_type().to_string(),
+//!     };
+//!     dispatcher.deliver(&[route]).await?;
+//!     Ok(())
 //! }
 //! ```
 
-use reqwest::Client;
+use rand::Rng;
+use reqwest::{Certificate, Client, Identity};
 use serde::{Deserialize, Serialize};
-use tap_didcomm_core::Message as CoreMessage;
-use tracing::debug;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tap_didcomm_core::{service::DIDCommService, DIDResolver};
+use tracing::{debug, error, warn};
 
-use crate::error::{Error, Result};
+use crate::{
+    error::{Error, Result},
+    routing::Route,
+    transport::{
+        scheme, HttpTransport, Transport, WebSocketTransport, DEFAULT_MAX_CONNECTIONS_PER_HOST,
+        DEFAULT_MAX_REPLY_SIZE,
+    },
+};
 
 /// Configuration for message dispatch
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DispatchConfig {
-    /// The base URL for the `DIDComm` service
-    pub base_url: String,
-    /// The endpoint path for message dispatch
+    /// The base URL of the `DIDComm` service to deliver to when a recipient
+    /// advertises no endpoint. Unset by default, so that sending to such a
+    /// recipient fails instead of going to an unintended host.
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// The endpoint path appended to `base_url`
     pub endpoint: String,
-    /// Whether to use HTTPS for dispatch
+    /// Whether to only dispatch to HTTPS and WSS endpoints
     pub use_https: bool,
-    /// The HTTP client timeout in seconds
+    /// The timeout for a delivery in seconds, covering the HTTP request or
+    /// opening a WebSocket connection and sending over it
     pub timeout: u64,
+    /// The timeout for establishing a connection in seconds
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// The maximum number of idle connections kept open to each host for
+    /// reuse. This only bounds the pool; `max_connections_per_host` bounds
+    /// the connections in use.
+    #[serde(default = "default_pool_max_idle_per_host")]
+    pub pool_max_idle_per_host: usize,
+    /// The maximum number of HTTP deliveries in flight to each host. Further
+    /// deliveries to the host wait, for up to `timeout`, for one to finish.
+    #[serde(default = "default_max_connections_per_host")]
+    pub max_connections_per_host: usize,
+    /// How long an idle connection is kept open in seconds
+    #[serde(default = "default_pool_idle_timeout")]
+    pub pool_idle_timeout: u64,
+    /// The largest synchronous reply read from an endpoint, in bytes
+    #[serde(default = "default_max_reply_size")]
+    pub max_reply_size: usize,
+    /// The TLS configuration of the HTTP client
+    #[serde(default)]
+    pub tls: TlsConfig,
+    /// The policy for retrying failed deliveries
+    #[serde(default)]
+    pub retry: RetryPolicy,
+}
+
+const fn default_connect_timeout() -> u64 {
+    10
+}
+
+const fn default_pool_max_idle_per_host() -> usize {
+    32
+}
+
+const fn default_max_connections_per_host() -> usize {
+    DEFAULT_MAX_CONNECTIONS_PER_HOST
+}
+
+const fn default_pool_idle_timeout() -> u64 {
+    90
+}
+
+const fn default_max_reply_size() -> usize {
+    DEFAULT_MAX_REPLY_SIZE
 }
 
 impl Default for DispatchConfig {
     fn default() -> Self {
         Self {
-            base_url: "http://localhost".to_string(),
+            base_url: None,
             endpoint: "/didcomm".to_string(),
             use_https: false,
             timeout: 30,
+            connect_timeout: default_connect_timeout(),
+            pool_max_idle_per_host: default_pool_max_idle_per_host(),
+            max_connections_per_host: default_max_connections_per_host(),
+            pool_idle_timeout: default_pool_idle_timeout(),
+            max_reply_size: default_max_reply_size(),
+            tls: TlsConfig::default(),
+            retry: RetryPolicy::default(),
         }
     }
 }
 
-/// Dispatch a message to its recipients
+/// TLS configuration for the HTTP client
 ///
-/// # Arguments
+/// Certificates and keys are read from PEM files when the client is built.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// Additional root certificates to trust, besides the system roots
+    pub ca_certificates: Vec<PathBuf>,
+    /// The certificate chain presented for mutual TLS
+    pub client_certificate: Option<PathBuf>,
+    /// The PKCS #8 private key of the client certificate
+    pub client_key: Option<PathBuf>,
+}
+
+/// The outcome of delivering an envelope to an endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+    /// The endpoint the envelope was delivered to
+    pub endpoint: String,
+    /// The HTTP status the endpoint responded with
+    pub status: u16,
+    /// The packed message the endpoint replied with, if any
+    pub reply: Option<String>,
+}
+
+/// The result of sending a message to some of its recipients
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendResult {
+    /// The ID of the message sent
+    pub message_id: String,
+    /// The recipients the message was delivered for
+    pub recipients: Vec<String>,
+    /// The endpoint the message was delivered to
+    pub endpoint: String,
+    /// The HTTP status the endpoint responded with
+    pub status: u16,
+    /// The packed message the endpoint replied with, if any
+    pub reply: Option<String>,
+}
+
+/// A failed attempt to deliver an envelope
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedAttempt {
+    /// The endpoint delivery was attempted to
+    pub endpoint: String,
+    /// The number of the attempt to this endpoint, starting at 1
+    pub attempt: u32,
+    /// The HTTP status the endpoint responded with, if it responded
+    pub status: Option<u16>,
+    /// The delay in seconds the endpoint asked for with `Retry-After`
+    pub retry_after: Option<u64>,
+    /// A description of the failure
+    pub error: String,
+}
+
+impl FailedAttempt {
+    /// Whether the failure is transient and delivery should be retried
+    ///
+    /// Network errors, server errors and `429 Too Many Requests` are retried;
+    /// other responses mean the endpoint will not accept the message.
+    #[must_use]
+    pub fn is_retryable(&self) -> bool {
+        match self.status {
+            Some(status) => status >= 500 || status == 429,
+            None => true,
+        }
+    }
+}
+
+/// Policy for retrying failed deliveries
 ///
-/// * `message` - The message to dispatch
-/// * `config` - The dispatch configuration
+/// Each route is attempted up to `max_attempts` times before failing over to
+/// the next one. The delay between attempts grows exponentially from
+/// `initial_backoff_ms`, randomized by `jitter` and capped at
+/// `max_backoff_ms`. A `Retry-After` header given in seconds takes
+/// precedence over the computed delay, within the same cap.
 ///
-/// # Returns
+/// The `multiplier` must be finite and at least 1, and the `jitter` between 0
+/// and 1; see [`RetryPolicy::validate`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts per route, including the first
+    pub max_attempts: u32,
+    /// The delay before the first retry in milliseconds
+    pub initial_backoff_ms: u64,
+    /// The maximum delay between attempts in milliseconds
+    pub max_backoff_ms: u64,
+    /// The factor the delay grows by after each retry
+    pub multiplier: f64,
+    /// The fraction by which each delay is randomly varied, between 0 and 1
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+/// The largest exponent the backoff grows by, beyond which every delay is
+/// capped anyway
+const MAX_BACKOFF_EXPONENT: u32 = 64;
+
+impl RetryPolicy {
+    /// A policy that attempts each route once
+    #[must_use]
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Checks that the policy's values are in range
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Config` if there are no attempts, the multiplier is
+    /// not finite or below 1, or the jitter is not between 0 and 1
+    pub fn validate(&self) -> Result<()> {
+        if self.max_attempts == 0 {
+            return Err(Error::Config(
+                "The retry policy must allow at least one attempt".into(),
+            ));
+        }
+        if !self.multiplier.is_finite() || self.multiplier < 1.0 {
+            return Err(Error::Config(format!(
+                "The retry multiplier must be finite and at least 1, not {}",
+                self.multiplier
+            )));
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(Error::Config(format!(
+                "The retry jitter must be between 0 and 1, not {}",
+                self.jitter
+            )));
+        }
+        Ok(())
+    }
+
+    /// The delay before retrying after a failed attempt
+    ///
+    /// The delay never exceeds `max_backoff_ms`, even if the policy's values
+    /// are out of range.
+    ///
+    /// # Arguments
+    ///
+    /// * `failed` - The failed attempt
+    #[must_use]
+    pub fn backoff(&self, failed: &FailedAttempt) -> Duration {
+        let max = Duration::from_millis(self.max_backoff_ms);
+        if let Some(seconds) = failed.retry_after {
+            return Duration::from_secs(seconds).min(max);
+        }
+
+        let exponent = i32::try_from(failed.attempt.saturating_sub(1).min(MAX_BACKOFF_EXPONENT))
+            .unwrap_or(i32::MAX);
+        let multiplier = if self.multiplier.is_finite() {
+            self.multiplier.max(1.0)
+        } else {
+            1.0
+        };
+        let jitter = if self.jitter > 0.0 && self.jitter.is_finite() {
+            let jitter = self.jitter.min(1.0);
+            rand::thread_rng().gen_range(-jitter..=jitter)
+        } else {
+            0.0
+        };
+        let seconds = Duration::from_millis(self.initial_backoff_ms).as_secs_f64()
+            * multiplier.powi(exponent)
+            * (1.0 + jitter);
+        Duration::try_from_secs_f64(seconds).map_or(max, |delay| delay.min(max))
+    }
+}
+
+/// Build the HTTP client used for delivery
 ///
-/// A Result indicating success or failure of message dispatch
+/// The client pools connections, so it should be built once and shared.
 ///
 /// # Errors
 ///
-/// Returns an error if:
-/// - The HTTP request fails
-/// - The server returns a non-success status code
-pub async fn dispatch_message(message: &CoreMessage, config: &DispatchConfig) -> Result<()> {
-    let client = Client::new();
-    let url = format!("{}{}", config.base_url, config.endpoint);
+/// Returns an error if a certificate or key cannot be read, or the client
+/// cannot be built
+pub fn client(config: &DispatchConfig) -> Result<Client> {
+    let mut builder = Client::builder()
+        .timeout(Duration::from_secs(config.timeout))
+        .connect_timeout(Duration::from_secs(config.connect_timeout))
+        .pool_max_idle_per_host(config.pool_max_idle_per_host)
+        .pool_idle_timeout(Duration::from_secs(config.pool_idle_timeout))
+        .https_only(config.use_https);
+
+    for path in &config.tls.ca_certificates {
+        let certificate =
+            Certificate::from_pem(&read_pem(path)?).map_err(|e| Error::Config(e.to_string()))?;
+        builder = builder.add_root_certificate(certificate);
+    }
+
+    match (&config.tls.client_certificate, &config.tls.client_key) {
+        (Some(certificate), Some(key)) => {
+            let identity = Identity::from_pkcs8_pem(&read_pem(certificate)?, &read_pem(key)?)
+                .map_err(|e| Error::Config(e.to_string()))?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => {
+            return Err(Error::Config(
+                "A client certificate and key must be configured together".into(),
+            ))
+        }
+    }
+
+    builder.build().map_err(|e| Error::Http(e.to_string()))
+}
+
+fn read_pem(path: &PathBuf) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| Error::Config(format!("{}: {e}", path.display())))
+}
+
+/// Delivers envelopes over the transport for each endpoint's URI scheme
+///
+/// Failed attempts are retried according to the dispatch configuration's
+/// retry policy before failing over to the next route.
+#[derive(Clone)]
+pub struct Dispatcher {
+    transports: HashMap<String, Arc<dyn Transport>>,
+    retry: RetryPolicy,
+    https_only: bool,
+}
 
-    debug!("Dispatching message to {url}");
+impl Dispatcher {
+    /// Create a dispatcher with HTTP and WebSocket transports
+    ///
+    /// Every clone of the dispatcher shares one HTTP client and its
+    /// connection pool. If `use_https` is set, only `https` and `wss`
+    /// endpoints have a transport.
+    ///
+    /// If the HTTP client cannot be built, for example because a TLS
+    /// certificate cannot be read, the error is logged and every HTTP
+    /// delivery fails. Use [`Dispatcher::try_new`] to have it reported.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The dispatch configuration
+    #[must_use]
+    pub fn new(config: &DispatchConfig) -> Self {
+        let http = client(config).map_or_else(
+            |e| {
+                error!("HTTP delivery is unavailable: {e}");
+                HttpTransport::unavailable(e.to_string())
+            },
+            HttpTransport::new,
+        );
+        Self::with_http(config, http)
+    }
 
-    let response = client
-        .post(&url)
-        .json(message)
-        .send()
-        .await
-        .map_err(|e| Error::Http(e.to_string()))?;
+    /// Create a dispatcher with HTTP and WebSocket transports, checking the
+    /// configuration first
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The dispatch configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the retry policy is invalid or the HTTP client
+    /// cannot be built, for example because a TLS certificate or key cannot
+    /// be read
+    pub fn try_new(config: &DispatchConfig) -> Result<Self> {
+        config.retry.validate()?;
+        Ok(Self::with_http(config, HttpTransport::new(client(config)?)))
+    }
+
+    fn with_http(config: &DispatchConfig, http: HttpTransport) -> Self {
+        let http: Arc<dyn Transport> = Arc::new(
+            http.with_max_reply_size(config.max_reply_size)
+                .with_max_connections_per_host(
+                    config.max_connections_per_host,
+                    Duration::from_secs(config.timeout),
+                ),
+        );
+        let ws: Arc<dyn Transport> =
+            Arc::new(WebSocketTransport::new().with_timeout(Duration::from_secs(config.timeout)));
 
-    if !response.status().is_success() {
-        return Err(Error::Http(format!(
-            "Failed to dispatch message: {}",
-            response.status()
-        )));
+        let mut transports = HashMap::new();
+        if !config.use_https {
+            transports.insert("http".to_string(), Arc::clone(&http));
+            transports.insert("ws".to_string(), Arc::clone(&ws));
+        }
+        transports.insert("https".to_string(), http);
+        transports.insert("wss".to_string(), ws);
+        Self {
+            transports,
+            retry: config.retry.clone(),
+            https_only: config.use_https,
+        }
     }
 
-    Ok(())
+    /// Set the transport for endpoints with a URI scheme
+    ///
+    /// # Arguments
+    ///
+    /// * `scheme` - The URI scheme, such as `https` or `mem`
+    /// * `transport` - The transport to use
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Config` if `use_https` is set and the scheme is
+    /// plaintext `http` or `ws`
+    pub fn set_transport(
+        &mut self,
+        scheme: &str,
+        transport: impl Transport + 'static,
+    ) -> Result<()> {
+        let scheme = scheme.to_ascii_lowercase();
+        if self.https_only && matches!(scheme.as_str(), "http" | "ws") {
+            return Err(Error::Config(format!(
+                "Cannot deliver over {scheme} when only HTTPS and WSS are allowed"
+            )));
+        }
+        self.transports.insert(scheme, Arc::new(transport));
+        Ok(())
+    }
+
+    /// Get the transport for an endpoint
+    #[must_use]
+    pub fn transport(&self, endpoint: &str) -> Option<Arc<dyn Transport>> {
+        scheme(endpoint).and_then(|scheme| self.transports.get(&scheme).cloned())
+    }
+
+    /// Deliver a message over the first route that accepts it
+    ///
+    /// # Arguments
+    ///
+    /// * `routes` - The routes to the recipient, in order of preference
+    ///
+    /// # Returns
+    ///
+    /// The delivery over the route that accepted the message
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Undeliverable` with every failed attempt if delivery
+    /// over every route fails, or `Error::Dispatch` if there are no routes
+    pub async fn deliver(&self, routes: &[Route]) -> Result<Delivery> {
+        self.deliver_with(routes, |_, _| {}).await
+    }
+
+    /// Deliver a message over the first route that accepts it, reporting
+    /// each retry before waiting for it
+    ///
+    /// # Arguments
+    ///
+    /// * `routes` - The routes to the recipient, in order of preference
+    /// * `on_retry` - Called with each failed attempt that will be retried
+    ///   and the delay before the next one
+    ///
+    /// # Returns
+    ///
+    /// The delivery over the route that accepted the message
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Undeliverable` with every failed attempt if delivery
+    /// over every route fails, or `Error::Dispatch` if there are no routes
+    pub async fn deliver_with(
+        &self,
+        routes: &[Route],
+        on_retry: impl Fn(&FailedAttempt, Duration) + Send + Sync,
+    ) -> Result<Delivery> {
+        if routes.is_empty() {
+            return Err(Error::Dispatch("No route to recipient".into()));
+        }
+
+        let mut failures = Vec::new();
+        for route in routes {
+            let Some(transport) = self.transport(&route.endpoint) else {
+                warn!("No transport for {}", route.endpoint);
+                failures.push(FailedAttempt {
+                    endpoint: route.endpoint.clone(),
+                    attempt: 1,
+                    status: None,
+                    retry_after: None,
+                    error: "No transport for the endpoint's URI scheme".to_string(),
+                });
+                continue;
+            };
+
+            for attempt in 1..=self.retry.max_attempts.max(1) {
+                debug!(
+                    "Delivering envelope to {} (attempt {attempt})",
+                    route.endpoint
+                );
+                let error = match transport.deliver(route).await {
+                    Ok(delivery) => return Ok(delivery),
+                    Err(error) => error,
+                };
+                warn!("Delivery to {} failed: {error}", route.endpoint);
+
+                let failed = FailedAttempt {
+                    endpoint: route.endpoint.clone(),
+                    attempt,
+                    status: error.status,
+                    retry_after: error.retry_after,
+                    error: error.message,
+                };
+                let retry = failed.is_retryable() && attempt < self.retry.max_attempts;
+                let backoff = self.retry.backoff(&failed);
+                if retry {
+                    on_retry(&failed, backoff);
+                }
+                failures.push(failed);
+                if !retry {
+                    break;
+                }
+                tokio::time::sleep(backoff).await;
+            }
+        }
+        Err(Error::Undeliverable(failures))
+    }
 }
 
-/// Get the service endpoint for a message
+/// A `DIDComm` endpoint of a recipient, resolved from its DID document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedEndpoint {
+    /// The ID of the service the endpoint was resolved from
+    pub service_id: String,
+    /// The URI to deliver messages to
+    pub uri: String,
+    /// The media types the endpoint accepts
+    pub accept: Vec<String>,
+    /// Keys of the mediators messages are forwarded through, outermost first
+    pub routing_keys: Vec<String>,
+}
+
+/// Resolve the `DIDComm` endpoints of a DID, in order of preference
+///
+/// Services are returned in document order, skipping those that do not
+/// accept `DIDComm` v2. A service whose URI is a DID, such as a mediator's,
+/// is replaced by that DID's endpoints, with the mediator's routing keys
+/// ahead of the recipient's. Only one level of such indirection is followed.
 ///
 /// # Arguments
 ///
-/// * `message` - The message to get the endpoint for
+/// * `resolver` - The DID resolver
+/// * `did` - The DID, or a DID URL whose fragment is ignored
 ///
 /// # Returns
 ///
-/// The service endpoint URL
-#[must_use]
-fn get_service_endpoint(_message: &CoreMessage) -> String {
-    // TODO: Implement service endpoint resolution from DID Document
-    "http://localhost:8080/didcomm".to_string()
+/// The endpoints, which are tried in order when delivering
+///
+/// # Errors
+///
+/// Returns an error if a DID cannot be resolved or its document is invalid
+pub async fn resolve_endpoints(
+    resolver: &dyn DIDResolver,
+    did: &str,
+) -> Result<Vec<ResolvedEndpoint>> {
+    let mut endpoints = Vec::new();
+    for service in services_of(resolver, did).await? {
+        if !service.uri.starts_with("did:") {
+            endpoints.push(ResolvedEndpoint {
+                service_id: service.id,
+                uri: service.uri,
+                accept: service.accept,
+                routing_keys: service.routing_keys,
+            });
+            continue;
+        }
+
+        for mediator in services_of(resolver, &service.uri).await? {
+            if mediator.uri.starts_with("did:") {
+                debug!("Skipping nested DID endpoint {}", mediator.uri);
+                continue;
+            }
+            endpoints.push(ResolvedEndpoint {
+                service_id: service.id.clone(),
+                uri: mediator.uri,
+                accept: service.accept.clone(),
+                routing_keys: mediator
+                    .routing_keys
+                    .into_iter()
+                    .chain(service.routing_keys.iter().cloned())
+                    .collect(),
+            });
+        }
+    }
+    Ok(endpoints)
+}
+
+/// Resolve the `DIDCommMessaging` services of a DID that accept `DIDComm` v2
+async fn services_of(resolver: &dyn DIDResolver, did: &str) -> Result<Vec<DIDCommService>> {
+    let did = did.split('#').next().unwrap_or(did);
+    let doc = resolver.resolve(did).await?;
+    Ok(DIDCommService::from_did_document_str(&doc)?
+        .into_iter()
+        .filter(|service| {
+            service.accept.is_empty() || service.accept.iter().any(|accept| accept == "didcomm/v2")
+        })
+        .collect())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use serde_json::json;
+    use tap_didcomm_core::PackingType;
     use wiremock::{
-        matchers::{method, path},
+        matchers::{header, method, path},
         Mock, MockServer, ResponseTemplate,
     };
 
     #[tokio::test]
-    async fn test_dispatch_message() {
+    async fn test_https_only() {
         let mock_server = MockServer::start().await;
-
         Mock::given(method("POST"))
-            .and(path("/didcomm"))
-            .respond_with(ResponseTemplate::new(200))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(0)
             .mount(&mock_server)
             .await;
 
         let config = DispatchConfig {
-            base_url: mock_server.uri(),
-            endpoint: "/didcomm".to_string(),
-            use_https: false,
-            timeout: 30,
+            base_url: Some(mock_server.uri()),
+            use_https: true,
+            retry: RetryPolicy::none(),
+            ..DispatchConfig::default()
+        };
+        let mut dispatcher = Dispatcher::new(&config);
+        assert!(dispatcher.transport(&mock_server.uri()).is_none());
+        assert!(dispatcher
+            .transport("https://example.com/didcomm")
+            .is_some());
+        assert!(dispatcher.transport("ws://example.com/didcomm").is_none());
+        let route = Route {
+            endpoint: mock_server.uri(),
+            envelope: "{}".to_string(),
+            media_type: "application/didcomm-plain+json".to_string(),
+        };
+        assert!(matches!(
+            dispatcher.deliver(std::slice::from_ref(&route)).await,
+            Err(Error::Undeliverable(attempts)) if attempts.len() == 1
+        ));
+
+        // Plaintext transports cannot be registered again
+        let http = HttpTransport::new(Client::new());
+        assert!(matches!(
+            dispatcher.set_transport("HTTP", http.clone()),
+            Err(Error::Config(_))
+        ));
+        assert!(dispatcher
+            .set_transport("ws", WebSocketTransport::new())
+            .is_err());
+        assert!(dispatcher.transport(&mock_server.uri()).is_none());
+        assert!(dispatcher.set_transport("mem", http).is_ok());
+        assert!(dispatcher.deliver(&[route]).await.is_err());
+    }
+
+    #[test]
+    fn test_client_tls_config() {
+        let mut config = DispatchConfig::default();
+        config.tls.client_certificate = Some(PathBuf::from("client.pem"));
+        assert!(matches!(client(&config), Err(Error::Config(_))));
+        assert!(matches!(
+            Dispatcher::try_new(&config),
+            Err(Error::Config(_))
+        ));
+
+        config.tls = TlsConfig {
+            ca_certificates: vec![PathBuf::from("/nonexistent/ca.pem")],
+            ..TlsConfig::default()
+        };
+        assert!(matches!(client(&config), Err(Error::Config(_))));
+
+        let config: DispatchConfig = serde_json::from_value(json!({
+            "base_url": "https://example.com",
+            "endpoint": "/didcomm",
+            "use_https": true,
+            "timeout": 5
+        }))
+        .unwrap();
+        assert_eq!(config.pool_max_idle_per_host, 32);
+        assert_eq!(config.max_connections_per_host, 32);
+        assert!(config.tls.ca_certificates.is_empty());
+        assert!(client(&config).is_ok());
+    }
+
+    /// Resolves DID documents from a fixed map
+    struct Resolver(std::collections::HashMap<&'static str, serde_json::Value>);
+
+    #[async_trait::async_trait]
+    impl DIDResolver for Resolver {
+        async fn resolve(&self, did: &str) -> tap_didcomm_core::Result<String> {
+            Ok(self
+                .0
+                .get(did)
+                .cloned()
+                .unwrap_or_else(|| json!({"id": did}))
+                .to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_endpoints() {
+        let resolver = Resolver(
+            [
+                (
+                    "did:example:bob",
+                    json!({
+                        "id": "did:example:bob",
+                        "service": [
+                            {
+                                "id": "did:example:bob#legacy",
+                                "type": "DIDCommMessaging",
+                                "serviceEndpoint": {"uri": "https://old.example.com", "accept": ["didcomm/aip2;env=rfc19"]}
+                            },
+                            {
+                                "id": "did:example:bob#mediated",
+                                "type": "DIDCommMessaging",
+                                "serviceEndpoint": {"uri": "did:example:mediator", "routingKeys": ["did:example:bob-mediator#key-1"]}
+                            },
+                            {
+                                "id": "did:example:bob#direct",
+                                "type": "DIDCommMessaging",
+                                "serviceEndpoint": "https://bob.example.com"
+                            }
+                        ]
+                    }),
+                ),
+                (
+                    "did:example:mediator",
+                    json!({
+                        "id": "did:example:mediator",
+                        "service": [{
+                            "id": "did:example:mediator#didcomm",
+                            "type": "DIDCommMessaging",
+                            "serviceEndpoint": {"uri": "https://mediator.example.com", "routingKeys": ["did:example:mediator#key-1"]}
+                        }]
+                    }),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        let endpoints = resolve_endpoints(&resolver, "did:example:bob#key-1")
+            .await
+            .unwrap();
+        assert_eq!(endpoints.len(), 2);
+        assert_eq!(endpoints[0].service_id, "did:example:bob#mediated");
+        assert_eq!(endpoints[0].uri, "https://mediator.example.com");
+        assert_eq!(
+            endpoints[0].routing_keys,
+            vec![
+                "did:example:mediator#key-1",
+                "did:example:bob-mediator#key-1"
+            ]
+        );
+        assert_eq!(endpoints[1].uri, "https://bob.example.com");
+        assert!(resolve_endpoints(&resolver, "did:example:carol")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_deliver_with_failover() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/down"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/rejecting"))
+            .respond_with(ResponseTemplate::new(400))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/up"))
+            .and(header("content-type", "application/didcomm-encrypted+json"))
+            .respond_with(ResponseTemplate::new(202))
+            .mount(&mock_server)
+            .await;
+
+        let routes: Vec<Route> = ["/down", "/rejecting", "/up"]
+            .into_iter()
+            .map(|endpoint| Route {
+                endpoint: format!("{}{endpoint}", mock_server.uri()),
+                envelope: "envelope".to_string(),
+                media_type: PackingType::AnonV2.media_type().to_string(),
+            })
+            .collect();
+        let dispatcher = Dispatcher::new(&DispatchConfig {
+            retry: RetryPolicy {
+                max_attempts: 2,
+                initial_backoff_ms: 1,
+                ..RetryPolicy::default()
+            },
+            ..DispatchConfig::default()
+        });
+
+        let delivery = dispatcher.deliver(&routes).await.unwrap();
+        assert_eq!(delivery.endpoint, routes[2].endpoint);
+        assert_eq!(delivery.status, 202);
+        assert!(delivery.reply.is_none());
+
+        let mut unsupported = routes[0].clone();
+        unsupported.endpoint = "smtp://mail.example.com".to_string();
+        let dispatcher = Dispatcher::new(&DispatchConfig {
+            retry: RetryPolicy::none(),
+            ..DispatchConfig::default()
+        });
+        let Err(Error::Undeliverable(failures)) = dispatcher
+            .deliver(&[unsupported, routes[0].clone(), routes[1].clone()])
+            .await
+        else {
+            panic!("expected an undeliverable error");
         };
+        assert_eq!(failures.len(), 3);
+        assert_eq!(failures[0].status, None);
+        assert_eq!(failures[1].status, Some(503));
+        assert_eq!(failures[2].status, Some(400));
+        assert!(!failures[2].is_retryable());
+        assert!(dispatcher.deliver(&[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_retry_after() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("reply"))
+            .mount(&mock_server)
+            .await;
 
-        let message = CoreMessage::new("test", json!({"test": "value"})).unwrap();
-        let result = dispatch_message(&message, &config).await;
-        assert!(result.is_ok());
+        let route = Route {
+            endpoint: mock_server.uri(),
+            envelope: "envelope".to_string(),
+            media_type: PackingType::Signed.media_type().to_string(),
+        };
+        let policy = RetryPolicy {
+            initial_backoff_ms: 60_000,
+            ..RetryPolicy::default()
+        };
+        let dispatcher = Dispatcher::new(&DispatchConfig {
+            retry: policy.clone(),
+            ..DispatchConfig::default()
+        });
+        let delivery = dispatcher.deliver(&[route]).await.unwrap();
+        assert_eq!(delivery.reply.as_deref(), Some("reply"));
+
+        let failed = FailedAttempt {
+            endpoint: mock_server.uri(),
+            attempt: 3,
+            status: None,
+            retry_after: None,
+            error: "connection refused".to_string(),
+        };
+        assert!(failed.is_retryable());
+        assert_eq!(policy.backoff(&failed), Duration::from_secs(30));
+        let backoff = RetryPolicy::default().backoff(&failed).as_millis();
+        assert!((1_600..=2_400).contains(&backoff), "backoff: {backoff}");
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let failed = |attempt| FailedAttempt {
+            endpoint: "https://example.com".to_string(),
+            attempt,
+            status: None,
+            retry_after: None,
+            error: "connection refused".to_string(),
+        };
+        let max = Duration::from_secs(30);
+        assert_eq!(RetryPolicy::default().backoff(&failed(u32::MAX)), max);
+
+        // Out of range values are rejected when the dispatcher is built, and
+        // never make the backoff panic
+        for policy in [
+            RetryPolicy {
+                multiplier: f64::INFINITY,
+                ..RetryPolicy::default()
+            },
+            RetryPolicy {
+                multiplier: -2.0,
+                ..RetryPolicy::default()
+            },
+            RetryPolicy {
+                jitter: 5.0,
+                ..RetryPolicy::default()
+            },
+            RetryPolicy {
+                max_attempts: 0,
+                ..RetryPolicy::default()
+            },
+        ] {
+            assert!(policy.backoff(&failed(1_000)) <= max);
+            assert!(matches!(policy.validate(), Err(Error::Config(_))));
+            let config = DispatchConfig {
+                retry: policy,
+                ..DispatchConfig::default()
+            };
+            assert!(Dispatcher::try_new(&config).is_err());
+        }
+        assert!(RetryPolicy::default().validate().is_ok());
     }
 
     #[tokio::test]
     async fn test_dispatch_config() {
         let config = DispatchConfig::default();
-        assert_eq!(config.base_url, "http://localhost");
+        assert_eq!(config.base_url, None);
         assert_eq!(config.endpoint, "/didcomm");
-        assert_eq!(config.use_https, false);
+        assert!(!config.use_https);
         assert_eq!(config.timeout, 30);
     }
 }
@@ -19,6 +19,8 @@
 
 use tap_didcomm_core::Error as CoreError;
 
+use crate::dispatch::FailedAttempt;
+
 /// Custom error type for the node crate
 #[derive(Debug)]
 pub enum Error {
@@ -34,11 +36,43 @@ pub enum Error {
     Config(String),
     /// Invalid format
     InvalidFormat(String),
+    /// Storage error
+    Storage(String),
+    /// Message was received before
+    Duplicate(String),
+    /// Message could not be delivered over any route
+    Undeliverable(Vec<FailedAttempt>),
+    /// The node or a handler is at capacity and the message should be retried later
+    Busy(String),
+    /// Message is not addressed to any identity hosted by the node
+    UnknownRecipient(String),
+    /// Message was rejected by the node's policy
+    PolicyViolation(String),
+    /// Message was received from a sender or source over its rate limit
+    RateLimited(String),
+    /// Message exceeds a size, recipient or attachment quota
+    QuotaExceeded(String),
+    /// Message does not authenticate the sender it acts for
+    Unauthenticated(String),
 }
 
 /// Result type for the node crate
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// Whether the error is transient and the message should be retried later
+    ///
+    /// A node that is busy, over a rate limit or failing to store or hand off
+    /// a message may accept it later; other errors will recur.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::Busy(_) | Error::RateLimited(_) | Error::Storage(_) | Error::Actor(_)
+        )
+    }
+}
+
 impl From<CoreError> for Error {
     fn from(err: CoreError) -> Self {
         Error::Core(err)
@@ -54,6 +88,15 @@ impl From<Error> for CoreError {
             Error::Http(msg) => CoreError::Plugin(format!("HTTP error: {msg}")),
             Error::Config(msg) => CoreError::Plugin(format!("Configuration error: {msg}")),
             Error::InvalidFormat(msg) => CoreError::Plugin(format!("Format error: {msg}")),
+            Error::Storage(msg) => CoreError::Plugin(format!("Storage error: {msg}")),
+            Error::Duplicate(msg) => CoreError::Plugin(format!("Duplicate message: {msg}")),
+            err @ Error::Undeliverable(_) => CoreError::Plugin(err.to_string()),
+            Error::Busy(msg) => CoreError::Plugin(format!("Busy: {msg}")),
+            Error::UnknownRecipient(msg) => CoreError::Plugin(format!("Unknown recipient: {msg}")),
+            Error::PolicyViolation(msg) => CoreError::Plugin(format!("Policy violation: {msg}")),
+            Error::RateLimited(msg) => CoreError::Plugin(format!("Rate limited: {msg}")),
+            Error::QuotaExceeded(msg) => CoreError::Plugin(format!("Quota exceeded: {msg}")),
+            Error::Unauthenticated(msg) => CoreError::Plugin(format!("Unauthenticated: {msg}")),
         }
     }
 }
@@ -67,6 +110,24 @@ impl std::fmt::Display for Error {
             Error::Http(msg) => write!(f, "HTTP error: {msg}"),
             Error::Config(msg) => write!(f, "Configuration error: {msg}"),
             Error::InvalidFormat(msg) => write!(f, "Format error: {msg}"),
+            Error::Storage(msg) => write!(f, "Storage error: {msg}"),
+            Error::Duplicate(msg) => write!(f, "Duplicate message: {msg}"),
+            Error::Undeliverable(attempts) => match attempts.last() {
+                Some(last) => write!(
+                    f,
+                    "Undeliverable message: {} failed attempts, last to {}: {}",
+                    attempts.len(),
+                    last.endpoint,
+                    last.error
+                ),
+                None => write!(f, "Undeliverable message"),
+            },
+            Error::Busy(msg) => write!(f, "Busy: {msg}"),
+            Error::UnknownRecipient(msg) => write!(f, "Unknown recipient: {msg}"),
+            Error::PolicyViolation(msg) => write!(f, "Policy violation: {msg}"),
+            Error::RateLimited(msg) => write!(f, "Rate limited: {msg}"),
+            Error::QuotaExceeded(msg) => write!(f, "Quota exceeded: {msg}"),
+            Error::Unauthenticated(msg) => write!(f, "Unauthenticated: {msg}"),
         }
     }
 }
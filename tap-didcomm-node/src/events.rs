@@ -0,0 +1,215 @@
+//! Events for observers of a node.
+//!
+//! Handlers act on messages, while observers such as audit logs, metrics
+//! exporters and dashboards only want to know what the node did. The node
+//! publishes a [`NodeEvent`] on its [`EventBus`] when it receives or sends a
+//! message, when delivery fails or is retried, and when it drops or rejects
+//! a message.
+//!
+//! The bus is a broadcast channel: every subscriber sees every event, and a
+//! subscriber that falls more than the bus's capacity behind skips the
+//! events it missed rather than slowing the node down.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! use futures::StreamExt;
+//! use tap_didcomm_node::events::EventKind;
+//! use tap_didcomm_node::DIDCommNode;
+//!
+//! async fn audit(node: &DIDCommNode) {
+//!     let mut events = Box::pin(node.subscribe());
+//!     while let Some(event) = events.next().await {
+//!         if let EventKind::DeliveryFailed { error } = &event.kind {
+//!             println!("{:?} to {:?} failed: {error}", event.message_id, event.peers);
+//!         }
+//!     }
+//! }
+//! ```
+
+use futures::Stream;
+use std::time::Duration;
+use tap_didcomm_core::Message;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// The number of events a subscriber can fall behind by default
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// What happened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    /// A message was received and passed to its handlers
+    MessageReceived,
+    /// A received envelope could not be unpacked
+    UnpackFailed {
+        /// Why unpacking failed
+        error: String,
+    },
+    /// A message was delivered to an endpoint
+    MessageSent {
+        /// The endpoint that accepted the message
+        endpoint: String,
+    },
+    /// A message could not be delivered over any route
+    DeliveryFailed {
+        /// Why delivery failed
+        error: String,
+    },
+    /// A failed delivery attempt will be retried
+    RetryScheduled {
+        /// The endpoint delivery failed to
+        endpoint: String,
+        /// The number of the attempt that failed, starting at 1
+        attempt: u32,
+        /// How long until the next attempt
+        delay: Duration,
+    },
+    /// A message that was received before was dropped
+    DuplicateDropped,
+    /// A received message was rejected by the node's policy
+    PolicyRejected {
+        /// The problem code of the rejection
+        code: String,
+    },
+}
+
+/// Something a node did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeEvent {
+    /// What happened
+    pub kind: EventKind,
+
+    /// The ID of the message, if it is known
+    pub message_id: Option<String>,
+
+    /// The ID of the message's thread, if it is known
+    pub thread_id: Option<String>,
+
+    /// The sender of a received message or the recipients of a sent one
+    pub peers: Vec<String>,
+
+    /// The Unix timestamp of the event
+    pub timestamp: u64,
+
+    /// How long the node took, such as to receive or deliver the message
+    pub elapsed: Option<Duration>,
+}
+
+impl NodeEvent {
+    /// Creates an event without a message
+    #[must_use]
+    pub fn new(kind: EventKind, timestamp: u64) -> Self {
+        Self {
+            kind,
+            message_id: None,
+            thread_id: None,
+            peers: Vec::new(),
+            timestamp,
+            elapsed: None,
+        }
+    }
+
+    /// Creates an event about a received message, whose peer is its sender
+    #[must_use]
+    pub fn received(kind: EventKind, message: &Message, timestamp: u64) -> Self {
+        Self {
+            message_id: Some(message.id.as_str().to_string()),
+            thread_id: Some(message.thread_id().to_string()),
+            peers: message.from.iter().cloned().collect(),
+            ..Self::new(kind, timestamp)
+        }
+    }
+
+    /// Sets how long the node took
+    #[must_use]
+    pub fn elapsed(mut self, elapsed: Duration) -> Self {
+        self.elapsed = Some(elapsed);
+        self
+    }
+}
+
+/// Broadcasts a node's events to its subscribers
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<NodeEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl EventBus {
+    /// Creates a bus that subscribers can fall `capacity` events behind
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity.max(1));
+        Self { tx }
+    }
+
+    /// Publishes an event to every subscriber
+    pub fn publish(&self, event: NodeEvent) {
+        // An error only means that nobody is subscribed
+        let _ = self.tx.send(event);
+    }
+
+    /// Gets the number of subscribers
+    #[must_use]
+    pub fn subscribers(&self) -> usize {
+        self.tx.receiver_count()
+    }
+
+    /// Subscribes to the events published from now on
+    pub fn subscribe(&self) -> impl Stream<Item = NodeEvent> {
+        futures::stream::unfold(self.tx.subscribe(), |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => return Some((event, rx)),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Event subscriber fell behind, skipped {skipped} events");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_event_bus() {
+        let bus = EventBus::new(2);
+        let mut first = Box::pin(bus.subscribe());
+        let mut second = Box::pin(bus.subscribe());
+        assert_eq!(bus.subscribers(), 2);
+
+        let message = Message::new("test", json!({}))
+            .unwrap()
+            .from("did:example:alice")
+            .thid("thread-1");
+        let event = NodeEvent::received(EventKind::MessageReceived, &message, 1_000)
+            .elapsed(Duration::from_millis(5));
+        bus.publish(event.clone());
+        assert_eq!(first.next().await.unwrap(), event);
+        assert_eq!(
+            second.next().await.unwrap().thread_id.as_deref(),
+            Some("thread-1")
+        );
+
+        // A subscriber that falls behind skips to the events it can still see
+        for timestamp in 0..3 {
+            bus.publish(NodeEvent::new(EventKind::DuplicateDropped, timestamp));
+        }
+        assert_eq!(first.next().await.unwrap().timestamp, 1);
+        drop(bus);
+        assert_eq!(first.next().await.unwrap().timestamp, 2);
+        assert!(first.next().await.is_none());
+    }
+}
@@ -0,0 +1,209 @@
+//! Typed message handlers.
+//!
+//! A [`MessageHandler`] receives each message routed to it together with a
+//! [`HandlerContext`] and the [`UnpackMetadata`] of its envelope. The
+//! messages it returns are packed and sent by the node, so a handler that
+//! answers requests only has to build the replies.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use async_trait::async_trait;
+//! use serde_json::json;
+//! use tap_didcomm_core::Message;
+//! use tap_didcomm_node::error::Result;
+//! use tap_didcomm_node::handler::{HandlerContext, MessageHandler, UnpackMetadata};
+//!
+//! struct Ping;
+//!
+//! #[async_trait]
+//! impl MessageHandler for Ping {
+//!     async fn handle(
+//!         &self,
+//!         _ctx: HandlerContext<'_>,
+//!         msg: Message,
+//!         _meta: UnpackMetadata,
+//!     ) -> Result<Vec<Message>> {
+//!         Ok(vec![msg.reply("https://didcomm.org/trust-ping/2.0/ping-response", json!({}))?])
+//!     }
+//! }
+//! ```
+
+use async_trait::async_trait;
+use std::sync::Arc;
+use tap_didcomm_core::{Message, PackingType, UnpackedMessage};
+
+use crate::{error::Result, node::DIDCommNode, thread::ThreadTracker};
+
+/// How a received message was packed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnpackMetadata {
+    /// The packing of the envelope, or `None` for a plaintext message
+    pub packing: Option<PackingType>,
+
+    /// The key ID the envelope was authcrypted by, if it was authcrypted
+    pub authenticated_sender: Option<String>,
+
+    /// The key ID whose signature over the message was verified, if it was signed
+    pub signed_by: Option<String>,
+
+    /// The size of the envelope in bytes
+    pub envelope_size: usize,
+
+    /// The Unix timestamp at which the message was received
+    pub received_at: u64,
+}
+
+impl UnpackMetadata {
+    /// Creates the metadata of a message unpacked from an envelope
+    ///
+    /// # Arguments
+    ///
+    /// * `unpacked` - The unpacked message and its protection
+    /// * `envelope_size` - The size of the envelope in bytes
+    /// * `received_at` - The Unix timestamp at which it was received
+    #[must_use]
+    pub fn new(unpacked: &UnpackedMessage, envelope_size: usize, received_at: u64) -> Self {
+        Self {
+            packing: unpacked.packing,
+            authenticated_sender: unpacked.authenticated_sender.clone(),
+            signed_by: unpacked.signed_by.clone(),
+            envelope_size,
+            received_at,
+        }
+    }
+
+    /// Gets the media type of the envelope
+    #[must_use]
+    pub fn media_type(&self) -> &'static str {
+        self.packing
+            .map_or("application/didcomm-plain+json", PackingType::media_type)
+    }
+
+    /// Gets the DID authenticated as the sender of a message by its envelope
+    /// or signature.
+    ///
+    /// Only this DID can be trusted. It is `None` for plaintext and
+    /// anoncrypted messages, whatever their `from` claims.
+    #[must_use]
+    pub fn sender(&self) -> Option<&str> {
+        self.authenticated_sender
+            .as_deref()
+            .or(self.signed_by.as_deref())
+            .map(|kid| kid.split_once('#').map_or(kid, |(did, _)| did))
+    }
+
+    /// Gets the sender a message claims in its `from`.
+    ///
+    /// This is not verified unless the envelope authenticates a sender, in
+    /// which case it is the same DID as [`UnpackMetadata::sender`], so it
+    /// must not be used for trust decisions.
+    #[must_use]
+    pub fn claimed_sender(msg: &Message) -> Option<&str> {
+        msg.from.as_deref()
+    }
+
+    /// Gets the `from` of a message if the envelope authcrypted it by that
+    /// sender.
+    ///
+    /// Protocols that act on behalf of the sender, such as mediation and
+    /// pickup, must only trust this DID.
+    #[must_use]
+    pub fn authcrypted_from<'a>(&self, msg: &'a Message) -> Option<&'a str> {
+        let from = msg.from.as_deref()?;
+        let sender = self.authenticated_sender.as_deref()?;
+        let sender = sender.split_once('#').map_or(sender, |(did, _)| did);
+        (sender == from).then_some(from)
+    }
+}
+
+/// What a handler can use of the node it runs in
+#[derive(Clone, Copy)]
+pub struct HandlerContext<'a> {
+    node: &'a DIDCommNode,
+}
+
+impl<'a> HandlerContext<'a> {
+    /// Creates a context for handlers running in `node`
+    #[must_use]
+    pub fn new(node: &'a DIDCommNode) -> Self {
+        Self { node }
+    }
+
+    /// Gets the node the handler runs in
+    #[must_use]
+    pub fn node(&self) -> &'a DIDCommNode {
+        self.node
+    }
+
+    /// Gets the DID the node acts as, if it is configured
+    #[must_use]
+    pub fn identity(&self) -> Option<&'a str> {
+        self.node.config().did.as_deref()
+    }
+
+    /// Gets the messages received and sent by the node, grouped by thread
+    #[must_use]
+    pub fn threads(&self) -> Arc<ThreadTracker> {
+        self.node.threads()
+    }
+
+    /// Sends a message other than a reply with the node.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be packed or delivered
+    pub async fn send(&self, message: &Message, packing: PackingType) -> Result<()> {
+        self.node.send(message, packing).await.map(drop)
+    }
+}
+
+/// A handler for received messages
+#[async_trait]
+pub trait MessageHandler: Send + Sync {
+    /// Handles a message.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - Access to the node the handler runs in
+    /// * `msg` - The unpacked message
+    /// * `meta` - How the message was packed
+    ///
+    /// # Returns
+    ///
+    /// Messages for the node to pack and send, typically replies
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be handled
+    async fn handle(
+        &self,
+        ctx: HandlerContext<'_>,
+        msg: Message,
+        meta: UnpackMetadata,
+    ) -> Result<Vec<Message>>;
+}
+
+#[async_trait]
+impl<T: MessageHandler + ?Sized> MessageHandler for Arc<T> {
+    async fn handle(
+        &self,
+        ctx: HandlerContext<'_>,
+        msg: Message,
+        meta: UnpackMetadata,
+    ) -> Result<Vec<Message>> {
+        (**self).handle(ctx, msg, meta).await
+    }
+}
+
+/// Gets the packing the node sends a handler's message with.
+///
+/// Messages with a sender are authcrypted and anonymous ones anoncrypted.
+#[must_use]
+pub fn reply_packing(message: &Message) -> PackingType {
+    if message.from.is_some() {
+        PackingType::AuthcryptV2
+    } else {
+        PackingType::AnonV2
+    }
+}
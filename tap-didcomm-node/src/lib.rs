@@ -1,79 +1,45 @@
-//! Node.js optimized `DIDComm` v2 implementation.
+//! `DIDComm` v2 node built on `tap-didcomm-core`.
 //!
-//! This crate provides Node.js-specific optimizations and bindings for the `DIDComm` v2
-//! protocol implementation. It builds on top of `tap-didcomm-core` and adds Node.js-specific
-//! features and optimizations.
+//! This crate runs a `DIDComm` node: it receives and unpacks envelopes, routes
+//! the messages to handlers and packs and delivers the messages it sends. It
+//! builds on top of `tap-didcomm-core` for packing and plugins.
 //!
 //! # Features
 //!
-//! - Node.js-optimized WASM bindings
-//! - Integration with Node.js crypto modules
-//! - Node.js-specific memory management
-//! - Async/await support through Node.js promises
-//! - TypeScript type definitions
+//! - Handler routing by message type, protocol or pattern
+//! - Inbound and outbound middleware, policy and rate limits
+//! - Delivery over pluggable transports with retries, an outbox and a
+//!   dead-letter queue
+//! - Mediation, forwarding and message pickup
+//! - Threads, message storage and node events
 //!
 //! # Architecture
 //!
-//! The crate extends the core implementation with:
-//! - Node.js-specific plugin implementations
-//! - Optimized memory management for Node.js
-//! - Integration with Node.js native modules
-//! - Custom error handling for Node.js
+//! The crate is organized into these main modules:
+//! - `node`: The `DIDCommNode` and its configuration
+//! - `actor`: Channel-based handlers for received messages
+//! - `dispatch`: Endpoint resolution and delivery of packed envelopes
+//! - `error`: Error types and handling
 //!
 //! # Examples
 //!
 //! ```rust,no_run
-//! use tap_didcomm_node::{NodePlugin, Message, PackingType};
-//! use wasm_bindgen::prelude::*;
-//!
-//! #[wasm_bindgen]
-//! pub async fn pack_message(
-//!     message: &JsValue,
-//!     plugin: &NodePlugin,
-//!     packing: PackingType,
-//! ) -> Result<JsValue, JsError> {
-//!     let message: Message = serde_wasm_bindgen::from_value(message.clone())?;
-//!     let packed = plugin.pack_message(&message, packing).await?;
-//!     Ok(serde_wasm_bindgen::to_value(&packed)?)
+//! use std::sync::Arc;
+//! use tap_didcomm_node::{mock::MockPlugin, DIDCommNode, NodeConfig};
+//!
+//! async fn example() -> tap_didcomm_node::Result<()> {
+//!     let node = Arc::new(DIDCommNode::try_new(NodeConfig::default(), MockPlugin::new())?);
+//!     let handle = node.start()?;
+//!     handle.shutdown().await;
+//!     Ok(())
 //! }
 //! ```
 //!
-//! # Node.js Usage
-//!
-//! ```typescript
-//! import { NodePlugin, Message, PackingType } from '@tap-didcomm/node';
-//!
-//! const plugin = new NodePlugin();
-//! const message = {
-//!   id: 'msg-1',
-//!   type: 'example/1.0',
-//!   body: { text: 'Hello DIDComm!' }
-//! };
-//!
-//! const packed = await plugin.packMessage(message, PackingType.AUTHCRYPT);
-//! ```
-//!
-//! # Memory Management
-//!
-//! The crate implements custom memory management optimized for Node.js:
-//! - Efficient buffer handling
-//! - Automatic memory cleanup
-//! - Integration with Node.js garbage collection
-//!
-//! # Error Handling
-//!
-//! Errors are converted to JavaScript errors with:
-//! - Proper stack traces
-//! - Error codes
-//! - Descriptive messages
-//! - TypeScript type definitions
-//!
 //! # Security Considerations
 //!
-//! - Use Node.js's secure random number generator
+//! - Only trust the authenticated sender of a message, never its `from`
 //! - Properly handle memory containing sensitive data
 //! - Use appropriate key storage solutions
-//! - Follow Node.js security best practices
 //! - Keep dependencies up to date
 
 #![deny(missing_docs)]
@@ -82,45 +48,57 @@
 #![deny(clippy::pedantic)]
 #![allow(clippy::module_name_repetitions)]
 
-use wasm_bindgen::prelude::*;
+pub mod actor;
+pub mod dispatch;
+pub mod error;
+pub mod mock;
+pub mod node;
 
-mod error;
-mod plugin;
-mod types;
-mod utils;
+pub mod blob;
+pub mod coordinate_mediation;
+pub mod dead_letter;
+pub mod events;
+pub mod handler;
+pub mod lifecycle;
+pub mod limits;
+pub mod mediator;
+pub mod middleware;
+pub mod outbox;
+pub mod pickup;
+pub mod policy;
+pub mod replay;
+pub mod router;
+pub mod routing;
+pub mod store;
+pub mod supervisor;
+pub mod tenant;
+pub mod thread;
+pub mod transport;
 
+pub use actor::{HandlerHandle, HandlerRegistry};
+pub use blob::{AttachmentResolver, BlobStore, FileSystemBlobStore, MemoryBlobStore};
+pub use dead_letter::{DeadLetter, DeadLetterQueue, MemoryDeadLetterQueue};
+pub use dispatch::{Dispatcher, RetryPolicy, SendResult};
 pub use error::{Error, Result};
-pub use plugin::NodePlugin;
-pub use types::*;
-
-/// Initialize the WASM module with the given memory configuration.
-#[wasm_bindgen]
-pub async fn initialize(memory: &JsValue) -> Result<()> {
-    utils::set_panic_hook();
-    // Initialize memory and return
-    Ok(())
-}
-
-/// Pack a message using the given plugin and packing type.
-#[wasm_bindgen]
-pub async fn pack_message(
-    message: &JsValue,
-    plugin: &NodePlugin,
-    packing: PackingType,
-) -> Result<JsValue> {
-    let message: Message = serde_wasm_bindgen::from_value(message.clone())?;
-    let packed = plugin.pack_message(&message, packing).await?;
-    Ok(serde_wasm_bindgen::to_value(&packed)?)
-}
-
-/// Unpack a message using the given plugin.
-#[wasm_bindgen]
-pub async fn unpack_message(
-    packed: &JsValue,
-    plugin: &NodePlugin,
-    recipient: Option<String>,
-) -> Result<JsValue> {
-    let packed: PackedMessage = serde_wasm_bindgen::from_value(packed.clone())?;
-    let unpacked = plugin.unpack_message(&packed, recipient.as_deref()).await?;
-    Ok(serde_wasm_bindgen::to_value(&unpacked)?)
-}
+pub use events::{EventBus, EventKind, NodeEvent};
+pub use handler::{HandlerContext, MessageHandler, UnpackMetadata};
+pub use lifecycle::{LifecycleConfig, Listener, NodeHandle, ShutdownReport};
+pub use limits::{LimitsConfig, RateLimit, RateLimiter};
+pub use mediator::{Mediator, MediatorConfig, MessageQueue};
+pub use middleware::{ExpiryMiddleware, Flow, LoggingMiddleware, Middleware};
+pub use node::{AttachmentConfig, DIDCommNode, NodeConfig};
+#[cfg(feature = "sqlite")]
+pub use outbox::SqliteOutbox;
+pub use outbox::{MemoryOutbox, Outbox, OutboxConfig, OutboxStatus};
+pub use pickup::{PickupClient, PickupService};
+pub use policy::{PolicyConfig, PolicyRule, Protection};
+pub use replay::{DuplicatePolicy, FileReplayStore, MemoryReplayStore, ReplayConfig, ReplayStore};
+pub use router::{HandlerPattern, HandlerTarget, ProblemReporter, Router};
+pub use routing::{RoutedMessage, RoutingConfig};
+#[cfg(feature = "sqlite")]
+pub use store::SqliteMessageStore;
+pub use store::{MemoryMessageStore, MessageStore, StoreKey, StoredMessage};
+pub use supervisor::{spawn_supervised, HandlerConfig, SupervisedHandler};
+pub use tenant::{Tenant, TenantHost, TenantMetrics};
+pub use thread::{Direction, ThreadConfig, ThreadEntry, ThreadTracker};
+pub use transport::{MemoryListener, MemoryNetwork, MemoryTransport, Transport};
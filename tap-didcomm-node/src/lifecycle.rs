@@ -0,0 +1,259 @@
+//! Starting and stopping a node.
+//!
+//! [`crate::DIDCommNode::start`] spawns the node's background tasks and
+//! returns a [`NodeHandle`]:
+//!
+//! - Every [`Listener`], such as a transport receiving messages for the node
+//! - The outbox worker, which delivers envelopes left in the outbox
+//! - A cleanup task, which prunes expired keys from the replay store and old
+//!   messages from the message store
+//!
+//! [`NodeHandle::shutdown`] stops the node gracefully. It stops accepting
+//! messages, stops the background tasks, then waits for messages being
+//! received and for outbox deliveries to finish, up to a deadline. The
+//! [`ShutdownReport`] lists the envelopes that were left undelivered, which
+//! a persistent outbox delivers once the node starts again.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! use tap_didcomm_node::{mock::MockPlugin, DIDCommNode, NodeConfig};
+//! use std::sync::Arc;
+//!
+//! # async fn example() -> tap_didcomm_node::error::Result<()> {
+//! let node = Arc::new(DIDCommNode::new(NodeConfig::default(), MockPlugin));
+//! let handle = node.start()?;
+//!
+//! // ... receive and send messages ...
+//!
+//! let report = handle.shutdown().await;
+//! assert!(report.undelivered.is_empty());
+//! # Ok(())
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::{error::Result, node::DIDCommNode, outbox::OutboxEntry};
+
+/// Configuration for the node's background tasks and shutdown
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LifecycleConfig {
+    /// How often expired keys are pruned from the replay store, in seconds
+    pub cleanup_interval_secs: u64,
+
+    /// How long messages are kept in the message store, in seconds, or
+    /// `None` to keep them until they are removed
+    pub message_retention_secs: Option<u64>,
+
+    /// How long shutdown waits for work in progress, in milliseconds
+    pub shutdown_deadline_ms: u64,
+}
+
+impl Default for LifecycleConfig {
+    fn default() -> Self {
+        Self {
+            cleanup_interval_secs: 300,
+            message_retention_secs: None,
+            shutdown_deadline_ms: 30_000,
+        }
+    }
+}
+
+/// Tells background tasks that the node is shutting down
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    rx: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub(crate) fn new(rx: watch::Receiver<bool>) -> Self {
+        Self { rx }
+    }
+
+    /// Whether shutdown has started
+    #[must_use]
+    pub fn is_shutdown(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Waits until shutdown starts
+    pub async fn wait(&mut self) {
+        // An error means the handle is gone, which also stops the node
+        let _ = self.rx.wait_for(|shutdown| *shutdown).await;
+    }
+}
+
+/// A source of messages for the node, such as a server transport
+///
+/// Listeners are started by [`crate::DIDCommNode::start`] and should pass
+/// what they receive to [`crate::DIDCommNode::receive`] until shutdown.
+#[async_trait]
+pub trait Listener: Send + Sync {
+    /// Receives messages for `node` until `shutdown` starts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the listener fails
+    async fn listen(&self, node: Arc<DIDCommNode>, shutdown: Shutdown) -> Result<()>;
+}
+
+/// What was left when a node shut down
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownReport {
+    /// Outbox entries that were not delivered before the deadline
+    pub undelivered: Vec<OutboxEntry>,
+
+    /// The number of messages still being received at the deadline
+    pub receiving: usize,
+
+    /// Whether the deadline passed before all work finished
+    pub timed_out: bool,
+}
+
+impl ShutdownReport {
+    /// Whether all work finished before the node stopped
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.undelivered.is_empty() && self.receiving == 0 && !self.timed_out
+    }
+}
+
+/// A handle to a started node
+pub struct NodeHandle {
+    node: Arc<DIDCommNode>,
+    shutdown: watch::Sender<bool>,
+    tasks: Vec<(String, JoinHandle<()>)>,
+}
+
+impl NodeHandle {
+    pub(crate) fn new(
+        node: Arc<DIDCommNode>,
+        shutdown: watch::Sender<bool>,
+        tasks: Vec<(String, JoinHandle<()>)>,
+    ) -> Self {
+        Self {
+            node,
+            shutdown,
+            tasks,
+        }
+    }
+
+    /// Gets the node
+    #[must_use]
+    pub fn node(&self) -> &Arc<DIDCommNode> {
+        &self.node
+    }
+
+    /// Shuts the node down within the configured deadline
+    ///
+    /// # Returns
+    ///
+    /// What was left undone when the node stopped
+    pub async fn shutdown(self) -> ShutdownReport {
+        let deadline = Duration::from_millis(self.node.config().lifecycle.shutdown_deadline_ms);
+        self.shutdown_within(deadline).await
+    }
+
+    /// Shuts the node down, waiting up to `deadline` for work in progress.
+    ///
+    /// Background tasks that have not stopped by the deadline are aborted.
+    ///
+    /// # Returns
+    ///
+    /// What was left undone when the node stopped
+    pub async fn shutdown_within(self, deadline: Duration) -> ShutdownReport {
+        info!("Shutting down DIDComm node");
+        self.node.stop_accepting();
+        let _ = self.shutdown.send(true);
+
+        let node = Arc::clone(&self.node);
+        let mut tasks = self.tasks;
+        let drained = tokio::time::timeout(deadline, async {
+            for (name, task) in &mut tasks {
+                if let Err(e) = task.await {
+                    warn!("Background task {name} failed: {e}");
+                }
+            }
+            node.drain().await;
+        })
+        .await;
+
+        for (name, task) in &tasks {
+            if !task.is_finished() {
+                warn!("Aborting background task {name}");
+                task.abort();
+            }
+        }
+
+        let mut report = self.node.shutdown_report().await;
+        report.timed_out = drained.is_err();
+        if !report.is_clean() {
+            warn!(
+                "Node stopped with {} undelivered envelopes and {} messages being received",
+                report.undelivered.len(),
+                report.receiving
+            );
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::Error,
+        mock::MockPlugin,
+        node::NodeConfig,
+        routing::Route,
+        transport::{MemoryListener, MemoryNetwork, MemoryTransport, Transport},
+    };
+    use base64::Engine;
+    use serde_json::json;
+    use tap_didcomm_core::Message;
+
+    #[tokio::test]
+    async fn test_start_and_shutdown() {
+        let network = Arc::new(MemoryNetwork::new());
+        let mut node = DIDCommNode::new(NodeConfig::default(), MockPlugin::new());
+        node.add_listener(MemoryListener::new(Arc::clone(&network), "bob"));
+        let node = Arc::new(node);
+        let handle = node.start().unwrap();
+        assert!(matches!(node.start(), Err(Error::Config(_))));
+
+        let message = Message::new("test", json!({})).unwrap();
+        let route = Route {
+            endpoint: "mem://bob".to_string(),
+            envelope: base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .encode(serde_json::to_vec(&message).unwrap()),
+            media_type: "application/didcomm-plain+json".to_string(),
+        };
+        let transport = MemoryTransport::new(Arc::clone(&network));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            // The listener opens its inbox once its task runs
+            while transport.deliver(&route).await.is_err() {
+                tokio::task::yield_now().await;
+            }
+            while node.threads().get(message.id.as_str()).is_empty() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let report = handle.shutdown_within(Duration::from_secs(5)).await;
+        assert!(report.is_clean());
+        assert!(matches!(
+            node.receive(route.envelope.as_bytes()).await,
+            Err(Error::Busy(_))
+        ));
+        // The listener closed its inbox
+        assert!(transport.deliver(&route).await.is_err());
+    }
+}
@@ -0,0 +1,325 @@
+//! Rate limits and quotas for received messages.
+//!
+//! A peer can flood a node with messages, or send messages that are cheap to
+//! produce but expensive to process. [`LimitsConfig`] bounds both:
+//!
+//! - Rate limits are token buckets kept by a [`RateLimiter`]. Transports such
+//!   as the web server limit each source IP address with
+//!   [`crate::DIDCommNode::check_source`] before unpacking anything, and the
+//!   node limits each authenticated sender DID once a message is unpacked.
+//!   The `from` of a plaintext or anonymous message is never used as a key,
+//!   since anyone can claim it.
+//! - Quotas bound the number of recipients of a message and the number and
+//!   size of its attachments, inline or linked, in addition to
+//!   [`crate::NodeConfig::max_message_size`] for the envelope.
+//!
+//! Messages over a rate limit are rejected with `Error::RateLimited` and
+//! messages over a quota with `Error::QuotaExceeded`.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use std::time::{Duration, Instant};
+//! use tap_didcomm_node::limits::{RateLimit, RateLimiter};
+//!
+//! let limiter = RateLimiter::new(RateLimit::new(2, 1.0));
+//! let now = Instant::now();
+//!
+//! assert!(limiter.try_acquire_at("did:example:alice", now));
+//! assert!(limiter.try_acquire_at("did:example:alice", now));
+//! assert!(!limiter.try_acquire_at("did:example:alice", now));
+//! assert!(limiter.try_acquire_at("did:example:bob", now));
+//!
+//! // A token is added every second
+//! assert!(limiter.try_acquire_at("did:example:alice", now + Duration::from_secs(1)));
+//! ```
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::Instant;
+use tap_didcomm_core::Message;
+
+use crate::error::{Error, Result};
+
+/// The rate of a token bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// The number of messages that can be received at once
+    pub burst: u32,
+
+    /// The number of messages per second that can be received over time
+    pub per_second: f64,
+}
+
+impl RateLimit {
+    /// Creates a rate limit
+    ///
+    /// # Arguments
+    ///
+    /// * `burst` - The number of messages that can be received at once
+    /// * `per_second` - The sustained number of messages per second
+    #[must_use]
+    pub fn new(burst: u32, per_second: f64) -> Self {
+        Self { burst, per_second }
+    }
+}
+
+/// Rate limits and quotas for received messages
+#[derive(Debug, Clone, Default)]
+pub struct LimitsConfig {
+    /// The rate of messages accepted from each authenticated sender DID, or
+    /// `None` for no limit. Messages without an authenticated sender are
+    /// only limited by their source.
+    pub per_sender: Option<RateLimit>,
+
+    /// The rate of messages accepted from each source IP address, or `None`
+    /// for no limit
+    pub per_source: Option<RateLimit>,
+
+    /// The maximum number of recipients in a message's `to`
+    pub max_recipients: Option<usize>,
+
+    /// The maximum number of attachments of a message
+    pub max_attachments: Option<usize>,
+
+    /// The maximum total size in bytes of a message's attachments.
+    ///
+    /// Linked attachments count with their `byte_count`, which their fetched
+    /// content must match, so with this quota set a linked attachment
+    /// without a `byte_count` is rejected.
+    pub max_attachment_bytes: Option<usize>,
+}
+
+impl LimitsConfig {
+    /// Checks a received message against the quotas.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The unpacked message
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::QuotaExceeded` for the first quota the message exceeds
+    pub fn check_quotas(&self, msg: &Message) -> Result<()> {
+        let recipients = msg.to.as_ref().map_or(0, Vec::len);
+        check_quota("recipients", recipients, self.max_recipients)?;
+
+        let attachments = msg.attachments.as_deref().unwrap_or_default();
+        check_quota("attachments", attachments.len(), self.max_attachments)?;
+
+        if self.max_attachment_bytes.is_some() {
+            let mut bytes: usize = 0;
+            for attachment in attachments {
+                let data = &attachment.data;
+                // Base64 holds 3 bytes in every 4 characters
+                let inline = data.base64.as_ref().map_or(0, |b64| b64.len() / 4 * 3)
+                    + data.json.as_ref().map_or(0, |json| json.to_string().len());
+                let linked = match (&data.links, attachment.byte_count) {
+                    (None, _) => 0,
+                    (Some(_), Some(byte_count)) => {
+                        usize::try_from(byte_count).unwrap_or(usize::MAX)
+                    }
+                    (Some(_), None) => {
+                        return Err(Error::QuotaExceeded(format!(
+                            "Linked attachment {} has no byte_count",
+                            attachment.id
+                        )))
+                    }
+                };
+                bytes = bytes.saturating_add(inline).saturating_add(linked);
+            }
+            check_quota("attachment bytes", bytes, self.max_attachment_bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fails if `value` is over `max`
+pub(crate) fn check_quota(name: &str, value: usize, max: Option<usize>) -> Result<()> {
+    match max {
+        Some(max) if value > max => Err(Error::QuotaExceeded(format!(
+            "{value} {name} exceeds the limit of {max}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// The tokens of one key
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets keyed by sender, source address or anything else
+#[derive(Debug)]
+pub struct RateLimiter<K> {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    /// Creates a limiter in which every key has a bucket of `limit`
+    #[must_use]
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Gets the limit of each key
+    #[must_use]
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Takes a token for `key` now.
+    ///
+    /// # Returns
+    ///
+    /// Whether a token was available
+    pub fn try_acquire<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.try_acquire_at(key, Instant::now())
+    }
+
+    /// Takes a token for `key` at `now`.
+    ///
+    /// # Returns
+    ///
+    /// Whether a token was available
+    pub fn try_acquire_at<Q>(&self, key: &Q, now: Instant) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if !buckets.contains_key(key) {
+            let bucket = Bucket {
+                tokens: f64::from(self.limit.burst),
+                updated: now,
+            };
+            buckets.insert(key.to_owned(), bucket);
+        }
+        let Some(bucket) = buckets.get_mut(key) else {
+            return false;
+        };
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens =
+            (bucket.tokens + elapsed * self.limit.per_second).min(f64::from(self.limit.burst));
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Forgets the keys whose buckets have refilled by `now`, as they behave
+    /// like keys that were never seen.
+    ///
+    /// # Returns
+    ///
+    /// The number of keys forgotten
+    pub fn prune(&self, now: Instant) -> usize {
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let before = buckets.len();
+        let limit = self.limit;
+        buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * limit.per_second < f64::from(limit.burst)
+        });
+        before - buckets.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+    use tap_didcomm_core::types::{Attachment, AttachmentData};
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::<String>::new(RateLimit::new(2, 0.5));
+        let start = Instant::now();
+
+        assert!(limiter.try_acquire_at("alice", start));
+        assert!(limiter.try_acquire_at("alice", start));
+        assert!(!limiter.try_acquire_at("alice", start));
+        assert!(!limiter.try_acquire_at("alice", start + Duration::from_secs(1)));
+        assert!(limiter.try_acquire_at("alice", start + Duration::from_secs(2)));
+        assert!(limiter.try_acquire_at("bob", start));
+
+        // Refilled buckets are forgotten
+        assert_eq!(limiter.prune(start + Duration::from_secs(3)), 1);
+        assert_eq!(limiter.prune(start + Duration::from_secs(6)), 1);
+        assert!(limiter.try_acquire_at("alice", start + Duration::from_secs(6)));
+    }
+
+    #[test]
+    fn test_quotas() {
+        let limits = LimitsConfig {
+            max_recipients: Some(2),
+            max_attachments: Some(2),
+            max_attachment_bytes: Some(10),
+            ..LimitsConfig::default()
+        };
+        let attachment = |base64: &str| Attachment {
+            id: "a".to_string(),
+            data: AttachmentData {
+                base64: Some(base64.to_string()),
+                ..AttachmentData::default()
+            },
+            ..Attachment::default()
+        };
+
+        let msg = Message::new("test", json!({}))
+            .unwrap()
+            .to(["did:example:alice", "did:example:bob"])
+            .with_attachment(attachment("AAAAAAAA"));
+        assert!(limits.check_quotas(&msg).is_ok());
+
+        let too_many = msg.clone().to(["did:example:a"; 3]);
+        assert!(matches!(
+            limits.check_quotas(&too_many),
+            Err(Error::QuotaExceeded(_))
+        ));
+        let too_large = msg.clone().with_attachment(attachment("AAAAAAAA"));
+        assert!(limits.check_quotas(&too_large).is_err());
+
+        // Linked attachments count with their declared size
+        let linked = |byte_count: Option<u64>| Attachment {
+            id: "b".to_string(),
+            byte_count,
+            data: AttachmentData::links(["https://example.com/b"], "zhash".to_string()),
+            ..Attachment::default()
+        };
+        let msg = Message::new("test", json!({}))
+            .unwrap()
+            .with_attachment(attachment("AAAA"));
+        assert!(limits
+            .check_quotas(&msg.clone().with_attachment(linked(Some(7))))
+            .is_ok());
+        for byte_count in [Some(8), None] {
+            assert!(matches!(
+                limits.check_quotas(&msg.clone().with_attachment(linked(byte_count))),
+                Err(Error::QuotaExceeded(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,671 @@
+//! Mediator role for `DIDComm` nodes.
+//!
+//! A mediator receives `forward` messages on behalf of agents that cannot be
+//! reached directly, such as mobile wallets or agents behind a firewall. For
+//! each forward addressed to it, the mediator looks up the `next` recipient
+//! and either:
+//!
+//! - queues the attached envelope, if the recipient is one of its clients, so
+//!   the client can pick it up later; or
+//! - relays the envelope to the `DIDCommMessaging` endpoint of the next hop,
+//!   if the recipient was registered to be relayed to. The node delivers
+//!   relayed envelopes through its outbox, with retries and dead letters.
+//!
+//! Forwards for any other recipient are rejected, so the mediator cannot be
+//! used as an open relay. Queues are bounded per recipient and envelopes
+//! that are not picked up expire.
+//!
+//! A relayed envelope can optionally be re-wrapped in fresh anoncrypted
+//! forward messages for the routing keys of the next hop, so the ciphertext
+//! going out cannot be correlated with the ciphertext that came in.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use tap_didcomm_node::mediator::MessageQueue;
+//!
+//! # fn example() -> tap_didcomm_node::error::Result<()> {
+//! let queue = MessageQueue::new();
+//! let id = queue.push("did:example:bob#key-1", "envelope".to_string(), 1_000)?;
+//!
+//! let pending = queue.pending("did:example:bob#key-1", 10);
+//! assert_eq!(pending[0].envelope, "envelope");
+//! assert_eq!(queue.remove("did:example:bob#key-1", &[id]), 1);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, RwLock};
+use tap_didcomm_core::{DIDCommPlugin, Message, MessageId, PackingType};
+use tokio::sync::broadcast;
+use tracing::debug;
+
+use crate::{
+    dispatch::resolve_endpoints,
+    error::{Error, Result},
+    routing::{parse_forward, wrap_in_forwards, Route},
+};
+
+/// Configuration for the mediator role.
+#[derive(Debug, Clone, Default)]
+pub struct MediatorConfig {
+    /// Whether the node handles `forward` messages addressed to it
+    pub enabled: bool,
+
+    /// Whether relayed envelopes are re-wrapped in fresh anoncrypted forward
+    /// messages for the routing keys of the next hop
+    pub rewrap: bool,
+
+    /// Limits on the envelopes queued for each recipient
+    pub queue: QueueLimits,
+}
+
+/// Limits on the envelopes queued for each recipient.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueLimits {
+    /// The most envelopes queued for a recipient
+    pub max_messages: usize,
+
+    /// The most bytes of envelopes queued for a recipient
+    pub max_bytes: usize,
+
+    /// How long an envelope is kept if it is not picked up, in seconds
+    pub ttl: u64,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        Self {
+            max_messages: 1_000,
+            max_bytes: 64 * 1024 * 1024, // 64 MiB
+            ttl: 7 * 24 * 60 * 60,       // 1 week
+        }
+    }
+}
+
+/// An envelope waiting to be picked up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedMessage {
+    /// The ID of the queued message
+    pub id: String,
+    /// The packed envelope
+    pub envelope: String,
+    /// Unix timestamp when the envelope was queued
+    pub queued_at: u64,
+}
+
+/// Envelopes waiting to be picked up, per recipient.
+///
+/// The queue is safe to share between the node and its handlers.
+#[derive(Debug)]
+pub struct MessageQueue {
+    queues: RwLock<HashMap<String, VecDeque<QueuedMessage>>>,
+    /// Announces the recipient of each queued envelope
+    queued: broadcast::Sender<String>,
+    /// Limits on each recipient's envelopes
+    limits: QueueLimits,
+}
+
+impl Default for MessageQueue {
+    fn default() -> Self {
+        Self::with_limits(QueueLimits::default())
+    }
+}
+
+impl MessageQueue {
+    /// Creates a new empty queue with the default limits
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new empty queue with the given limits
+    #[must_use]
+    pub fn with_limits(limits: QueueLimits) -> Self {
+        Self {
+            queues: RwLock::default(),
+            queued: broadcast::channel(256).0,
+            limits,
+        }
+    }
+
+    /// Subscribes to the recipients of newly queued envelopes
+    ///
+    /// Used to deliver envelopes to recipients that are connected live.
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.queued.subscribe()
+    }
+
+    /// Gets the recipients that have queued envelopes
+    #[must_use]
+    pub fn recipients(&self) -> Vec<String> {
+        self.queues
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Queues an envelope for a recipient
+    ///
+    /// Expired envelopes of the recipient are dropped first.
+    ///
+    /// # Arguments
+    ///
+    /// * `recipient` - The recipient's DID or key ID
+    /// * `envelope` - The packed envelope
+    /// * `now` - The current Unix timestamp
+    ///
+    /// # Returns
+    ///
+    /// The ID of the queued message
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::QuotaExceeded` if the recipient's queue is full
+    pub fn push(&self, recipient: &str, envelope: String, now: u64) -> Result<String> {
+        let id = MessageId::random().as_str().to_string();
+        {
+            let mut queues = self
+                .queues
+                .write()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            let queue = queues.entry(recipient.to_string()).or_default();
+            let expires = now.saturating_sub(self.limits.ttl);
+            queue.retain(|queued| queued.queued_at >= expires);
+
+            let bytes: usize = queue.iter().map(|queued| queued.envelope.len()).sum();
+            if queue.len() >= self.limits.max_messages
+                || bytes.saturating_add(envelope.len()) > self.limits.max_bytes
+            {
+                return Err(Error::QuotaExceeded(format!(
+                    "The queue for {recipient} is full"
+                )));
+            }
+            queue.push_back(QueuedMessage {
+                id: id.clone(),
+                envelope,
+                queued_at: now,
+            });
+        }
+        // Nobody may be listening, which is fine
+        let _ = self.queued.send(recipient.to_string());
+        Ok(id)
+    }
+
+    /// Drops envelopes that have been queued for longer than the TTL
+    ///
+    /// # Returns
+    ///
+    /// The number of dropped envelopes
+    pub fn prune(&self, now: u64) -> usize {
+        let expires = now.saturating_sub(self.limits.ttl);
+        let mut queues = self
+            .queues
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut pruned = 0;
+        queues.retain(|_, queue| {
+            let before = queue.len();
+            queue.retain(|queued| queued.queued_at >= expires);
+            pruned += before - queue.len();
+            !queue.is_empty()
+        });
+        pruned
+    }
+
+    /// Gets up to `limit` queued envelopes for a recipient, oldest first,
+    /// without removing them
+    ///
+    /// # Arguments
+    ///
+    /// * `recipient` - The recipient's DID or key ID
+    /// * `limit` - The maximum number of envelopes to return
+    #[must_use]
+    pub fn pending(&self, recipient: &str, limit: usize) -> Vec<QueuedMessage> {
+        self.queues
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(recipient)
+            .map(|queue| queue.iter().take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Removes queued envelopes that have been picked up
+    ///
+    /// # Arguments
+    ///
+    /// * `recipient` - The recipient's DID or key ID
+    /// * `ids` - The IDs of the envelopes to remove
+    ///
+    /// # Returns
+    ///
+    /// The number of removed envelopes
+    pub fn remove(&self, recipient: &str, ids: &[String]) -> usize {
+        let mut queues = self
+            .queues
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let Some(queue) = queues.get_mut(recipient) else {
+            return 0;
+        };
+        let before = queue.len();
+        queue.retain(|queued| !ids.contains(&queued.id));
+        let removed = before - queue.len();
+        if queue.is_empty() {
+            queues.remove(recipient);
+        }
+        removed
+    }
+
+    /// Gets the number of queued envelopes for a recipient
+    #[must_use]
+    pub fn count(&self, recipient: &str) -> usize {
+        self.queues
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(recipient)
+            .map_or(0, VecDeque::len)
+    }
+}
+
+/// What the mediator did with a forwarded envelope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForwardOutcome {
+    /// The envelope was queued for pickup by a client
+    Queued {
+        /// The recipient the envelope was queued for
+        recipient: String,
+        /// The ID of the queued message
+        id: String,
+    },
+    /// The envelope is to be relayed to the next hop
+    Relayed {
+        /// The recipient the envelope is relayed for
+        recipient: String,
+        /// The routes to the next hop, to be delivered through the outbox
+        routes: Vec<Route>,
+    },
+}
+
+/// Handles `forward` messages for the clients of a mediator.
+#[derive(Debug, Default)]
+pub struct Mediator {
+    /// Envelopes waiting to be picked up
+    queue: Arc<MessageQueue>,
+
+    /// DIDs and key IDs whose envelopes are queued for pickup
+    recipients: RwLock<HashSet<String>>,
+
+    /// DIDs whose envelopes are delivered to their own endpoints
+    relayed: RwLock<HashSet<String>>,
+}
+
+impl Mediator {
+    /// Creates a new mediator with an empty queue and no clients
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new mediator whose queue has the given limits
+    #[must_use]
+    pub fn with_limits(limits: QueueLimits) -> Self {
+        Self {
+            queue: Arc::new(MessageQueue::with_limits(limits)),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the mediator's message queue
+    #[must_use]
+    pub fn queue(&self) -> Arc<MessageQueue> {
+        Arc::clone(&self.queue)
+    }
+
+    /// Adds a recipient whose envelopes are queued for pickup
+    ///
+    /// # Arguments
+    ///
+    /// * `recipient` - The recipient's DID or key ID
+    pub fn add_recipient(&self, recipient: impl Into<String>) {
+        self.recipients
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(recipient.into());
+    }
+
+    /// Removes a recipient, returning whether it was known
+    ///
+    /// Envelopes already queued for the recipient are kept.
+    pub fn remove_recipient(&self, recipient: &str) -> bool {
+        self.recipients
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .remove(recipient)
+    }
+
+    /// Adds a recipient whose envelopes are delivered to the
+    /// `DIDCommMessaging` endpoints of its DID document
+    ///
+    /// # Arguments
+    ///
+    /// * `recipient` - The recipient's DID
+    pub fn add_relayed_recipient(&self, recipient: impl Into<String>) {
+        self.relayed
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(recipient.into());
+    }
+
+    /// Removes a relayed recipient, returning whether it was known
+    pub fn remove_relayed_recipient(&self, recipient: &str) -> bool {
+        self.relayed
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .remove(recipient)
+    }
+
+    /// Checks whether envelopes for a recipient are delivered to its endpoints
+    #[must_use]
+    pub fn is_relayed(&self, recipient: &str) -> bool {
+        let did = recipient.split('#').next().unwrap_or(recipient);
+        self.relayed
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .contains(did)
+    }
+
+    /// Checks whether envelopes for a recipient are queued for pickup.
+    ///
+    /// A key ID matches if either the key ID or its DID has been added.
+    #[must_use]
+    pub fn is_recipient(&self, recipient: &str) -> bool {
+        let recipients = self
+            .recipients
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let did = recipient.split('#').next().unwrap_or(recipient);
+        recipients.contains(recipient) || recipients.contains(did)
+    }
+
+    /// Handles a `forward` message addressed to the mediator.
+    ///
+    /// An envelope for a client is queued for pickup. An envelope for a
+    /// relayed recipient is not delivered here: the routes to the next hop
+    /// are returned, so that the caller can hand them to its outbox, which
+    /// retries them and keeps undeliverable ones as dead letters.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The unpacked forward message
+    /// * `plugin` - Plugin used to resolve the next hop and re-wrap envelopes
+    /// * `rewrap` - Whether to re-wrap a relayed envelope for the routing keys
+    ///   of the next hop
+    /// * `now` - The current Unix timestamp
+    ///
+    /// # Returns
+    ///
+    /// Whether the envelope was queued or is to be relayed
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The message is not a valid forward message
+    /// - The recipient is neither queued for nor relayed to
+    ///   (`Error::UnknownRecipient`)
+    /// - The recipient's queue is full (`Error::QuotaExceeded`)
+    /// - The envelope cannot be re-wrapped
+    /// - The next hop has no `DIDComm` service
+    pub async fn handle_forward(
+        &self,
+        message: &Message,
+        plugin: &dyn DIDCommPlugin,
+        rewrap: bool,
+        now: u64,
+    ) -> Result<ForwardOutcome> {
+        let (next, envelope) = parse_forward(message)?;
+
+        if self.is_recipient(&next) {
+            let id = self.queue.push(&next, envelope, now)?;
+            debug!("Queued forwarded message {id} for {next}");
+            return Ok(ForwardOutcome::Queued {
+                recipient: next,
+                id,
+            });
+        }
+        if !self.is_relayed(&next) {
+            return Err(Error::UnknownRecipient(format!(
+                "Not forwarding for {next}, which is not a client of this mediator"
+            )));
+        }
+
+        let mut routes = Vec::new();
+        for endpoint in resolve_endpoints(plugin.resolver(), &next).await? {
+            let envelope = if rewrap {
+                wrap_in_forwards(&envelope, &next, &endpoint.routing_keys, plugin).await?
+            } else {
+                envelope.clone()
+            };
+            routes.push(Route {
+                endpoint: endpoint.uri,
+                envelope,
+                media_type: PackingType::AnonV2.media_type().to_string(),
+            });
+        }
+        debug!("Relaying message for {next} over {} routes", routes.len());
+
+        Ok(ForwardOutcome::Relayed {
+            recipient: next,
+            routes,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{mock::MockPlugin, routing::forward_message};
+    use async_trait::async_trait;
+    use serde_json::json;
+    use tap_didcomm_core::{unpack_message, DIDResolver, Encryptor, Signer};
+
+    /// Resolves every DID to a `DIDComm` service behind a mediator
+    pub(crate) struct RelayPlugin(pub(crate) String);
+
+    #[async_trait]
+    impl DIDResolver for RelayPlugin {
+        async fn resolve(&self, did: &str) -> tap_didcomm_core::Result<String> {
+            Ok(json!({
+                "id": did,
+                "service": [{
+                    "id": format!("{did}#didcomm"),
+                    "type": "DIDCommMessaging",
+                    "serviceEndpoint": {
+                        "uri": self.0,
+                        "routingKeys": ["did:example:m2#key-1"]
+                    }
+                }]
+            })
+            .to_string())
+        }
+    }
+
+    #[async_trait]
+    impl Signer for RelayPlugin {
+        async fn sign(&self, message: &[u8], from: &str) -> tap_didcomm_core::Result<Vec<u8>> {
+            MockPlugin.sign(message, from).await
+        }
+
+        async fn verify(
+            &self,
+            message: &[u8],
+            signature: &[u8],
+            from: &str,
+        ) -> tap_didcomm_core::Result<bool> {
+            MockPlugin.verify(message, signature, from).await
+        }
+    }
+
+    #[async_trait]
+    impl Encryptor for RelayPlugin {
+        async fn encrypt(
+            &self,
+            message: &[u8],
+            to: &[&str],
+            from: Option<&str>,
+        ) -> tap_didcomm_core::Result<Vec<u8>> {
+            MockPlugin.encrypt(message, to, from).await
+        }
+
+        async fn decrypt(
+            &self,
+            message: &[u8],
+            recipient: &str,
+        ) -> tap_didcomm_core::Result<Vec<u8>> {
+            MockPlugin.decrypt(message, recipient).await
+        }
+    }
+
+    impl DIDCommPlugin for RelayPlugin {
+        fn resolver(&self) -> &dyn DIDResolver {
+            self
+        }
+
+        fn signer(&self) -> &dyn Signer {
+            self
+        }
+
+        fn encryptor(&self) -> &dyn Encryptor {
+            self
+        }
+    }
+
+    #[test]
+    fn test_message_queue() {
+        let queue = MessageQueue::new();
+        let first = queue.push("did:example:bob", "one".to_string(), 1).unwrap();
+        let second = queue.push("did:example:bob", "two".to_string(), 2).unwrap();
+        queue
+            .push("did:example:carol", "three".to_string(), 3)
+            .unwrap();
+
+        let pending = queue.pending("did:example:bob", 1);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, first);
+
+        assert_eq!(
+            queue.remove("did:example:bob", &[first, "unknown".into()]),
+            1
+        );
+        assert_eq!(queue.count("did:example:bob"), 1);
+        assert_eq!(queue.remove("did:example:bob", &[second]), 1);
+        assert_eq!(queue.count("did:example:bob"), 0);
+        assert_eq!(queue.count("did:example:carol"), 1);
+    }
+
+    #[test]
+    fn test_queue_limits() {
+        let queue = MessageQueue::with_limits(QueueLimits {
+            max_messages: 2,
+            max_bytes: 8,
+            ttl: 100,
+        });
+        queue.push("did:example:bob", "one".to_string(), 0).unwrap();
+        queue
+            .push("did:example:bob", "two".to_string(), 50)
+            .unwrap();
+        assert!(matches!(
+            queue.push("did:example:bob", "three".to_string(), 50),
+            Err(Error::QuotaExceeded(_))
+        ));
+        assert!(matches!(
+            queue.push("did:example:carol", "too large".to_string(), 50),
+            Err(Error::QuotaExceeded(_))
+        ));
+
+        // Expired envelopes make room
+        queue
+            .push("did:example:bob", "four".to_string(), 101)
+            .unwrap();
+        assert_eq!(queue.count("did:example:bob"), 2);
+        assert_eq!(queue.prune(151), 1);
+        assert_eq!(queue.pending("did:example:bob", 10)[0].envelope, "four");
+        assert_eq!(queue.prune(1_000), 1);
+        assert!(queue.recipients().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_forward_is_queued_for_client() {
+        let mediator = Mediator::new();
+        mediator.add_recipient("did:example:bob");
+        assert!(mediator.is_recipient("did:example:bob#key-1"));
+        assert!(!mediator.is_recipient("did:example:carol"));
+
+        let forward = forward_message("ZW52ZWxvcGU", "did:example:bob#key-1").unwrap();
+        let outcome = mediator
+            .handle_forward(&forward, &MockPlugin, false, 1_000)
+            .await
+            .unwrap();
+        let ForwardOutcome::Queued { recipient, id } = outcome else {
+            panic!("forward was not queued");
+        };
+        assert_eq!(recipient, "did:example:bob#key-1");
+
+        let pending = mediator.queue().pending(&recipient, 10);
+        assert_eq!(pending[0].id, id);
+        assert_eq!(pending[0].envelope, "ZW52ZWxvcGU");
+    }
+
+    #[tokio::test]
+    async fn test_forward_for_unknown_recipient_is_rejected() {
+        let mediator = Mediator::new();
+        mediator.add_recipient("did:example:bob");
+
+        let forward = forward_message("ZW52ZWxvcGU", "did:example:carol#key-1").unwrap();
+        let result = mediator
+            .handle_forward(&forward, &MockPlugin, false, 1_000)
+            .await;
+        assert!(matches!(result, Err(Error::UnknownRecipient(_))));
+        assert!(mediator.queue().recipients().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_relayed_forward_is_rewrapped_for_next_hop() {
+        let mediator = Mediator::new();
+        mediator.add_relayed_recipient("did:example:bob");
+
+        let forward = forward_message("ZW52ZWxvcGU", "did:example:bob#key-1").unwrap();
+        let outcome = mediator
+            .handle_forward(
+                &forward,
+                &RelayPlugin("https://m2.example.com".into()),
+                true,
+                1_000,
+            )
+            .await
+            .unwrap();
+        let ForwardOutcome::Relayed { recipient, routes } = outcome else {
+            panic!("forward was not relayed");
+        };
+        assert_eq!(recipient, "did:example:bob#key-1");
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].endpoint, "https://m2.example.com");
+
+        // The envelope is wrapped for the mediator in front of the recipient
+        let rewrapped = unpack_message(
+            &routes[0].envelope,
+            &MockPlugin,
+            Some("did:example:m2#key-1".into()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(rewrapped.to, Some(vec!["did:example:m2#key-1".to_string()]));
+        let (next, envelope) = parse_forward(&rewrapped).unwrap();
+        assert_eq!(next, "did:example:bob#key-1");
+        assert_eq!(envelope, "ZW52ZWxvcGU");
+    }
+}
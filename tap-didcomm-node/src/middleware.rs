@@ -0,0 +1,252 @@
+//! Middleware for received and sent messages.
+//!
+//! Middleware runs on every received message after it is unpacked and before
+//! it reaches a handler, and on every sent message before it is packed. Each
+//! middleware in the chain can inspect or modify the message, stop it from
+//! going further with [`Flow::Stop`] or reject it with an error. Middleware
+//! runs in the order it was added to the node, for both directions.
+//!
+//! # Components
+//!
+//! - [`Middleware`]: A hook on the inbound and outbound paths
+//! - [`LoggingMiddleware`]: Logs every message
+//! - [`ExpiryMiddleware`]: Rejects messages past their `expires_time`
+//!
+//! # Examples
+//!
+//! ```rust
+//! use tap_didcomm_node::middleware::{ExpiryMiddleware, LoggingMiddleware};
+//! use tap_didcomm_node::{mock::MockPlugin, DIDCommNode, NodeConfig};
+//! use tap_didcomm_core::clock::SystemClock;
+//! use std::sync::Arc;
+//!
+//! let mut node = DIDCommNode::new(NodeConfig::default(), MockPlugin);
+//! node.add_middleware(LoggingMiddleware);
+//! node.add_middleware(ExpiryMiddleware::new(Arc::new(SystemClock)));
+//! ```
+
+use async_trait::async_trait;
+use std::sync::Arc;
+use tap_didcomm_core::{clock::Clock, Error as CoreError, Message, PackingType};
+use tracing::info;
+
+use crate::{
+    error::{Error, Result},
+    handler::UnpackMetadata,
+};
+
+/// What happens to a message after a middleware has seen it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// Pass the message, possibly modified, to the rest of the chain
+    Continue,
+    /// Stop processing the message without an error
+    Stop,
+}
+
+/// A hook on the inbound and outbound message paths
+///
+/// Hooks may modify the message in place. Both pass it on unchanged by
+/// default.
+#[async_trait]
+pub trait Middleware: Send + Sync {
+    /// Runs on a received message before it reaches a handler.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error to reject the message
+    async fn inbound(&self, _msg: &mut Message, _meta: &UnpackMetadata) -> Result<Flow> {
+        Ok(Flow::Continue)
+    }
+
+    /// Runs on a message being sent before it is packed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error to reject the message
+    async fn outbound(&self, _msg: &mut Message, _packing: PackingType) -> Result<Flow> {
+        Ok(Flow::Continue)
+    }
+}
+
+/// Runs a received message through a chain of middleware.
+///
+/// # Returns
+///
+/// `Flow::Stop` if a middleware stopped the message
+///
+/// # Errors
+///
+/// Returns the error of the middleware that rejected the message
+pub async fn run_inbound(
+    chain: &[Arc<dyn Middleware>],
+    msg: &mut Message,
+    meta: &UnpackMetadata,
+) -> Result<Flow> {
+    for middleware in chain {
+        if middleware.inbound(msg, meta).await? == Flow::Stop {
+            return Ok(Flow::Stop);
+        }
+    }
+    Ok(Flow::Continue)
+}
+
+/// Runs a message being sent through a chain of middleware.
+///
+/// # Returns
+///
+/// `Flow::Stop` if a middleware stopped the message
+///
+/// # Errors
+///
+/// Returns the error of the middleware that rejected the message
+pub async fn run_outbound(
+    chain: &[Arc<dyn Middleware>],
+    msg: &mut Message,
+    packing: PackingType,
+) -> Result<Flow> {
+    for middleware in chain {
+        if middleware.outbound(msg, packing).await? == Flow::Stop {
+            return Ok(Flow::Stop);
+        }
+    }
+    Ok(Flow::Continue)
+}
+
+/// Logs the ID, type and parties of every message
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoggingMiddleware;
+
+#[async_trait]
+impl Middleware for LoggingMiddleware {
+    async fn inbound(&self, msg: &mut Message, meta: &UnpackMetadata) -> Result<Flow> {
+        info!(
+            "Received {} message {} from {:?} ({} bytes, {})",
+            msg.typ.as_str(),
+            msg.id.as_str(),
+            msg.from,
+            meta.envelope_size,
+            meta.media_type()
+        );
+        Ok(Flow::Continue)
+    }
+
+    async fn outbound(&self, msg: &mut Message, packing: PackingType) -> Result<Flow> {
+        info!(
+            "Sending {} message {} to {:?} ({})",
+            msg.typ.as_str(),
+            msg.id.as_str(),
+            msg.to,
+            packing.media_type()
+        );
+        Ok(Flow::Continue)
+    }
+}
+
+/// Rejects received and outgoing messages past their `expires_time`
+pub struct ExpiryMiddleware {
+    clock: Arc<dyn Clock>,
+}
+
+impl ExpiryMiddleware {
+    /// Creates a middleware checking expiry against `clock`
+    #[must_use]
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self { clock }
+    }
+
+    fn check(&self, msg: &Message) -> Result<()> {
+        let now = self.clock.now();
+        match msg.expires_time {
+            Some(expires_time) if now > expires_time => {
+                Err(Error::Core(CoreError::MessageExpired(format!(
+                    "Message {} expired at {expires_time}, current time is {now}",
+                    msg.id.as_str()
+                ))))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for ExpiryMiddleware {
+    async fn inbound(&self, msg: &mut Message, _meta: &UnpackMetadata) -> Result<Flow> {
+        self.check(msg)?;
+        Ok(Flow::Continue)
+    }
+
+    async fn outbound(&self, msg: &mut Message, _packing: PackingType) -> Result<Flow> {
+        self.check(msg)?;
+        Ok(Flow::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tap_didcomm_core::clock::FixedClock;
+
+    /// Tags messages and drops those of type `drop`
+    struct Tag(&'static str);
+
+    #[async_trait]
+    impl Middleware for Tag {
+        async fn inbound(&self, msg: &mut Message, _meta: &UnpackMetadata) -> Result<Flow> {
+            if msg.typ.as_str() == "drop" {
+                return Ok(Flow::Stop);
+            }
+            msg.body["tags"].as_array_mut().unwrap().push(json!(self.0));
+            Ok(Flow::Continue)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middleware_chain() {
+        let chain: Vec<Arc<dyn Middleware>> = vec![Arc::new(Tag("first")), Arc::new(Tag("second"))];
+        let meta = UnpackMetadata::default();
+
+        let mut msg = Message::new("test", json!({"tags": []})).unwrap();
+        assert_eq!(
+            run_inbound(&chain, &mut msg, &meta).await.unwrap(),
+            Flow::Continue
+        );
+        assert_eq!(msg.body["tags"], json!(["first", "second"]));
+
+        let mut dropped = Message::new("drop", json!({"tags": []})).unwrap();
+        assert_eq!(
+            run_inbound(&chain, &mut dropped, &meta).await.unwrap(),
+            Flow::Stop
+        );
+
+        // The default outbound hook passes messages on unchanged
+        assert_eq!(
+            run_outbound(&chain, &mut msg, PackingType::Signed)
+                .await
+                .unwrap(),
+            Flow::Continue
+        );
+        assert_eq!(msg.body["tags"], json!(["first", "second"]));
+    }
+
+    #[tokio::test]
+    async fn test_expiry_middleware() {
+        let clock = Arc::new(FixedClock::new(1_000));
+        let chain: Vec<Arc<dyn Middleware>> = vec![Arc::new(ExpiryMiddleware::new(clock.clone()))];
+        let mut msg = Message::new("test", json!({})).unwrap().expires_at(1_100);
+
+        assert!(run_outbound(&chain, &mut msg, PackingType::Signed)
+            .await
+            .is_ok());
+
+        clock.advance(200);
+        assert!(matches!(
+            run_inbound(&chain, &mut msg, &UnpackMetadata::default()).await,
+            Err(Error::Core(CoreError::MessageExpired(_)))
+        ));
+        assert!(run_outbound(&chain, &mut msg, PackingType::Signed)
+            .await
+            .is_err());
+    }
+}
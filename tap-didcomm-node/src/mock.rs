@@ -52,39 +52,48 @@ impl Signer for MockPlugin {
 
     async fn verify(&self, message: &[u8], signature: &[u8], _from: &str) -> Result<bool> {
         // For testing, verify that the signature is the base64 encoded message
-        let decoded = STANDARD.decode(signature).map_err(CoreError::Base64)?;
+        let decoded = STANDARD
+            .decode(signature)
+            .map_err(|e| CoreError::Base64(e.to_string()))?;
         Ok(message == decoded)
     }
 }
 
 #[async_trait]
 impl Encryptor for MockPlugin {
-    async fn encrypt(
-        &self,
-        message: &[u8],
-        _to: Vec<String>,
-        _from: Option<String>,
-    ) -> Result<Vec<u8>> {
+    async fn encrypt(&self, message: &[u8], _to: &[&str], _from: Option<&str>) -> Result<Vec<u8>> {
         // For testing, just base64 encode the message as mock encryption
         Ok(STANDARD.encode(message).into_bytes())
     }
 
-    async fn decrypt(&self, message: &[u8], _recipient: String) -> Result<Vec<u8>> {
+    async fn decrypt(&self, message: &[u8], _recipient: &str) -> Result<Vec<u8>> {
         // For testing, base64 decode the message as mock decryption
-        STANDARD.decode(message).map_err(CoreError::Base64)
+        STANDARD
+            .decode(message)
+            .map_err(|e| CoreError::Base64(e.to_string()))
+    }
+
+    async fn decrypt_from(
+        &self,
+        message: &[u8],
+        recipient: &str,
+        _sender: &str,
+    ) -> Result<Vec<u8>> {
+        // For testing, trust the sender the envelope names
+        self.decrypt(message, recipient).await
     }
 }
 
 impl DIDCommPlugin for MockPlugin {
-    fn as_resolver(&self) -> &dyn DIDResolver {
+    fn resolver(&self) -> &dyn DIDResolver {
         self
     }
 
-    fn as_signer(&self) -> &dyn Signer {
+    fn signer(&self) -> &dyn Signer {
         self
     }
 
-    fn as_encryptor(&self) -> &dyn Encryptor {
+    fn encryptor(&self) -> &dyn Encryptor {
         self
     }
 }
@@ -115,14 +124,14 @@ mod tests {
         let encrypted = plugin
             .encrypt(
                 message,
-                vec!["did:example:recipient".to_string()],
-                Some("did:example:sender".to_string()),
+                &["did:example:recipient"],
+                Some("did:example:sender"),
             )
             .await
             .unwrap();
 
         let decrypted = plugin
-            .decrypt(&encrypted, "did:example:recipient".to_string())
+            .decrypt(&encrypted, "did:example:recipient")
             .await
             .unwrap();
         assert_eq!(decrypted, message);
@@ -11,7 +11,8 @@
 //! The node is built around these main components:
 //! - `DIDCommNode`: The main node struct that coordinates all operations
 //! - `NodeConfig`: Configuration options for the node
-//! - `HandlerRegistry`: Registry of message handlers (from the actor module)
+//! - `Router`: Routes messages to handlers by type, protocol or pattern (from the router module)
+//! - `ThreadTracker`: Received and sent messages grouped by thread (from the thread module)
 //!
 //! # Examples
 //!
@@ -19,9 +20,10 @@
 //! use tap_didcomm_node::{DIDCommNode, NodeConfig, HandlerHandle};
 //! use tap_didcomm_core::Message;
 //! use tap_didcomm_node::mock::MockPlugin;
+//! use std::sync::Arc;
 //! use tokio::sync::mpsc;
 //!
-//! fn example() -> tap_didcomm_node::error::Result<()> {
+//! async fn example() -> tap_didcomm_node::error::Result<()> {
 //!     let config = NodeConfig::default();
 //!     let mut node = DIDCommNode::new(config, MockPlugin::new());
 //!     
@@ -33,18 +35,50 @@
 //!     node.register_handler("test", handler);
 //!     
 //!     // Start processing messages
-//!     node.start()
+//!     let node = Arc::new(node);
+//!     let handle = node.start()?;
+//!
+//!     // Stop once work in progress is done
+//!     handle.shutdown().await;
+//!     Ok(())
 //! }
 //! ```
 
-use std::collections::HashMap;
-use tap_didcomm_core::{pack_message, unpack_message, DIDCommPlugin, Message, PackingType};
-use tracing::{error, info};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tap_didcomm_core::{
+    clock::{Clock, SystemClock, TimestampPolicy},
+    pack_message, unpack_envelope, DIDCommPlugin, Message, PackingType, UnpackedMessage,
+};
+use tokio::sync::{watch, Semaphore};
+use tracing::{debug, error, info, warn};
 
 use crate::{
     actor::{HandlerHandle, Message as ActorMessage},
-    dispatch::{dispatch_message, DispatchConfig},
+    blob::{
+        offload_attachments, AttachmentResolver, BlobStore, DEFAULT_FETCH_TIMEOUT,
+        DEFAULT_MAX_ATTACHMENT_SIZE,
+    },
+    dead_letter::{DeadLetter, DeadLetterQueue, MemoryDeadLetterQueue},
+    dispatch::{resolve_endpoints, DispatchConfig, Dispatcher, SendResult},
     error::{Error, Result},
+    events::{EventBus, EventKind, NodeEvent},
+    handler::{reply_packing, HandlerContext, MessageHandler, UnpackMetadata},
+    lifecycle::{LifecycleConfig, Listener, NodeHandle, Shutdown, ShutdownReport},
+    limits::{check_quota, LimitsConfig, RateLimiter},
+    mediator::{ForwardOutcome, Mediator, MediatorConfig},
+    middleware::{run_inbound, run_outbound, Flow, Middleware},
+    outbox::{MemoryOutbox, Outbox, OutboxConfig, OutboxEntry, OutboxStatus},
+    policy::{PolicyConfig, Violation},
+    replay::{DuplicatePolicy, MemoryReplayStore, ReplayConfig, ReplayKey, ReplayStore},
+    router::{problem_report, HandlerPattern, HandlerTarget, Router, PROBLEM_REPORT},
+    routing::{wrap_in_forwards, Route, RoutedMessage, RoutingConfig, FORWARD_TYPE},
+    store::{MessageStore, StoredMessage},
+    supervisor::{HandlerConfig, Reservation, SupervisedHandler},
+    thread::{Direction, ThreadConfig, ThreadTracker},
+    transport::Transport,
 };
 
 /// Configuration for a `DIDComm` node.
@@ -59,6 +93,7 @@ use crate::{
 /// use tap_didcomm_node::NodeConfig;
 ///
 /// let config = NodeConfig {
+///     did: Some("did:example:node".to_string()),
 ///     port: 8080,
 ///     host: "localhost".to_string(),
 ///     ..Default::default()
@@ -66,6 +101,10 @@ use crate::{
 /// ```
 #[derive(Debug, Clone)]
 pub struct NodeConfig {
+    /// The DID the node acts as, used as the sender of replies from handlers
+    /// that do not set one
+    pub did: Option<String>,
+
     /// The port to listen on for incoming messages
     pub port: u16,
 
@@ -80,20 +119,119 @@ pub struct NodeConfig {
 
     /// Configuration for message dispatch
     pub dispatch: DispatchConfig,
+
+    /// Configuration for attachment handling
+    pub attachments: AttachmentConfig,
+
+    /// Rules for the `created_time` and `expires_time` of received messages
+    pub timestamps: TimestampPolicy,
+
+    /// Replay protection for received messages
+    pub replay: ReplayConfig,
+
+    /// Configuration for routing outgoing messages through mediators
+    pub routing: RoutingConfig,
+
+    /// Configuration for the mediator role
+    pub mediator: MediatorConfig,
+
+    /// Configuration for the outbox worker
+    pub outbox: OutboxConfig,
+
+    /// Queue sizes, concurrency and timeouts of message handlers
+    pub handlers: HandlerConfig,
+
+    /// Which received messages are accepted
+    pub policy: PolicyConfig,
+
+    /// Rate limits and quotas for received messages
+    pub limits: LimitsConfig,
+
+    /// Background tasks and shutdown
+    pub lifecycle: LifecycleConfig,
+
+    /// Bounds of the thread tracker
+    pub threads: ThreadConfig,
 }
 
 impl Default for NodeConfig {
     fn default() -> Self {
         Self {
+            did: None,
             port: 8080,
             host: "127.0.0.1".to_string(),
             use_https: false,
             max_message_size: 1024 * 1024, // 1MB
             dispatch: DispatchConfig::default(),
+            attachments: AttachmentConfig::default(),
+            timestamps: TimestampPolicy::default(),
+            replay: ReplayConfig::default(),
+            routing: RoutingConfig::default(),
+            mediator: MediatorConfig::default(),
+            outbox: OutboxConfig::default(),
+            handlers: HandlerConfig::default(),
+            policy: PolicyConfig::default(),
+            limits: LimitsConfig::default(),
+            lifecycle: LifecycleConfig::default(),
+            threads: ThreadConfig::default(),
+        }
+    }
+}
+
+/// Configuration for attachment handling.
+#[derive(Debug, Clone)]
+pub struct AttachmentConfig {
+    /// Inline attachments larger than this many bytes are encrypted into the
+    /// node's blob store before packing. `None` disables offloading.
+    pub offload_threshold: Option<usize>,
+
+    /// The public `https` URL the node's blob store is served at. Offloaded
+    /// attachments link to the hash of their encrypted content under this
+    /// URL, and nothing is offloaded without it.
+    pub public_url: Option<String>,
+
+    /// Whether linked attachments of received messages are fetched, verified
+    /// against their hash and inlined before the message reaches handlers
+    pub fetch_linked: bool,
+
+    /// The hosts linked attachments are fetched from over `http(s)`. Links to
+    /// other hosts are not fetched, so that a sender cannot make the node
+    /// request internal services.
+    pub allowed_hosts: Vec<String>,
+
+    /// The largest linked attachment that is fetched, in bytes
+    pub max_size: usize,
+
+    /// The timeout for fetching a linked attachment, in seconds
+    pub fetch_timeout: u64,
+}
+
+impl Default for AttachmentConfig {
+    fn default() -> Self {
+        Self {
+            offload_threshold: None,
+            public_url: None,
+            fetch_linked: false,
+            allowed_hosts: Vec::new(),
+            max_size: DEFAULT_MAX_ATTACHMENT_SIZE,
+            fetch_timeout: DEFAULT_FETCH_TIMEOUT,
         }
     }
 }
 
+impl AttachmentConfig {
+    /// Builds a resolver that fetches linked attachments as configured
+    fn resolver(&self) -> AttachmentResolver {
+        self.allowed_hosts
+            .iter()
+            .fold(AttachmentResolver::new(), |resolver, host| {
+                resolver.allow_host(host.as_str())
+            })
+            .with_max_size(self.max_size)
+            .with_timeout(Duration::from_secs(self.fetch_timeout))
+    }
+}
+
 /// A `DIDComm` node that can send and receive messages.
 ///
 /// The `DIDCommNode` is the main entry point for `DIDComm` operations. It handles:
@@ -132,8 +270,62 @@ pub struct DIDCommNode {
     /// The plugin providing DID resolution and crypto operations
     plugin: Box<dyn DIDCommPlugin>,
 
-    /// Registry of message handlers
-    handlers: HashMap<String, Vec<HandlerHandle>>,
+    /// Routes received messages to their handlers
+    router: Router,
+
+    /// Middleware run on received and sent messages, in order
+    middleware: Vec<Arc<dyn Middleware>>,
+
+    /// Permits for messages being received, for backpressure
+    in_flight: Semaphore,
+
+    /// Rate limits of senders, if they are limited
+    sender_limiter: Option<RateLimiter<String>>,
+
+    /// Rate limits of source addresses, if they are limited
+    source_limiter: Option<RateLimiter<IpAddr>>,
+
+    /// Whether the node receives messages, which stops at shutdown
+    accepting: AtomicBool,
+
+    /// Whether the node's background tasks were started
+    started: AtomicBool,
+
+    /// Sources of messages started with the node
+    listeners: Vec<Arc<dyn Listener>>,
+
+    /// Received and sent messages grouped by thread
+    threads: Arc<ThreadTracker>,
+
+    /// Store that large outgoing attachments are moved to
+    blob_store: Option<Arc<dyn BlobStore>>,
+
+    /// Resolver for linked attachments of received messages
+    attachment_resolver: AttachmentResolver,
+
+    /// Source of the current time for timestamp checks
+    clock: Arc<dyn Clock>,
+
+    /// Keys of received messages, for replay detection
+    replay_store: Arc<dyn ReplayStore>,
+
+    /// Received and sent messages, if they are kept
+    message_store: Option<Arc<dyn MessageStore>>,
+
+    /// Queues and forwards messages for the node's clients when it is a mediator
+    mediator: Arc<Mediator>,
+
+    /// Messages that could not be delivered
+    dead_letters: Arc<dyn DeadLetterQueue>,
+
+    /// Messages being sent, with their delivery status
+    outbox: Arc<dyn Outbox>,
+
+    /// Delivers envelopes over the transport for each endpoint
+    dispatcher: Dispatcher,
+
+    /// Broadcasts what the node does to observers
+    events: EventBus,
 }
 
 impl DIDCommNode {
@@ -147,15 +339,167 @@ impl DIDCommNode {
     /// # Returns
     ///
     /// A new `DIDCommNode` instance
+    ///
+    /// # Panics
+    ///
+    /// Panics if the dispatch configuration is invalid, for example because a
+    /// TLS certificate cannot be read. Use [`DIDCommNode::try_new`] to have
+    /// the error returned instead.
     #[must_use]
     pub fn new(config: NodeConfig, plugin: impl DIDCommPlugin + 'static) -> Self {
+        match Self::try_new(config, plugin) {
+            Ok(node) => node,
+            Err(e) => panic!("Invalid node configuration: {e}"),
+        }
+    }
+
+    /// Create a new `DIDComm` node, checking its configuration first.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Configuration for the node
+    /// * `plugin` - Plugin providing DID resolution and crypto operations
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the retry policy is invalid or the HTTP client
+    /// cannot be built, for example because a TLS certificate or key cannot
+    /// be read
+    pub fn try_new(config: NodeConfig, plugin: impl DIDCommPlugin + 'static) -> Result<Self> {
+        let dispatcher = Dispatcher::try_new(&config.dispatch)?;
+        Ok(Self::with_dispatcher(config, plugin, dispatcher))
+    }
+
+    fn with_dispatcher(
+        config: NodeConfig,
+        plugin: impl DIDCommPlugin + 'static,
+        dispatcher: Dispatcher,
+    ) -> Self {
         Self {
+            dispatcher,
+            in_flight: Semaphore::new(config.handlers.max_in_flight.max(1)),
+            attachment_resolver: config.attachments.resolver(),
+            mediator: Arc::new(Mediator::with_limits(config.mediator.queue)),
+            sender_limiter: config.limits.per_sender.map(RateLimiter::new),
+            source_limiter: config.limits.per_source.map(RateLimiter::new),
+            threads: Arc::new(ThreadTracker::with_config(config.threads.clone())),
             config,
             plugin: Box::new(plugin),
-            handlers: HashMap::new(),
+            router: Router::new(),
+            middleware: Vec::new(),
+            accepting: AtomicBool::new(true),
+            started: AtomicBool::new(false),
+            listeners: Vec::new(),
+            blob_store: None,
+            clock: Arc::new(SystemClock),
+            replay_store: Arc::new(MemoryReplayStore::default()),
+            message_store: None,
+            dead_letters: Arc::new(MemoryDeadLetterQueue::new()),
+            outbox: Arc::new(MemoryOutbox::new()),
+            events: EventBus::default(),
         }
     }
 
+    /// Set the clock used to check message timestamps.
+    ///
+    /// Defaults to the system clock.
+    ///
+    /// # Arguments
+    ///
+    /// * `clock` - The clock to use
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Set the store used to detect replayed messages.
+    ///
+    /// Defaults to an in-memory store, which forgets received messages when
+    /// the node restarts.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The replay store to use
+    pub fn set_replay_store(&mut self, store: impl ReplayStore + 'static) {
+        self.replay_store = Arc::new(store);
+    }
+
+    /// Set the store that received and sent messages are kept in.
+    ///
+    /// By default messages are not kept beyond the thread tracker. Old
+    /// messages are pruned according to
+    /// `NodeConfig::lifecycle.message_retention_secs` while the node runs.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The message store to use
+    pub fn set_message_store(&mut self, store: impl MessageStore + 'static) {
+        self.message_store = Some(Arc::new(store));
+    }
+
+    /// Set the queue that undeliverable messages are stored in.
+    ///
+    /// Defaults to an in-memory queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `queue` - The dead-letter queue to use
+    pub fn set_dead_letter_queue(&mut self, queue: impl DeadLetterQueue + 'static) {
+        self.dead_letters = Arc::new(queue);
+    }
+
+    /// Set the outbox that messages are written to before delivery.
+    ///
+    /// Defaults to an in-memory outbox, which loses messages that are not
+    /// yet delivered when the node stops. Use a persistent outbox, such as
+    /// `SqliteOutbox`, for crash-safe delivery.
+    ///
+    /// # Arguments
+    ///
+    /// * `outbox` - The outbox to use
+    pub fn set_outbox(&mut self, outbox: impl Outbox + 'static) {
+        self.outbox = Arc::new(outbox);
+    }
+
+    /// Set the transport for endpoints with a URI scheme.
+    ///
+    /// HTTP and WebSocket endpoints have transports by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `scheme` - The URI scheme, such as `mem`
+    /// * `transport` - The transport to use
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Config` if `NodeConfig::dispatch.use_https` is set and
+    /// the scheme is plaintext `http` or `ws`
+    pub fn set_transport(
+        &mut self,
+        scheme: &str,
+        transport: impl Transport + 'static,
+    ) -> Result<()> {
+        self.dispatcher.set_transport(scheme, transport)
+    }
+
+    /// Set the blob store used for linked attachments.
+    ///
+    /// If `NodeConfig::attachments.public_url` is set, outgoing attachments
+    /// above `NodeConfig::attachments.offload_threshold` are encrypted into
+    /// the store, and links into the store are resolved for received messages.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The blob store to use
+    pub fn set_blob_store(&mut self, store: impl BlobStore + 'static) {
+        let store: Arc<dyn BlobStore> = Arc::new(store);
+        self.attachment_resolver = self
+            .config
+            .attachments
+            .resolver()
+            .with_store(Arc::clone(&store));
+        self.blob_store = Some(store);
+    }
+
     /// Register a handler for a specific message type.
     ///
     /// # Arguments
@@ -164,31 +508,284 @@ impl DIDCommNode {
     /// * `handler` - The handler that will process messages of this type
     pub fn register_handler(&mut self, msg_type: impl Into<String>, handler: HandlerHandle) {
         let msg_type = msg_type.into();
-        self.handlers
-            .entry(msg_type.clone())
-            .or_default()
-            .push(handler);
         info!("Registered handler for message type: {msg_type}");
+        self.router.add(HandlerPattern::Exact(msg_type), handler);
+    }
+
+    /// Register a handler for a protocol or a pattern of message types.
+    ///
+    /// A PIURI such as `https://tap.rsvp/schema/1.0` routes every message of
+    /// the protocol with the same major version to the handler, and a
+    /// pattern containing `*` routes every message type it matches. Messages
+    /// go to the handlers of their most specific match.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The PIURI, pattern or message type to handle
+    /// * `handler` - The handler that will process the matching messages
+    pub fn register_pattern_handler(&mut self, pattern: &str, handler: HandlerHandle) {
+        info!("Registered handler for pattern: {pattern}");
+        self.router.add(HandlerPattern::parse(pattern), handler);
+    }
+
+    /// Set the handler for messages that no other handler is registered for.
+    ///
+    /// Use a [`crate::router::ProblemReporter`] to answer them with a problem
+    /// report. Without a default handler, such messages are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - The handler that will process unrouted messages
+    pub fn set_default_handler(&mut self, handler: impl Into<HandlerTarget>) {
+        self.router.set_default(handler);
+    }
+
+    /// Add a middleware to the end of the chain.
+    ///
+    /// Middleware runs on received messages after they are unpacked and on
+    /// sent messages before they are packed, in the order it was added.
+    ///
+    /// # Arguments
+    ///
+    /// * `middleware` - The middleware to add
+    pub fn add_middleware(&mut self, middleware: impl Middleware + 'static) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /// Register a typed handler for a message type, protocol or pattern.
+    ///
+    /// The pattern is parsed as in [`DIDCommNode::register_pattern_handler`].
+    /// The messages the handler returns are packed and sent by the node.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The PIURI, pattern or message type to handle
+    /// * `handler` - The handler that will process the matching messages
+    pub fn register_message_handler(
+        &mut self,
+        pattern: &str,
+        handler: impl MessageHandler + 'static,
+    ) {
+        let handler = SupervisedHandler::new(Arc::new(handler), &self.config.handlers);
+        info!("Registered message handler for pattern: {pattern}");
+        self.router.add(
+            HandlerPattern::parse(pattern),
+            HandlerTarget::Handler(Arc::new(handler)),
+        );
+    }
+
+    /// Add a source of messages, such as a server transport.
+    ///
+    /// Listeners run from when the node is started until it shuts down.
+    ///
+    /// # Arguments
+    ///
+    /// * `listener` - The listener to add
+    pub fn add_listener(&mut self, listener: impl Listener + 'static) {
+        self.listeners.push(Arc::new(listener));
     }
 
-    /// Start the node and begin processing messages.
+    /// Start the node's background tasks.
     ///
-    /// This method starts the HTTP server and begins listening for incoming
-    /// messages. It will run until the node is shut down.
+    /// This spawns the listeners, the outbox worker and a task pruning
+    /// expired keys from the replay store and old messages from the message
+    /// store.
     ///
     /// # Returns
     ///
-    /// A future that completes when the node is shut down
+    /// A handle to shut the node down with
     ///
     /// # Errors
     ///
-    /// Returns an error if the node fails to start
-    pub fn start(&self) -> Result<()> {
+    /// Returns an error if the node was already started
+    pub fn start(self: &Arc<Self>) -> Result<NodeHandle> {
+        if self.started.swap(true, Ordering::SeqCst) {
+            return Err(Error::Config("The node was already started".into()));
+        }
         info!(
             "Starting DIDComm node on {}:{}",
             self.config.host, self.config.port
         );
-        Ok(())
+
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let mut tasks = Vec::new();
+        for (index, listener) in self.listeners.iter().enumerate() {
+            let listener = Arc::clone(listener);
+            let node = Arc::clone(self);
+            let shutdown = Shutdown::new(shutdown_rx.clone());
+            let task = tokio::spawn(async move {
+                if let Err(e) = listener.listen(node, shutdown).await {
+                    error!("Listener {index} failed: {e}");
+                }
+            });
+            tasks.push((format!("listener {index}"), task));
+        }
+
+        let node = Arc::clone(self);
+        let shutdown = Shutdown::new(shutdown_rx.clone());
+        tasks.push((
+            "outbox".to_string(),
+            tokio::spawn(async move { node.outbox_loop(shutdown).await }),
+        ));
+
+        let node = Arc::clone(self);
+        let shutdown = Shutdown::new(shutdown_rx);
+        tasks.push((
+            "cleanup".to_string(),
+            tokio::spawn(async move { node.cleanup_loop(shutdown).await }),
+        ));
+
+        Ok(NodeHandle::new(Arc::clone(self), shutdown_tx, tasks))
+    }
+
+    /// Stop receiving messages, as the node is shutting down
+    pub(crate) fn stop_accepting(&self) {
+        self.accepting.store(false, Ordering::SeqCst);
+    }
+
+    /// Wait for messages being received and for outbox deliveries to finish.
+    pub(crate) async fn drain(&self) {
+        let permits = u32::try_from(self.config.handlers.max_in_flight.max(1)).unwrap_or(u32::MAX);
+        // Every permit is back once no message is being received
+        drop(self.in_flight.acquire_many(permits).await);
+
+        let interval = Duration::from_millis(20);
+        loop {
+            let backlog = match self.outbox.list(Some(OutboxStatus::Pending)).await {
+                Ok(pending) if !pending.is_empty() => {
+                    if let Err(e) = self.process_outbox().await {
+                        error!("Failed to process outbox: {e}");
+                        tokio::time::sleep(interval).await;
+                    }
+                    continue;
+                }
+                Ok(_) => self.outbox.list(Some(OutboxStatus::InFlight)).await,
+                Err(e) => Err(e),
+            };
+            match backlog {
+                Ok(in_flight) if in_flight.is_empty() => return,
+                Ok(_) => {}
+                Err(e) => error!("Failed to read outbox: {e}"),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Report the work left undone at shutdown.
+    pub(crate) async fn shutdown_report(&self) -> ShutdownReport {
+        let mut undelivered = Vec::new();
+        for status in [OutboxStatus::Pending, OutboxStatus::InFlight] {
+            match self.outbox.list(Some(status)).await {
+                Ok(entries) => undelivered.extend(entries),
+                Err(e) => error!("Failed to read outbox: {e}"),
+            }
+        }
+        ShutdownReport {
+            undelivered,
+            receiving: self
+                .config
+                .handlers
+                .max_in_flight
+                .max(1)
+                .saturating_sub(self.in_flight.available_permits()),
+            timed_out: false,
+        }
+    }
+
+    /// Prune expired keys from the replay store, expired envelopes from the
+    /// mediator's queue, idle threads and old messages from the message store
+    /// until shutdown.
+    async fn cleanup_loop(&self, mut shutdown: Shutdown) {
+        let interval = Duration::from_secs(self.config.lifecycle.cleanup_interval_secs.max(1));
+        loop {
+            tokio::select! {
+                () = shutdown.wait() => return,
+                () = tokio::time::sleep(interval) => {}
+            }
+            match self.replay_store.prune(self.clock.now()).await {
+                Ok(0) => {}
+                Ok(pruned) => debug!("Pruned {pruned} expired replay keys"),
+                Err(e) => error!("Failed to prune replay store: {e}"),
+            }
+            let retention = self.config.outbox.retention_secs;
+            match self
+                .outbox
+                .prune(self.clock.now().saturating_sub(retention))
+                .await
+            {
+                Ok(0) => {}
+                Ok(pruned) => debug!("Pruned {pruned} finished outbox entries"),
+                Err(e) => error!("Failed to prune outbox: {e}"),
+            }
+            let expired = self.mediator.queue().prune(self.clock.now());
+            if expired > 0 {
+                debug!("Dropped {expired} expired queued envelopes");
+            }
+            let idle = self.threads.prune(self.clock.now());
+            if idle > 0 {
+                debug!("Pruned {idle} idle threads");
+            }
+            let now = Instant::now();
+            let pruned = self.sender_limiter.as_ref().map_or(0, |l| l.prune(now))
+                + self.source_limiter.as_ref().map_or(0, |l| l.prune(now));
+            if pruned > 0 {
+                debug!("Pruned {pruned} idle rate limits");
+            }
+            if let (Some(store), Some(retention)) = (
+                &self.message_store,
+                self.config.lifecycle.message_retention_secs,
+            ) {
+                match store
+                    .prune(self.clock.now().saturating_sub(retention))
+                    .await
+                {
+                    Ok(0) => {}
+                    Ok(pruned) => debug!("Pruned {pruned} old messages"),
+                    Err(e) => error!("Failed to prune message store: {e}"),
+                }
+            }
+        }
+    }
+
+    /// Takes a token from the rate limit of a source address.
+    ///
+    /// Transports call this before receiving a message, so that a flood from
+    /// one address is turned away before anything is unpacked.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address the message came from
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::RateLimited` if the address is over its limit
+    pub fn check_source(&self, addr: IpAddr) -> Result<()> {
+        match &self.source_limiter {
+            Some(limiter) if !limiter.try_acquire(&addr) => {
+                warn!("Rate limited messages from {addr}");
+                Err(Error::RateLimited(format!("Too many messages from {addr}")))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks an unpacked message against the sender's rate limit and the
+    /// quotas
+    ///
+    /// Only an authenticated sender has a rate limit of its own. The `from`
+    /// of other messages can be forged, to exhaust someone else's limit or to
+    /// get a fresh one for every message, so they are limited by their source
+    /// address alone.
+    fn check_limits(&self, msg: &Message, meta: &UnpackMetadata) -> Result<()> {
+        if let (Some(limiter), Some(sender)) = (&self.sender_limiter, meta.sender()) {
+            if !limiter.try_acquire(sender) {
+                warn!("Rate limited messages from {sender}");
+                return Err(Error::RateLimited(format!(
+                    "Too many messages from {sender}"
+                )));
+            }
+        }
+        self.config.limits.check_quotas(msg)
     }
 
     /// Process an incoming packed message.
@@ -208,93 +805,809 @@ impl DIDCommNode {
     ///
     /// Returns an error if:
     /// - The message is not valid UTF-8
+    /// - The node is shutting down, or it or a handler of the message is
+    ///   busy (`Error::Busy`)
+    /// - The message is larger than `max_message_size` or exceeds another
+    ///   quota (`Error::QuotaExceeded`)
     /// - The message cannot be unpacked
+    /// - Its authenticated sender is over its rate limit
+    ///   (`Error::RateLimited`)
+    /// - The message has expired or was created too far in the future
+    /// - The message was received before and duplicates are rejected
+    /// - The node's policy rejects the message (`Error::PolicyViolation`)
+    /// - A middleware rejects the message
+    /// - A forward message cannot be queued, or its relay to the next hop
+    ///   cannot be written to the outbox
+    /// - A linked attachment cannot be fetched or fails verification
     /// - The message cannot be routed to a handler
+    /// - A handler fails to handle the message (`Error::Actor`). The message
+    ///   is still recorded, so a retransmission is a duplicate.
     pub async fn receive(&self, packed_msg: &[u8]) -> Result<()> {
-        let msg = unpack_message(
-            std::str::from_utf8(packed_msg)
-                .map_err(|e| Error::InvalidFormat(format!("Invalid UTF-8: {e}")))?,
-            self.plugin.as_ref(),
-            None,
-        )
-        .await
-        .map_err(Error::Core)?;
-
-        // Dispatch to registered handlers
-        if let Some(handlers) = self.handlers.get(&msg.typ.0) {
-            for handler in handlers {
-                if let Err(e) = handler.send(ActorMessage(msg.clone())).await {
-                    error!("Failed to send message to handler: {e}");
+        if !self.accepting.load(Ordering::SeqCst) {
+            return Err(Error::Busy("The node is shutting down".into()));
+        }
+        let _in_flight = self
+            .in_flight
+            .try_acquire()
+            .map_err(|_| Error::Busy("Too many messages being received".into()))?;
+
+        check_quota(
+            "bytes",
+            packed_msg.len(),
+            Some(self.config.max_message_size),
+        )?;
+
+        let started = Instant::now();
+        let now = self.clock.now();
+        let unpacked = self.unpack(packed_msg, now).await?;
+        let meta = UnpackMetadata::new(&unpacked, packed_msg.len(), now);
+        let mut msg = unpacked.message;
+        self.check_limits(&msg, &meta)?;
+
+        // Stale and replayed messages are dropped before anything acts on
+        // them, so a replay cannot trigger problem reports or middleware
+        self.config
+            .timestamps
+            .check(&msg, now)
+            .map_err(Error::Core)?;
+        if self.config.replay.enabled {
+            self.config
+                .replay
+                .check(&msg, self.config.timestamps.max_clock_skew, now)?;
+        }
+        if self.is_duplicate(&msg, &meta, now).await? {
+            return Ok(());
+        }
+        // A policy rejection is final, so the key is kept
+        if let Err(violation) = self.config.policy.evaluate(&msg, &meta) {
+            return Err(self.reject(&msg, &meta, violation, now).await);
+        }
+
+        // The message's key is forgotten if it failed before any handler ran,
+        // so that a retransmission is not dropped. Once a handler has run, the
+        // key is kept so that a retransmission cannot run it again.
+        let key = ReplayKey::for_message(&msg, &meta);
+        let failed = match self
+            .process_received(&mut msg, &meta, packed_msg, now)
+            .await
+        {
+            Ok(Some(failed)) => failed,
+            Ok(None) => {
+                debug!("Middleware stopped message {}", msg.id.as_str());
+                return Ok(());
+            }
+            Err(e) => {
+                if self.config.replay.enabled {
+                    if let Err(e) = self.replay_store.forget(&key).await {
+                        error!("Failed to forget replay key of {}: {e}", key.message_id);
+                    }
                 }
+                return Err(e);
             }
+        };
+        if failed > 0 {
+            return Err(Error::Actor(format!(
+                "{failed} handlers failed to handle message {}",
+                msg.id.as_str()
+            )));
         }
+        self.publish_received(&msg, now, started);
 
         Ok(())
     }
 
-    /// Send a message to another `DIDComm` node.
-    ///
-    /// This method packs and dispatches a message to its intended recipient.
-    ///
-    /// # Arguments
-    ///
-    /// * `message` - The message to send
-    /// * `packing` - The packing type to use
+    /// Run the inbound middleware on a received message that is not a
+    /// duplicate, then forward it, or resolve its attachments and dispatch it
+    /// to handlers.
     ///
     /// # Returns
     ///
-    /// A Result indicating success or failure of message sending
+    /// The number of handlers that failed to handle the message, or `None`
+    /// if a middleware stopped it
     ///
     /// # Errors
     ///
-    /// Returns an error if:
-    /// - The message cannot be packed
-    /// - The message cannot be dispatched
-    pub async fn send(&self, message: &Message, packing: PackingType) -> Result<()> {
-        let _packed = pack_message(message, self.plugin.as_ref(), packing)
-            .await
-            .map_err(Error::Core)?;
+    /// Returns an error, before any handler runs, if a middleware rejects the
+    /// message, a handler is busy, a forward message cannot be relayed or a
+    /// linked attachment cannot be resolved
+    async fn process_received(
+        &self,
+        msg: &mut Message,
+        meta: &UnpackMetadata,
+        packed_msg: &[u8],
+        now: u64,
+    ) -> Result<Option<usize>> {
+        if run_inbound(&self.middleware, msg, meta).await? == Flow::Stop {
+            return Ok(None);
+        }
 
-        dispatch_message(message, &self.config.dispatch).await?;
+        if self.config.mediator.enabled && msg.typ.as_str() == FORWARD_TYPE {
+            let outcome = self
+                .mediator
+                .handle_forward(msg, self.plugin.as_ref(), self.config.mediator.rewrap, now)
+                .await?;
+            // Relays are left to the outbox worker, so receiving does not wait
+            // on the next hop
+            if let ForwardOutcome::Relayed { recipient, routes } = outcome {
+                self.outbox
+                    .enqueue(&[OutboxEntry::new(
+                        msg.id.as_str(),
+                        vec![recipient],
+                        routes,
+                        now,
+                    )])
+                    .await?;
+            }
+            return Ok(Some(0));
+        }
 
-        Ok(())
-    }
+        // Reserve room in the handlers' queues before anything is recorded,
+        // so that a busy node can be retried
+        let reservations = self
+            .router
+            .handlers(msg.typ.as_str())
+            .into_iter()
+            .map(HandlerTarget::try_reserve)
+            .collect::<Result<Vec<_>>>()?;
 
-    /// Returns a reference to the node's configuration.
-    #[must_use]
-    pub fn config(&self) -> &NodeConfig {
-        &self.config
+        if self.config.attachments.fetch_linked {
+            self.attachment_resolver.resolve_attachments(msg).await?;
+        }
+
+        self.threads.record_inbound(msg, meta.sender(), now);
+        let envelope = String::from_utf8_lossy(packed_msg);
+        self.store_message(
+            StoredMessage::new(Direction::Inbound, msg.clone(), now)
+                .envelope(envelope, meta.clone()),
+        )
+        .await;
+
+        // Dispatch to registered handlers, which run concurrently
+        if reservations.is_empty() {
+            debug!("No handler for message type: {}", msg.typ.as_str());
+        }
+        let msg = &*msg;
+        let handled = futures::future::join_all(
+            reservations
+                .into_iter()
+                .map(|reservation| self.run_handler(reservation, msg, meta)),
+        )
+        .await;
+        Ok(Some(handled.into_iter().filter(|handled| !handled).count()))
     }
 
-    /// Returns a reference to the node's plugin.
-    #[must_use]
-    pub fn plugin(&self) -> &dyn DIDCommPlugin {
-        self.plugin.as_ref()
+    /// Reject a message that violates the node's policy.
+    ///
+    /// The sender is sent a problem report if the envelope authenticated it
+    /// and the message is not a problem report itself. Reports are never
+    /// sent to the unverified `from` of a message, so that a forged one
+    /// cannot direct them at someone else.
+    ///
+    /// # Returns
+    ///
+    /// The error to fail receiving the message with
+    async fn reject(
+        &self,
+        msg: &Message,
+        meta: &UnpackMetadata,
+        violation: Violation,
+        now: u64,
+    ) -> Error {
+        warn!("Rejected message {}: {violation}", msg.id.as_str());
+        self.events.publish(NodeEvent::received(
+            EventKind::PolicyRejected {
+                code: violation.code.to_string(),
+            },
+            msg,
+            now,
+        ));
+        if self.config.policy.report
+            && meta.sender().is_some()
+            && msg.typ.as_str() != PROBLEM_REPORT
+        {
+            let args: Vec<&str> = violation.args.iter().map(String::as_str).collect();
+            match problem_report(msg, violation.code, &violation.comment, &args) {
+                Ok(report) => self.send_replies(vec![report]).await,
+                Err(e) => error!("Failed to create problem report: {e}"),
+            }
+        }
+        Error::PolicyViolation(violation.to_string())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::mock::MockPlugin;
-    use serde_json::json;
-    use tokio::sync::mpsc;
+    /// Check a received message against the replay store.
+    ///
+    /// # Returns
+    ///
+    /// Whether the message is a duplicate to drop
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Duplicate` if duplicates are rejected
+    async fn is_duplicate(&self, msg: &Message, meta: &UnpackMetadata, now: u64) -> Result<bool> {
+        if !self.config.replay.enabled {
+            return Ok(false);
+        }
+        let key = ReplayKey::for_message(msg, meta);
+        let retain_until =
+            self.config
+                .replay
+                .retain_until(msg, self.config.timestamps.max_clock_skew, now);
+        if self
+            .replay_store
+            .check_and_record(&key, retain_until, now)
+            .await?
+        {
+            return Ok(false);
+        }
+        match self.config.replay.policy {
+            DuplicatePolicy::Drop => {
+                debug!("Dropping duplicate message: {}", msg.id.as_str());
+                self.events
+                    .publish(NodeEvent::received(EventKind::DuplicateDropped, msg, now));
+                Ok(true)
+            }
+            DuplicatePolicy::Reject => Err(Error::Duplicate(format!(
+                "Message {} was already received",
+                msg.id.as_str()
+            ))),
+        }
+    }
 
-    struct TestHandler {
-        received: bool,
-        tx: mpsc::Sender<()>,
+    /// Unpack a received envelope, publishing an event if it fails.
+    async fn unpack(&self, packed_msg: &[u8], now: u64) -> Result<UnpackedMessage> {
+        let unpacked = match std::str::from_utf8(packed_msg) {
+            Ok(packed) => unpack_envelope(packed, self.plugin.as_ref(), None)
+                .await
+                .map_err(Error::Core),
+            Err(e) => Err(Error::InvalidFormat(format!("Invalid UTF-8: {e}"))),
+        };
+        if let Err(e) = &unpacked {
+            let error = e.to_string();
+            self.events
+                .publish(NodeEvent::new(EventKind::UnpackFailed { error }, now));
+        }
+        unpacked
     }
 
-    impl TestHandler {
-        fn new(tx: mpsc::Sender<()>) -> Self {
-            Self {
-                received: false,
-                tx,
+    /// Keep a message in the message store, if there is one.
+    ///
+    /// Failures are logged, as the message has already been accepted.
+    async fn store_message(&self, message: StoredMessage) {
+        if let Some(store) = &self.message_store {
+            if let Err(e) = store.save(&message).await {
+                error!("Failed to store message {}: {e}", message.id());
             }
         }
     }
 
-    #[tokio::test]
+    /// Publish that a message was received, with the time taken to handle it.
+    fn publish_received(&self, msg: &Message, now: u64, started: Instant) {
+        self.events.publish(
+            NodeEvent::received(EventKind::MessageReceived, msg, now).elapsed(started.elapsed()),
+        );
+    }
+
+    /// Run a handler on a received message in its reserved place.
+    ///
+    /// Failures are logged, so that one handler cannot fail the others.
+    ///
+    /// # Returns
+    ///
+    /// Whether the handler handled the message
+    async fn run_handler(
+        &self,
+        reservation: Reservation<'_>,
+        msg: &Message,
+        meta: &UnpackMetadata,
+    ) -> bool {
+        match reservation {
+            Reservation::Actor(permit) => {
+                let timeout = self.config.handlers.timeout();
+                match tokio::time::timeout(
+                    timeout,
+                    permit.send(ActorMessage(msg.clone(), meta.clone())),
+                )
+                .await
+                {
+                    Ok(Ok(())) => true,
+                    Ok(Err(e)) => {
+                        error!("Failed to send message to handler: {e}");
+                        false
+                    }
+                    Err(_) => {
+                        error!("Handler timed out after {}ms", timeout.as_millis());
+                        false
+                    }
+                }
+            }
+            Reservation::Handler(handler, _queued) => {
+                match handler
+                    .handle(HandlerContext::new(self), msg.clone(), meta.clone())
+                    .await
+                {
+                    Ok(replies) => {
+                        self.send_replies(replies).await;
+                        true
+                    }
+                    Err(e) => {
+                        error!("Handler failed to handle message: {e}");
+                        false
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send the messages a handler returned.
+    ///
+    /// Replies without a sender are sent as the node's DID. Failures are
+    /// logged, as the received message has been handled.
+    pub(crate) async fn send_replies(&self, replies: Vec<Message>) {
+        for mut reply in replies {
+            if reply.from.is_none() {
+                reply.from.clone_from(&self.config.did);
+            }
+            if let Err(e) = self.send(&reply, reply_packing(&reply)).await {
+                error!("Failed to send reply {}: {e}", reply.id.as_str());
+            }
+        }
+    }
+
+    /// Send a message to another `DIDComm` node.
+    ///
+    /// This method packs the message and writes an envelope per distinct set
+    /// of routes to the outbox, in one transaction, before delivering them to
+    /// the recipients' endpoints with the media type of the packing as their
+    /// `Content-Type`. Recipients that advertise no endpoint are delivered to
+    /// `NodeConfig::dispatch.base_url`, if it is set. If the node stops
+    /// mid-delivery, the outbox worker delivers the envelopes once it runs
+    /// again.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to send
+    /// * `packing` - The packing type to use
+    ///
+    /// # Returns
+    ///
+    /// The result of each delivery, one per distinct set of routes. It is
+    /// empty if a middleware stopped the message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - A middleware rejects the message
+    /// - An attachment cannot be moved to the blob store
+    /// - The message cannot be packed or routed
+    /// - A recipient advertises no endpoint and no `base_url` is configured
+    /// - The envelopes cannot be written to the outbox
+    /// - An envelope cannot be delivered to an endpoint, after retries, in
+    ///   which case it is stored in the dead-letter queue
+    pub async fn send(&self, message: &Message, packing: PackingType) -> Result<Vec<SendResult>> {
+        let mut outgoing = message.clone();
+        if run_outbound(&self.middleware, &mut outgoing, packing).await? == Flow::Stop {
+            debug!("Middleware stopped message {}", message.id.as_str());
+            return Ok(Vec::new());
+        }
+        if let (Some(store), Some(threshold), Some(public_url)) = (
+            &self.blob_store,
+            self.config.attachments.offload_threshold,
+            &self.config.attachments.public_url,
+        ) {
+            offload_attachments(&mut outgoing, store.as_ref(), threshold, public_url).await?;
+        }
+
+        let now = self.clock.now();
+        let mut entries = Vec::new();
+        for mut routed in self.pack_for_delivery(&outgoing, packing).await? {
+            if routed.routes.is_empty() {
+                let base_url = self.config.dispatch.base_url.as_ref().ok_or_else(|| {
+                    Error::UnknownRecipient(format!(
+                        "No DIDComm endpoint for {}",
+                        routed.recipients.join(", ")
+                    ))
+                })?;
+                routed.routes.push(Route {
+                    endpoint: format!("{base_url}{}", self.config.dispatch.endpoint),
+                    envelope: routed.envelope,
+                    media_type: packing.media_type().to_string(),
+                });
+            }
+            // Claimed up front so the outbox worker leaves it to us
+            entries.push(OutboxEntry {
+                status: OutboxStatus::InFlight,
+                attempts: 1,
+                ..OutboxEntry::new(outgoing.id.as_str(), routed.recipients, routed.routes, now)
+            });
+        }
+        self.outbox.enqueue(&entries).await?;
+        self.threads.record_outbound(&outgoing, now);
+        self.store_message(StoredMessage {
+            meta: UnpackMetadata {
+                packing: Some(packing),
+                ..UnpackMetadata::default()
+            },
+            ..StoredMessage::new(Direction::Outbound, outgoing.clone(), now)
+        })
+        .await;
+
+        let mut results = Vec::with_capacity(entries.len());
+        let mut failure = None;
+        for entry in entries {
+            match self.deliver_entry(entry, Some(outgoing.thread_id())).await {
+                Ok(result) => results.push(result),
+                Err(e) => failure = failure.or(Some(e)),
+            }
+        }
+
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(results),
+        }
+    }
+
+    /// Deliver the outbox entries that are pending.
+    ///
+    /// Entries that have been in flight for longer than
+    /// `NodeConfig::outbox.lease_secs` were interrupted, for example by the
+    /// node stopping, and are delivered again.
+    ///
+    /// # Returns
+    ///
+    /// The number of entries delivery was attempted for
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the outbox cannot be read or written
+    pub async fn process_outbox(&self) -> Result<usize> {
+        let now = self.clock.now();
+        let requeued = self
+            .outbox
+            .requeue_stale(now.saturating_sub(self.config.outbox.lease_secs), now)
+            .await?;
+        if requeued > 0 {
+            info!("Resuming delivery of {requeued} interrupted outbox entries");
+        }
+
+        let entries = self
+            .outbox
+            .claim(self.config.outbox.batch_size, now)
+            .await?;
+        let count = entries.len();
+        for entry in entries {
+            let id = entry.id.clone();
+            if let Err(e) = self.deliver_entry(entry, None).await {
+                error!("Failed to deliver outbox entry {id}: {e}");
+            }
+        }
+        Ok(count)
+    }
+
+    /// Run the outbox worker, delivering pending entries until the task is
+    /// cancelled.
+    ///
+    /// Checks the outbox every `NodeConfig::outbox.poll_interval_ms`.
+    pub async fn run_outbox(&self) {
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        self.outbox_loop(Shutdown::new(shutdown_rx)).await;
+    }
+
+    /// Process the outbox until shutdown.
+    async fn outbox_loop(&self, mut shutdown: Shutdown) {
+        let interval = Duration::from_millis(self.config.outbox.poll_interval_ms);
+        while !shutdown.is_shutdown() {
+            match self.process_outbox().await {
+                // Keep going while there is a backlog
+                Ok(count) if count >= self.config.outbox.batch_size => continue,
+                Ok(_) => {}
+                Err(e) => error!("Failed to process outbox: {e}"),
+            }
+            tokio::select! {
+                () = shutdown.wait() => return,
+                () = tokio::time::sleep(interval) => {}
+            }
+        }
+    }
+
+    /// Deliver a claimed outbox entry and record the outcome.
+    ///
+    /// The outcome and every retry are published as events about the
+    /// message, in the thread given if it is known. The lease on the entry is
+    /// renewed while delivery is in progress, so the outbox worker does not
+    /// deliver it a second time while retries are still running.
+    async fn deliver_entry(
+        &self,
+        entry: OutboxEntry,
+        thread_id: Option<&str>,
+    ) -> Result<SendResult> {
+        let started = Instant::now();
+        let event = |kind| NodeEvent {
+            message_id: Some(entry.message_id.clone()),
+            thread_id: thread_id.map(str::to_string),
+            peers: entry.recipients.clone(),
+            ..NodeEvent::new(kind, self.clock.now())
+        };
+        let delivered = {
+            let delivery = self
+                .dispatcher
+                .deliver_with(&entry.routes, |failed, delay| {
+                    self.events.publish(event(EventKind::RetryScheduled {
+                        endpoint: failed.endpoint.clone(),
+                        attempt: failed.attempt,
+                        delay,
+                    }));
+                });
+            tokio::pin!(delivery);
+            let mut renewal = tokio::time::interval(
+                Duration::from_millis(self.config.outbox.lease_secs.saturating_mul(500))
+                    .max(Duration::from_millis(100)),
+            );
+            // The first tick completes immediately, and the lease is fresh
+            renewal.tick().await;
+            loop {
+                tokio::select! {
+                    delivered = &mut delivery => break delivered,
+                    _ = renewal.tick() => {
+                        if let Err(e) = self.outbox.renew(&entry.id, self.clock.now()).await {
+                            warn!("Failed to renew lease of outbox entry {}: {e}", entry.id);
+                        }
+                    }
+                }
+            }
+        };
+        match &delivered {
+            Ok(delivery) => self.events.publish(
+                event(EventKind::MessageSent {
+                    endpoint: delivery.endpoint.clone(),
+                })
+                .elapsed(started.elapsed()),
+            ),
+            Err(e) => self.events.publish(
+                event(EventKind::DeliveryFailed {
+                    error: e.to_string(),
+                })
+                .elapsed(started.elapsed()),
+            ),
+        }
+
+        match delivered {
+            Ok(delivery) => {
+                self.outbox
+                    .complete(&entry.id, OutboxStatus::Delivered, None, self.clock.now())
+                    .await?;
+                Ok(SendResult {
+                    message_id: entry.message_id,
+                    recipients: entry.recipients,
+                    endpoint: delivery.endpoint,
+                    status: delivery.status,
+                    reply: delivery.reply,
+                })
+            }
+            Err(e) => {
+                let now = self.clock.now();
+                self.outbox
+                    .complete(&entry.id, OutboxStatus::Failed, Some(e.to_string()), now)
+                    .await?;
+                if let Error::Undeliverable(attempts) = &e {
+                    let letter = DeadLetter::new(
+                        entry.message_id,
+                        entry.recipients,
+                        entry.routes,
+                        attempts.clone(),
+                        now,
+                    );
+                    error!(
+                        "Message {} is undeliverable, stored as dead letter {}",
+                        letter.message_id, letter.id
+                    );
+                    self.dead_letters.push(letter).await?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Deliver a dead letter again.
+    ///
+    /// The dead letter is removed from the dead-letter queue and its routes are
+    /// tried again. If delivery fails again, it is put back with the new failed
+    /// attempts added to its history.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the dead letter
+    ///
+    /// # Returns
+    ///
+    /// The result of the delivery
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - There is no dead letter with that ID
+    /// - The envelope cannot be delivered to an endpoint
+    pub async fn replay_dead_letter(&self, id: &str) -> Result<SendResult> {
+        let mut letter = self
+            .dead_letters
+            .take(id)
+            .await?
+            .ok_or_else(|| Error::Dispatch(format!("No dead letter {id}")))?;
+
+        match self.dispatcher.deliver(&letter.routes).await {
+            Ok(delivery) => Ok(SendResult {
+                message_id: letter.message_id,
+                recipients: letter.recipients,
+                endpoint: delivery.endpoint,
+                status: delivery.status,
+                reply: delivery.reply,
+            }),
+            Err(Error::Undeliverable(attempts)) => {
+                letter.attempts.extend(attempts.iter().cloned());
+                letter.failed_at = self.clock.now();
+                self.dead_letters.push(letter).await?;
+                Err(Error::Undeliverable(attempts))
+            }
+            Err(e) => {
+                self.dead_letters.push(letter).await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Pack a message and route it to each of its recipients.
+    ///
+    /// The endpoints of every recipient are resolved from their
+    /// `DIDCommMessaging` services, in document order. For endpoints that list
+    /// routing keys, if `NodeConfig::routing.forward` is set, the packed message
+    /// is wrapped in a forward message for each mediator. Recipients reached
+    /// through the same routes share a single delivery.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to pack
+    /// * `packing` - The packing type to use
+    ///
+    /// # Returns
+    ///
+    /// One routed message per distinct set of routes
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The message cannot be packed
+    /// - A recipient's DID cannot be resolved
+    /// - A forward message cannot be packed
+    pub async fn pack_for_delivery(
+        &self,
+        message: &Message,
+        packing: PackingType,
+    ) -> Result<Vec<RoutedMessage>> {
+        let envelope = pack_message(message, self.plugin.as_ref(), packing)
+            .await
+            .map_err(Error::Core)?;
+
+        let recipients = message.to.clone().unwrap_or_default();
+        if recipients.is_empty() {
+            return Ok(vec![RoutedMessage {
+                recipients,
+                envelope,
+                routes: Vec::new(),
+            }]);
+        }
+
+        let mut deliveries: Vec<RoutedMessage> = Vec::new();
+        for recipient in recipients {
+            let mut routes = Vec::new();
+            for endpoint in resolve_endpoints(self.plugin.resolver(), &recipient).await? {
+                let route = if self.config.routing.forward && !endpoint.routing_keys.is_empty() {
+                    Route {
+                        endpoint: endpoint.uri,
+                        envelope: wrap_in_forwards(
+                            &envelope,
+                            &recipient,
+                            &endpoint.routing_keys,
+                            self.plugin.as_ref(),
+                        )
+                        .await?,
+                        media_type: PackingType::AnonV2.media_type().to_string(),
+                    }
+                } else {
+                    Route {
+                        endpoint: endpoint.uri,
+                        envelope: envelope.clone(),
+                        media_type: packing.media_type().to_string(),
+                    }
+                };
+                routes.push(route);
+            }
+
+            // Recipients reached the same way share a single delivery
+            match deliveries.iter_mut().find(|other| other.routes == routes) {
+                Some(other) => other.recipients.push(recipient),
+                None => deliveries.push(RoutedMessage {
+                    recipients: vec![recipient],
+                    envelope: envelope.clone(),
+                    routes,
+                }),
+            }
+        }
+
+        Ok(deliveries)
+    }
+
+    /// Returns the outbox of messages being sent.
+    #[must_use]
+    pub fn outbox(&self) -> &Arc<dyn Outbox> {
+        &self.outbox
+    }
+
+    /// Returns the queue of messages that could not be delivered.
+    #[must_use]
+    pub fn dead_letters(&self) -> &Arc<dyn DeadLetterQueue> {
+        &self.dead_letters
+    }
+
+    /// Returns a reference to the node's configuration.
+    #[must_use]
+    pub fn config(&self) -> &NodeConfig {
+        &self.config
+    }
+
+    /// Returns the node's thread tracker.
+    ///
+    /// Handlers can keep a clone of the tracker to look up the other
+    /// messages in the thread of a message they receive.
+    #[must_use]
+    pub fn threads(&self) -> Arc<ThreadTracker> {
+        Arc::clone(&self.threads)
+    }
+
+    /// Subscribes to the node's events.
+    ///
+    /// The stream yields every event published after this call, such as
+    /// messages being received, sent or failing to be delivered.
+    pub fn subscribe(&self) -> impl futures::Stream<Item = NodeEvent> {
+        self.events.subscribe()
+    }
+
+    /// Gets the bus the node publishes its events on.
+    ///
+    /// Handlers and middleware can publish events of their own on it.
+    #[must_use]
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Returns the current Unix timestamp according to the node's clock.
+    #[must_use]
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// Returns the node's mediator.
+    ///
+    /// Clients are added to the mediator so that forward messages for them
+    /// are queued for pickup instead of delivered.
+    #[must_use]
+    pub fn mediator(&self) -> Arc<Mediator> {
+        Arc::clone(&self.mediator)
+    }
+
+    /// Returns a reference to the node's plugin.
+    #[must_use]
+    pub fn plugin(&self) -> &dyn DIDCommPlugin {
+        self.plugin.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{actor::HandlerMessage, dispatch::RetryPolicy, mock::MockPlugin};
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
     async fn test_message_handling() {
         let (tx, mut rx) = mpsc::channel(1);
         let (handler_tx, mut handler_rx) = mpsc::channel(32);
@@ -317,7 +1630,9 @@ mod tests {
 
         // Start handler task
         tokio::spawn(async move {
-            while let Some(msg) = handler_rx.recv().await {
+            while let Some(HandlerMessage::HandleMessage(_msg, reply_tx)) = handler_rx.recv().await
+            {
+                reply_tx.send(Ok(())).unwrap();
                 tx.send(()).await.unwrap();
             }
         });
@@ -328,4 +1643,1042 @@ mod tests {
         // Wait for handler to process message
         rx.recv().await.unwrap();
     }
+
+    #[tokio::test]
+    async fn test_receive_records_thread() {
+        use base64::Engine;
+
+        let node = DIDCommNode::new(NodeConfig::default(), MockPlugin);
+        let message = tap_didcomm_core::Message::new("test", json!({"hello": "world"}))
+            .unwrap()
+            .from("did:example:sender")
+            .thid("thread-1");
+        let packed = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&message).unwrap());
+
+        node.receive(packed.as_bytes()).await.unwrap();
+
+        let thread = node.threads().get("thread-1");
+        assert_eq!(thread.len(), 1);
+        assert_eq!(thread[0].direction, Direction::Inbound);
+    }
+
+    #[tokio::test]
+    async fn test_policy_rejects_with_problem_report() {
+        use crate::policy::{PolicyRule, INSUFFICIENT_PROTECTION};
+        use base64::Engine;
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(202))
+            .mount(&mock_server)
+            .await;
+        let config = NodeConfig {
+            dispatch: DispatchConfig {
+                base_url: Some(mock_server.uri()),
+                ..DispatchConfig::default()
+            },
+            policy: PolicyConfig {
+                rules: vec![PolicyRule {
+                    encrypted: true,
+                    ..PolicyRule::new("https://tap.rsvp/schema/1.0#*")
+                }],
+                ..PolicyConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let node = DIDCommNode::new(config, MockPlugin);
+        let events = node.subscribe();
+        let transfer = || {
+            tap_didcomm_core::Message::new("https://tap.rsvp/schema/1.0#Transfer", json!({}))
+                .unwrap()
+                .from("did:example:sender")
+                .to(["did:example:node"])
+        };
+
+        let signed = transfer();
+        let packed = pack_message(&signed, &MockPlugin, PackingType::Signed)
+            .await
+            .unwrap();
+        let result = node.receive(packed.as_bytes()).await;
+        assert!(matches!(result, Err(Error::PolicyViolation(_))));
+
+        // The authenticated sender was told why, and only the report is in the thread
+        let thread = node.threads().get(signed.thread_id());
+        assert_eq!(thread.len(), 1);
+        assert_eq!(thread[0].direction, Direction::Outbound);
+        assert_eq!(thread[0].message.typ.as_str(), PROBLEM_REPORT);
+        assert_eq!(
+            thread[0].message.to,
+            Some(vec!["did:example:sender".to_string()])
+        );
+        let requests = mock_server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        let Some(NodeEvent {
+            kind: EventKind::PolicyRejected { code },
+            ..
+        }) = futures::StreamExt::next(&mut Box::pin(events)).await
+        else {
+            panic!("expected a rejection event");
+        };
+        assert_eq!(code, INSUFFICIENT_PROTECTION);
+
+        // A replay is dropped before the policy, so it is not reported again
+        node.receive(packed.as_bytes()).await.unwrap();
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 1);
+
+        // A plaintext message is rejected without a report to its claimed sender
+        let plaintext = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&transfer()).unwrap());
+        assert!(node.receive(plaintext.as_bytes()).await.is_err());
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 1);
+
+        // Encrypted messages meet the rule
+        let packed = pack_message(&transfer(), &MockPlugin, PackingType::AnonV2)
+            .await
+            .unwrap();
+        node.receive(packed.as_bytes()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_policy_matches_authenticated_sender() {
+        use crate::policy::{PolicyRule, UNTRUSTED_SENDER};
+
+        let config = NodeConfig {
+            policy: PolicyConfig {
+                allow_senders: Some(vec!["did:example:bank".to_string()]),
+                rules: vec![PolicyRule {
+                    authenticated: true,
+                    ..PolicyRule::new("https://tap.rsvp/schema/1.0#Authorize")
+                }],
+                ..PolicyConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let node = DIDCommNode::new(config, MockPlugin);
+        let message = |typ: &str| {
+            tap_didcomm_core::Message::new(typ, json!({}))
+                .unwrap()
+                .from("did:example:bank")
+                .to(["did:example:node"])
+        };
+        let receive = |message: tap_didcomm_core::Message, packing| {
+            let node = &node;
+            async move {
+                let packed = pack_message(&message, &MockPlugin, packing).await.unwrap();
+                node.receive(packed.as_bytes()).await
+            }
+        };
+
+        let authorize = "https://tap.rsvp/schema/1.0#Authorize";
+        receive(message("test"), PackingType::AuthcryptV2)
+            .await
+            .unwrap();
+        receive(message(authorize), PackingType::AuthcryptV2)
+            .await
+            .unwrap();
+
+        assert_eq!(node.threads().thread_ids().len(), 2);
+
+        // Claiming to be the bank in an anoncrypted message does not pass the allowlist
+        let events = node.subscribe();
+        let result = receive(message("test"), PackingType::AnonV2).await;
+        assert!(matches!(result, Err(Error::PolicyViolation(_))));
+        let Some(NodeEvent {
+            kind: EventKind::PolicyRejected { code },
+            ..
+        }) = futures::StreamExt::next(&mut Box::pin(events)).await
+        else {
+            panic!("expected a rejection event");
+        };
+        assert_eq!(code, UNTRUSTED_SENDER);
+        assert!(receive(message(authorize), PackingType::AnonV2)
+            .await
+            .is_err());
+
+        // Nothing was reported to the claimed sender
+        assert_eq!(node.threads().thread_ids().len(), 2);
+    }
+
+    #[test]
+    fn test_try_new_reports_config_errors() {
+        let mut config = NodeConfig::default();
+        config.dispatch.tls.ca_certificates = vec!["/nonexistent/ca.pem".into()];
+        assert!(matches!(
+            DIDCommNode::try_new(config, MockPlugin),
+            Err(Error::Config(_))
+        ));
+        assert!(DIDCommNode::try_new(NodeConfig::default(), MockPlugin).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid node configuration")]
+    fn test_new_panics_on_config_errors() {
+        let mut config = NodeConfig::default();
+        config.dispatch.tls.ca_certificates = vec!["/nonexistent/ca.pem".into()];
+        let _ = DIDCommNode::new(config, MockPlugin);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits_and_quotas() {
+        use crate::limits::RateLimit;
+        use base64::Engine;
+
+        let config = NodeConfig {
+            max_message_size: 1024,
+            limits: LimitsConfig {
+                per_sender: Some(RateLimit::new(1, 0.001)),
+                per_source: Some(RateLimit::new(1, 0.001)),
+                ..LimitsConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let node = DIDCommNode::new(config, MockPlugin);
+        let message = |from: &str| {
+            tap_didcomm_core::Message::new("test", json!({}))
+                .unwrap()
+                .from(from)
+                .to(["did:example:node"])
+        };
+        let authcrypt = |from: &str| {
+            let message = message(from);
+            async move {
+                tap_didcomm_core::pack_message(&message, &MockPlugin, PackingType::AuthcryptV2)
+                    .await
+                    .unwrap()
+            }
+        };
+        let plaintext = |from: &str| {
+            base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .encode(serde_json::to_vec(&message(from)).unwrap())
+        };
+
+        assert!(node
+            .receive(authcrypt("did:example:alice").await.as_bytes())
+            .await
+            .is_ok());
+        assert!(matches!(
+            node.receive(authcrypt("did:example:alice").await.as_bytes())
+                .await,
+            Err(Error::RateLimited(_))
+        ));
+        assert!(node
+            .receive(authcrypt("did:example:bob").await.as_bytes())
+            .await
+            .is_ok());
+
+        // A claimed sender neither uses up nor is held to alice's limit
+        for _ in 0..2 {
+            assert!(node
+                .receive(plaintext("did:example:alice").as_bytes())
+                .await
+                .is_ok());
+        }
+        assert!(matches!(
+            node.receive(&[b'A'; 2048]).await,
+            Err(Error::QuotaExceeded(_))
+        ));
+
+        let addr = IpAddr::from([192, 0, 2, 1]);
+        assert!(node.check_source(addr).is_ok());
+        assert!(matches!(
+            node.check_source(addr),
+            Err(Error::RateLimited(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_stored() {
+        use crate::store::MemoryMessageStore;
+        use base64::Engine;
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(202))
+            .mount(&mock_server)
+            .await;
+        let config = NodeConfig {
+            dispatch: DispatchConfig {
+                base_url: Some(mock_server.uri()),
+                ..DispatchConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let mut node = DIDCommNode::new(config, MockPlugin);
+        node.set_message_store(MemoryMessageStore::new());
+        let store = node.message_store.clone().unwrap();
+
+        let request = tap_didcomm_core::Message::new("test", json!({}))
+            .unwrap()
+            .from("did:example:sender");
+        let packed = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&request).unwrap());
+        node.receive(packed.as_bytes()).await.unwrap();
+        let reply = request
+            .reply("test", json!({}))
+            .unwrap()
+            .to(["did:example:sender"]);
+        node.send(&reply, PackingType::AnonV2).await.unwrap();
+
+        let stored = store.get(request.id.as_str()).await.unwrap().unwrap();
+        assert_eq!(stored.envelope.as_deref(), Some(packed.as_str()));
+        assert_eq!(stored.meta.envelope_size, packed.len());
+        let history = store.list_by_peer("did:example:sender").await.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].direction, Direction::Outbound);
+        assert_eq!(history[1].meta.packing, Some(PackingType::AnonV2));
+    }
+
+    #[tokio::test]
+    async fn test_middleware_pipeline() {
+        use crate::{
+            handler::UnpackMetadata,
+            middleware::{Flow, Middleware},
+        };
+        use base64::Engine;
+
+        /// Rejects `blocked` messages, tags received ones and holds back drafts
+        struct Policy;
+
+        #[async_trait::async_trait]
+        impl Middleware for Policy {
+            async fn inbound(&self, msg: &mut Message, _meta: &UnpackMetadata) -> Result<Flow> {
+                if msg.typ.as_str() == "blocked" {
+                    return Err(Error::InvalidFormat("Blocked".into()));
+                }
+                msg.pthid = Some("checked".to_string());
+                Ok(Flow::Continue)
+            }
+
+            async fn outbound(&self, msg: &mut Message, _packing: PackingType) -> Result<Flow> {
+                if msg.typ.as_str() == "draft" {
+                    return Ok(Flow::Stop);
+                }
+                Ok(Flow::Continue)
+            }
+        }
+
+        let mut node = DIDCommNode::new(NodeConfig::default(), MockPlugin);
+        node.add_middleware(Policy);
+
+        for (typ, accepted) in [("blocked", false), ("test", true)] {
+            let message = tap_didcomm_core::Message::new(typ, json!({})).unwrap();
+            let packed = base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .encode(serde_json::to_vec(&message).unwrap());
+            let result = node.receive(packed.as_bytes()).await;
+            assert_eq!(result.is_ok(), accepted);
+            assert_eq!(
+                node.threads()
+                    .latest(message.thread_id())
+                    .and_then(|entry| entry.message.pthid),
+                accepted.then(|| "checked".to_string())
+            );
+        }
+
+        let draft = tap_didcomm_core::Message::new("draft", json!({}))
+            .unwrap()
+            .to(["did:example:recipient"]);
+        let results = node.send(&draft, PackingType::AnonV2).await.unwrap();
+        assert!(results.is_empty());
+        assert!(node.outbox().list(None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_receive_reports_busy_handler() {
+        use base64::Engine;
+
+        let config = NodeConfig {
+            handlers: HandlerConfig {
+                timeout_ms: 50,
+                ..HandlerConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let mut node = DIDCommNode::new(config, MockPlugin);
+        let (handler_tx, mut handler_rx) = mpsc::channel(1);
+        node.register_handler("test", HandlerHandle::new(handler_tx));
+
+        let packed: Vec<_> = (0..2)
+            .map(|_| {
+                let message = tap_didcomm_core::Message::new("test", json!({})).unwrap();
+                base64::engine::general_purpose::URL_SAFE_NO_PAD
+                    .encode(serde_json::to_vec(&message).unwrap())
+            })
+            .collect();
+
+        // The handler does not reply in time, so its queue stays full
+        assert!(matches!(
+            node.receive(packed[0].as_bytes()).await,
+            Err(Error::Actor(_))
+        ));
+        assert!(matches!(
+            node.receive(packed[1].as_bytes()).await,
+            Err(Error::Busy(_))
+        ));
+
+        // The busy message was not recorded, so it can be retried
+        assert!(handler_rx.recv().await.is_some());
+        tokio::spawn(async move {
+            while let Some(HandlerMessage::HandleMessage(_msg, reply_tx)) = handler_rx.recv().await
+            {
+                reply_tx.send(Ok(())).unwrap();
+            }
+        });
+        node.receive(packed[1].as_bytes()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_receive_rejects_expired_message() {
+        use base64::Engine;
+        use tap_didcomm_core::{clock::FixedClock, Error as CoreError};
+
+        let clock = Arc::new(FixedClock::new(1_000));
+        let mut node = DIDCommNode::new(NodeConfig::default(), MockPlugin);
+        node.set_clock(clock.clone());
+
+        let mut message = tap_didcomm_core::Message::new("test", json!({}))
+            .unwrap()
+            .expires_at(1_100);
+        message.created_time = 1_000;
+        let packed = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&message).unwrap());
+
+        node.receive(packed.as_bytes()).await.unwrap();
+
+        clock.advance(1_000);
+        let result = node.receive(packed.as_bytes()).await;
+        assert!(matches!(
+            result,
+            Err(Error::Core(CoreError::MessageExpired(_)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_receive_handles_duplicates() {
+        use base64::Engine;
+
+        let mut node = DIDCommNode::new(NodeConfig::default(), MockPlugin);
+        let message = tap_didcomm_core::Message::new("test", json!({}))
+            .unwrap()
+            .from("did:example:sender");
+        let packed = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&message).unwrap());
+
+        node.receive(packed.as_bytes()).await.unwrap();
+        node.receive(packed.as_bytes()).await.unwrap();
+        assert_eq!(node.threads().get(message.thread_id()).len(), 1);
+
+        node.config.replay.policy = DuplicatePolicy::Reject;
+        let result = node.receive(packed.as_bytes()).await;
+        assert!(matches!(result, Err(Error::Duplicate(_))));
+    }
+
+    #[tokio::test]
+    async fn test_receive_rejects_replay_after_window() {
+        use base64::Engine;
+        use tap_didcomm_core::{clock::FixedClock, Error as CoreError};
+
+        let clock = Arc::new(FixedClock::new(1_000));
+        let mut node = DIDCommNode::new(NodeConfig::default(), MockPlugin);
+        node.set_clock(clock.clone());
+
+        // A message that expires long after its key would be retained
+        let mut message = tap_didcomm_core::Message::new("test", json!({}))
+            .unwrap()
+            .from("did:example:sender")
+            .expires_at(1_000 + 30 * 24 * 60 * 60);
+        message.created_time = 1_000;
+        let packed = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&message).unwrap());
+        node.receive(packed.as_bytes()).await.unwrap();
+
+        clock.advance(node.config.replay.max_retention + 1);
+        node.replay_store.prune(clock.now()).await.unwrap();
+        let result = node.receive(packed.as_bytes()).await;
+        assert!(matches!(
+            result,
+            Err(Error::Core(CoreError::MessageExpired(_)))
+        ));
+        assert_eq!(node.threads().get(message.thread_id()).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_replay_key_is_kept_once_handled() {
+        use crate::handler::{HandlerContext, MessageHandler, UnpackMetadata};
+        use base64::Engine;
+        use std::sync::atomic::AtomicUsize;
+        use tap_didcomm_core::{Attachment, AttachmentData};
+
+        struct FailOnce(Arc<AtomicUsize>);
+
+        #[async_trait::async_trait]
+        impl MessageHandler for FailOnce {
+            async fn handle(
+                &self,
+                _ctx: HandlerContext<'_>,
+                _msg: Message,
+                _meta: UnpackMetadata,
+            ) -> Result<Vec<Message>> {
+                if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return Err(Error::Storage("Database unavailable".into()));
+                }
+                Ok(Vec::new())
+            }
+        }
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut node = DIDCommNode::new(NodeConfig::default(), MockPlugin);
+        node.config.replay.policy = DuplicatePolicy::Reject;
+        node.config.attachments.fetch_linked = true;
+        node.register_message_handler("test", FailOnce(Arc::clone(&calls)));
+        let encode = |message: &Message| {
+            base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .encode(serde_json::to_vec(message).unwrap())
+        };
+
+        // A message that fails before any handler runs is accepted again
+        let unresolved = tap_didcomm_core::Message::new("test", json!({}))
+            .unwrap()
+            .from("did:example:sender")
+            .with_attachment(Attachment::new(
+                "doc",
+                AttachmentData::links(["mem://missing"], "missing".to_string()),
+            ));
+        for _ in 0..2 {
+            let result = node.receive(encode(&unresolved).as_bytes()).await;
+            assert!(matches!(result, Err(Error::Storage(_))));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        // Once a handler ran, the message is a duplicate even if it failed,
+        // so the handler does not run twice
+        let message = tap_didcomm_core::Message::new("test", json!({}))
+            .unwrap()
+            .from("did:example:sender");
+        let packed = encode(&message);
+        let result = node.receive(packed.as_bytes()).await;
+        assert!(matches!(result, Err(Error::Actor(_))));
+        let result = node.receive(packed.as_bytes()).await;
+        assert!(matches!(result, Err(Error::Duplicate(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_receive_routes_by_protocol() {
+        use crate::router::{ProblemReporter, PROBLEM_REPORT};
+        use base64::Engine;
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let config = NodeConfig {
+            dispatch: DispatchConfig {
+                base_url: Some(mock_server.uri()),
+                ..DispatchConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let mut node = DIDCommNode::new(config, MockPlugin);
+
+        let (handler_tx, mut handler_rx) = mpsc::channel(1);
+        node.register_pattern_handler(
+            "https://tap.rsvp/schema/1.0",
+            HandlerHandle::new(handler_tx),
+        );
+        let (default_tx, default_rx) = mpsc::channel(1);
+        node.set_default_handler(HandlerHandle::new(default_tx));
+        let node = Arc::new(node);
+        tokio::spawn(ProblemReporter::new(Arc::clone(&node)).run(default_rx));
+
+        let (types_tx, mut types_rx) = mpsc::channel(2);
+        tokio::spawn(async move {
+            while let Some(HandlerMessage::HandleMessage(msg, reply_tx)) = handler_rx.recv().await {
+                types_tx.send(msg.0.typ.0).await.unwrap();
+                reply_tx.send(Ok(())).unwrap();
+            }
+        });
+
+        let mut thids = Vec::new();
+        for typ in [
+            "https://tap.rsvp/schema/1.1/Transfer",
+            "https://example.com/unknown/1.0/hello",
+        ] {
+            let message = tap_didcomm_core::Message::new(typ, json!({}))
+                .unwrap()
+                .from("did:example:sender")
+                .to(["did:example:recipient"]);
+            let packed = base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .encode(serde_json::to_vec(&message).unwrap());
+            node.receive(packed.as_bytes()).await.unwrap();
+            thids.push(message.thread_id().to_string());
+        }
+
+        assert_eq!(
+            types_rx.recv().await.unwrap(),
+            "https://tap.rsvp/schema/1.1/Transfer"
+        );
+        assert!(types_rx.try_recv().is_err());
+
+        // Only the unrouted message was answered with a problem report
+        assert_eq!(node.threads().get(&thids[0]).len(), 1);
+        let reply = node.threads().latest(&thids[1]).unwrap();
+        assert_eq!(reply.direction, Direction::Outbound);
+        assert_eq!(reply.message.typ.as_str(), PROBLEM_REPORT);
+        assert_eq!(
+            reply.message.to,
+            Some(vec!["did:example:sender".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_message_handler_replies_are_sent() {
+        use crate::handler::{HandlerContext, MessageHandler, UnpackMetadata};
+        use base64::Engine;
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+        struct Ping;
+
+        #[async_trait::async_trait]
+        impl MessageHandler for Ping {
+            async fn handle(
+                &self,
+                ctx: HandlerContext<'_>,
+                msg: Message,
+                meta: UnpackMetadata,
+            ) -> Result<Vec<Message>> {
+                assert_eq!(ctx.identity(), Some("did:example:node"));
+                assert_eq!(ctx.threads().get(msg.thread_id()).len(), 1);
+                assert_eq!(meta.media_type(), "application/didcomm-plain+json");
+                assert!(meta.envelope_size > 0);
+                let mut pong = msg.reply("pong", json!({}))?;
+                pong.from = None;
+                Ok(vec![pong])
+            }
+        }
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let config = NodeConfig {
+            did: Some("did:example:node".to_string()),
+            dispatch: DispatchConfig {
+                base_url: Some(mock_server.uri()),
+                ..DispatchConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let mut node = DIDCommNode::new(config, MockPlugin);
+        node.register_message_handler("ping", Ping);
+
+        let message = tap_didcomm_core::Message::new("ping", json!({}))
+            .unwrap()
+            .from("did:example:sender");
+        let packed = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&message).unwrap());
+        node.receive(packed.as_bytes()).await.unwrap();
+
+        let reply = node.threads().latest(message.thread_id()).unwrap();
+        assert_eq!(reply.direction, Direction::Outbound);
+        assert_eq!(reply.message.typ.as_str(), "pong");
+        assert_eq!(reply.message.from.as_deref(), Some("did:example:node"));
+    }
+
+    #[tokio::test]
+    async fn test_handler_receives_unpack_metadata() {
+        use crate::handler::{HandlerContext, MessageHandler, UnpackMetadata};
+
+        struct Record(mpsc::Sender<UnpackMetadata>);
+
+        #[async_trait::async_trait]
+        impl MessageHandler for Record {
+            async fn handle(
+                &self,
+                _ctx: HandlerContext<'_>,
+                _msg: Message,
+                meta: UnpackMetadata,
+            ) -> Result<Vec<Message>> {
+                self.0.send(meta).await.unwrap();
+                Ok(Vec::new())
+            }
+        }
+
+        let (tx, mut rx) = mpsc::channel(4);
+        let mut node = DIDCommNode::new(NodeConfig::default(), MockPlugin);
+        node.register_message_handler("test", Record(tx));
+
+        let message = tap_didcomm_core::Message::new("test", json!({}))
+            .unwrap()
+            .from("did:example:alice")
+            .to(["did:example:node"]);
+        let packed = pack_message(&message, &MockPlugin, PackingType::AuthcryptV2)
+            .await
+            .unwrap();
+        node.receive(packed.as_bytes()).await.unwrap();
+
+        let meta = rx.recv().await.unwrap();
+        assert_eq!(meta.packing, Some(PackingType::AuthcryptV2));
+        assert_eq!(
+            meta.authenticated_sender.as_deref(),
+            Some("did:example:alice")
+        );
+        assert_eq!(meta.signed_by, None);
+        assert_eq!(meta.sender(), Some("did:example:alice"));
+        assert_eq!(meta.envelope_size, packed.len());
+        assert_eq!(meta.media_type(), "application/didcomm-encrypted+json");
+
+        // An anoncrypted message claiming a sender authenticates no one
+        let message = tap_didcomm_core::Message::new("test", json!({}))
+            .unwrap()
+            .from("did:example:alice")
+            .to(["did:example:node"]);
+        let packed = pack_message(&message, &MockPlugin, PackingType::AnonV2)
+            .await
+            .unwrap();
+        node.receive(packed.as_bytes()).await.unwrap();
+
+        let meta = rx.recv().await.unwrap();
+        assert_eq!(meta.packing, Some(PackingType::AnonV2));
+        assert_eq!(meta.sender(), None);
+        assert_eq!(
+            UnpackMetadata::claimed_sender(&message),
+            Some("did:example:alice")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_delivers_packed_envelope() {
+        use wiremock::{
+            matchers::{body_string, header, method, path},
+            Mock, MockServer, ResponseTemplate,
+        };
+
+        let message = tap_didcomm_core::Message::new("test", json!({"secret": "value"}))
+            .unwrap()
+            .from("did:example:sender")
+            .to(["did:example:recipient"]);
+        let packed = pack_message(&message, &MockPlugin, PackingType::AuthcryptV2)
+            .await
+            .unwrap();
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/didcomm"))
+            .and(header("content-type", "application/didcomm-encrypted+json"))
+            .and(body_string(packed))
+            .respond_with(ResponseTemplate::new(202).set_body_string("reply"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let config = NodeConfig {
+            dispatch: DispatchConfig {
+                base_url: Some(mock_server.uri()),
+                ..DispatchConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let node = DIDCommNode::new(config, MockPlugin);
+        let results = node.send(&message, PackingType::AuthcryptV2).await.unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].message_id, message.id.as_str());
+        assert_eq!(results[0].recipients, vec!["did:example:recipient"]);
+        assert_eq!(
+            results[0].endpoint,
+            format!("{}/didcomm", mock_server.uri())
+        );
+        assert_eq!(results[0].status, 202);
+        assert_eq!(results[0].reply.as_deref(), Some("reply"));
+
+        let entries = node.outbox().list(None).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].status, OutboxStatus::Delivered);
+    }
+
+    #[tokio::test]
+    async fn test_send_stores_dead_letter() {
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(202))
+            .mount(&mock_server)
+            .await;
+
+        let config = NodeConfig {
+            dispatch: DispatchConfig {
+                base_url: Some(mock_server.uri()),
+                retry: RetryPolicy {
+                    max_attempts: 2,
+                    initial_backoff_ms: 1,
+                    ..RetryPolicy::default()
+                },
+                ..DispatchConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let node = DIDCommNode::new(config, MockPlugin);
+        let message = tap_didcomm_core::Message::new("test", json!({}))
+            .unwrap()
+            .to(["did:example:recipient"]);
+
+        let Err(Error::Undeliverable(attempts)) = node.send(&message, PackingType::AnonV2).await
+        else {
+            panic!("expected an undeliverable error");
+        };
+        assert_eq!(attempts.len(), 2);
+
+        let letters = node.dead_letters().list().await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].message_id, message.id.as_str());
+        assert_eq!(letters[0].attempts, attempts);
+
+        let result = node.replay_dead_letter(&letters[0].id).await.unwrap();
+        assert_eq!(result.status, 202);
+        assert!(node.dead_letters().list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_events_are_published() {
+        use base64::Engine;
+        use futures::StreamExt;
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(202))
+            .mount(&mock_server)
+            .await;
+
+        let config = NodeConfig {
+            dispatch: DispatchConfig {
+                base_url: Some(mock_server.uri()),
+                retry: RetryPolicy {
+                    max_attempts: 2,
+                    initial_backoff_ms: 1,
+                    ..RetryPolicy::default()
+                },
+                ..DispatchConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let node = DIDCommNode::new(config, MockPlugin);
+        let events = node.subscribe();
+
+        let received = tap_didcomm_core::Message::new("test", json!({}))
+            .unwrap()
+            .from("did:example:sender");
+        let packed = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&received).unwrap());
+        node.receive(packed.as_bytes()).await.unwrap();
+        node.receive(packed.as_bytes()).await.unwrap();
+        assert!(node.receive(b"not an envelope").await.is_err());
+
+        let sent = tap_didcomm_core::Message::new("test", json!({}))
+            .unwrap()
+            .to(["did:example:recipient"])
+            .thid("thread-1");
+        node.send(&sent, PackingType::AnonV2).await.unwrap();
+
+        let events: Vec<NodeEvent> = events.take(5).collect().await;
+        assert_eq!(events[0].kind, EventKind::MessageReceived);
+        assert_eq!(events[0].message_id.as_deref(), Some(received.id.as_str()));
+        assert_eq!(events[0].peers, ["did:example:sender"]);
+        assert!(events[0].elapsed.is_some());
+        assert_eq!(events[1].kind, EventKind::DuplicateDropped);
+        assert!(matches!(events[2].kind, EventKind::UnpackFailed { .. }));
+        assert!(matches!(
+            events[3].kind,
+            EventKind::RetryScheduled { attempt: 1, .. }
+        ));
+        assert!(matches!(events[4].kind, EventKind::MessageSent { .. }));
+        assert_eq!(events[4].message_id.as_deref(), Some(sent.id.as_str()));
+        assert_eq!(events[4].thread_id.as_deref(), Some("thread-1"));
+        assert_eq!(events[4].peers, ["did:example:recipient"]);
+    }
+
+    #[tokio::test]
+    async fn test_outbox_resumes_interrupted_delivery() {
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let node = DIDCommNode::new(NodeConfig::default(), MockPlugin);
+        let route = Route {
+            endpoint: mock_server.uri(),
+            envelope: "envelope".to_string(),
+            media_type: PackingType::AnonV2.media_type().to_string(),
+        };
+        // Left in flight by a node that stopped mid-delivery
+        let interrupted = OutboxEntry {
+            status: OutboxStatus::InFlight,
+            attempts: 1,
+            ..OutboxEntry::new("msg-1", Vec::new(), vec![route.clone()], 0)
+        };
+        let pending = OutboxEntry::new("msg-2", Vec::new(), vec![route], node.now());
+        node.outbox()
+            .enqueue(&[interrupted.clone(), pending.clone()])
+            .await
+            .unwrap();
+
+        assert_eq!(node.process_outbox().await.unwrap(), 2);
+        assert_eq!(node.process_outbox().await.unwrap(), 0);
+        let delivered = node
+            .outbox()
+            .list(Some(OutboxStatus::Delivered))
+            .await
+            .unwrap();
+        assert_eq!(delivered.len(), 2);
+        assert_eq!(delivered[0].id, interrupted.id);
+        assert_eq!(delivered[0].attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn test_slow_delivery_keeps_its_lease() {
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(202).set_delay(Duration::from_millis(2_500)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let config = NodeConfig {
+            dispatch: DispatchConfig {
+                base_url: Some(mock_server.uri()),
+                ..DispatchConfig::default()
+            },
+            outbox: OutboxConfig {
+                lease_secs: 1,
+                ..OutboxConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let node = DIDCommNode::new(config, MockPlugin);
+        let message = tap_didcomm_core::Message::new("test", json!({}))
+            .unwrap()
+            .to(["did:example:recipient"]);
+
+        // The worker runs while the send is still waiting for a reply
+        let (sent, claimed) = tokio::join!(node.send(&message, PackingType::AnonV2), async {
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            node.process_outbox().await
+        });
+        assert_eq!(sent.unwrap().len(), 1);
+        assert_eq!(claimed.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_pack_for_delivery_without_endpoints() {
+        let node = DIDCommNode::new(NodeConfig::default(), MockPlugin);
+        let message = tap_didcomm_core::Message::new("test", json!({}))
+            .unwrap()
+            .to(["did:example:recipient"]);
+
+        let routed = node
+            .pack_for_delivery(&message, PackingType::AnonV2)
+            .await
+            .unwrap();
+        assert_eq!(routed.len(), 1);
+        assert!(routed[0].routes.is_empty());
+        assert_eq!(
+            routed[0].envelope,
+            pack_message(&message, &MockPlugin, PackingType::AnonV2)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_without_endpoint() {
+        let node = DIDCommNode::new(NodeConfig::default(), MockPlugin);
+        let message = tap_didcomm_core::Message::new("test", json!({}))
+            .unwrap()
+            .to(["did:example:recipient"]);
+
+        assert!(matches!(
+            node.send(&message, PackingType::AnonV2).await,
+            Err(Error::UnknownRecipient(_))
+        ));
+        assert!(node.outbox().list(None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mediator_queues_forward() {
+        use crate::routing::forward_message;
+        use base64::Engine;
+
+        let config = NodeConfig {
+            mediator: MediatorConfig {
+                enabled: true,
+                ..MediatorConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let node = DIDCommNode::new(config, MockPlugin);
+        node.mediator().add_recipient("did:example:bob");
+
+        let forward = forward_message("ZW52ZWxvcGU", "did:example:bob#key-1").unwrap();
+        let packed = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&forward).unwrap());
+        node.receive(packed.as_bytes()).await.unwrap();
+
+        let queue = node.mediator().queue();
+        assert_eq!(queue.count("did:example:bob#key-1"), 1);
+        assert!(node.threads().get(forward.thread_id()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mediator_relays_forward_through_outbox() {
+        use crate::{mediator::tests::RelayPlugin, routing::forward_message};
+        use base64::Engine;
+
+        let config = NodeConfig {
+            mediator: MediatorConfig {
+                enabled: true,
+                ..MediatorConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let node = DIDCommNode::new(config, RelayPlugin("https://m2.example.com".into()));
+        node.mediator().add_relayed_recipient("did:example:bob");
+
+        let forward = forward_message("ZW52ZWxvcGU", "did:example:bob#key-1").unwrap();
+        let packed = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&forward).unwrap());
+        node.receive(packed.as_bytes()).await.unwrap();
+
+        // Receiving does not deliver, the outbox worker does
+        let entries = node.outbox().list(None).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].status, OutboxStatus::Pending);
+        assert_eq!(entries[0].message_id, forward.id.as_str());
+        assert_eq!(entries[0].recipients, vec!["did:example:bob#key-1"]);
+        assert_eq!(entries[0].routes[0].endpoint, "https://m2.example.com");
+    }
 }
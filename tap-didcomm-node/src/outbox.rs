@@ -0,0 +1,743 @@
+//! Persistent outbox for outgoing messages.
+//!
+//! Packed messages are written to the outbox before they are delivered, so a
+//! message is not lost if the node stops mid-delivery. Every entry moves
+//! through the statuses of [`OutboxStatus`]; an entry left in flight for
+//! longer than its lease, because the node stopped while delivering it, is
+//! returned to pending and picked up again by the outbox worker. The lease is
+//! renewed for as long as delivery is in progress, and delivered and failed
+//! entries are pruned once they are older than the retention period.
+//!
+//! # Components
+//!
+//! - [`Outbox`]: Storage for outgoing messages and their delivery status
+//! - [`MemoryOutbox`]: An in-memory outbox, for tests
+//! - [`SqliteOutbox`]: An outbox persisted to an `SQLite` database, available
+//!   with the `sqlite` feature
+//! - [`OutboxConfig`]: How often and in what batches the worker delivers
+//!
+//! # Examples
+//!
+//! ```rust
+//! use tap_didcomm_node::outbox::{MemoryOutbox, Outbox, OutboxEntry, OutboxStatus};
+//!
+//! # async fn example() -> tap_didcomm_node::error::Result<()> {
+//! let outbox = MemoryOutbox::new();
+//! let entry = OutboxEntry::new("msg-1", vec!["did:example:bob".into()], Vec::new(), 1_000);
+//! outbox.enqueue(&[entry]).await?;
+//!
+//! let claimed = outbox.claim(10, 1_001).await?;
+//! assert_eq!(claimed[0].status, OutboxStatus::InFlight);
+//! outbox.complete(&claimed[0].id, OutboxStatus::Delivered, None, 1_002).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Mutex;
+use tap_didcomm_core::MessageId;
+
+use crate::{
+    error::{Error, Result},
+    routing::Route,
+};
+
+/// Configuration for the outbox worker.
+#[derive(Debug, Clone)]
+pub struct OutboxConfig {
+    /// How often the worker checks for pending entries, in milliseconds
+    pub poll_interval_ms: u64,
+
+    /// The maximum number of entries delivered per check
+    pub batch_size: usize,
+
+    /// How long an entry may stay in flight, in seconds, before it is assumed
+    /// to have been interrupted and is delivered again
+    pub lease_secs: u64,
+
+    /// How long delivered and failed entries are kept, in seconds
+    pub retention_secs: u64,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_ms: 1_000,
+            batch_size: 16,
+            lease_secs: 300,
+            retention_secs: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// The delivery status of an outbox entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutboxStatus {
+    /// Waiting to be delivered
+    Pending,
+    /// Being delivered
+    InFlight,
+    /// Delivered to an endpoint
+    Delivered,
+    /// Could not be delivered over any route
+    Failed,
+}
+
+impl OutboxStatus {
+    /// Returns the name the status is stored under
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::InFlight => "in_flight",
+            Self::Delivered => "delivered",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for OutboxStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pending" => Ok(Self::Pending),
+            "in_flight" => Ok(Self::InFlight),
+            "delivered" => Ok(Self::Delivered),
+            "failed" => Ok(Self::Failed),
+            _ => Err(Error::InvalidFormat(format!("Unknown outbox status: {s}"))),
+        }
+    }
+}
+
+/// A packed message in the outbox.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxEntry {
+    /// The ID of the entry
+    pub id: String,
+
+    /// The ID of the message
+    pub message_id: String,
+
+    /// The recipients the message is delivered for
+    pub recipients: Vec<String>,
+
+    /// The routes to try, in order
+    pub routes: Vec<Route>,
+
+    /// The delivery status
+    pub status: OutboxStatus,
+
+    /// The number of times delivery has been started
+    pub attempts: u32,
+
+    /// Why the last delivery failed, if it did
+    pub last_error: Option<String>,
+
+    /// The Unix timestamp at which the entry was enqueued
+    pub created_at: u64,
+
+    /// The Unix timestamp of the last status change
+    pub updated_at: u64,
+}
+
+impl OutboxEntry {
+    /// Creates a pending entry with a random ID.
+    #[must_use]
+    pub fn new(
+        message_id: impl Into<String>,
+        recipients: Vec<String>,
+        routes: Vec<Route>,
+        now: u64,
+    ) -> Self {
+        Self {
+            id: MessageId::random().as_str().to_string(),
+            message_id: message_id.into(),
+            recipients,
+            routes,
+            status: OutboxStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+/// Storage for outgoing messages and their delivery status.
+#[async_trait]
+pub trait Outbox: Send + Sync {
+    /// Stores entries, either all of them or none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entries cannot be written
+    async fn enqueue(&self, entries: &[OutboxEntry]) -> Result<()>;
+
+    /// Moves the oldest pending entries in flight.
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The maximum number of entries to claim
+    /// * `now` - The current Unix timestamp
+    ///
+    /// # Returns
+    ///
+    /// The claimed entries, oldest first
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the outbox cannot be read or written
+    async fn claim(&self, limit: usize, now: u64) -> Result<Vec<OutboxEntry>>;
+
+    /// Records the outcome of delivering an entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the entry
+    /// * `status` - The new status
+    /// * `error` - Why delivery failed, if it did
+    /// * `now` - The current Unix timestamp
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no such entry or it cannot be written
+    async fn complete(
+        &self,
+        id: &str,
+        status: OutboxStatus,
+        error: Option<String>,
+        now: u64,
+    ) -> Result<()>;
+
+    /// Returns entries that went in flight before `before` to pending.
+    ///
+    /// # Returns
+    ///
+    /// The number of entries returned to pending
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the outbox cannot be written
+    async fn requeue_stale(&self, before: u64, now: u64) -> Result<usize>;
+
+    /// Renews the lease of an entry in flight, so that it is not returned to
+    /// pending while it is still being delivered.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the outbox cannot be written
+    async fn renew(&self, id: &str, now: u64) -> Result<()>;
+
+    /// Removes delivered and failed entries last updated before `before`.
+    ///
+    /// # Returns
+    ///
+    /// The number of entries removed
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the outbox cannot be written
+    async fn prune(&self, before: u64) -> Result<usize>;
+
+    /// Gets an entry by ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the outbox cannot be read
+    async fn get(&self, id: &str) -> Result<Option<OutboxEntry>>;
+
+    /// Lists entries, oldest first, optionally only those with a status.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the outbox cannot be read
+    async fn list(&self, status: Option<OutboxStatus>) -> Result<Vec<OutboxEntry>>;
+}
+
+/// An in-memory outbox.
+///
+/// Entries are lost when the node restarts, so this is meant for tests.
+#[derive(Debug, Default)]
+pub struct MemoryOutbox {
+    entries: Mutex<Vec<OutboxEntry>>,
+}
+
+impl MemoryOutbox {
+    /// Creates an empty outbox
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Outbox for MemoryOutbox {
+    async fn enqueue(&self, entries: &[OutboxEntry]) -> Result<()> {
+        self.entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .extend_from_slice(entries);
+        Ok(())
+    }
+
+    async fn claim(&self, limit: usize, now: u64) -> Result<Vec<OutboxEntry>> {
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        Ok(entries
+            .iter_mut()
+            .filter(|entry| entry.status == OutboxStatus::Pending)
+            .take(limit)
+            .map(|entry| {
+                entry.status = OutboxStatus::InFlight;
+                entry.attempts += 1;
+                entry.updated_at = now;
+                entry.clone()
+            })
+            .collect())
+    }
+
+    async fn complete(
+        &self,
+        id: &str,
+        status: OutboxStatus,
+        error: Option<String>,
+        now: u64,
+    ) -> Result<()> {
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let entry = entries
+            .iter_mut()
+            .find(|entry| entry.id == id)
+            .ok_or_else(|| Error::Storage(format!("No outbox entry {id}")))?;
+        entry.status = status;
+        entry.last_error = error;
+        entry.updated_at = now;
+        Ok(())
+    }
+
+    async fn requeue_stale(&self, before: u64, now: u64) -> Result<usize> {
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut requeued = 0;
+        for entry in entries
+            .iter_mut()
+            .filter(|entry| entry.status == OutboxStatus::InFlight && entry.updated_at < before)
+        {
+            entry.status = OutboxStatus::Pending;
+            entry.updated_at = now;
+            requeued += 1;
+        }
+        Ok(requeued)
+    }
+
+    async fn renew(&self, id: &str, now: u64) -> Result<()> {
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if let Some(entry) = entries
+            .iter_mut()
+            .find(|entry| entry.id == id && entry.status == OutboxStatus::InFlight)
+        {
+            entry.updated_at = now;
+        }
+        Ok(())
+    }
+
+    async fn prune(&self, before: u64) -> Result<usize> {
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let count = entries.len();
+        entries.retain(|entry| {
+            !matches!(entry.status, OutboxStatus::Delivered | OutboxStatus::Failed)
+                || entry.updated_at >= before
+        });
+        Ok(count - entries.len())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<OutboxEntry>> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .iter()
+            .find(|entry| entry.id == id)
+            .cloned())
+    }
+
+    async fn list(&self, status: Option<OutboxStatus>) -> Result<Vec<OutboxEntry>> {
+        Ok(self
+            .entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .iter()
+            .filter(|entry| status.is_none() || status == Some(entry.status))
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteOutbox;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{Outbox, OutboxEntry, OutboxStatus};
+    use crate::error::{Error, Result};
+    use async_trait::async_trait;
+    use rusqlite::{params, Connection, OptionalExtension, Row};
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    const COLUMNS: &str =
+        "id, message_id, recipients, routes, status, attempts, last_error, created_at, updated_at";
+
+    fn storage_error(e: impl std::fmt::Display) -> Error {
+        Error::Storage(e.to_string())
+    }
+
+    /// An outbox persisted to an `SQLite` database.
+    ///
+    /// Recipients and routes are stored as JSON. Status changes are written
+    /// in a transaction, so an entry is never claimed twice. Queries run on
+    /// the blocking thread pool, so they do not stall the async runtime.
+    #[derive(Debug)]
+    pub struct SqliteOutbox {
+        conn: Arc<Mutex<Connection>>,
+    }
+
+    impl SqliteOutbox {
+        /// Opens or creates an outbox database at `path`.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the database cannot be opened or migrated
+        pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+            Self::init(Connection::open(path).map_err(storage_error)?)
+        }
+
+        /// Creates an outbox in a private in-memory database.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the database cannot be created
+        pub fn open_in_memory() -> Result<Self> {
+            Self::init(Connection::open_in_memory().map_err(storage_error)?)
+        }
+
+        fn init(conn: Connection) -> Result<Self> {
+            conn.execute_batch(
+                "PRAGMA journal_mode = WAL;
+                 CREATE TABLE IF NOT EXISTS outbox (
+                     id TEXT PRIMARY KEY,
+                     message_id TEXT NOT NULL,
+                     recipients TEXT NOT NULL,
+                     routes TEXT NOT NULL,
+                     status TEXT NOT NULL,
+                     attempts INTEGER NOT NULL,
+                     last_error TEXT,
+                     created_at INTEGER NOT NULL,
+                     updated_at INTEGER NOT NULL
+                 );
+                 CREATE INDEX IF NOT EXISTS outbox_status ON outbox (status, created_at);",
+            )
+            .map_err(storage_error)?;
+            Ok(Self {
+                conn: Arc::new(Mutex::new(conn)),
+            })
+        }
+
+        /// Runs `query` with the connection on the blocking thread pool
+        async fn run<T, F>(&self, query: F) -> Result<T>
+        where
+            T: Send + 'static,
+            F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+        {
+            let conn = Arc::clone(&self.conn);
+            tokio::task::spawn_blocking(move || {
+                query(
+                    &mut conn
+                        .lock()
+                        .unwrap_or_else(std::sync::PoisonError::into_inner),
+                )
+            })
+            .await
+            .map_err(storage_error)?
+        }
+    }
+
+    /// Parses a text column, reporting failures as conversion errors
+    fn parse_column<T, E>(
+        row: &Row<'_>,
+        index: usize,
+        parse: impl FnOnce(&str) -> std::result::Result<T, E>,
+    ) -> rusqlite::Result<T>
+    where
+        E: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let text: String = row.get(index)?;
+        parse(&text).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, e.into())
+        })
+    }
+
+    /// Reads an entry from a row selected with [`COLUMNS`]
+    fn entry(row: &Row<'_>) -> rusqlite::Result<OutboxEntry> {
+        Ok(OutboxEntry {
+            id: row.get(0)?,
+            message_id: row.get(1)?,
+            recipients: parse_column(row, 2, |text| serde_json::from_str(text))?,
+            routes: parse_column(row, 3, |text| serde_json::from_str(text))?,
+            status: parse_column(row, 4, |status| {
+                status.parse::<OutboxStatus>().map_err(|e| e.to_string())
+            })?,
+            attempts: row.get(5)?,
+            last_error: row.get(6)?,
+            created_at: row.get(7)?,
+            updated_at: row.get(8)?,
+        })
+    }
+
+    fn select(
+        conn: &Connection,
+        filter: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<OutboxEntry>> {
+        let mut stmt = conn
+            .prepare(&format!("SELECT {COLUMNS} FROM outbox {filter}"))
+            .map_err(storage_error)?;
+        let rows = stmt.query_map(params, entry).map_err(storage_error)?;
+        rows.collect::<rusqlite::Result<_>>().map_err(storage_error)
+    }
+
+    #[async_trait]
+    impl Outbox for SqliteOutbox {
+        async fn enqueue(&self, entries: &[OutboxEntry]) -> Result<()> {
+            let entries = entries.to_vec();
+            self.run(move |conn| {
+                let tx = conn.transaction().map_err(storage_error)?;
+                for entry in &entries {
+                    tx.execute(
+                        &format!("INSERT INTO outbox ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)"),
+                        params![
+                            entry.id,
+                            entry.message_id,
+                            serde_json::to_string(&entry.recipients).map_err(storage_error)?,
+                            serde_json::to_string(&entry.routes).map_err(storage_error)?,
+                            entry.status.as_str(),
+                            entry.attempts,
+                            entry.last_error,
+                            entry.created_at,
+                            entry.updated_at,
+                        ],
+                    )
+                    .map_err(storage_error)?;
+                }
+                tx.commit().map_err(storage_error)
+            })
+            .await
+        }
+
+        async fn claim(&self, limit: usize, now: u64) -> Result<Vec<OutboxEntry>> {
+            self.run(move |conn| {
+                let tx = conn.transaction().map_err(storage_error)?;
+                let mut entries = select(
+                    &tx,
+                    "WHERE status = ?1 ORDER BY created_at, rowid LIMIT ?2",
+                    params![OutboxStatus::Pending.as_str(), limit],
+                )?;
+                for entry in &mut entries {
+                    entry.status = OutboxStatus::InFlight;
+                    entry.attempts += 1;
+                    entry.updated_at = now;
+                    tx.execute(
+                        "UPDATE outbox SET status = ?2, attempts = ?3, updated_at = ?4 WHERE id = ?1",
+                        params![entry.id, entry.status.as_str(), entry.attempts, now],
+                    )
+                    .map_err(storage_error)?;
+                }
+                tx.commit().map_err(storage_error)?;
+                Ok(entries)
+            })
+            .await
+        }
+
+        async fn complete(
+            &self,
+            id: &str,
+            status: OutboxStatus,
+            error: Option<String>,
+            now: u64,
+        ) -> Result<()> {
+            let id = id.to_string();
+            self.run(move |conn| {
+                let updated = conn
+                    .execute(
+                        "UPDATE outbox SET status = ?2, last_error = ?3, updated_at = ?4 WHERE id = ?1",
+                        params![id, status.as_str(), error, now],
+                    )
+                    .map_err(storage_error)?;
+                if updated == 0 {
+                    return Err(Error::Storage(format!("No outbox entry {id}")));
+                }
+                Ok(())
+            })
+            .await
+        }
+
+        async fn requeue_stale(&self, before: u64, now: u64) -> Result<usize> {
+            self.run(move |conn| {
+                conn.execute(
+                    "UPDATE outbox SET status = ?1, updated_at = ?2
+                     WHERE status = ?3 AND updated_at < ?4",
+                    params![
+                        OutboxStatus::Pending.as_str(),
+                        now,
+                        OutboxStatus::InFlight.as_str(),
+                        before
+                    ],
+                )
+                .map_err(storage_error)
+            })
+            .await
+        }
+
+        async fn renew(&self, id: &str, now: u64) -> Result<()> {
+            let id = id.to_string();
+            self.run(move |conn| {
+                conn.execute(
+                    "UPDATE outbox SET updated_at = ?2 WHERE id = ?1 AND status = ?3",
+                    params![id, now, OutboxStatus::InFlight.as_str()],
+                )
+                .map(|_| ())
+                .map_err(storage_error)
+            })
+            .await
+        }
+
+        async fn prune(&self, before: u64) -> Result<usize> {
+            self.run(move |conn| {
+                conn.execute(
+                    "DELETE FROM outbox WHERE status IN (?1, ?2) AND updated_at < ?3",
+                    params![
+                        OutboxStatus::Delivered.as_str(),
+                        OutboxStatus::Failed.as_str(),
+                        before
+                    ],
+                )
+                .map_err(storage_error)
+            })
+            .await
+        }
+
+        async fn get(&self, id: &str) -> Result<Option<OutboxEntry>> {
+            let id = id.to_string();
+            self.run(move |conn| {
+                conn.query_row(
+                    &format!("SELECT {COLUMNS} FROM outbox WHERE id = ?1"),
+                    params![id],
+                    entry,
+                )
+                .optional()
+                .map_err(storage_error)
+            })
+            .await
+        }
+
+        async fn list(&self, status: Option<OutboxStatus>) -> Result<Vec<OutboxEntry>> {
+            self.run(move |conn| match status {
+                Some(status) => select(
+                    conn,
+                    "WHERE status = ?1 ORDER BY created_at, rowid",
+                    params![status.as_str()],
+                ),
+                None => select(conn, "ORDER BY created_at, rowid", []),
+            })
+            .await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn exercise(outbox: &dyn Outbox) {
+        let first = OutboxEntry::new("msg-1", vec!["did:example:bob".into()], Vec::new(), 1);
+        let second = OutboxEntry::new("msg-2", vec!["did:example:carol".into()], Vec::new(), 2);
+        outbox
+            .enqueue(&[first.clone(), second.clone()])
+            .await
+            .unwrap();
+
+        let claimed = outbox.claim(1, 10).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].id, first.id);
+        assert_eq!(claimed[0].status, OutboxStatus::InFlight);
+        assert_eq!(claimed[0].attempts, 1);
+
+        // Renewing the lease keeps an entry in flight
+        outbox.renew(&first.id, 12).await.unwrap();
+        assert_eq!(outbox.requeue_stale(11, 15).await.unwrap(), 0);
+
+        // The node stopped while delivering; the entry is delivered again
+        assert_eq!(outbox.requeue_stale(5, 20).await.unwrap(), 0);
+        assert_eq!(outbox.requeue_stale(13, 20).await.unwrap(), 1);
+        let claimed = outbox.claim(10, 30).await.unwrap();
+        assert_eq!(claimed.len(), 2);
+        assert_eq!(claimed[0].attempts, 2);
+
+        outbox
+            .complete(&first.id, OutboxStatus::Delivered, None, 40)
+            .await
+            .unwrap();
+        outbox
+            .complete(&second.id, OutboxStatus::Failed, Some("503".into()), 40)
+            .await
+            .unwrap();
+        assert!(outbox
+            .complete("missing", OutboxStatus::Failed, None, 40)
+            .await
+            .is_err());
+
+        let failed = outbox.list(Some(OutboxStatus::Failed)).await.unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].last_error.as_deref(), Some("503"));
+        assert_eq!(outbox.list(None).await.unwrap().len(), 2);
+        let delivered = outbox.get(&first.id).await.unwrap().unwrap();
+        assert_eq!(delivered.status, OutboxStatus::Delivered);
+        assert!(outbox.claim(10, 50).await.unwrap().is_empty());
+
+        // Entries in flight are never pruned, finished ones past retention are
+        let third = OutboxEntry::new("msg-3", vec!["did:example:dave".into()], Vec::new(), 60);
+        outbox.enqueue(std::slice::from_ref(&third)).await.unwrap();
+        outbox.claim(10, 60).await.unwrap();
+        assert_eq!(outbox.prune(40).await.unwrap(), 0);
+        assert_eq!(outbox.prune(100).await.unwrap(), 2);
+        assert_eq!(outbox.list(None).await.unwrap().len(), 1);
+        assert!(outbox.get(&third.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_memory_outbox() {
+        exercise(&MemoryOutbox::new()).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sqlite_outbox() {
+        exercise(&SqliteOutbox::open_in_memory().unwrap()).await;
+    }
+}
@@ -0,0 +1,1020 @@
+//! Message pickup protocol 3.0.
+//!
+//! Clients of a mediator fetch the envelopes queued for them with the
+//! `messagepickup/3.0` protocol:
+//!
+//! - `status-request`: Ask how many envelopes are waiting
+//! - `delivery-request`: Ask for up to `limit` envelopes, returned as the
+//!   attachments of a `delivery` message
+//! - `messages-received`: Acknowledge envelopes so they are removed from the
+//!   queue
+//! - `live-delivery-change`: Switch live mode on or off. In live mode, envelopes
+//!   are delivered over an open connection as soon as they are queued.
+//!
+//! [`PickupService`] is the mediator side and [`PickupClient`] the client
+//! side. Requests must be authcrypted by the client they are sent for, and
+//! responses by the mediator. Live connections are opened by a transport such
+//! as the WebSocket endpoint of `tap-didcomm-web`, which calls
+//! [`PickupService::connect`] and sends the `delivery` messages it receives to
+//! the client. A connection that falls [`LIVE_BUFFER`] messages behind is
+//! dropped, and at most [`MAX_BATCH`] envelopes are delivered at once.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use tap_didcomm_node::handler::UnpackMetadata;
+//! use tap_didcomm_node::mediator::Mediator;
+//! use tap_didcomm_node::pickup::{PickupClient, PickupService};
+//! use tap_didcomm_core::PackingType;
+//! use std::sync::Arc;
+//!
+//! # fn example() -> tap_didcomm_node::error::Result<()> {
+//! let mediator = Arc::new(Mediator::new());
+//! mediator.queue().push("did:example:alice", "ZW52ZWxvcGU".to_string(), 1_000)?;
+//!
+//! let service = PickupService::new("did:example:mediator", mediator);
+//! let client = PickupClient::new("did:example:alice", "did:example:mediator");
+//!
+//! // The request was authcrypted by the client
+//! let meta = UnpackMetadata {
+//!     packing: Some(PackingType::AuthcryptV2),
+//!     authenticated_sender: Some("did:example:alice#key-1".into()),
+//!     ..Default::default()
+//! };
+//! let request = client.delivery_request(10, None)?;
+//! let delivery = service.handle(&request, &meta, 1_010)?.unwrap();
+//!
+//! // The delivery was authcrypted by the mediator
+//! let meta = UnpackMetadata {
+//!     packing: Some(PackingType::AuthcryptV2),
+//!     authenticated_sender: Some("did:example:mediator#key-1".into()),
+//!     ..Default::default()
+//! };
+//! let envelopes = client.handle(&delivery, &meta)?;
+//! assert_eq!(envelopes[0].1, "ZW52ZWxvcGU");
+//! # Ok(())
+//! # }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use tap_didcomm_core::{Message, PackingType};
+use tokio::sync::{
+    broadcast::error::RecvError,
+    mpsc::{self, error::TrySendError},
+};
+use tracing::{debug, error, warn};
+
+use crate::{
+    actor::HandlerMessage,
+    coordinate_mediation::RecipientRegistry,
+    error::{Error, Result},
+    handler::UnpackMetadata,
+    mediator::{Mediator, QueuedMessage},
+    node::DIDCommNode,
+    routing::{attached_envelope, envelope_attachment},
+};
+
+/// Message type of a request for the number of queued envelopes
+pub const STATUS_REQUEST: &str = "https://didcomm.org/messagepickup/3.0/status-request";
+/// Message type of the number of queued envelopes
+pub const STATUS: &str = "https://didcomm.org/messagepickup/3.0/status";
+/// Message type of a request for queued envelopes
+pub const DELIVERY_REQUEST: &str = "https://didcomm.org/messagepickup/3.0/delivery-request";
+/// Message type of a batch of queued envelopes
+pub const DELIVERY: &str = "https://didcomm.org/messagepickup/3.0/delivery";
+/// Message type of an acknowledgement of delivered envelopes
+pub const MESSAGES_RECEIVED: &str = "https://didcomm.org/messagepickup/3.0/messages-received";
+/// Message type of a change of live mode
+pub const LIVE_DELIVERY_CHANGE: &str = "https://didcomm.org/messagepickup/3.0/live-delivery-change";
+/// Message type of a problem report
+pub const PROBLEM_REPORT: &str = "https://didcomm.org/report-problem/2.0/problem-report";
+
+/// Message types handled by the mediator side
+pub const SERVICE_MESSAGE_TYPES: [&str; 4] = [
+    STATUS_REQUEST,
+    DELIVERY_REQUEST,
+    MESSAGES_RECEIVED,
+    LIVE_DELIVERY_CHANGE,
+];
+/// Message types handled by the client side
+pub const CLIENT_MESSAGE_TYPES: [&str; 2] = [STATUS, DELIVERY];
+
+/// The maximum number of envelopes in a `delivery`, and of envelopes
+/// delivered over a live connection but not yet acknowledged
+pub const MAX_BATCH: usize = 100;
+/// The number of `delivery` messages a live connection can fall behind before
+/// it is dropped
+pub const LIVE_BUFFER: usize = 16;
+
+/// The envelopes queued for a client.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct PickupStatus {
+    /// The recipient DID the status is limited to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient_did: Option<String>,
+    /// The number of queued envelopes
+    pub message_count: usize,
+    /// How long the oldest envelope has been queued, in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub longest_waited_seconds: Option<u64>,
+    /// When the newest envelope was queued
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub newest_received_time: Option<u64>,
+    /// When the oldest envelope was queued
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oldest_received_time: Option<u64>,
+    /// The total size of the queued envelopes
+    #[serde(default)]
+    pub total_bytes: usize,
+    /// Whether live mode is on
+    #[serde(default)]
+    pub live_delivery: bool,
+}
+
+/// A client's open connection to the mediator
+#[derive(Debug)]
+struct LiveConnection {
+    /// Channel to the transport holding the connection
+    sender: mpsc::Sender<Message>,
+    /// Whether the client switched live mode on
+    live: bool,
+    /// Envelopes delivered over the connection but not yet acknowledged
+    delivered: HashSet<String>,
+}
+
+/// The mediator side of message pickup.
+#[derive(Debug)]
+pub struct PickupService {
+    /// The mediator's DID
+    did: String,
+    /// The mediator whose queue envelopes are picked up from
+    mediator: Arc<Mediator>,
+    /// The recipient DIDs of each client, if mediation is coordinated
+    registry: Option<Arc<RecipientRegistry>>,
+    /// Open connections by client DID
+    connections: RwLock<HashMap<String, LiveConnection>>,
+}
+
+impl PickupService {
+    /// Creates a pickup service for the queue of a mediator.
+    ///
+    /// Without a registry, clients can only pick up envelopes queued for
+    /// their own DID.
+    ///
+    /// # Arguments
+    ///
+    /// * `did` - The mediator's DID
+    /// * `mediator` - The mediator whose queue envelopes are picked up from
+    #[must_use]
+    pub fn new(did: impl Into<String>, mediator: Arc<Mediator>) -> Self {
+        Self {
+            did: did.into(),
+            mediator,
+            registry: None,
+            connections: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Lets clients pick up envelopes for the recipient DIDs registered with
+    /// mediation coordination
+    #[must_use]
+    pub fn with_registry(mut self, registry: Arc<RecipientRegistry>) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Registers an open connection to a client.
+    ///
+    /// Live mode is off until the client sends a `live-delivery-change`. If
+    /// the transport falls [`LIVE_BUFFER`] messages behind, the connection is
+    /// removed and the receiver closes.
+    ///
+    /// # Returns
+    ///
+    /// The `delivery` messages to send to the client over the connection
+    pub fn connect(&self, client: &str) -> mpsc::Receiver<Message> {
+        let (sender, receiver) = mpsc::channel(LIVE_BUFFER);
+        self.connections
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(
+                client.to_string(),
+                LiveConnection {
+                    sender,
+                    live: false,
+                    delivered: HashSet::new(),
+                },
+            );
+        receiver
+    }
+
+    /// Checks whether a client has an open connection
+    #[must_use]
+    pub fn is_connected(&self, client: &str) -> bool {
+        self.connections
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .contains_key(client)
+    }
+
+    /// Removes the connection to a client
+    pub fn disconnect(&self, client: &str) {
+        self.connections
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .remove(client);
+    }
+
+    /// Handles a message pickup message from a client.
+    ///
+    /// The client is the sender the envelope authenticated, so a message
+    /// whose `from` was not authenticated cannot pick up anyone's envelopes.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message from the client
+    /// * `meta` - How the message was packed
+    /// * `now` - The current Unix timestamp
+    ///
+    /// # Returns
+    ///
+    /// The response to send back, or `None` for other message types
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message does not authenticate its sender, its
+    /// body is invalid, or it asks for a recipient DID that is not the
+    /// client's
+    pub fn handle(
+        &self,
+        message: &Message,
+        meta: &UnpackMetadata,
+        now: u64,
+    ) -> Result<Option<Message>> {
+        let typ = message.typ.as_str();
+        if !SERVICE_MESSAGE_TYPES.contains(&typ) {
+            return Ok(None);
+        }
+        let client = meta.authcrypted_from(message).ok_or_else(|| {
+            Error::Unauthenticated(format!("{typ} is not authcrypted by its sender"))
+        })?;
+        let recipient_did = message
+            .body
+            .get("recipient_did")
+            .and_then(serde_json::Value::as_str);
+
+        let response = match typ {
+            STATUS_REQUEST => self.status(message, client, recipient_did, now)?,
+            DELIVERY_REQUEST => {
+                let limit = message
+                    .body
+                    .get("limit")
+                    .and_then(serde_json::Value::as_u64)
+                    .and_then(|limit| usize::try_from(limit).ok())
+                    .ok_or_else(|| Error::InvalidFormat("delivery-request has no limit".into()))?
+                    .min(MAX_BATCH);
+                let pending = self.pending(client, recipient_did, limit)?;
+                if pending.is_empty() {
+                    self.status(message, client, recipient_did, now)?
+                } else {
+                    let mut delivery = message.reply(DELIVERY, recipient_body(recipient_did))?;
+                    for queued in pending {
+                        delivery = delivery
+                            .with_attachment(envelope_attachment(queued.id, &queued.envelope)?);
+                    }
+                    delivery
+                }
+            }
+            MESSAGES_RECEIVED => {
+                let ids: Vec<String> = message
+                    .body
+                    .get("message_id_list")
+                    .cloned()
+                    .map(serde_json::from_value)
+                    .transpose()
+                    .map_err(|e| Error::InvalidFormat(format!("Invalid message_id_list: {e}")))?
+                    .unwrap_or_default();
+                let removed: usize = self
+                    .queue_keys(client, None)?
+                    .iter()
+                    .map(|key| self.mediator.queue().remove(key, &ids))
+                    .sum();
+                debug!("{client} acknowledged {removed} messages");
+                if let Some(connection) = self
+                    .connections
+                    .write()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner)
+                    .get_mut(client)
+                {
+                    connection.delivered.retain(|id| !ids.contains(id));
+                }
+                self.status(message, client, None, now)?
+            }
+            _ => {
+                let live = message
+                    .body
+                    .get("live_delivery")
+                    .and_then(serde_json::Value::as_bool)
+                    .ok_or_else(|| {
+                        Error::InvalidFormat("live-delivery-change has no live_delivery".into())
+                    })?;
+                let connected = self
+                    .connections
+                    .write()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner)
+                    .get_mut(client)
+                    .map(|connection| connection.live = live)
+                    .is_some();
+                if live && !connected {
+                    message.reply(
+                        PROBLEM_REPORT,
+                        json!({
+                            "code": "e.m.live-mode-not-supported",
+                            "comment": "Connection does not support Live Delivery"
+                        }),
+                    )?
+                } else {
+                    if live {
+                        self.flush(client)?;
+                    }
+                    self.status(message, client, None, now)?
+                }
+            }
+        };
+        Ok(Some(response))
+    }
+
+    /// Delivers the envelopes queued for a live client that have not been
+    /// delivered over its connection yet.
+    ///
+    /// At most [`MAX_BATCH`] envelopes are delivered and not yet
+    /// acknowledged at a time. If the connection is closed or full, it is
+    /// removed.
+    ///
+    /// # Returns
+    ///
+    /// The number of delivered envelopes
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a queued envelope cannot be attached
+    pub fn flush(&self, client: &str) -> Result<usize> {
+        let pending = self.pending(client, None, MAX_BATCH)?;
+        let mut connections = self
+            .connections
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let Some(connection) = connections.get_mut(client).filter(|c| c.live) else {
+            return Ok(0);
+        };
+
+        let mut delivery = Message::new(DELIVERY, json!({}))?
+            .from(self.did.as_str())
+            .to([client]);
+        let mut count = 0;
+        for queued in pending {
+            if connection.delivered.len() >= MAX_BATCH {
+                break;
+            }
+            if connection.delivered.insert(queued.id.clone()) {
+                delivery =
+                    delivery.with_attachment(envelope_attachment(queued.id, &queued.envelope)?);
+                count += 1;
+            }
+        }
+        if count > 0 {
+            if let Err(e) = connection.sender.try_send(delivery) {
+                match e {
+                    TrySendError::Full(_) => warn!("Live connection to {client} fell behind"),
+                    TrySendError::Closed(_) => debug!("Live connection to {client} was closed"),
+                }
+                connections.remove(client);
+                return Ok(0);
+            }
+        }
+        Ok(count)
+    }
+
+    /// Delivers envelopes to live clients as they are queued, until the
+    /// queue is dropped.
+    pub async fn run_live(self: Arc<Self>) {
+        let mut queued = self.mediator.queue().subscribe();
+        loop {
+            let clients: Vec<String> = match queued.recv().await {
+                Ok(recipient) => self.clients_of(&recipient),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Live delivery skipped {skipped} notifications");
+                    self.connections
+                        .read()
+                        .unwrap_or_else(std::sync::PoisonError::into_inner)
+                        .keys()
+                        .cloned()
+                        .collect()
+                }
+                Err(RecvError::Closed) => return,
+            };
+            for client in clients {
+                if let Err(e) = self.flush(&client) {
+                    error!("Failed live delivery to {client}: {e}");
+                }
+            }
+        }
+    }
+
+    /// Finds the connected clients a queue key belongs to
+    fn clients_of(&self, key: &str) -> Vec<String> {
+        let did = did_of(key);
+        self.connections
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .keys()
+            .filter(|client| {
+                client.as_str() == did
+                    || self
+                        .registry
+                        .as_ref()
+                        .and_then(|registry| registry.client_of(did))
+                        .is_some_and(|owner| &owner == *client)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Finds the queue keys a client may pick up, optionally limited to one
+    /// recipient DID
+    fn queue_keys(&self, client: &str, recipient_did: Option<&str>) -> Result<Vec<String>> {
+        let mut allowed: HashSet<String> = self
+            .registry
+            .as_ref()
+            .map(|registry| registry.recipients(client, 0, usize::MAX).0)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        allowed.insert(client.to_string());
+
+        if let Some(recipient_did) = recipient_did {
+            if !allowed.contains(did_of(recipient_did)) {
+                return Err(Error::InvalidFormat(format!(
+                    "{recipient_did} is not a recipient of {client}"
+                )));
+            }
+            allowed = HashSet::from([did_of(recipient_did).to_string()]);
+        }
+
+        Ok(self
+            .mediator
+            .queue()
+            .recipients()
+            .into_iter()
+            .filter(|key| allowed.contains(did_of(key)))
+            .collect())
+    }
+
+    /// Gets up to `limit` envelopes queued for a client, oldest first
+    fn pending(
+        &self,
+        client: &str,
+        recipient_did: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<QueuedMessage>> {
+        let queue = self.mediator.queue();
+        let mut pending: Vec<QueuedMessage> = self
+            .queue_keys(client, recipient_did)?
+            .iter()
+            .flat_map(|key| queue.pending(key, limit))
+            .collect();
+        pending.sort_by_key(|queued| queued.queued_at);
+        pending.truncate(limit);
+        Ok(pending)
+    }
+
+    /// Builds a `status` reply
+    fn status(
+        &self,
+        message: &Message,
+        client: &str,
+        recipient_did: Option<&str>,
+        now: u64,
+    ) -> Result<Message> {
+        let pending = self.pending(client, recipient_did, usize::MAX)?;
+        let oldest = pending.iter().map(|queued| queued.queued_at).min();
+        let status = PickupStatus {
+            recipient_did: recipient_did.map(ToString::to_string),
+            message_count: pending.len(),
+            longest_waited_seconds: oldest.map(|oldest| now.saturating_sub(oldest)),
+            newest_received_time: pending.iter().map(|queued| queued.queued_at).max(),
+            oldest_received_time: oldest,
+            total_bytes: pending.iter().map(|queued| queued.envelope.len()).sum(),
+            live_delivery: self
+                .connections
+                .read()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .get(client)
+                .is_some_and(|connection| connection.live),
+        };
+        let body = serde_json::to_value(status)
+            .map_err(|e| Error::InvalidFormat(format!("Failed to encode status: {e}")))?;
+        Ok(message.reply(STATUS, body)?)
+    }
+}
+
+/// The DID of a DID or DID URL
+fn did_of(did_url: &str) -> &str {
+    did_url.split('#').next().unwrap_or(did_url)
+}
+
+/// A message body holding an optional recipient DID
+fn recipient_body(recipient_did: Option<&str>) -> serde_json::Value {
+    recipient_did.map_or_else(|| json!({}), |did| json!({ "recipient_did": did }))
+}
+
+/// The client side of message pickup.
+#[derive(Debug)]
+pub struct PickupClient {
+    /// The client's DID
+    did: String,
+    /// The mediator's DID
+    mediator_did: String,
+    /// The last status received from the mediator
+    status: RwLock<Option<PickupStatus>>,
+}
+
+impl PickupClient {
+    /// Creates a client for the given mediator
+    ///
+    /// # Arguments
+    ///
+    /// * `did` - The client's DID
+    /// * `mediator_did` - The mediator's DID
+    #[must_use]
+    pub fn new(did: impl Into<String>, mediator_did: impl Into<String>) -> Self {
+        Self {
+            did: did.into(),
+            mediator_did: mediator_did.into(),
+            status: RwLock::new(None),
+        }
+    }
+
+    /// Builds a `status-request` for the mediator
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be created
+    pub fn status_request(&self, recipient_did: Option<&str>) -> Result<Message> {
+        self.message(STATUS_REQUEST, recipient_body(recipient_did))
+    }
+
+    /// Builds a `delivery-request` for the mediator
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The maximum number of envelopes to deliver
+    /// * `recipient_did` - The recipient DID to deliver envelopes for, or all
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be created
+    pub fn delivery_request(&self, limit: usize, recipient_did: Option<&str>) -> Result<Message> {
+        let mut body = recipient_body(recipient_did);
+        body["limit"] = json!(limit);
+        self.message(DELIVERY_REQUEST, body)
+    }
+
+    /// Builds a `messages-received` for the mediator
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be created
+    pub fn messages_received(&self, ids: &[String]) -> Result<Message> {
+        self.message(MESSAGES_RECEIVED, json!({ "message_id_list": ids }))
+    }
+
+    /// Builds a `live-delivery-change` for the mediator
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be created
+    pub fn live_delivery_change(&self, live: bool) -> Result<Message> {
+        self.message(LIVE_DELIVERY_CHANGE, json!({ "live_delivery": live }))
+    }
+
+    fn message(&self, typ: &str, body: serde_json::Value) -> Result<Message> {
+        Ok(Message::new(typ, body)?
+            .from(self.did.as_str())
+            .to([self.mediator_did.as_str()]))
+    }
+
+    /// Returns the last status received from the mediator
+    #[must_use]
+    pub fn status(&self) -> Option<PickupStatus> {
+        self.status
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Handles a message from the mediator.
+    ///
+    /// The message must be authcrypted by the mediator, so nobody else can
+    /// inject envelopes or a status.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message from the mediator
+    /// * `meta` - How the message was packed
+    ///
+    /// # Returns
+    ///
+    /// The IDs and envelopes of a `delivery`, which should be received and
+    /// then acknowledged with [`PickupClient::messages_received`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message is not authcrypted by the mediator or
+    /// its body is invalid
+    pub fn handle(
+        &self,
+        message: &Message,
+        meta: &UnpackMetadata,
+    ) -> Result<Vec<(String, String)>> {
+        let typ = message.typ.as_str();
+        if !CLIENT_MESSAGE_TYPES.contains(&typ) {
+            return Ok(Vec::new());
+        }
+        if meta.authcrypted_from(message) != Some(self.mediator_did.as_str()) {
+            return Err(Error::Unauthenticated(format!(
+                "{typ} is not authcrypted by {}",
+                self.mediator_did
+            )));
+        }
+
+        if typ == STATUS {
+            let status: PickupStatus = serde_json::from_value(message.body.clone())
+                .map_err(|e| Error::InvalidFormat(format!("Invalid status: {e}")))?;
+            *self
+                .status
+                .write()
+                .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(status);
+            return Ok(Vec::new());
+        }
+
+        message
+            .attachments
+            .iter()
+            .flatten()
+            .map(|attachment| Ok((attachment.id.clone(), attached_envelope(attachment)?)))
+            .collect()
+    }
+}
+
+/// A handler that answers message pickup requests using a `DIDComm` node
+pub struct PickupHandler {
+    node: Arc<DIDCommNode>,
+    service: Arc<PickupService>,
+}
+
+impl PickupHandler {
+    /// Creates a new handler with the given node and pickup service
+    #[must_use]
+    pub fn new(node: Arc<DIDCommNode>, service: Arc<PickupService>) -> Self {
+        Self { node, service }
+    }
+
+    /// Runs the handler, processing messages from the given channel
+    ///
+    /// Responses are authcrypted and sent to the requesting client.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if message processing fails
+    pub async fn run(self, mut rx: mpsc::Receiver<HandlerMessage>) -> Result<()> {
+        while let Some(msg) = rx.recv().await {
+            let now = self.node.now();
+            match msg {
+                HandlerMessage::HandleMessage(message, reply_tx) => {
+                    let result = match self.service.handle(&message.0, &message.1, now) {
+                        Ok(Some(response)) => self
+                            .node
+                            .send(&response, PackingType::AuthcryptV2)
+                            .await
+                            .map(drop),
+                        Ok(None) => Ok(()),
+                        Err(e) => Err(e),
+                    };
+                    if reply_tx.send(result).is_err() {
+                        error!("Failed to send reply");
+                    }
+                }
+                HandlerMessage::Process {
+                    message,
+                    meta,
+                    response,
+                } => {
+                    let result = self.service.handle(&message, &meta, now).and_then(|reply| {
+                        reply.ok_or_else(|| {
+                            Error::InvalidFormat(format!(
+                                "Not a message pickup request: {}",
+                                message.typ.as_str()
+                            ))
+                        })
+                    });
+                    if response.send(result).await.is_err() {
+                        error!("Failed to send response");
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A handler that receives the envelopes a mediator delivers to a client
+pub struct PickupClientHandler {
+    node: Arc<DIDCommNode>,
+    client: Arc<PickupClient>,
+}
+
+impl PickupClientHandler {
+    /// Creates a new handler with the given node and pickup client
+    #[must_use]
+    pub fn new(node: Arc<DIDCommNode>, client: Arc<PickupClient>) -> Self {
+        Self { node, client }
+    }
+
+    /// Handles a message from the mediator, receiving delivered envelopes
+    /// with the node and acknowledging them.
+    ///
+    /// Envelopes that failed with a transient error are not acknowledged, so
+    /// they stay queued and are delivered again.
+    async fn handle(&self, message: &Message, meta: &UnpackMetadata) -> Result<()> {
+        let delivered = self.client.handle(message, meta)?;
+        if delivered.is_empty() {
+            return Ok(());
+        }
+
+        let mut ids = Vec::with_capacity(delivered.len());
+        for (id, envelope) in delivered {
+            match self.node.receive(envelope.as_bytes()).await {
+                Ok(()) => ids.push(id),
+                Err(e) if e.is_transient() => {
+                    warn!("Leaving delivered message {id} queued: {e}");
+                }
+                Err(e) => {
+                    error!("Failed to receive delivered message {id}: {e}");
+                    ids.push(id);
+                }
+            }
+        }
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let ack = self.client.messages_received(&ids)?;
+        self.node
+            .send(&ack, PackingType::AuthcryptV2)
+            .await
+            .map(drop)
+    }
+
+    /// Runs the handler, processing messages from the given channel
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if message processing fails
+    pub async fn run(self, mut rx: mpsc::Receiver<HandlerMessage>) -> Result<()> {
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                HandlerMessage::HandleMessage(message, reply_tx) => {
+                    let result = self.handle(&message.0, &message.1).await;
+                    if reply_tx.send(result).is_err() {
+                        error!("Failed to send reply");
+                    }
+                }
+                HandlerMessage::Process {
+                    message,
+                    meta,
+                    response,
+                } => {
+                    let result = self.handle(&message, &meta).await.map(|()| message);
+                    if response.send(result).await.is_err() {
+                        error!("Failed to send response");
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> (Arc<Mediator>, PickupService, PickupClient) {
+        let mediator = Arc::new(Mediator::new());
+        let service = PickupService::new("did:example:mediator", Arc::clone(&mediator));
+        let client = PickupClient::new("did:example:alice", "did:example:mediator");
+        (mediator, service, client)
+    }
+
+    fn authcrypted_by(kid: &str) -> UnpackMetadata {
+        UnpackMetadata {
+            packing: Some(PackingType::AuthcryptV2),
+            authenticated_sender: Some(kid.to_string()),
+            ..UnpackMetadata::default()
+        }
+    }
+
+    #[test]
+    fn test_status_delivery_and_ack() {
+        let (mediator, service, client) = setup();
+        let meta = authcrypted_by("did:example:alice#key-1");
+        let from_mediator = authcrypted_by("did:example:mediator#key-1");
+        let queue = mediator.queue();
+        queue
+            .push("did:example:alice#key-1", "b25l".to_string(), 1_000)
+            .unwrap();
+        queue
+            .push("did:example:alice", "dHdv".to_string(), 1_005)
+            .unwrap();
+        queue
+            .push("did:example:bob", "dGhyZWU".to_string(), 1_001)
+            .unwrap();
+
+        let status = service
+            .handle(&client.status_request(None).unwrap(), &meta, 1_010)
+            .unwrap()
+            .unwrap();
+        client.handle(&status, &from_mediator).unwrap();
+        let status = client.status().unwrap();
+        assert_eq!(status.message_count, 2);
+        assert_eq!(status.longest_waited_seconds, Some(10));
+        assert!(!status.live_delivery);
+
+        let delivery = service
+            .handle(&client.delivery_request(1, None).unwrap(), &meta, 1_010)
+            .unwrap()
+            .unwrap();
+        assert_eq!(delivery.typ.as_str(), DELIVERY);
+        let delivered = client.handle(&delivery, &from_mediator).unwrap();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].1, "b25l");
+
+        let ids: Vec<String> = delivered.into_iter().map(|(id, _)| id).collect();
+        let status = service
+            .handle(&client.messages_received(&ids).unwrap(), &meta, 1_010)
+            .unwrap()
+            .unwrap();
+        assert_eq!(status.body["message_count"], 1);
+        assert_eq!(queue.count("did:example:bob"), 1);
+
+        // Other clients' recipients cannot be picked up
+        let request = client
+            .delivery_request(10, Some("did:example:bob"))
+            .unwrap();
+        assert!(service.handle(&request, &meta, 1_010).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_live_delivery() {
+        let (mediator, service, client) = setup();
+        let service = Arc::new(service);
+        let meta = authcrypted_by("did:example:alice#key-1");
+        let from_mediator = authcrypted_by("did:example:mediator#key-1");
+
+        // Live mode needs an open connection
+        let report = service
+            .handle(&client.live_delivery_change(true).unwrap(), &meta, 1_000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.typ.as_str(), PROBLEM_REPORT);
+
+        let mut connection = service.connect("did:example:alice");
+        mediator
+            .queue()
+            .push("did:example:alice", "b25l".to_string(), 1_000)
+            .unwrap();
+        let status = service
+            .handle(&client.live_delivery_change(true).unwrap(), &meta, 1_000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(status.body["live_delivery"], true);
+        let delivery = connection.recv().await.unwrap();
+        assert_eq!(
+            client.handle(&delivery, &from_mediator).unwrap()[0].1,
+            "b25l"
+        );
+
+        tokio::spawn(Arc::clone(&service).run_live());
+        tokio::task::yield_now().await;
+        mediator
+            .queue()
+            .push("did:example:alice#key-1", "dHdv".to_string(), 1_001)
+            .unwrap();
+        let delivery = connection.recv().await.unwrap();
+        let delivered = client.handle(&delivery, &from_mediator).unwrap();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].1, "dHdv");
+    }
+
+    #[test]
+    fn test_rejects_unauthenticated_client() {
+        let (mediator, service, client) = setup();
+        mediator
+            .queue()
+            .push("did:example:alice", "b25l".to_string(), 1_000)
+            .unwrap();
+        let request = client.delivery_request(10, None).unwrap();
+
+        // A plaintext, anoncrypted or signed request only claims its `from`
+        for meta in [
+            UnpackMetadata::default(),
+            UnpackMetadata {
+                packing: Some(PackingType::AnonV2),
+                ..UnpackMetadata::default()
+            },
+            UnpackMetadata {
+                packing: Some(PackingType::Signed),
+                signed_by: Some("did:example:alice#key-1".into()),
+                ..UnpackMetadata::default()
+            },
+            authcrypted_by("did:example:mallory#key-1"),
+        ] {
+            assert!(matches!(
+                service.handle(&request, &meta, 1_010),
+                Err(Error::Unauthenticated(_))
+            ));
+        }
+        assert_eq!(mediator.queue().count("did:example:alice"), 1);
+    }
+
+    #[test]
+    fn test_client_rejects_unauthenticated_mediator() {
+        let (mediator, service, client) = setup();
+        let meta = authcrypted_by("did:example:alice#key-1");
+        mediator
+            .queue()
+            .push("did:example:alice", "b25l".to_string(), 1_000)
+            .unwrap();
+        let delivery = service
+            .handle(&client.delivery_request(10, None).unwrap(), &meta, 1_010)
+            .unwrap()
+            .unwrap();
+
+        // A delivery that only claims the mediator's `from` is not trusted
+        for meta in [
+            UnpackMetadata::default(),
+            authcrypted_by("did:example:mallory#key-1"),
+        ] {
+            assert!(matches!(
+                client.handle(&delivery, &meta),
+                Err(Error::Unauthenticated(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_live_connection_bounds() {
+        let (mediator, service, client) = setup();
+        let meta = authcrypted_by("did:example:alice#key-1");
+        let from_mediator = authcrypted_by("did:example:mediator#key-1");
+        let _connection = service.connect("did:example:alice");
+        for i in 0..=MAX_BATCH as u64 {
+            mediator
+                .queue()
+                .push("did:example:alice", format!("ZW52{i:04}"), 1_000 + i)
+                .unwrap();
+        }
+        service
+            .handle(&client.live_delivery_change(true).unwrap(), &meta, 2_000)
+            .unwrap();
+
+        // Only a batch is delivered until it is acknowledged
+        assert_eq!(service.flush("did:example:alice").unwrap(), 0);
+        let delivery = service
+            .handle(&client.delivery_request(1_000, None).unwrap(), &meta, 2_000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            client.handle(&delivery, &from_mediator).unwrap().len(),
+            MAX_BATCH
+        );
+
+        // A connection that is not read from is dropped once it is full
+        for i in 0..LIVE_BUFFER {
+            let queued = mediator.queue().pending("did:example:alice", 1);
+            let ids = vec![queued[0].id.clone()];
+            service
+                .handle(&client.messages_received(&ids).unwrap(), &meta, 2_000)
+                .unwrap();
+            mediator
+                .queue()
+                .push("did:example:alice", format!("bW9yZQ{i:02}"), 2_000)
+                .unwrap();
+            service.flush("did:example:alice").unwrap();
+        }
+        assert!(!service.is_connected("did:example:alice"));
+    }
+}
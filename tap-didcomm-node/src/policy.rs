@@ -0,0 +1,405 @@
+//! Policies for received messages.
+//!
+//! A [`PolicyConfig`] declares which senders the node accepts messages from
+//! and how each message type must be protected, such as "TAP Authorize
+//! messages must be authcrypted and signed" or "never accept anoncrypted
+//! transfers". [`crate::DIDCommNode::receive`] evaluates the policy after
+//! unpacking a message and dropping it if it is stale or a replay, using its
+//! [`UnpackMetadata`]. Rejected messages never reach middleware or handlers,
+//! and their sender is sent a problem report if the envelope authenticated
+//! it.
+//!
+//! Senders are only ever matched against the DID the envelope authenticated,
+//! by authcrypt or a signature. The `from` of a plaintext or anoncrypted
+//! message can be forged, so such messages count as anonymous.
+//!
+//! Policies can be loaded from configuration files, as they are plain data:
+//!
+//! ```json
+//! {
+//!   "allow_senders": ["did:example:bank", "did:example:vasp"],
+//!   "rules": [
+//!     {
+//!       "types": "https://tap.rsvp/schema/1.0#Authorize",
+//!       "authenticated": true,
+//!       "signed": true
+//!     },
+//!     {
+//!       "types": "https://tap.rsvp/schema/1.0#*",
+//!       "deny": ["anoncrypt", "plaintext"]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! # Examples
+//!
+//! ```rust
+//! use serde_json::json;
+//! use tap_didcomm_core::{Message, PackingType};
+//! use tap_didcomm_node::handler::UnpackMetadata;
+//! use tap_didcomm_node::policy::{PolicyConfig, PolicyRule, Protection};
+//!
+//! let policy = PolicyConfig {
+//!     rules: vec![PolicyRule {
+//!         deny: vec![Protection::Anoncrypt],
+//!         ..PolicyRule::new("https://tap.rsvp/schema/1.0#Transfer")
+//!     }],
+//!     ..PolicyConfig::default()
+//! };
+//!
+//! let transfer = Message::new("https://tap.rsvp/schema/1.0#Transfer", json!({})).unwrap();
+//! let meta = UnpackMetadata {
+//!     packing: Some(PackingType::AnonV2),
+//!     ..UnpackMetadata::default()
+//! };
+//! assert!(policy.evaluate(&transfer, &meta).is_err());
+//! ```
+
+use serde::{Deserialize, Serialize};
+use tap_didcomm_core::{Message, PackingType};
+
+use crate::{handler::UnpackMetadata, router::HandlerPattern};
+
+/// Problem code for messages from senders the node does not accept
+pub const UNTRUSTED_SENDER: &str = "e.p.trust.sender";
+
+/// Problem code for messages that are not protected as their type requires
+pub const INSUFFICIENT_PROTECTION: &str = "e.p.trust.crypto";
+
+/// How the envelope of a received message was packed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Protection {
+    /// Neither encrypted nor signed
+    Plaintext,
+    /// Signed without encryption
+    Signed,
+    /// Encrypted for the recipient without authenticating the sender
+    Anoncrypt,
+    /// Encrypted with an authenticated sender
+    Authcrypt,
+}
+
+impl Protection {
+    /// Gets the protection of the outermost envelope of a received message
+    #[must_use]
+    pub fn of(meta: &UnpackMetadata) -> Self {
+        match meta.packing {
+            None => Self::Plaintext,
+            Some(PackingType::Signed) => Self::Signed,
+            Some(PackingType::AnonV2) => Self::Anoncrypt,
+            Some(PackingType::AuthcryptV2) => Self::Authcrypt,
+        }
+    }
+
+    /// Returns the name the protection is configured with
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Plaintext => "plaintext",
+            Self::Signed => "signed",
+            Self::Anoncrypt => "anoncrypt",
+            Self::Authcrypt => "authcrypt",
+        }
+    }
+}
+
+/// Requirements for messages of some types, and optionally some senders
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyRule {
+    /// The message types the rule applies to, as a handler pattern: an exact
+    /// type, a protocol PIURI or a wildcard
+    pub types: String,
+
+    /// The senders the rule applies to, or empty for every sender
+    pub senders: Vec<String>,
+
+    /// Whether the sender must be authenticated by authcrypt
+    pub authenticated: bool,
+
+    /// Whether the message must be encrypted
+    pub encrypted: bool,
+
+    /// Whether the message must be signed
+    pub signed: bool,
+
+    /// Protections that are rejected
+    pub deny: Vec<Protection>,
+}
+
+impl PolicyRule {
+    /// Creates a rule for message types without requirements
+    #[must_use]
+    pub fn new(types: impl Into<String>) -> Self {
+        Self {
+            types: types.into(),
+            ..Self::default()
+        }
+    }
+
+    fn applies_to(&self, msg: &Message, sender: Option<&str>) -> bool {
+        HandlerPattern::parse(&self.types).matches(msg.typ.as_str())
+            && (self.senders.is_empty()
+                || sender
+                    .is_some_and(|sender| self.senders.iter().any(|did| same_did(did, sender))))
+    }
+
+    fn check(&self, msg: &Message, meta: &UnpackMetadata) -> Result<(), Violation> {
+        let protection = Protection::of(meta);
+        let typ = msg.typ.as_str();
+        let missing = if self.deny.contains(&protection) {
+            return Err(Violation::protection(
+                "Messages of type {1} must not be {2}",
+                typ,
+                protection.as_str(),
+            ));
+        } else if self.authenticated && meta.authenticated_sender.is_none() {
+            "authenticated"
+        } else if self.encrypted
+            && !matches!(protection, Protection::Anoncrypt | Protection::Authcrypt)
+        {
+            "encrypted"
+        } else if self.signed && meta.signed_by.is_none() && protection != Protection::Signed {
+            "signed"
+        } else {
+            return Ok(());
+        };
+        Err(Violation::protection(
+            "Messages of type {1} must be {2}",
+            typ,
+            missing,
+        ))
+    }
+}
+
+/// Which received messages the node accepts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyConfig {
+    /// The only authenticated senders messages are accepted from, or `None`
+    /// to accept any sender. Anonymous messages, including plaintext and
+    /// anoncrypted ones whatever their `from`, are rejected when this is set.
+    pub allow_senders: Option<Vec<String>>,
+
+    /// Senders whose messages are rejected
+    pub deny_senders: Vec<String>,
+
+    /// Requirements by message type, which must all be met
+    pub rules: Vec<PolicyRule>,
+
+    /// Whether rejected messages are answered with a problem report
+    pub report: bool,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        Self {
+            allow_senders: None,
+            deny_senders: Vec::new(),
+            rules: Vec::new(),
+            report: true,
+        }
+    }
+}
+
+impl PolicyConfig {
+    /// Checks a received message against the policy.
+    ///
+    /// The sender is the DID authenticated by the envelope or its signature.
+    /// A message without one is anonymous, whatever its `from` claims.
+    ///
+    /// # Arguments
+    ///
+    /// * `msg` - The unpacked message
+    /// * `meta` - How the message was packed
+    ///
+    /// # Errors
+    ///
+    /// Returns the first rule the message violates
+    pub fn evaluate(&self, msg: &Message, meta: &UnpackMetadata) -> Result<(), Violation> {
+        let sender = meta.sender();
+
+        let denied =
+            sender.is_some_and(|sender| self.deny_senders.iter().any(|did| same_did(did, sender)));
+        let allowed = match (&self.allow_senders, sender) {
+            (None, _) => true,
+            (Some(allowed), Some(sender)) => allowed.iter().any(|did| same_did(did, sender)),
+            (Some(_), None) => false,
+        };
+        if denied || !allowed {
+            return Err(Violation {
+                code: UNTRUSTED_SENDER,
+                comment: "Messages from {1} are not accepted".to_string(),
+                args: vec![sender.unwrap_or("anonymous senders").to_string()],
+            });
+        }
+
+        self.rules
+            .iter()
+            .filter(|rule| rule.applies_to(msg, sender))
+            .try_for_each(|rule| rule.check(msg, meta))
+    }
+}
+
+/// Why a message was rejected, as the contents of a problem report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The problem code
+    pub code: &'static str,
+
+    /// The comment, with `{1}`, `{2}`... referring to the arguments
+    pub comment: String,
+
+    /// The arguments of the comment
+    pub args: Vec<String>,
+}
+
+impl Violation {
+    fn protection(comment: &str, typ: &str, protection: &str) -> Self {
+        Self {
+            code: INSUFFICIENT_PROTECTION,
+            comment: comment.to_string(),
+            args: vec![typ.to_string(), protection.to_string()],
+        }
+    }
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut comment = self.comment.clone();
+        for (index, arg) in self.args.iter().enumerate() {
+            comment = comment.replace(&format!("{{{}}}", index + 1), arg);
+        }
+        write!(f, "{comment} ({})", self.code)
+    }
+}
+
+/// Whether a DID or key ID belongs to a DID
+fn same_did(did: &str, kid: &str) -> bool {
+    kid.split_once('#').map_or(kid, |(did, _)| did) == did
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const AUTHORIZE: &str = "https://tap.rsvp/schema/1.0#Authorize";
+
+    fn meta(packing: Option<PackingType>, sender: Option<&str>, signed: bool) -> UnpackMetadata {
+        UnpackMetadata {
+            packing,
+            authenticated_sender: sender.map(str::to_string),
+            signed_by: sender.filter(|_| signed).map(str::to_string),
+            ..UnpackMetadata::default()
+        }
+    }
+
+    #[test]
+    fn test_sender_lists() {
+        let policy: PolicyConfig = serde_json::from_value(json!({
+            "allow_senders": ["did:example:bank", "did:example:mallory"],
+            "deny_senders": ["did:example:mallory"]
+        }))
+        .unwrap();
+        let from = |did: &str| Message::new("test", json!({})).unwrap().from(did);
+        let authcrypted_by = |did: &str| meta(Some(PackingType::AuthcryptV2), Some(did), false);
+        let signed_by = |did: &str| UnpackMetadata {
+            packing: Some(PackingType::Signed),
+            signed_by: Some(format!("{did}#key-1")),
+            ..UnpackMetadata::default()
+        };
+
+        let bank = from("did:example:bank");
+        assert!(policy
+            .evaluate(&bank, &authcrypted_by("did:example:bank"))
+            .is_ok());
+        assert!(policy
+            .evaluate(&bank, &signed_by("did:example:bank"))
+            .is_ok());
+        let violation = policy
+            .evaluate(
+                &from("did:example:mallory"),
+                &authcrypted_by("did:example:mallory"),
+            )
+            .unwrap_err();
+        assert_eq!(violation.code, UNTRUSTED_SENDER);
+        assert!(policy
+            .evaluate(&from("did:example:eve"), &authcrypted_by("did:example:eve"))
+            .is_err());
+        assert!(policy
+            .evaluate(
+                &Message::new("test", json!({})).unwrap(),
+                &UnpackMetadata::default()
+            )
+            .is_err());
+
+        // A claimed sender is anonymous without an authenticating envelope
+        let plain = UnpackMetadata::default();
+        let anoncrypted = meta(Some(PackingType::AnonV2), None, false);
+        assert_eq!(
+            policy.evaluate(&bank, &plain).unwrap_err().args,
+            vec!["anonymous senders".to_string()]
+        );
+        assert!(policy.evaluate(&bank, &anoncrypted).is_err());
+        let open = PolicyConfig {
+            deny_senders: vec!["did:example:mallory".into()],
+            ..PolicyConfig::default()
+        };
+        assert!(open
+            .evaluate(&from("did:example:mallory"), &anoncrypted)
+            .is_ok());
+        assert!(open
+            .evaluate(&bank, &authcrypted_by("did:example:mallory#key-1"))
+            .is_err());
+
+        // The authenticated sender counts, not the claimed one
+        let authcrypted = meta(
+            Some(PackingType::AuthcryptV2),
+            Some("did:example:eve#key-1"),
+            false,
+        );
+        assert!(policy
+            .evaluate(&from("did:example:bank"), &authcrypted)
+            .is_err());
+    }
+
+    #[test]
+    fn test_protection_rules() {
+        let policy = PolicyConfig {
+            rules: vec![
+                PolicyRule {
+                    authenticated: true,
+                    signed: true,
+                    ..PolicyRule::new(AUTHORIZE)
+                },
+                PolicyRule {
+                    deny: vec![Protection::Anoncrypt],
+                    ..PolicyRule::new("https://tap.rsvp/schema/1.0#*")
+                },
+            ],
+            ..PolicyConfig::default()
+        };
+        let authorize = Message::new(AUTHORIZE, json!({}))
+            .unwrap()
+            .from("did:example:bank");
+        let other = Message::new("https://didcomm.org/trust-ping/2.0/ping", json!({})).unwrap();
+
+        let sender = Some("did:example:bank");
+        let authcrypted = meta(Some(PackingType::AuthcryptV2), sender, false);
+        let signed = meta(Some(PackingType::AuthcryptV2), sender, true);
+        let anoncrypted = meta(Some(PackingType::AnonV2), None, false);
+
+        assert!(policy.evaluate(&authorize, &signed).is_ok());
+        let violation = policy.evaluate(&authorize, &authcrypted).unwrap_err();
+        assert_eq!(violation.code, INSUFFICIENT_PROTECTION);
+        assert_eq!(
+            violation.to_string(),
+            format!("Messages of type {AUTHORIZE} must be signed (e.p.trust.crypto)")
+        );
+        assert!(policy.evaluate(&authorize, &anoncrypted).is_err());
+        assert!(policy.evaluate(&other, &anoncrypted).is_ok());
+    }
+}
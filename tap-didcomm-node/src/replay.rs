@@ -0,0 +1,579 @@
+//! Replay protection for received messages.
+//!
+//! A packed message that is received twice must not be dispatched to handlers
+//! twice. This module keeps track of the messages a node has seen, keyed on
+//! the sender and message ID, until they can no longer be accepted anyway.
+//! Messages older than the retention window are rejected, so a key is never
+//! forgotten while its message could still be replayed.
+//!
+//! # Components
+//!
+//! - [`ReplayStore`]: Storage for seen message keys
+//! - [`MemoryReplayStore`]: A bounded in-memory store that refuses new
+//!   messages when full
+//! - [`FileReplayStore`]: A store persisted to a JSON lines file
+//! - [`ReplayConfig`]: Whether duplicates are dropped or rejected, and for how
+//!   long keys are retained
+//!
+//! # Examples
+//!
+//! ```rust
+//! use tap_didcomm_node::replay::{MemoryReplayStore, ReplayKey, ReplayStore};
+//!
+//! # async fn example() -> tap_didcomm_node::error::Result<()> {
+//! let store = MemoryReplayStore::new(1_000);
+//! let key = ReplayKey::new(Some("did:example:alice"), "msg-1");
+//!
+//! assert!(store.check_and_record(&key, 2_000, 1_000).await?);
+//! assert!(!store.check_and_record(&key, 2_000, 1_001).await?);
+//! # Ok(())
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tap_didcomm_core::Message;
+use tokio::io::AsyncWriteExt;
+
+use tap_didcomm_core::Error as CoreError;
+
+use crate::error::{Error, Result};
+use crate::handler::UnpackMetadata;
+
+/// Identifies a received message for replay detection.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ReplayKey {
+    /// The authenticated sender's DID, if the envelope authenticated one
+    pub sender: Option<String>,
+    /// The message ID
+    pub message_id: String,
+}
+
+impl ReplayKey {
+    /// Creates a new replay key
+    #[must_use]
+    pub fn new(sender: Option<&str>, message_id: impl Into<String>) -> Self {
+        Self {
+            sender: sender.map(ToString::to_string),
+            message_id: message_id.into(),
+        }
+    }
+
+    /// Creates the replay key of a message.
+    ///
+    /// The key uses the sender the envelope authenticated, not the `from` of
+    /// the message, so a forged `from` cannot claim another sender's message
+    /// IDs or escape detection of its own replays.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The received message
+    /// * `meta` - How the message was packed
+    #[must_use]
+    pub fn for_message(message: &Message, meta: &UnpackMetadata) -> Self {
+        Self::new(meta.sender(), message.id.as_str())
+    }
+}
+
+/// What to do with a message that has been received before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicatePolicy {
+    /// Silently drop the duplicate without dispatching it
+    #[default]
+    Drop,
+    /// Fail with `Error::Duplicate`
+    Reject,
+}
+
+/// Configuration for replay protection.
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    /// Whether replay protection is enabled
+    pub enabled: bool,
+
+    /// What to do with duplicates
+    pub policy: DuplicatePolicy,
+
+    /// How long a message without `expires_time` is accepted, and its key
+    /// retained, in seconds after its `created_time`
+    pub default_retention: u64,
+
+    /// The longest time a message is accepted, and its key retained, in
+    /// seconds after its `created_time`, whatever its `expires_time`
+    pub max_retention: u64,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            policy: DuplicatePolicy::Drop,
+            default_retention: 24 * 60 * 60, // 1 day
+            max_retention: 7 * 24 * 60 * 60, // 1 week
+        }
+    }
+}
+
+impl ReplayConfig {
+    /// Computes until when a message can be accepted.
+    ///
+    /// A message is accepted until it expires, plus the tolerated clock skew,
+    /// or for `default_retention` if it does not expire, but never longer than
+    /// `max_retention` after it was created.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The received message
+    /// * `max_clock_skew` - The tolerated clock skew in seconds
+    #[must_use]
+    pub fn accept_until(&self, message: &Message, max_clock_skew: u64) -> u64 {
+        let until = message.expires_time.map_or_else(
+            || message.created_time.saturating_add(self.default_retention),
+            |expires_time| expires_time.saturating_add(max_clock_skew),
+        );
+        until.min(message.created_time.saturating_add(self.max_retention))
+    }
+
+    /// Computes until when the key of a message must be retained.
+    ///
+    /// A key is retained for as long as its message can be accepted, after
+    /// which a replay is rejected by [`ReplayConfig::check`].
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The received message
+    /// * `max_clock_skew` - The tolerated clock skew in seconds
+    /// * `now` - The current Unix timestamp in seconds
+    #[must_use]
+    pub fn retain_until(&self, message: &Message, max_clock_skew: u64, now: u64) -> u64 {
+        self.accept_until(message, max_clock_skew).max(now)
+    }
+
+    /// Checks that a message is still within the retention window, so that a
+    /// replay of it would be detected.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The received message
+    /// * `max_clock_skew` - The tolerated clock skew in seconds
+    /// * `now` - The current Unix timestamp in seconds
+    ///
+    /// # Errors
+    ///
+    /// Returns `MessageExpired` if the message can no longer be accepted
+    pub fn check(&self, message: &Message, max_clock_skew: u64, now: u64) -> Result<()> {
+        let until = self.accept_until(message, max_clock_skew);
+        if now > until {
+            return Err(Error::Core(CoreError::MessageExpired(format!(
+                "Message {} created at {} is past the replay window, which ended at {until}",
+                message.id.as_str(),
+                message.created_time
+            ))));
+        }
+        Ok(())
+    }
+}
+
+/// Storage for the keys of received messages.
+#[async_trait]
+pub trait ReplayStore: Send + Sync {
+    /// Records a key unless it has been recorded before and is still retained.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the received message
+    /// * `retain_until` - The Unix timestamp until which the key is retained
+    /// * `now` - The current Unix timestamp
+    ///
+    /// # Returns
+    ///
+    /// `true` if the message is new, `false` if it is a duplicate
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read or written
+    async fn check_and_record(&self, key: &ReplayKey, retain_until: u64, now: u64) -> Result<bool>;
+
+    /// Removes a recorded key, so that its message is accepted again.
+    ///
+    /// This is used when a message fails before any handler runs after its
+    /// key was recorded, so that a retransmission is not dropped as a
+    /// duplicate.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be written
+    async fn forget(&self, key: &ReplayKey) -> Result<()>;
+
+    /// Removes keys that are no longer retained.
+    ///
+    /// # Returns
+    ///
+    /// The number of removed keys
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be written
+    async fn prune(&self, now: u64) -> Result<usize>;
+}
+
+#[derive(Debug, Default)]
+struct RetainedKeys {
+    /// Retention deadline of each key
+    entries: HashMap<ReplayKey, u64>,
+    /// Keys by retention deadline, so that expired keys are found first
+    deadlines: BTreeSet<(u64, ReplayKey)>,
+}
+
+impl RetainedKeys {
+    /// Removes keys that are no longer retained at `now`
+    fn prune(&mut self, now: u64) -> usize {
+        let mut removed = 0;
+        while let Some((until, key)) = self.deadlines.first().cloned() {
+            if until >= now {
+                break;
+            }
+            self.deadlines.pop_first();
+            self.entries.remove(&key);
+            removed += 1;
+        }
+        removed
+    }
+
+    /// Removes a key
+    fn remove(&mut self, key: &ReplayKey) {
+        if let Some(until) = self.entries.remove(key) {
+            self.deadlines.remove(&(until, key.clone()));
+        }
+    }
+}
+
+/// An in-memory replay store holding at most a fixed number of keys.
+///
+/// Keys are never evicted while they are retained, as a flood of new
+/// message IDs would otherwise push out the keys of messages that can still
+/// be replayed. When the store is full of retained keys, new messages are
+/// refused with `Error::Busy` until keys expire, so the capacity should be
+/// sized for the expected message volume over the retention window.
+#[derive(Debug)]
+pub struct MemoryReplayStore {
+    capacity: usize,
+    inner: Mutex<RetainedKeys>,
+}
+
+impl MemoryReplayStore {
+    /// Creates a store holding at most `capacity` keys
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(RetainedKeys::default()),
+        }
+    }
+
+    /// Returns the number of keys in the store
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .entries
+            .len()
+    }
+
+    /// Returns whether the store is empty
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for MemoryReplayStore {
+    fn default() -> Self {
+        Self::new(100_000)
+    }
+}
+
+#[async_trait]
+impl ReplayStore for MemoryReplayStore {
+    async fn check_and_record(&self, key: &ReplayKey, retain_until: u64, now: u64) -> Result<bool> {
+        let mut inner = self
+            .inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if inner.entries.get(key).is_some_and(|until| *until >= now) {
+            return Ok(false);
+        }
+        inner.remove(key);
+
+        if inner.entries.len() >= self.capacity && inner.prune(now) == 0 {
+            return Err(Error::Busy(format!(
+                "Replay store is full with {} retained keys",
+                inner.entries.len()
+            )));
+        }
+        inner.entries.insert(key.clone(), retain_until);
+        inner.deadlines.insert((retain_until, key.clone()));
+        Ok(true)
+    }
+
+    async fn forget(&self, key: &ReplayKey) -> Result<()> {
+        self.inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .remove(key);
+        Ok(())
+    }
+
+    async fn prune(&self, now: u64) -> Result<usize> {
+        Ok(self
+            .inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .prune(now))
+    }
+}
+
+/// A line in the replay store file
+#[derive(Debug, Serialize, Deserialize)]
+struct ReplayRecord {
+    #[serde(flatten)]
+    key: ReplayKey,
+    retain_until: u64,
+}
+
+/// A replay store persisted to a JSON lines file, so that duplicates are
+/// still detected after the node restarts.
+///
+/// Keys are kept in memory and appended to the file as they are recorded.
+/// Pruning rewrites the file without the keys that are no longer retained.
+#[derive(Debug)]
+pub struct FileReplayStore {
+    path: PathBuf,
+    entries: tokio::sync::Mutex<HashMap<ReplayKey, u64>>,
+}
+
+impl FileReplayStore {
+    /// Opens the store at the given path, loading any keys recorded before.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to persist keys in; created if missing
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut entries = HashMap::new();
+        match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => {
+                for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+                    let record: ReplayRecord = serde_json::from_str(line)
+                        .map_err(|e| Error::Storage(format!("Invalid replay store record: {e}")))?;
+                    entries.insert(record.key, record.retain_until);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::Storage(format!("Failed to read replay store: {e}"))),
+        }
+        Ok(Self {
+            path,
+            entries: tokio::sync::Mutex::new(entries),
+        })
+    }
+
+    async fn append(&self, record: &ReplayRecord) -> Result<()> {
+        let mut line = serde_json::to_vec(record)
+            .map_err(|e| Error::Storage(format!("Failed to encode replay record: {e}")))?;
+        line.push(b'\n');
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| Error::Storage(format!("Failed to open replay store: {e}")))?;
+        file.write_all(&line)
+            .await
+            .map_err(|e| Error::Storage(format!("Failed to write replay store: {e}")))?;
+        file.sync_data()
+            .await
+            .map_err(|e| Error::Storage(format!("Failed to write replay store: {e}")))
+    }
+}
+
+#[async_trait]
+impl ReplayStore for FileReplayStore {
+    async fn check_and_record(&self, key: &ReplayKey, retain_until: u64, now: u64) -> Result<bool> {
+        let mut entries = self.entries.lock().await;
+        if entries.get(key).is_some_and(|until| *until >= now) {
+            return Ok(false);
+        }
+        self.append(&ReplayRecord {
+            key: key.clone(),
+            retain_until,
+        })
+        .await?;
+        entries.insert(key.clone(), retain_until);
+        Ok(true)
+    }
+
+    async fn forget(&self, key: &ReplayKey) -> Result<()> {
+        let mut entries = self.entries.lock().await;
+        if entries.remove(key).is_some() {
+            // A record that is no longer retained overrides the earlier one
+            // when the file is loaded
+            self.append(&ReplayRecord {
+                key: key.clone(),
+                retain_until: 0,
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn prune(&self, now: u64) -> Result<usize> {
+        let mut entries = self.entries.lock().await;
+        let before = entries.len();
+        entries.retain(|_, until| *until >= now);
+
+        let mut contents = Vec::new();
+        for (key, retain_until) in entries.iter() {
+            let record = ReplayRecord {
+                key: key.clone(),
+                retain_until: *retain_until,
+            };
+            serde_json::to_writer(&mut contents, &record)
+                .map_err(|e| Error::Storage(format!("Failed to encode replay record: {e}")))?;
+            contents.push(b'\n');
+        }
+
+        let tmp = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp, contents)
+            .await
+            .map_err(|e| Error::Storage(format!("Failed to write replay store: {e}")))?;
+        tokio::fs::rename(&tmp, &self.path)
+            .await
+            .map_err(|e| Error::Storage(format!("Failed to write replay store: {e}")))?;
+
+        Ok(before - entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_memory_replay_store() {
+        let store = MemoryReplayStore::new(2);
+        let first = ReplayKey::new(Some("did:example:alice"), "msg-1");
+        let second = ReplayKey::new(Some("did:example:bob"), "msg-1");
+        let third = ReplayKey::new(None, "msg-3");
+
+        assert!(store.check_and_record(&first, 100, 0).await.unwrap());
+        assert!(!store.check_and_record(&first, 100, 1).await.unwrap());
+        assert!(store.check_and_record(&second, 50, 2).await.unwrap());
+
+        // Retained keys are never evicted, so a full store refuses new keys
+        assert!(matches!(
+            store.check_and_record(&third, 100, 3).await,
+            Err(Error::Busy(_))
+        ));
+        assert!(!store.check_and_record(&second, 50, 3).await.unwrap());
+
+        // Once a key expires, it makes room for a new one
+        assert!(store.check_and_record(&third, 100, 51).await.unwrap());
+        assert_eq!(store.len(), 2);
+        assert!(!store.check_and_record(&first, 100, 51).await.unwrap());
+
+        // Forgotten keys are accepted again
+        store.forget(&third).await.unwrap();
+        assert!(store.check_and_record(&third, 100, 52).await.unwrap());
+
+        // Keys past their retention are accepted again
+        assert!(store.check_and_record(&first, 200, 101).await.unwrap());
+        assert_eq!(store.prune(1_000).await.unwrap(), 2);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_key_uses_authenticated_sender() {
+        let message = Message::new("test", json!({}))
+            .unwrap()
+            .from("did:example:alice");
+        let forged = UnpackMetadata::default();
+        let authcrypted = UnpackMetadata {
+            authenticated_sender: Some("did:example:alice#key-1".into()),
+            ..UnpackMetadata::default()
+        };
+
+        assert_eq!(ReplayKey::for_message(&message, &forged).sender, None);
+        assert_eq!(
+            ReplayKey::for_message(&message, &authcrypted)
+                .sender
+                .as_deref(),
+            Some("did:example:alice")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_replay_store_survives_restart() {
+        let path =
+            std::env::temp_dir().join(format!("tap-didcomm-replay-{}.jsonl", std::process::id()));
+        let key = ReplayKey::new(Some("did:example:alice"), "msg-1");
+        let old = ReplayKey::new(Some("did:example:alice"), "msg-0");
+
+        let store = FileReplayStore::open(&path).await.unwrap();
+        assert!(store.check_and_record(&key, 200, 0).await.unwrap());
+        assert!(store.check_and_record(&old, 50, 0).await.unwrap());
+        drop(store);
+
+        let store = FileReplayStore::open(&path).await.unwrap();
+        assert!(!store.check_and_record(&key, 200, 10).await.unwrap());
+        assert_eq!(store.prune(100).await.unwrap(), 1);
+        drop(store);
+
+        let store = FileReplayStore::open(&path).await.unwrap();
+        assert!(!store.check_and_record(&key, 200, 150).await.unwrap());
+        assert!(store.check_and_record(&old, 200, 150).await.unwrap());
+
+        // Forgotten keys stay forgotten after a restart
+        store.forget(&key).await.unwrap();
+        drop(store);
+        let store = FileReplayStore::open(&path).await.unwrap();
+        assert!(store.check_and_record(&key, 200, 160).await.unwrap());
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[test]
+    fn test_retention() {
+        let config = ReplayConfig {
+            default_retention: 100,
+            max_retention: 1_000,
+            ..ReplayConfig::default()
+        };
+        let mut message = Message::new("test", json!({})).unwrap();
+        message.created_time = 500;
+
+        assert_eq!(config.retain_until(&message, 10, 500), 600);
+        assert!(config.check(&message, 10, 600).is_ok());
+        assert!(config.check(&message, 10, 601).is_err());
+        message.expires_time = Some(700);
+        assert_eq!(config.retain_until(&message, 10, 500), 710);
+        message.expires_time = Some(5_000);
+        assert_eq!(config.retain_until(&message, 10, 500), 1_500);
+
+        // A message that expires after the retention window is rejected once
+        // its key would no longer be retained
+        assert!(config.check(&message, 10, 1_500).is_ok());
+        assert!(matches!(
+            config.check(&message, 10, 1_501),
+            Err(Error::Core(CoreError::MessageExpired(_)))
+        ));
+    }
+}
@@ -0,0 +1,474 @@
+//! Routing of received messages to handlers.
+//!
+//! Handlers are registered for a [`HandlerPattern`]:
+//!
+//! - An exact message type, such as `https://tap.rsvp/schema/1.0/Transfer`
+//! - A protocol PIURI, such as `https://tap.rsvp/schema/1.0`, which matches
+//!   every message of the protocol with the same major version. Following
+//!   `DIDComm` semver rules, a `1.0` handler also receives `1.1` messages.
+//! - A wildcard, where `*` matches any characters, such as
+//!   `https://didcomm.org/*`
+//!
+//! A message goes to the handlers of the most specific kind of pattern that
+//! matches it: exact types before protocols before wildcards. Messages that
+//! match no pattern go to the default handler. [`ProblemReporter`] is a
+//! default handler that answers them with a problem report.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use tap_didcomm_node::router::HandlerPattern;
+//!
+//! let pattern = HandlerPattern::parse("https://tap.rsvp/schema/1.0");
+//! assert!(pattern.matches("https://tap.rsvp/schema/1.2/Transfer"));
+//! assert!(!pattern.matches("https://tap.rsvp/schema/2.0/Transfer"));
+//! ```
+
+use serde_json::json;
+use std::sync::Arc;
+use tap_didcomm_core::{Message, PackingType};
+use tokio::sync::mpsc;
+use tracing::error;
+
+use crate::{
+    actor::{HandlerHandle, HandlerMessage},
+    error::{Error, Result},
+    handler::MessageHandler,
+    node::DIDCommNode,
+    supervisor::{HandlerConfig, Reservation, SupervisedHandler},
+};
+
+/// Message type of a problem report
+pub const PROBLEM_REPORT: &str = "https://didcomm.org/report-problem/2.0/problem-report";
+
+/// Problem code for a message type the node has no handler for
+pub const UNSUPPORTED_MESSAGE: &str = "e.p.msg.unsupported";
+
+/// A parsed message type URI, `{doc_uri}{protocol}/{major}.{minor}/{name}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageTypeUri {
+    /// The URI up to and including the slash before the protocol name
+    pub doc_uri: String,
+    /// The protocol name
+    pub protocol: String,
+    /// The major version of the protocol
+    pub major: u32,
+    /// The minor version of the protocol
+    pub minor: u32,
+    /// The message name, which is empty for a PIURI
+    pub name: String,
+}
+
+impl MessageTypeUri {
+    /// Parses a message type URI.
+    ///
+    /// # Returns
+    ///
+    /// The parsed URI, or `None` if it has no `{major}.{minor}` version
+    #[must_use]
+    pub fn parse(typ: &str) -> Option<Self> {
+        let typ = typ.trim_end_matches('/');
+        if let Some(piuri) = Self::parse_piuri(typ) {
+            return Some(piuri);
+        }
+        let (piuri, name) = typ.rsplit_once('/')?;
+        Some(Self {
+            name: name.to_string(),
+            ..Self::parse_piuri(piuri)?
+        })
+    }
+
+    /// Parses a protocol identifier URI, which has no message name
+    fn parse_piuri(piuri: &str) -> Option<Self> {
+        let (rest, version) = piuri.rsplit_once('/')?;
+        let (major, minor) = version.split_once('.')?;
+        let (doc_uri, protocol) = rest.rsplit_once('/')?;
+        if protocol.is_empty() {
+            return None;
+        }
+        Some(Self {
+            doc_uri: format!("{doc_uri}/"),
+            protocol: protocol.to_string(),
+            major: major.parse().ok()?,
+            minor: minor.parse().ok()?,
+            name: String::new(),
+        })
+    }
+
+    /// Whether both URIs belong to the same protocol with the same major
+    /// version, whatever their minor versions
+    #[must_use]
+    pub fn is_compatible(&self, other: &Self) -> bool {
+        self.doc_uri == other.doc_uri
+            && self.protocol == other.protocol
+            && self.major == other.major
+    }
+}
+
+/// A pattern of message types that a handler is registered for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandlerPattern {
+    /// Exactly one message type
+    Exact(String),
+    /// Every message of a protocol with the same major version
+    Protocol(MessageTypeUri),
+    /// Message types matching a pattern where `*` matches any characters
+    Wildcard(String),
+}
+
+impl HandlerPattern {
+    /// Parses a pattern.
+    ///
+    /// A pattern containing `*` is a wildcard, a PIURI without a message
+    /// name is a protocol and anything else is an exact message type.
+    #[must_use]
+    pub fn parse(pattern: &str) -> Self {
+        if pattern.contains('*') {
+            return Self::Wildcard(pattern.to_string());
+        }
+        match MessageTypeUri::parse(pattern) {
+            Some(uri) if uri.name.is_empty() => Self::Protocol(uri),
+            _ => Self::Exact(pattern.to_string()),
+        }
+    }
+
+    /// Whether the pattern matches a message type
+    #[must_use]
+    pub fn matches(&self, typ: &str) -> bool {
+        match self {
+            Self::Exact(exact) => exact == typ,
+            Self::Protocol(piuri) => MessageTypeUri::parse(typ)
+                .is_some_and(|uri| !uri.name.is_empty() && piuri.is_compatible(&uri)),
+            Self::Wildcard(pattern) => wildcard_matches(pattern, typ),
+        }
+    }
+
+    /// How specific the pattern is, lower being more specific
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Exact(_) => 0,
+            Self::Protocol(_) => 1,
+            Self::Wildcard(_) => 2,
+        }
+    }
+}
+
+fn wildcard_matches(pattern: &str, typ: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(first) = parts.next() else {
+        return true;
+    };
+    let Some(mut rest) = typ.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// A handler that messages are routed to
+#[derive(Clone)]
+pub enum HandlerTarget {
+    /// An actor receiving messages over a channel
+    Actor(HandlerHandle),
+    /// A typed handler whose replies the node sends
+    Handler(Arc<SupervisedHandler>),
+}
+
+impl HandlerTarget {
+    /// Reserves a place in the handler's queue without waiting
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Busy` if the queue is full
+    pub fn try_reserve(&self) -> Result<Reservation<'_>> {
+        match self {
+            Self::Actor(handle) => handle.try_reserve().map(Reservation::Actor),
+            Self::Handler(handler) => handler
+                .try_reserve()
+                .map(|permit| Reservation::Handler(handler, permit)),
+        }
+    }
+}
+
+impl From<HandlerHandle> for HandlerTarget {
+    fn from(handle: HandlerHandle) -> Self {
+        Self::Actor(handle)
+    }
+}
+
+impl From<Arc<dyn MessageHandler>> for HandlerTarget {
+    fn from(handler: Arc<dyn MessageHandler>) -> Self {
+        Self::Handler(Arc::new(SupervisedHandler::new(
+            handler,
+            &HandlerConfig::default(),
+        )))
+    }
+}
+
+/// Routes received messages to the handlers registered for their type
+#[derive(Clone, Default)]
+pub struct Router {
+    routes: Vec<(HandlerPattern, HandlerTarget)>,
+    default: Option<HandlerTarget>,
+}
+
+impl Router {
+    /// Creates a router without handlers
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a handler for a pattern
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The message types to route to the handler
+    /// * `handler` - The handler
+    pub fn add(&mut self, pattern: HandlerPattern, handler: impl Into<HandlerTarget>) {
+        self.routes.push((pattern, handler.into()));
+    }
+
+    /// Sets the handler for messages that match no pattern
+    pub fn set_default(&mut self, handler: impl Into<HandlerTarget>) {
+        self.default = Some(handler.into());
+    }
+
+    /// Gets the handlers a message type is routed to
+    ///
+    /// # Returns
+    ///
+    /// The handlers of the most specific matching patterns, in the order
+    /// they were registered, or the default handler if no pattern matches
+    #[must_use]
+    pub fn handlers(&self, typ: &str) -> Vec<&HandlerTarget> {
+        let matching: Vec<_> = self
+            .routes
+            .iter()
+            .filter(|(pattern, _)| pattern.matches(typ))
+            .collect();
+        match matching.iter().map(|(pattern, _)| pattern.rank()).min() {
+            Some(rank) => matching
+                .into_iter()
+                .filter(|(pattern, _)| pattern.rank() == rank)
+                .map(|(_, handler)| handler)
+                .collect(),
+            None => self.default.iter().collect(),
+        }
+    }
+}
+
+/// Creates a problem report for a received message.
+///
+/// The report is a reply to the sender in the message's thread, with the
+/// thread of the message as its parent thread.
+///
+/// # Arguments
+///
+/// * `message` - The message the problem is with
+/// * `code` - The problem code, such as [`UNSUPPORTED_MESSAGE`]
+/// * `comment` - A description of the problem, where `{1}` is replaced by
+///   the first argument
+/// * `args` - Arguments for the comment
+///
+/// # Errors
+///
+/// Returns an error if the report cannot be created
+pub fn problem_report(
+    message: &Message,
+    code: &str,
+    comment: &str,
+    args: &[&str],
+) -> Result<Message> {
+    let mut report = message.reply(
+        PROBLEM_REPORT,
+        json!({
+            "code": code,
+            "comment": comment,
+            "args": args,
+        }),
+    )?;
+    report.pthid = Some(message.thread_id().to_string());
+    Ok(report)
+}
+
+/// A default handler that answers unrouted messages with a problem report
+pub struct ProblemReporter {
+    node: Arc<DIDCommNode>,
+}
+
+impl ProblemReporter {
+    /// Creates a new handler sending reports with the given node
+    #[must_use]
+    pub fn new(node: Arc<DIDCommNode>) -> Self {
+        Self { node }
+    }
+
+    /// Builds the report for an unrouted message.
+    ///
+    /// Anonymous messages and problem reports get no report.
+    fn report(message: &Message) -> Result<Option<Message>> {
+        if message.from.is_none() || message.typ.as_str() == PROBLEM_REPORT {
+            return Ok(None);
+        }
+        problem_report(
+            message,
+            UNSUPPORTED_MESSAGE,
+            "Message type {1} is not supported",
+            &[message.typ.as_str()],
+        )
+        .map(Some)
+    }
+
+    /// Runs the handler, processing messages from the given channel
+    ///
+    /// Reports are authcrypted and sent to the sender of the message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if message processing fails
+    pub async fn run(self, mut rx: mpsc::Receiver<HandlerMessage>) -> Result<()> {
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                HandlerMessage::HandleMessage(message, reply_tx) => {
+                    let result = match Self::report(&message.0) {
+                        Ok(Some(report)) => self
+                            .node
+                            .send(&report, PackingType::AuthcryptV2)
+                            .await
+                            .map(drop),
+                        Ok(None) => Ok(()),
+                        Err(e) => Err(e),
+                    };
+                    if reply_tx.send(result).is_err() {
+                        error!("Failed to send reply");
+                    }
+                }
+                HandlerMessage::Process {
+                    message, response, ..
+                } => {
+                    let result = Self::report(&message).and_then(|report| {
+                        report.ok_or_else(|| {
+                            Error::InvalidFormat("No problem report for the message".into())
+                        })
+                    });
+                    if response.send(result).await.is_err() {
+                        error!("Failed to send response");
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handler() -> (HandlerHandle, mpsc::Receiver<HandlerMessage>) {
+        let (tx, rx) = mpsc::channel(1);
+        (HandlerHandle::new(tx), rx)
+    }
+
+    #[test]
+    fn test_handler_patterns() {
+        let uri = MessageTypeUri::parse("https://tap.rsvp/schema/1.3/Transfer").unwrap();
+        assert_eq!(uri.doc_uri, "https://tap.rsvp/");
+        assert_eq!(uri.protocol, "schema");
+        assert_eq!((uri.major, uri.minor), (1, 3));
+        assert_eq!(uri.name, "Transfer");
+
+        let protocol = HandlerPattern::parse("https://tap.rsvp/schema/1.0");
+        assert!(matches!(protocol, HandlerPattern::Protocol(_)));
+        assert!(protocol.matches("https://tap.rsvp/schema/1.0/Transfer"));
+        assert!(protocol.matches("https://tap.rsvp/schema/1.7/Authorize"));
+        assert!(!protocol.matches("https://tap.rsvp/schema/2.0/Transfer"));
+        assert!(!protocol.matches("https://tap.rsvp/other/1.0/Transfer"));
+        assert!(!protocol.matches("https://tap.rsvp/schema/1.0"));
+
+        let exact = HandlerPattern::parse("https://tap.rsvp/schema/1.0/Transfer");
+        assert!(matches!(exact, HandlerPattern::Exact(_)));
+        assert!(!exact.matches("https://tap.rsvp/schema/1.1/Transfer"));
+
+        let wildcard = HandlerPattern::parse("https://didcomm.org/*/2.0/*");
+        assert!(wildcard.matches("https://didcomm.org/trust-ping/2.0/ping"));
+        assert!(!wildcard.matches("https://didcomm.org/messagepickup/3.0/status"));
+        assert!(HandlerPattern::parse("*").matches("test"));
+        assert!(!HandlerPattern::parse("a*a").matches("a"));
+    }
+
+    #[test]
+    fn test_router_prefers_specific_patterns() {
+        let mut router = Router::new();
+        assert!(router.handlers("test").is_empty());
+
+        let (exact, _exact_rx) = handler();
+        let (protocol, _protocol_rx) = handler();
+        let (wildcard, _wildcard_rx) = handler();
+        let (default, _default_rx) = handler();
+        router.add(HandlerPattern::parse("*"), wildcard);
+        router.add(
+            HandlerPattern::parse("https://tap.rsvp/schema/1.0"),
+            protocol,
+        );
+        router.add(
+            HandlerPattern::parse("https://tap.rsvp/schema/1.0/Transfer"),
+            exact.clone(),
+        );
+        router.add(
+            HandlerPattern::parse("https://tap.rsvp/schema/1.0/Transfer"),
+            exact,
+        );
+        router.set_default(default);
+
+        assert_eq!(
+            router
+                .handlers("https://tap.rsvp/schema/1.0/Transfer")
+                .len(),
+            2
+        );
+        assert_eq!(
+            router.handlers("https://tap.rsvp/schema/1.1/Reject").len(),
+            1
+        );
+        assert_eq!(router.handlers("test").len(), 1);
+
+        router.routes.clear();
+        assert_eq!(router.handlers("test").len(), 1);
+    }
+
+    #[test]
+    fn test_problem_report() {
+        let message = Message::new("https://example.com/unknown/1.0/hello", json!({}))
+            .unwrap()
+            .from("did:example:alice")
+            .to(["did:example:bob"])
+            .thid("thread-1");
+        let report = ProblemReporter::report(&message).unwrap().unwrap();
+        assert_eq!(report.typ.as_str(), PROBLEM_REPORT);
+        assert_eq!(report.from.as_deref(), Some("did:example:bob"));
+        assert_eq!(report.to, Some(vec!["did:example:alice".to_string()]));
+        assert_eq!(report.pthid.as_deref(), Some("thread-1"));
+        assert_eq!(report.body["code"], UNSUPPORTED_MESSAGE);
+        assert_eq!(
+            report.body["args"][0],
+            "https://example.com/unknown/1.0/hello"
+        );
+
+        // Never answer a problem report or an anonymous message
+        assert!(ProblemReporter::report(&report).unwrap().is_none());
+        let mut anonymous = message;
+        anonymous.from = None;
+        assert!(ProblemReporter::report(&anonymous).unwrap().is_none());
+    }
+}
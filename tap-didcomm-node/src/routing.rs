@@ -0,0 +1,344 @@
+//! Routing protocol 2.0 for messages delivered through mediators.
+//!
+//! When a recipient's `DIDCommMessaging` service lists `routingKeys`, the
+//! recipient cannot be reached directly. The packed message is instead wrapped
+//! in a `forward` message for each mediator, anoncrypted to that mediator's
+//! key, and delivered to the service endpoint, which belongs to the outermost
+//! mediator.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! use tap_didcomm_node::dispatch::resolve_endpoints;
+//! use tap_didcomm_node::routing::wrap_in_forwards;
+//! use tap_didcomm_node::mock::MockPlugin;
+//! use tap_didcomm_core::DIDCommPlugin;
+//! use tap_didcomm_node::error::Result;
+//!
+//! async fn route(envelope: &str) -> Result<Option<String>> {
+//!     let plugin = MockPlugin::new();
+//!     let endpoints = resolve_endpoints(plugin.resolver(), "did:example:bob").await?;
+//!     match endpoints.first() {
+//!         Some(endpoint) => Ok(Some(
+//!             wrap_in_forwards(envelope, "did:example:bob", &endpoint.routing_keys, &plugin)
+//!                 .await?,
+//!         )),
+//!         None => Ok(None),
+//!     }
+//! }
+//! ```
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tap_didcomm_core::{
+    pack_message, Attachment, AttachmentData, DIDCommPlugin, Message, MessageId, PackingType,
+};
+
+use crate::error::{Error, Result};
+
+/// The message type of routing protocol 2.0 forward messages
+pub const FORWARD_TYPE: &str = "https://didcomm.org/routing/2.0/forward";
+
+/// Configuration for routing outgoing messages.
+#[derive(Debug, Clone)]
+pub struct RoutingConfig {
+    /// Whether messages for recipients with routing keys are wrapped in
+    /// forward messages. When disabled, the envelope is delivered as packed.
+    pub forward: bool,
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self { forward: true }
+    }
+}
+
+/// An envelope and the endpoint to deliver it to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Route {
+    /// The endpoint to deliver the envelope to
+    pub endpoint: String,
+
+    /// The outermost envelope, wrapped for the endpoint's mediators
+    pub envelope: String,
+
+    /// The media type of the outermost envelope
+    pub media_type: String,
+}
+
+/// A packed message ready for delivery.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutedMessage {
+    /// The recipients reached through these routes
+    pub recipients: Vec<String>,
+
+    /// The packed message, before any forward wrapping
+    pub envelope: String,
+
+    /// The routes to try, in order, until one accepts the message. Empty if
+    /// the recipients advertise no `DIDComm` endpoint.
+    pub routes: Vec<Route>,
+}
+
+/// Wraps a packed message in a chain of forward messages.
+///
+/// The last routing key belongs to the mediator closest to the recipient, so
+/// wrapping starts there; the returned envelope is addressed to the first
+/// routing key.
+///
+/// # Arguments
+///
+/// * `envelope` - The packed message for the recipient
+/// * `next` - The recipient the innermost forward delivers to
+/// * `routing_keys` - The mediators' keys, outermost first
+/// * `plugin` - Plugin used to anoncrypt each forward message
+///
+/// # Returns
+///
+/// The outermost envelope, or `envelope` unchanged if there are no routing keys
+///
+/// # Errors
+///
+/// Returns an error if a forward message cannot be built or packed
+pub async fn wrap_in_forwards(
+    envelope: &str,
+    next: &str,
+    routing_keys: &[String],
+    plugin: &dyn DIDCommPlugin,
+) -> Result<String> {
+    let mut envelope = envelope.to_string();
+    let mut next = next.to_string();
+
+    for key in routing_keys.iter().rev() {
+        let forward = forward_message(&envelope, &next)?.to([key.as_str()]);
+        envelope = pack_message(&forward, plugin, PackingType::AnonV2).await?;
+        next.clone_from(key);
+    }
+
+    Ok(envelope)
+}
+
+/// Attaches a packed envelope to a message.
+///
+/// The envelope is attached as JSON when it is a JSON envelope, and as
+/// base64 otherwise.
+///
+/// # Arguments
+///
+/// * `id` - The ID of the attachment
+/// * `envelope` - The packed envelope
+///
+/// # Errors
+///
+/// Returns an error if the envelope is neither JSON nor valid base64url
+pub fn envelope_attachment(id: impl Into<String>, envelope: &str) -> Result<Attachment> {
+    let data = match serde_json::from_str(envelope) {
+        Ok(value) => AttachmentData::json(value),
+        Err(_) => AttachmentData::base64(
+            &URL_SAFE_NO_PAD
+                .decode(envelope)
+                .map_err(|e| Error::InvalidFormat(format!("Invalid envelope: {e}")))?,
+        ),
+    };
+    Ok(Attachment::new(id, data))
+}
+
+/// Extracts a packed envelope attached with [`envelope_attachment`].
+///
+/// # Returns
+///
+/// The envelope: as JSON if it was attached as JSON, and base64url encoded
+/// otherwise
+///
+/// # Errors
+///
+/// Returns an error if the attachment has no inline content
+pub fn attached_envelope(attachment: &Attachment) -> Result<String> {
+    let content = attachment
+        .data
+        .inline_content()?
+        .ok_or_else(|| Error::InvalidFormat("Attachment has no inline envelope".into()))?;
+    Ok(
+        match serde_json::from_slice::<serde_json::Value>(&content) {
+            Ok(value) => value.to_string(),
+            Err(_) => URL_SAFE_NO_PAD.encode(content),
+        },
+    )
+}
+
+/// Builds a forward message carrying an envelope for `next`.
+///
+/// # Errors
+///
+/// Returns an error if the envelope is neither JSON nor valid base64url
+pub fn forward_message(envelope: &str, next: &str) -> Result<Message> {
+    Ok(Message::new(FORWARD_TYPE, json!({ "next": next }))?
+        .with_attachment(envelope_attachment(MessageId::random().as_str(), envelope)?))
+}
+
+/// Extracts the recipient and the attached envelope of a forward message.
+///
+/// # Returns
+///
+/// The `next` recipient and the envelope, encoded like a packed message
+///
+/// # Errors
+///
+/// Returns an error if the message is not a valid forward message
+pub fn parse_forward(message: &Message) -> Result<(String, String)> {
+    if message.typ.as_str() != FORWARD_TYPE {
+        return Err(Error::InvalidFormat(format!(
+            "Not a forward message: {}",
+            message.typ.as_str()
+        )));
+    }
+    let next = message
+        .body
+        .get("next")
+        .and_then(serde_json::Value::as_str)
+        .ok_or_else(|| Error::InvalidFormat("Forward message has no next recipient".into()))?;
+    let attachment = message
+        .attachments
+        .as_ref()
+        .and_then(|attachments| attachments.first())
+        .ok_or_else(|| Error::InvalidFormat("Forward message has no attached envelope".into()))?;
+
+    Ok((next.to_string(), attached_envelope(attachment)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dispatch::resolve_endpoints, mock::MockPlugin};
+    use async_trait::async_trait;
+    use tap_didcomm_core::{unpack_message, DIDResolver, Encryptor, Signer};
+
+    /// A plugin whose DID documents route through two mediators
+    struct RoutedPlugin(MockPlugin);
+
+    #[async_trait]
+    impl DIDResolver for RoutedPlugin {
+        async fn resolve(&self, did: &str) -> tap_didcomm_core::Result<String> {
+            Ok(json!({
+                "id": did,
+                "service": [{
+                    "id": format!("{did}#didcomm"),
+                    "type": "DIDCommMessaging",
+                    "serviceEndpoint": {
+                        "uri": "https://m1.example.com/didcomm",
+                        "routingKeys": ["did:example:m1#key-1", "did:example:m2#key-1"]
+                    }
+                }]
+            })
+            .to_string())
+        }
+    }
+
+    #[async_trait]
+    impl Signer for RoutedPlugin {
+        async fn sign(&self, message: &[u8], from: &str) -> tap_didcomm_core::Result<Vec<u8>> {
+            self.0.sign(message, from).await
+        }
+
+        async fn verify(
+            &self,
+            message: &[u8],
+            signature: &[u8],
+            from: &str,
+        ) -> tap_didcomm_core::Result<bool> {
+            self.0.verify(message, signature, from).await
+        }
+    }
+
+    #[async_trait]
+    impl Encryptor for RoutedPlugin {
+        async fn encrypt(
+            &self,
+            message: &[u8],
+            to: &[&str],
+            from: Option<&str>,
+        ) -> tap_didcomm_core::Result<Vec<u8>> {
+            self.0.encrypt(message, to, from).await
+        }
+
+        async fn decrypt(
+            &self,
+            message: &[u8],
+            recipient: &str,
+        ) -> tap_didcomm_core::Result<Vec<u8>> {
+            self.0.decrypt(message, recipient).await
+        }
+
+        async fn decrypt_from(
+            &self,
+            message: &[u8],
+            recipient: &str,
+            sender: &str,
+        ) -> tap_didcomm_core::Result<Vec<u8>> {
+            self.0.decrypt_from(message, recipient, sender).await
+        }
+    }
+
+    impl DIDCommPlugin for RoutedPlugin {
+        fn resolver(&self) -> &dyn DIDResolver {
+            self
+        }
+
+        fn signer(&self) -> &dyn Signer {
+            self
+        }
+
+        fn encryptor(&self) -> &dyn Encryptor {
+            self
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forward_chain() {
+        let plugin = RoutedPlugin(MockPlugin::new());
+        let endpoints = resolve_endpoints(&plugin, "did:example:bob#key-1")
+            .await
+            .unwrap();
+        let service = &endpoints[0];
+        assert_eq!(service.uri, "https://m1.example.com/didcomm");
+
+        let message = Message::new("test", json!({"hello": "bob"}))
+            .unwrap()
+            .to(["did:example:bob"]);
+        let packed = pack_message(&message, &plugin, PackingType::AnonV2)
+            .await
+            .unwrap();
+        let wrapped = wrap_in_forwards(&packed, "did:example:bob", &service.routing_keys, &plugin)
+            .await
+            .unwrap();
+
+        // The outermost forward is for the first mediator and points at the second
+        let outer = unpack_message(&wrapped, &plugin, Some("did:example:m1#key-1".into()))
+            .await
+            .unwrap();
+        assert_eq!(outer.to, Some(vec!["did:example:m1#key-1".to_string()]));
+        let (next, inner) = parse_forward(&outer).unwrap();
+        assert_eq!(next, "did:example:m2#key-1");
+
+        let inner = unpack_message(&inner, &plugin, Some("did:example:m2#key-1".into()))
+            .await
+            .unwrap();
+        let (next, envelope) = parse_forward(&inner).unwrap();
+        assert_eq!(next, "did:example:bob");
+        assert_eq!(envelope, packed);
+    }
+
+    #[tokio::test]
+    async fn test_no_routing_keys() {
+        let plugin = MockPlugin::new();
+        assert!(resolve_endpoints(plugin.resolver(), "did:example:bob")
+            .await
+            .unwrap()
+            .is_empty());
+        let wrapped = wrap_in_forwards("envelope", "did:example:bob", &[], &plugin)
+            .await
+            .unwrap();
+        assert_eq!(wrapped, "envelope");
+    }
+}
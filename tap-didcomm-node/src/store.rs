@@ -0,0 +1,728 @@
+//! Storage for received and sent messages.
+//!
+//! A [`MessageStore`] keeps the node's inbox, outbox and thread history
+//! beyond what the in-memory [`crate::thread::ThreadTracker`] holds. Every
+//! [`StoredMessage`] has the plaintext message, the raw envelope it arrived
+//! in and the [`UnpackMetadata`] of that envelope, and can be looked up by
+//! message ID, thread or peer.
+//!
+//! Stores can encrypt messages at rest with a [`StoreKey`]. The message is
+//! sealed with AES-256-GCM; only its ID, direction and the time it was stored
+//! stay readable. A persistent store indexes the thread and peers of a
+//! message by their HMAC-SHA256 under the key, so messages can still be
+//! looked up by thread or peer without either being written in the clear.
+//! Without a key, the index holds thread IDs and peer DIDs as they are.
+//!
+//! # Components
+//!
+//! - [`MessageStore`]: Storage for messages, indexed by thread and peer
+//! - [`MemoryMessageStore`]: An in-memory store, for tests
+//! - [`SqliteMessageStore`]: A store persisted to an `SQLite` database,
+//!   available with the `sqlite` feature
+//! - [`StoreKey`]: A key for encrypting messages at rest
+//!
+//! # Examples
+//!
+//! ```rust
+//! use serde_json::json;
+//! use tap_didcomm_core::Message;
+//! use tap_didcomm_node::store::{MemoryMessageStore, MessageStore, StoreKey, StoredMessage};
+//! use tap_didcomm_node::thread::Direction;
+//!
+//! # async fn example() -> tap_didcomm_node::error::Result<()> {
+//! let mut store = MemoryMessageStore::new();
+//! store.set_key(StoreKey::generate());
+//!
+//! let message = Message::new("test", json!({}))?.from("did:example:alice");
+//! store.save(&StoredMessage::new(Direction::Inbound, message.clone(), 1_000)).await?;
+//!
+//! let history = store.list_by_peer("did:example:alice").await?;
+//! assert_eq!(history[0].id(), message.id.as_str());
+//! # Ok(())
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::Mutex;
+use tap_didcomm_core::{
+    jwe::algorithms::{decrypt_aes_gcm, encrypt_aes_gcm, generate_random_key},
+    Message, PackingType,
+};
+
+use crate::{
+    error::{Error, Result},
+    handler::UnpackMetadata,
+    thread::Direction,
+};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// A message kept by a [`MessageStore`]
+#[derive(Debug, Clone)]
+pub struct StoredMessage {
+    /// Whether the message was received or sent
+    pub direction: Direction,
+
+    /// The plaintext message
+    pub message: Message,
+
+    /// The envelope the message was received or sent in, if it is kept
+    pub envelope: Option<String>,
+
+    /// How the envelope was packed
+    pub meta: UnpackMetadata,
+
+    /// The Unix timestamp at which the message was stored
+    pub stored_at: u64,
+}
+
+impl StoredMessage {
+    /// Creates a stored message without an envelope
+    #[must_use]
+    pub fn new(direction: Direction, message: Message, now: u64) -> Self {
+        Self {
+            direction,
+            message,
+            envelope: None,
+            meta: UnpackMetadata::default(),
+            stored_at: now,
+        }
+    }
+
+    /// Sets the envelope and its metadata
+    #[must_use]
+    pub fn envelope(mut self, envelope: impl Into<String>, meta: UnpackMetadata) -> Self {
+        self.envelope = Some(envelope.into());
+        self.meta = meta;
+        self
+    }
+
+    /// Gets the ID of the message
+    #[must_use]
+    pub fn id(&self) -> &str {
+        self.message.id.as_str()
+    }
+
+    /// Gets the ID of the message's thread
+    #[must_use]
+    pub fn thread_id(&self) -> &str {
+        self.message.thread_id()
+    }
+
+    /// Gets the other parties: the sender of a received message or the
+    /// recipients of a sent one
+    #[must_use]
+    pub fn peers(&self) -> Vec<String> {
+        match self.direction {
+            Direction::Inbound => self.message.from.iter().cloned().collect(),
+            Direction::Outbound => self.message.to.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// A key for encrypting messages at rest with AES-256-GCM
+#[derive(Clone)]
+pub struct StoreKey([u8; KEY_LEN]);
+
+impl std::fmt::Debug for StoreKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StoreKey(..)")
+    }
+}
+
+impl StoreKey {
+    /// Creates a key from 32 bytes
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Config` if the key is not 32 bytes long
+    pub fn new(key: &[u8]) -> Result<Self> {
+        key.try_into()
+            .map(Self)
+            .map_err(|_| Error::Config(format!("A store key must be {KEY_LEN} bytes")))
+    }
+
+    /// Generates a random key
+    #[must_use]
+    pub fn generate() -> Self {
+        let mut key = [0; KEY_LEN];
+        key.copy_from_slice(&generate_random_key(KEY_LEN));
+        Self(key)
+    }
+
+    /// Encrypts `plaintext`, bound to the ID of its message
+    fn seal(&self, id: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = generate_random_key(NONCE_LEN);
+        let (ciphertext, tag) = encrypt_aes_gcm(&self.0, &nonce, id.as_bytes(), plaintext)?;
+        Ok([nonce, ciphertext, tag].concat())
+    }
+
+    /// Decrypts what [`StoreKey::seal`] encrypted for the same message ID
+    fn open(&self, id: &str, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return Err(Error::Storage(format!("Stored message {id} is truncated")));
+        }
+        let (nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        decrypt_aes_gcm(&self.0, nonce, id.as_bytes(), ciphertext, tag)
+            .map_err(|e| Error::Storage(format!("Cannot decrypt stored message {id}: {e}")))
+    }
+
+    /// Blinds an index value, such as a thread ID or peer DID, so that it
+    /// can be looked up without being stored in the clear
+    #[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+    fn blind(&self, kind: &str, value: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts keys of any length");
+        mac.update(b"tap-didcomm-store-index\0");
+        mac.update(kind.as_bytes());
+        mac.update(b"\0");
+        mac.update(value.as_bytes());
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    }
+}
+
+/// What is kept of a message besides its index
+#[derive(Serialize, Deserialize)]
+struct Payload {
+    message: Message,
+    envelope: Option<String>,
+    packing: Option<String>,
+    authenticated_sender: Option<String>,
+    #[serde(default)]
+    signed_by: Option<String>,
+    envelope_size: usize,
+    received_at: u64,
+}
+
+fn packing_name(packing: PackingType) -> &'static str {
+    match packing {
+        PackingType::Signed => "signed",
+        PackingType::AuthcryptV2 => "authcrypt",
+        PackingType::AnonV2 => "anoncrypt",
+    }
+}
+
+fn parse_packing(name: &str) -> Result<PackingType> {
+    match name {
+        "signed" => Ok(PackingType::Signed),
+        "authcrypt" => Ok(PackingType::AuthcryptV2),
+        "anoncrypt" => Ok(PackingType::AnonV2),
+        _ => Err(Error::Storage(format!("Unknown packing: {name}"))),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn direction_name(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Inbound => "inbound",
+        Direction::Outbound => "outbound",
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn parse_direction(name: &str) -> Result<Direction> {
+    match name {
+        "inbound" => Ok(Direction::Inbound),
+        "outbound" => Ok(Direction::Outbound),
+        _ => Err(Error::Storage(format!("Unknown direction: {name}"))),
+    }
+}
+
+/// Serializes the payload of a message, sealing it if there is a key
+fn encode(stored: &StoredMessage, key: Option<&StoreKey>) -> Result<Vec<u8>> {
+    let payload = Payload {
+        message: stored.message.clone(),
+        envelope: stored.envelope.clone(),
+        packing: stored.meta.packing.map(|p| packing_name(p).to_string()),
+        authenticated_sender: stored.meta.authenticated_sender.clone(),
+        signed_by: stored.meta.signed_by.clone(),
+        envelope_size: stored.meta.envelope_size,
+        received_at: stored.meta.received_at,
+    };
+    let json = serde_json::to_vec(&payload).map_err(|e| Error::Storage(e.to_string()))?;
+    match key {
+        Some(key) => key.seal(stored.id(), &json),
+        None => Ok(json),
+    }
+}
+
+/// Reads a message stored by [`encode`]
+fn decode(
+    id: &str,
+    direction: Direction,
+    stored_at: u64,
+    payload: &[u8],
+    key: Option<&StoreKey>,
+) -> Result<StoredMessage> {
+    let json = match key {
+        Some(key) => key.open(id, payload)?,
+        None => payload.to_vec(),
+    };
+    let payload: Payload = serde_json::from_slice(&json)
+        .map_err(|e| Error::Storage(format!("Invalid stored message {id}: {e}")))?;
+    Ok(StoredMessage {
+        direction,
+        message: payload.message,
+        envelope: payload.envelope,
+        meta: UnpackMetadata {
+            packing: payload.packing.as_deref().map(parse_packing).transpose()?,
+            authenticated_sender: payload.authenticated_sender,
+            signed_by: payload.signed_by,
+            envelope_size: payload.envelope_size,
+            received_at: payload.received_at,
+        },
+        stored_at,
+    })
+}
+
+/// Storage for received and sent messages
+#[async_trait]
+pub trait MessageStore: Send + Sync {
+    /// Stores a message, replacing one with the same ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be written
+    async fn save(&self, message: &StoredMessage) -> Result<()>;
+
+    /// Gets a message by ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read
+    async fn get(&self, id: &str) -> Result<Option<StoredMessage>>;
+
+    /// Lists the messages in a thread, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read
+    async fn list_by_thread(&self, thid: &str) -> Result<Vec<StoredMessage>>;
+
+    /// Lists the messages received from or sent to a DID, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read
+    async fn list_by_peer(&self, did: &str) -> Result<Vec<StoredMessage>>;
+
+    /// Removes messages stored before `before`.
+    ///
+    /// # Returns
+    ///
+    /// The number of messages removed
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be written
+    async fn prune(&self, before: u64) -> Result<usize>;
+}
+
+/// A message in a [`MemoryMessageStore`], with its payload encoded
+#[derive(Debug)]
+struct MemoryRecord {
+    id: String,
+    thread_id: String,
+    direction: Direction,
+    peers: Vec<String>,
+    stored_at: u64,
+    payload: Vec<u8>,
+}
+
+/// An in-memory message store.
+///
+/// Messages are lost when the node restarts, so this is meant for tests.
+#[derive(Debug, Default)]
+pub struct MemoryMessageStore {
+    records: Mutex<Vec<MemoryRecord>>,
+    key: Option<StoreKey>,
+}
+
+impl MemoryMessageStore {
+    /// Creates an empty store
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the key messages are encrypted with.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to use
+    pub fn set_key(&mut self, key: StoreKey) {
+        self.key = Some(key);
+    }
+
+    fn select(&self, filter: impl Fn(&MemoryRecord) -> bool) -> Result<Vec<StoredMessage>> {
+        self.records
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .iter()
+            .filter(|record| filter(record))
+            .map(|record| {
+                decode(
+                    &record.id,
+                    record.direction,
+                    record.stored_at,
+                    &record.payload,
+                    self.key.as_ref(),
+                )
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl MessageStore for MemoryMessageStore {
+    async fn save(&self, message: &StoredMessage) -> Result<()> {
+        let record = MemoryRecord {
+            id: message.id().to_string(),
+            thread_id: message.thread_id().to_string(),
+            direction: message.direction,
+            peers: message.peers(),
+            stored_at: message.stored_at,
+            payload: encode(message, self.key.as_ref())?,
+        };
+        let mut records = self
+            .records
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        records.retain(|existing| existing.id != record.id);
+        records.push(record);
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<StoredMessage>> {
+        Ok(self.select(|record| record.id == id)?.pop())
+    }
+
+    async fn list_by_thread(&self, thid: &str) -> Result<Vec<StoredMessage>> {
+        self.select(|record| record.thread_id == thid)
+    }
+
+    async fn list_by_peer(&self, did: &str) -> Result<Vec<StoredMessage>> {
+        self.select(|record| record.peers.iter().any(|peer| peer == did))
+    }
+
+    async fn prune(&self, before: u64) -> Result<usize> {
+        let mut records = self
+            .records
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let count = records.len();
+        records.retain(|record| record.stored_at >= before);
+        Ok(count - records.len())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteMessageStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{
+        decode, direction_name, encode, parse_direction, MessageStore, StoreKey, StoredMessage,
+    };
+    use crate::error::{Error, Result};
+    use async_trait::async_trait;
+    use rusqlite::{params, Connection};
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    fn storage_error(e: impl std::fmt::Display) -> Error {
+        Error::Storage(e.to_string())
+    }
+
+    /// A message store persisted to an `SQLite` database.
+    ///
+    /// Peers are kept in a table of their own, so messages can be listed by
+    /// peer with an index. With a key, the thread and peer columns hold
+    /// HMACs of the thread ID and peer DIDs rather than the values
+    /// themselves.
+    #[derive(Debug)]
+    pub struct SqliteMessageStore {
+        conn: Mutex<Connection>,
+        key: Option<StoreKey>,
+    }
+
+    impl SqliteMessageStore {
+        /// Opens or creates a message database at `path`.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the database cannot be opened or migrated
+        pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+            Self::init(Connection::open(path).map_err(storage_error)?)
+        }
+
+        /// Creates a store in a private in-memory database.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the database cannot be created
+        pub fn open_in_memory() -> Result<Self> {
+            Self::init(Connection::open_in_memory().map_err(storage_error)?)
+        }
+
+        /// Set the key messages are encrypted with, and their thread and
+        /// peers indexed by.
+        ///
+        /// Messages stored without a key, or with another key, cannot be
+        /// read or found once it is set.
+        ///
+        /// # Arguments
+        ///
+        /// * `key` - The key to use
+        pub fn set_key(&mut self, key: StoreKey) {
+            self.key = Some(key);
+        }
+
+        fn init(conn: Connection) -> Result<Self> {
+            conn.execute_batch(
+                "PRAGMA journal_mode = WAL;
+                 PRAGMA foreign_keys = ON;
+                 CREATE TABLE IF NOT EXISTS messages (
+                     id TEXT PRIMARY KEY,
+                     thread_id TEXT NOT NULL,
+                     direction TEXT NOT NULL,
+                     stored_at INTEGER NOT NULL,
+                     payload BLOB NOT NULL
+                 );
+                 CREATE INDEX IF NOT EXISTS messages_thread ON messages (thread_id, stored_at);
+                 CREATE INDEX IF NOT EXISTS messages_stored_at ON messages (stored_at);
+                 CREATE TABLE IF NOT EXISTS message_peers (
+                     message_id TEXT NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+                     peer TEXT NOT NULL,
+                     PRIMARY KEY (peer, message_id)
+                 );",
+            )
+            .map_err(storage_error)?;
+            Ok(Self {
+                conn: Mutex::new(conn),
+                key: None,
+            })
+        }
+
+        fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+            self.conn
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+        }
+
+        /// Returns the value stored in an index column for `value`
+        fn index(&self, kind: &str, value: &str) -> String {
+            self.key
+                .as_ref()
+                .map_or_else(|| value.to_string(), |key| key.blind(kind, value))
+        }
+
+        fn select(
+            &self,
+            filter: &str,
+            params: impl rusqlite::Params,
+        ) -> Result<Vec<StoredMessage>> {
+            let conn = self.conn();
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT m.id, m.direction, m.stored_at, m.payload FROM messages m {filter}"
+                ))
+                .map_err(storage_error)?;
+            let rows = stmt
+                .query_map(params, |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, u64>(2)?,
+                        row.get::<_, Vec<u8>>(3)?,
+                    ))
+                })
+                .map_err(storage_error)?;
+            rows.map(|row| {
+                let (id, direction, stored_at, payload) = row.map_err(storage_error)?;
+                decode(
+                    &id,
+                    parse_direction(&direction)?,
+                    stored_at,
+                    &payload,
+                    self.key.as_ref(),
+                )
+            })
+            .collect()
+        }
+    }
+
+    #[async_trait]
+    impl MessageStore for SqliteMessageStore {
+        async fn save(&self, message: &StoredMessage) -> Result<()> {
+            let payload = encode(message, self.key.as_ref())?;
+            let mut conn = self.conn();
+            let tx = conn.transaction().map_err(storage_error)?;
+            tx.execute(
+                "INSERT OR REPLACE INTO messages (id, thread_id, direction, stored_at, payload)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    message.id(),
+                    self.index("thread", message.thread_id()),
+                    direction_name(message.direction),
+                    message.stored_at,
+                    payload,
+                ],
+            )
+            .map_err(storage_error)?;
+            for peer in message.peers() {
+                tx.execute(
+                    "INSERT OR IGNORE INTO message_peers (message_id, peer) VALUES (?1, ?2)",
+                    params![message.id(), self.index("peer", &peer)],
+                )
+                .map_err(storage_error)?;
+            }
+            tx.commit().map_err(storage_error)
+        }
+
+        async fn get(&self, id: &str) -> Result<Option<StoredMessage>> {
+            Ok(self.select("WHERE m.id = ?1", params![id])?.pop())
+        }
+
+        async fn list_by_thread(&self, thid: &str) -> Result<Vec<StoredMessage>> {
+            self.select(
+                "WHERE m.thread_id = ?1 ORDER BY m.stored_at, m.rowid",
+                params![self.index("thread", thid)],
+            )
+        }
+
+        async fn list_by_peer(&self, did: &str) -> Result<Vec<StoredMessage>> {
+            self.select(
+                "JOIN message_peers p ON p.message_id = m.id
+                 WHERE p.peer = ?1 ORDER BY m.stored_at, m.rowid",
+                params![self.index("peer", did)],
+            )
+        }
+
+        async fn prune(&self, before: u64) -> Result<usize> {
+            self.conn()
+                .execute("DELETE FROM messages WHERE stored_at < ?1", params![before])
+                .map_err(storage_error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    async fn exercise(store: &dyn MessageStore) {
+        let request = Message::new("request", json!({"n": 1}))
+            .unwrap()
+            .from("did:example:alice");
+        let reply = request
+            .reply("response", json!({"n": 2}))
+            .unwrap()
+            .to(["did:example:alice", "did:example:bob"]);
+        let meta = UnpackMetadata {
+            packing: Some(PackingType::AuthcryptV2),
+            authenticated_sender: Some("did:example:alice".into()),
+            signed_by: Some("did:example:alice".into()),
+            envelope_size: 8,
+            received_at: 10,
+        };
+        let received =
+            StoredMessage::new(Direction::Inbound, request.clone(), 10).envelope("envelope", meta);
+        store.save(&received).await.unwrap();
+        store
+            .save(&StoredMessage::new(Direction::Outbound, reply.clone(), 20))
+            .await
+            .unwrap();
+
+        let stored = store.get(request.id.as_str()).await.unwrap().unwrap();
+        assert_eq!(stored.id(), received.id());
+        assert_eq!(stored.envelope, received.envelope);
+        assert_eq!(stored.meta, received.meta);
+        assert_eq!(stored.stored_at, 10);
+        assert!(store.get("missing").await.unwrap().is_none());
+
+        let thread = store.list_by_thread(request.thread_id()).await.unwrap();
+        assert_eq!(thread.len(), 2);
+        assert_eq!(thread[1].id(), reply.id.as_str());
+        assert_eq!(thread[1].direction, Direction::Outbound);
+        assert_eq!(
+            store.list_by_peer("did:example:alice").await.unwrap().len(),
+            2
+        );
+        assert_eq!(
+            store.list_by_peer("did:example:bob").await.unwrap().len(),
+            1
+        );
+
+        assert_eq!(store.prune(15).await.unwrap(), 1);
+        assert!(store.get(request.id.as_str()).await.unwrap().is_none());
+        assert_eq!(
+            store.list_by_peer("did:example:alice").await.unwrap().len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_memory_message_store() {
+        exercise(&MemoryMessageStore::new()).await;
+
+        let mut encrypted = MemoryMessageStore::new();
+        encrypted.set_key(StoreKey::generate());
+        exercise(&encrypted).await;
+    }
+
+    #[test]
+    fn test_store_key() {
+        assert!(StoreKey::new(&[0; 16]).is_err());
+        let key = StoreKey::new(&[7; 32]).unwrap();
+        let sealed = key.seal("msg-1", b"secret").unwrap();
+        assert!(!sealed.windows(6).any(|window| window == b"secret"));
+        assert_eq!(key.open("msg-1", &sealed).unwrap(), b"secret");
+        // The payload of one message cannot be passed off as another's
+        assert!(key.open("msg-2", &sealed).is_err());
+        assert!(StoreKey::generate().open("msg-1", &sealed).is_err());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sqlite_message_store() {
+        exercise(&SqliteMessageStore::open_in_memory().unwrap()).await;
+
+        let mut encrypted = SqliteMessageStore::open_in_memory().unwrap();
+        encrypted.set_key(StoreKey::generate());
+        exercise(&encrypted).await;
+
+        // Neither the thread nor the peers are written in the clear. The
+        // directory, and the database in it, are removed when `dir` drops.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("messages.db");
+        let mut store = SqliteMessageStore::open(&path).unwrap();
+        store.set_key(StoreKey::generate());
+        let message = Message::new("test", json!({}))
+            .unwrap()
+            .from("did:example:alice")
+            .thid("thread-1");
+        store
+            .save(&StoredMessage::new(Direction::Inbound, message, 10))
+            .await
+            .unwrap();
+        assert_eq!(store.list_by_thread("thread-1").await.unwrap().len(), 1);
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        let thread: String = conn
+            .query_row("SELECT thread_id FROM messages", [], |row| row.get(0))
+            .unwrap();
+        let peer: String = conn
+            .query_row("SELECT peer FROM message_peers", [], |row| row.get(0))
+            .unwrap();
+        assert!(!thread.contains("thread-1"));
+        assert!(!peer.contains("alice"));
+    }
+}
@@ -0,0 +1,302 @@
+//! Supervision and backpressure for message handlers.
+//!
+//! A slow or failing handler must not stall the node. Every handler has a
+//! bounded queue: when it is full, [`crate::DIDCommNode::receive`] fails
+//! with `Error::Busy` before the message is recorded, so the transport can
+//! ask the sender to retry later instead of buffering the message.
+//!
+//! # Components
+//!
+//! - [`HandlerConfig`]: Queue sizes, concurrency and timeouts
+//! - [`SupervisedHandler`]: A typed handler with a bounded queue, limited
+//!   concurrency and a timeout, whose panics are caught
+//! - [`spawn_supervised`]: Runs a handler actor and restarts it when it
+//!   crashes
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! use tap_didcomm_node::supervisor::{spawn_supervised, HandlerConfig};
+//! use tap_didcomm_node::{actor::Handler, DIDCommNode, MessageHandler, NodeConfig};
+//! use std::sync::Arc;
+//!
+//! fn example(node: Arc<DIDCommNode>, handler: Arc<dyn MessageHandler>) {
+//!     let config = HandlerConfig::default();
+//!     let handle = spawn_supervised(&config, move |rx| {
+//!         Handler::new(Arc::clone(&node), Arc::clone(&handler)).run(rx)
+//!     });
+//! }
+//! ```
+
+use futures::FutureExt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+use tap_didcomm_core::Message;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tracing::{error, warn};
+
+use crate::{
+    actor::{HandlerHandle, HandlerMessage, HandlerPermit},
+    error::{Error, Result},
+    handler::{HandlerContext, MessageHandler, UnpackMetadata},
+};
+
+/// Limits for message handlers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlerConfig {
+    /// The number of messages a handler can have queued or running
+    pub queue_capacity: usize,
+
+    /// The number of messages a typed handler handles at once
+    pub concurrency: usize,
+
+    /// How long a handler may take to handle a message, in milliseconds
+    pub timeout_ms: u64,
+
+    /// How often a crashed handler actor is restarted before giving up
+    pub max_restarts: u32,
+
+    /// The number of messages the node receives at once
+    pub max_in_flight: usize,
+}
+
+impl Default for HandlerConfig {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 64,
+            concurrency: 4,
+            timeout_ms: 30_000,
+            max_restarts: 10,
+            max_in_flight: 1024,
+        }
+    }
+}
+
+impl HandlerConfig {
+    /// Gets the handler timeout
+    #[must_use]
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+/// A typed handler with a bounded queue and limited concurrency
+pub struct SupervisedHandler {
+    handler: Arc<dyn MessageHandler>,
+    queued: Arc<Semaphore>,
+    running: Semaphore,
+    timeout: Duration,
+}
+
+impl SupervisedHandler {
+    /// Creates a supervised handler with the given limits
+    #[must_use]
+    pub fn new(handler: Arc<dyn MessageHandler>, config: &HandlerConfig) -> Self {
+        Self {
+            handler,
+            queued: Arc::new(Semaphore::new(config.queue_capacity.max(1))),
+            running: Semaphore::new(config.concurrency.max(1)),
+            timeout: config.timeout(),
+        }
+    }
+
+    /// Reserves a place in the handler's queue without waiting
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Busy` if the queue is full
+    pub fn try_reserve(&self) -> Result<OwnedSemaphorePermit> {
+        Arc::clone(&self.queued)
+            .try_acquire_owned()
+            .map_err(|_| Error::Busy("The handler's queue is full".into()))
+    }
+
+    /// Handles a message once a concurrency slot is free.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the handler fails, panics or times out
+    pub async fn handle(
+        &self,
+        ctx: HandlerContext<'_>,
+        msg: Message,
+        meta: UnpackMetadata,
+    ) -> Result<Vec<Message>> {
+        let _running = self
+            .running
+            .acquire()
+            .await
+            .map_err(|_| Error::Actor("Handler was closed".into()))?;
+        let handled = AssertUnwindSafe(self.handler.handle(ctx, msg, meta)).catch_unwind();
+        match tokio::time::timeout(self.timeout, handled).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(Error::Actor("Handler panicked".into())),
+            Err(_) => Err(Error::Actor(format!(
+                "Handler timed out after {}ms",
+                self.timeout.as_millis()
+            ))),
+        }
+    }
+}
+
+/// A place reserved in a handler's queue for a received message
+pub enum Reservation<'a> {
+    /// A place in an actor's channel
+    Actor(HandlerPermit<'a>),
+    /// A place in a typed handler's queue
+    Handler(&'a SupervisedHandler, OwnedSemaphorePermit),
+}
+
+/// Spawns a handler actor that is restarted when it crashes.
+///
+/// Messages are queued in a channel of `config.queue_capacity` and passed on
+/// to the actor one at a time. If the actor's task panics or returns, the
+/// message it was handling fails and a new actor is started with `factory`,
+/// up to `config.max_restarts` times. Queued messages are kept for the new
+/// actor.
+///
+/// # Arguments
+///
+/// * `config` - The handler limits
+/// * `factory` - Starts an actor reading from the given channel
+#[must_use]
+pub fn spawn_supervised<F, Fut>(config: &HandlerConfig, factory: F) -> HandlerHandle
+where
+    F: Fn(mpsc::Receiver<HandlerMessage>) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::channel(config.queue_capacity.max(1));
+    let max_restarts = config.max_restarts;
+
+    tokio::spawn(async move {
+        let mut restarts = 0;
+        // A message taken from the queue while the actor was crashing
+        let mut pending = None;
+        loop {
+            let (actor_tx, actor_rx) = mpsc::channel(1);
+            let mut actor = tokio::spawn(factory(actor_rx));
+            if let Some(msg) = pending.take() {
+                // A fresh channel has room for one message
+                let _ = actor_tx.try_send(msg);
+            }
+            loop {
+                tokio::select! {
+                    biased;
+                    result = &mut actor => {
+                        log_exit(result);
+                        break;
+                    }
+                    msg = rx.recv() => {
+                        let Some(msg) = msg else {
+                            // Every handle was dropped, let the actor finish
+                            drop(actor_tx);
+                            let _ = actor.await;
+                            return;
+                        };
+                        if let Err(mpsc::error::SendError(msg)) = actor_tx.send(msg).await {
+                            pending = Some(msg);
+                            log_exit(actor.await);
+                            break;
+                        }
+                    }
+                }
+            }
+
+            restarts += 1;
+            if restarts > max_restarts {
+                error!("Handler crashed {restarts} times, giving up");
+                return;
+            }
+            warn!("Restarting handler ({restarts}/{max_restarts})");
+        }
+    });
+
+    HandlerHandle::new(tx)
+}
+
+fn log_exit(result: std::result::Result<Result<()>, tokio::task::JoinError>) {
+    match result {
+        Ok(Ok(())) => warn!("Handler stopped"),
+        Ok(Err(e)) => error!("Handler failed: {e}"),
+        Err(e) => error!("Handler crashed: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        actor::Message as ActorMessage,
+        mock::MockPlugin,
+        node::{DIDCommNode, NodeConfig},
+    };
+    use async_trait::async_trait;
+    use serde_json::json;
+
+    /// Panics on `panic` messages and never finishes `hang` messages
+    struct Unreliable;
+
+    #[async_trait]
+    impl MessageHandler for Unreliable {
+        async fn handle(
+            &self,
+            _ctx: HandlerContext<'_>,
+            msg: Message,
+            _meta: UnpackMetadata,
+        ) -> Result<Vec<Message>> {
+            match msg.typ.as_str() {
+                "panic" => panic!("handler bug"),
+                "hang" => std::future::pending().await,
+                _ => Ok(vec![msg]),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_supervised_handler() {
+        let node = DIDCommNode::new(NodeConfig::default(), MockPlugin);
+        let config = HandlerConfig {
+            queue_capacity: 1,
+            timeout_ms: 50,
+            ..HandlerConfig::default()
+        };
+        let handler = SupervisedHandler::new(Arc::new(Unreliable), &config);
+
+        let permit = handler.try_reserve().unwrap();
+        assert!(matches!(handler.try_reserve(), Err(Error::Busy(_))));
+        drop(permit);
+
+        for (typ, error) in [
+            ("ok", None),
+            ("panic", Some("panicked")),
+            ("hang", Some("timed out")),
+        ] {
+            let msg = Message::new(typ, json!({})).unwrap();
+            let result = handler
+                .handle(HandlerContext::new(&node), msg, UnpackMetadata::default())
+                .await;
+            match error {
+                None => assert_eq!(result.unwrap().len(), 1),
+                Some(error) => assert!(result.unwrap_err().to_string().contains(error)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spawn_supervised_restarts_crashed_actor() {
+        let handle = spawn_supervised(&HandlerConfig::default(), |mut rx| async move {
+            while let Some(HandlerMessage::HandleMessage(msg, reply_tx)) = rx.recv().await {
+                assert_ne!(msg.0.typ.as_str(), "panic", "handler bug");
+                let _ = reply_tx.send(Ok(()));
+            }
+            Ok(())
+        });
+
+        let message = |typ| ActorMessage::new(typ, json!({})).unwrap();
+        assert!(handle.send(message("test")).await.is_ok());
+        assert!(handle.send(message("panic")).await.is_err());
+        assert!(handle.send(message("test")).await.is_ok());
+    }
+}
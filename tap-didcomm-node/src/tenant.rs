@@ -0,0 +1,412 @@
+//! Hosting many identities in one process.
+//!
+//! A [`TenantHost`] runs a [`DIDCommNode`] for each tenant, such as an
+//! institution whose agent is hosted by the service. Every tenant has its own
+//! DIDs, plugin and secrets, handlers, middleware, stores and metrics, so
+//! tenants only share the process.
+//!
+//! Received envelopes are routed to the tenants whose DIDs they are
+//! addressed to, using the `kid` of each recipient of an encrypted envelope
+//! or the `to` of a plaintext message.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use tap_didcomm_node::tenant::TenantHost;
+//! use tap_didcomm_node::{mock::MockPlugin, DIDCommNode, NodeConfig};
+//!
+//! # fn example() -> tap_didcomm_node::error::Result<()> {
+//! let mut host = TenantHost::new();
+//! for (id, did) in [("acme", "did:example:acme"), ("globex", "did:example:globex")] {
+//!     let config = NodeConfig {
+//!         did: Some(did.to_string()),
+//!         ..NodeConfig::default()
+//!     };
+//!     host.add_tenant(id, DIDCommNode::new(config, MockPlugin::new()), [did])?;
+//! }
+//! assert_eq!(host.tenant_for("did:example:acme#key-1").unwrap().id(), "acme");
+//! # Ok(())
+//! # }
+//! ```
+
+use base64::Engine;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::{debug, error};
+
+use crate::{
+    error::{Error, Result},
+    node::DIDCommNode,
+};
+
+/// Counters kept for each tenant
+#[derive(Debug, Default)]
+struct Counters {
+    received: AtomicU64,
+    failed: AtomicU64,
+    busy: AtomicU64,
+}
+
+/// A snapshot of a tenant's metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TenantMetrics {
+    /// Envelopes the tenant received and handled
+    pub received: u64,
+
+    /// Envelopes the tenant failed to receive
+    pub failed: u64,
+
+    /// Envelopes the tenant rejected because it was busy
+    pub busy: u64,
+}
+
+/// A local identity hosted by a [`TenantHost`]
+pub struct Tenant {
+    id: String,
+    dids: Vec<String>,
+    node: Arc<DIDCommNode>,
+    counters: Counters,
+}
+
+impl Tenant {
+    /// Gets the tenant's ID
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Gets the DIDs the tenant receives messages for
+    #[must_use]
+    pub fn dids(&self) -> &[String] {
+        &self.dids
+    }
+
+    /// Gets the tenant's node
+    #[must_use]
+    pub fn node(&self) -> &Arc<DIDCommNode> {
+        &self.node
+    }
+
+    /// Gets a snapshot of the tenant's metrics
+    #[must_use]
+    pub fn metrics(&self) -> TenantMetrics {
+        TenantMetrics {
+            received: self.counters.received.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+            busy: self.counters.busy.load(Ordering::Relaxed),
+        }
+    }
+
+    async fn receive(&self, envelope: &[u8]) -> Result<()> {
+        let result = self.node.receive(envelope).await;
+        let counter = match &result {
+            Ok(()) => &self.counters.received,
+            Err(Error::Busy(_)) => &self.counters.busy,
+            Err(_) => &self.counters.failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+}
+
+/// Runs a node for each of many tenants and routes envelopes between them
+#[derive(Default)]
+pub struct TenantHost {
+    /// Tenants by ID
+    tenants: HashMap<String, Arc<Tenant>>,
+
+    /// Tenant IDs by DID
+    dids: HashMap<String, String>,
+
+    /// Envelopes not addressed to any tenant
+    unroutable: AtomicU64,
+}
+
+impl TenantHost {
+    /// Creates a host without tenants
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a tenant.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The tenant's ID
+    /// * `node` - The node acting for the tenant, with its own plugin,
+    ///   handlers and stores
+    /// * `dids` - The DIDs the tenant receives messages for. If empty, the
+    ///   DID of the node's configuration is used.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Config` if the ID is taken, the tenant has no DIDs or
+    /// a DID belongs to another tenant
+    pub fn add_tenant<S: Into<String>>(
+        &mut self,
+        id: impl Into<String>,
+        node: DIDCommNode,
+        dids: impl IntoIterator<Item = S>,
+    ) -> Result<()> {
+        let id = id.into();
+        if self.tenants.contains_key(&id) {
+            return Err(Error::Config(format!("Tenant {id} already exists")));
+        }
+        let mut dids: Vec<String> = dids.into_iter().map(Into::into).collect();
+        if dids.is_empty() {
+            dids.extend(node.config().did.clone());
+        }
+        if dids.is_empty() {
+            return Err(Error::Config(format!("Tenant {id} has no DIDs")));
+        }
+        if let Some((did, owner)) = dids
+            .iter()
+            .find_map(|did| self.dids.get(did).map(|owner| (did, owner)))
+        {
+            return Err(Error::Config(format!(
+                "DID {did} already belongs to tenant {owner}"
+            )));
+        }
+
+        for did in &dids {
+            self.dids.insert(did.clone(), id.clone());
+        }
+        self.tenants.insert(
+            id.clone(),
+            Arc::new(Tenant {
+                id,
+                dids,
+                node: Arc::new(node),
+                counters: Counters::default(),
+            }),
+        );
+        Ok(())
+    }
+
+    /// Removes a tenant.
+    ///
+    /// # Returns
+    ///
+    /// The removed tenant, if it existed
+    pub fn remove_tenant(&mut self, id: &str) -> Option<Arc<Tenant>> {
+        let tenant = self.tenants.remove(id)?;
+        for did in &tenant.dids {
+            self.dids.remove(did);
+        }
+        Some(tenant)
+    }
+
+    /// Gets a tenant by ID
+    #[must_use]
+    pub fn tenant(&self, id: &str) -> Option<&Arc<Tenant>> {
+        self.tenants.get(id)
+    }
+
+    /// Gets all tenants
+    pub fn tenants(&self) -> impl Iterator<Item = &Arc<Tenant>> {
+        self.tenants.values()
+    }
+
+    /// Gets the tenant a DID or key ID belongs to
+    #[must_use]
+    pub fn tenant_for(&self, kid: &str) -> Option<&Arc<Tenant>> {
+        let did = kid.split_once('#').map_or(kid, |(did, _)| did);
+        self.dids.get(did).and_then(|id| self.tenants.get(id))
+    }
+
+    /// Gets the number of envelopes not addressed to any tenant
+    #[must_use]
+    pub fn unroutable(&self) -> u64 {
+        self.unroutable.load(Ordering::Relaxed)
+    }
+
+    /// Receives an envelope with each tenant it is addressed to.
+    ///
+    /// # Arguments
+    ///
+    /// * `envelope` - The packed message
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The recipients of the envelope cannot be read (`Error::InvalidFormat`)
+    /// - The envelope is not addressed to any tenant (`Error::UnknownRecipient`)
+    /// - A tenant fails to receive the message, in which case the first
+    ///   failure is returned after every tenant has received it
+    pub async fn receive(&self, envelope: &[u8]) -> Result<()> {
+        let recipients = recipient_kids(envelope)?;
+        let mut tenants: Vec<&Arc<Tenant>> = Vec::new();
+        for kid in &recipients {
+            match self.tenant_for(kid) {
+                Some(tenant) if !tenants.iter().any(|t| t.id == tenant.id) => {
+                    tenants.push(tenant);
+                }
+                Some(_) => {}
+                None => debug!("No tenant for recipient {kid}"),
+            }
+        }
+        if tenants.is_empty() {
+            self.unroutable.fetch_add(1, Ordering::Relaxed);
+            return Err(Error::UnknownRecipient(format!(
+                "No tenant for recipients {recipients:?}"
+            )));
+        }
+
+        let mut failure = None;
+        for tenant in tenants {
+            if let Err(e) = tenant.receive(envelope).await {
+                error!("Tenant {} failed to receive message: {e}", tenant.id);
+                failure = failure.or(Some(e));
+            }
+        }
+        failure.map_or(Ok(()), Err)
+    }
+}
+
+/// Gets the key IDs or DIDs an envelope is addressed to.
+///
+/// Envelopes may be base64url encoded. Encrypted envelopes list a `kid` for
+/// each recipient and plaintext messages their `to`.
+///
+/// # Errors
+///
+/// Returns `Error::InvalidFormat` if the envelope is not JSON or names no
+/// recipients
+pub fn recipient_kids(envelope: &[u8]) -> Result<Vec<String>> {
+    let value: Value = serde_json::from_slice(envelope).or_else(|_| {
+        let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(envelope.trim_ascii())
+            .map_err(|e| Error::InvalidFormat(format!("Invalid envelope: {e}")))?;
+        serde_json::from_slice(&decoded)
+            .map_err(|e| Error::InvalidFormat(format!("Invalid envelope: {e}")))
+    })?;
+
+    let strings = |values: Option<&Vec<Value>>, field: fn(&Value) -> Option<&Value>| {
+        values
+            .into_iter()
+            .flatten()
+            .filter_map(|value| field(value)?.as_str().map(str::to_string))
+            .collect::<Vec<_>>()
+    };
+    let mut kids = strings(value["recipients"].as_array(), |recipient| {
+        recipient.get("header")?.get("kid")
+    });
+    if kids.is_empty() {
+        kids = strings(value["to"].as_array(), |did| Some(did));
+    }
+    if kids.is_empty() {
+        return Err(Error::InvalidFormat("Envelope names no recipients".into()));
+    }
+    Ok(kids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::MockPlugin, node::NodeConfig};
+    use serde_json::json;
+    use tap_didcomm_core::Message;
+
+    fn tenant_node(did: &str) -> DIDCommNode {
+        let config = NodeConfig {
+            did: Some(did.to_string()),
+            ..NodeConfig::default()
+        };
+        DIDCommNode::new(config, MockPlugin::new())
+    }
+
+    #[test]
+    fn test_recipient_kids() {
+        let jwe = json!({
+            "ciphertext": "",
+            "recipients": [
+                {"header": {"kid": "did:example:acme#key-1"}, "encrypted_key": ""},
+                {"header": {"kid": "did:example:globex#key-1"}, "encrypted_key": ""}
+            ]
+        });
+        assert_eq!(
+            recipient_kids(jwe.to_string().as_bytes()).unwrap(),
+            ["did:example:acme#key-1", "did:example:globex#key-1"]
+        );
+
+        let message = Message::new("test", json!({}))
+            .unwrap()
+            .to(["did:example:acme"]);
+        let envelope = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&message).unwrap());
+        assert_eq!(
+            recipient_kids(envelope.as_bytes()).unwrap(),
+            ["did:example:acme"]
+        );
+        assert!(matches!(
+            recipient_kids(b"not an envelope"),
+            Err(Error::InvalidFormat(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_receive_routes_to_tenant() {
+        let mut host = TenantHost::new();
+        host.add_tenant(
+            "acme",
+            tenant_node("did:example:acme"),
+            Vec::<String>::new(),
+        )
+        .unwrap();
+        host.add_tenant(
+            "globex",
+            tenant_node("did:example:globex"),
+            ["did:example:globex", "did:example:globex-2"],
+        )
+        .unwrap();
+        assert!(matches!(
+            host.add_tenant(
+                "initech",
+                tenant_node("did:example:initech"),
+                ["did:example:acme"]
+            ),
+            Err(Error::Config(_))
+        ));
+
+        let envelope = |to: &[&str]| {
+            let message = Message::new("test", json!({}))
+                .unwrap()
+                .to(to.iter().copied());
+            let envelope = base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .encode(serde_json::to_vec(&message).unwrap());
+            (message, envelope)
+        };
+
+        let (message, packed) = envelope(&["did:example:globex-2"]);
+        host.receive(packed.as_bytes()).await.unwrap();
+        let acme = host.tenant("acme").unwrap();
+        let globex = host.tenant("globex").unwrap();
+        assert!(acme.node().threads().get(message.id.as_str()).is_empty());
+        assert!(!globex.node().threads().get(message.id.as_str()).is_empty());
+        assert_eq!(acme.metrics(), TenantMetrics::default());
+        assert_eq!(globex.metrics().received, 1);
+
+        // Every tenant addressed receives the message once
+        let (_, packed) = envelope(&[
+            "did:example:acme",
+            "did:example:globex",
+            "did:example:globex-2",
+        ]);
+        host.receive(packed.as_bytes()).await.unwrap();
+        assert_eq!(acme.metrics().received, 1);
+        assert_eq!(globex.metrics().received, 2);
+
+        let (_, packed) = envelope(&["did:example:unknown"]);
+        assert!(matches!(
+            host.receive(packed.as_bytes()).await,
+            Err(Error::UnknownRecipient(_))
+        ));
+        assert_eq!(host.unroutable(), 1);
+
+        host.remove_tenant("acme").unwrap();
+        assert!(host.tenant_for("did:example:acme#key-1").is_none());
+    }
+}
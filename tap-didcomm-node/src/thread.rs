@@ -0,0 +1,388 @@
+//! Thread tracking for `DIDComm` conversations.
+//!
+//! `DIDComm` protocols such as TAP (Transfer → Authorize → Settle) are made up of
+//! several messages that share a thread ID (`thid`). This module provides a
+//! [`ThreadTracker`] that records every message a node receives or sends,
+//! grouped by thread, so handlers can look up the conversation a message
+//! belongs to.
+//!
+//! The `thid` of a received message is chosen by its sender, so a thread only
+//! accepts received messages from the peers it is held with: the recipients
+//! of the messages the node sent in it and the authenticated senders of the
+//! messages it received. The tracker is bounded by a [`ThreadConfig`]: the
+//! least recently active thread is dropped when there are too many, the
+//! oldest messages of a thread when it grows too long, and idle threads when
+//! [`ThreadTracker::prune`] runs.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use tap_didcomm_node::thread::ThreadTracker;
+//! use tap_didcomm_core::Message;
+//! use serde_json::json;
+//!
+//! let tracker = ThreadTracker::new();
+//! let transfer = Message::new("https://tap.rsvp/schema/1.0#Transfer", json!({}))
+//!     .unwrap()
+//!     .to(["did:example:beneficiary"]);
+//! let authorize = transfer
+//!     .reply("https://tap.rsvp/schema/1.0#Authorize", json!({}))
+//!     .unwrap();
+//!
+//! tracker.record_outbound(&transfer, 1_000);
+//! assert!(tracker.record_inbound(&authorize, Some("did:example:beneficiary"), 1_010));
+//!
+//! // Others cannot add messages to the thread
+//! let forged = authorize.reply("https://tap.rsvp/schema/1.0#Settle", json!({})).unwrap();
+//! assert!(!tracker.record_inbound(&forged, Some("did:example:mallory"), 1_020));
+//!
+//! assert_eq!(tracker.get(transfer.thread_id()).len(), 2);
+//! ```
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::RwLock;
+use tap_didcomm_core::Message;
+use tracing::debug;
+
+/// The direction of a message relative to the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// The message was received by the node
+    Inbound,
+    /// The message was sent by the node
+    Outbound,
+}
+
+/// A message recorded in a thread.
+#[derive(Debug, Clone)]
+pub struct ThreadEntry {
+    /// Whether the message was received or sent
+    pub direction: Direction,
+    /// The plaintext message
+    pub message: Message,
+    /// When the message was recorded, as a Unix timestamp
+    pub recorded_at: u64,
+}
+
+/// Bounds of a [`ThreadTracker`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadConfig {
+    /// The maximum number of threads; the least recently active thread is
+    /// dropped to make room for a new one
+    pub max_threads: usize,
+
+    /// The maximum number of messages kept per thread; the oldest message is
+    /// dropped to make room for a new one
+    pub max_messages: usize,
+
+    /// How long a thread is kept after its last message, in seconds
+    pub retention_secs: u64,
+}
+
+impl Default for ThreadConfig {
+    fn default() -> Self {
+        Self {
+            max_threads: 10_000,
+            max_messages: 100,
+            retention_secs: 7 * 24 * 60 * 60, // 1 week
+        }
+    }
+}
+
+/// The messages of a thread and the peers it is held with
+#[derive(Debug, Default)]
+struct Thread {
+    entries: VecDeque<ThreadEntry>,
+    /// The DIDs whose received messages are accepted in the thread
+    peers: HashSet<String>,
+    /// When the last message was recorded
+    updated_at: u64,
+}
+
+/// Groups received and sent messages by thread ID.
+///
+/// The tracker is safe to share between the node and its handlers.
+#[derive(Debug, Default)]
+pub struct ThreadTracker {
+    config: ThreadConfig,
+    threads: RwLock<HashMap<String, Thread>>,
+}
+
+impl ThreadTracker {
+    /// Creates a new empty tracker with the default bounds
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new empty tracker with the given bounds
+    #[must_use]
+    pub fn with_config(config: ThreadConfig) -> Self {
+        Self {
+            config,
+            threads: RwLock::default(),
+        }
+    }
+
+    /// Records a received message in its thread.
+    ///
+    /// A message is only added to an existing thread if its authenticated
+    /// sender is a peer of the thread. A thread started by a message without
+    /// an authenticated sender has no peers and accepts any message, but
+    /// once a peer is known, messages without an authenticated sender are
+    /// refused.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to record
+    /// * `sender` - The DID the envelope authenticated as the sender
+    /// * `now` - The current Unix timestamp
+    ///
+    /// # Returns
+    ///
+    /// Whether the message was recorded
+    pub fn record_inbound(&self, message: &Message, sender: Option<&str>, now: u64) -> bool {
+        let mut threads = self
+            .threads
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if let Some(thread) = threads.get(message.thread_id()) {
+            let accepted = match sender {
+                Some(sender) => thread.peers.is_empty() || thread.peers.contains(sender),
+                None => thread.peers.is_empty(),
+            };
+            if !accepted {
+                debug!(
+                    "Not recording message {} from {sender:?} in thread {}",
+                    message.id.as_str(),
+                    message.thread_id()
+                );
+                return false;
+            }
+        }
+        self.push(
+            &mut threads,
+            Direction::Inbound,
+            message,
+            sender.map(ToString::to_string),
+            now,
+        );
+        true
+    }
+
+    /// Records a sent message in its thread, making its recipients peers of
+    /// the thread
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to record
+    /// * `now` - The current Unix timestamp
+    pub fn record_outbound(&self, message: &Message, now: u64) {
+        let mut threads = self
+            .threads
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        self.push(
+            &mut threads,
+            Direction::Outbound,
+            message,
+            message.to.iter().flatten().cloned(),
+            now,
+        );
+    }
+
+    /// Adds a message and peers to a thread, within the bounds
+    fn push(
+        &self,
+        threads: &mut HashMap<String, Thread>,
+        direction: Direction,
+        message: &Message,
+        peers: impl IntoIterator<Item = String>,
+        now: u64,
+    ) {
+        let thid = message.thread_id();
+        if !threads.contains_key(thid) && threads.len() >= self.config.max_threads {
+            let idle = threads
+                .iter()
+                .min_by_key(|(_, thread)| thread.updated_at)
+                .map(|(thid, _)| thid.clone());
+            if let Some(idle) = idle {
+                debug!("Dropping least recently active thread {idle}");
+                threads.remove(&idle);
+            }
+        }
+
+        let thread = threads.entry(thid.to_string()).or_default();
+        thread.peers.extend(peers);
+        thread.updated_at = thread.updated_at.max(now);
+        thread.entries.push_back(ThreadEntry {
+            direction,
+            message: message.clone(),
+            recorded_at: now,
+        });
+        while thread.entries.len() > self.config.max_messages.max(1) {
+            thread.entries.pop_front();
+        }
+    }
+
+    /// Gets all messages in a thread, in the order they were recorded
+    ///
+    /// # Arguments
+    ///
+    /// * `thid` - The thread ID
+    #[must_use]
+    pub fn get(&self, thid: &str) -> Vec<ThreadEntry> {
+        self.threads
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(thid)
+            .map(|thread| thread.entries.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Gets the most recent message in a thread
+    ///
+    /// # Arguments
+    ///
+    /// * `thid` - The thread ID
+    #[must_use]
+    pub fn latest(&self, thid: &str) -> Option<ThreadEntry> {
+        self.threads
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(thid)
+            .and_then(|thread| thread.entries.back().cloned())
+    }
+
+    /// Gets the IDs of all known threads
+    #[must_use]
+    pub fn thread_ids(&self) -> Vec<String> {
+        self.threads
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Removes a thread and returns its messages
+    ///
+    /// # Arguments
+    ///
+    /// * `thid` - The thread ID
+    pub fn remove(&self, thid: &str) -> Option<Vec<ThreadEntry>> {
+        self.threads
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .remove(thid)
+            .map(|thread| thread.entries.into())
+    }
+
+    /// Removes threads without a message for longer than the retention
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current Unix timestamp
+    ///
+    /// # Returns
+    ///
+    /// The number of removed threads
+    pub fn prune(&self, now: u64) -> usize {
+        let cutoff = now.saturating_sub(self.config.retention_secs);
+        let mut threads = self
+            .threads
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let before = threads.len();
+        threads.retain(|_, thread| thread.updated_at >= cutoff);
+        before - threads.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_thread_tracking() {
+        let tracker = ThreadTracker::new();
+
+        let transfer = Message::new("https://tap.rsvp/schema/1.0#Transfer", json!({}))
+            .unwrap()
+            .from("did:example:originator")
+            .to(vec!["did:example:beneficiary"]);
+        let authorize = transfer
+            .reply("https://tap.rsvp/schema/1.0#Authorize", json!({}))
+            .unwrap();
+        let other = Message::new("test", json!({})).unwrap();
+
+        tracker.record_outbound(&transfer, 1_000);
+        assert!(tracker.record_inbound(&authorize, Some("did:example:beneficiary"), 1_001));
+        tracker.record_outbound(&other, 1_002);
+
+        let thread = tracker.get(transfer.thread_id());
+        assert_eq!(thread.len(), 2);
+        assert_eq!(thread[0].direction, Direction::Outbound);
+        assert_eq!(thread[1].direction, Direction::Inbound);
+        assert_eq!(thread[1].message.id.as_str(), authorize.id.as_str());
+
+        let latest = tracker.latest(transfer.thread_id()).unwrap();
+        assert_eq!(latest.message.id.as_str(), authorize.id.as_str());
+        assert_eq!(tracker.thread_ids().len(), 2);
+
+        assert!(tracker.remove(other.thread_id()).is_some());
+        assert!(tracker.get(other.thread_id()).is_empty());
+    }
+
+    #[test]
+    fn test_threads_are_scoped_to_peers() {
+        let tracker = ThreadTracker::new();
+        let request = Message::new("test", json!({}))
+            .unwrap()
+            .from("did:example:alice");
+        let reply = request.reply("test", json!({})).unwrap();
+
+        // The authenticated sender of the first message is a peer
+        assert!(tracker.record_inbound(&request, Some("did:example:alice"), 1_000));
+        assert!(!tracker.record_inbound(&reply, Some("did:example:mallory"), 1_001));
+        assert!(!tracker.record_inbound(&reply, None, 1_001));
+        assert!(tracker.record_inbound(&reply, Some("did:example:alice"), 1_001));
+        assert_eq!(tracker.get(request.thread_id()).len(), 2);
+
+        // A thread without an authenticated peer accepts anyone
+        let anonymous = Message::new("test", json!({})).unwrap();
+        assert!(tracker.record_inbound(&anonymous, None, 1_000));
+        let reply = anonymous.reply("test", json!({})).unwrap();
+        assert!(tracker.record_inbound(&reply, None, 1_001));
+    }
+
+    #[test]
+    fn test_bounds_and_retention() {
+        let tracker = ThreadTracker::with_config(ThreadConfig {
+            max_threads: 2,
+            max_messages: 2,
+            retention_secs: 100,
+        });
+        let first = Message::new("test", json!({})).unwrap();
+        let second = Message::new("test", json!({})).unwrap();
+        let third = Message::new("test", json!({})).unwrap();
+
+        tracker.record_outbound(&first, 1_000);
+        tracker.record_outbound(&second, 1_001);
+        for i in 0..3 {
+            let reply = first.reply("test", json!({})).unwrap();
+            tracker.record_outbound(&reply, 1_002 + i);
+        }
+        assert_eq!(tracker.get(first.thread_id()).len(), 2);
+
+        // The least recently active thread makes room for a new one
+        tracker.record_outbound(&third, 1_010);
+        assert!(tracker.get(second.thread_id()).is_empty());
+        assert_eq!(tracker.thread_ids().len(), 2);
+
+        assert_eq!(tracker.prune(1_105), 1);
+        assert!(tracker.get(first.thread_id()).is_empty());
+        assert_eq!(tracker.get(third.thread_id()).len(), 1);
+    }
+}
@@ -0,0 +1,759 @@
+//! Transports that carry packed messages to endpoints.
+//!
+//! The [`Dispatcher`](crate::dispatch::Dispatcher) picks a transport by the
+//! URI scheme of each endpoint. By default, `http` and `https` endpoints are
+//! delivered with an HTTP POST and `ws` and `wss` endpoints over persistent
+//! WebSocket connections. Other schemes can be registered, such as `mem` for
+//! the in-memory transport, which lets several nodes in one process talk to
+//! each other in integration tests.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//! use tap_didcomm_node::transport::{MemoryNetwork, MemoryTransport};
+//! use tap_didcomm_node::{mock::MockPlugin, DIDCommNode, NodeConfig};
+//!
+//! # fn main() -> tap_didcomm_node::Result<()> {
+//! let network = Arc::new(MemoryNetwork::new());
+//! let mut alice = DIDCommNode::new(NodeConfig::default(), MockPlugin::new());
+//! alice.set_transport("mem", MemoryTransport::new(Arc::clone(&network)))?;
+//!
+//! let bob = Arc::new(DIDCommNode::new(NodeConfig::default(), MockPlugin::new()));
+//! network.attach("bob", bob);
+//! // Messages alice sends to `mem://bob` are now received by bob
+//! # Ok(())
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use futures::{stream::SplitSink, SinkExt, StreamExt};
+use reqwest::Client;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex, OwnedSemaphorePermit, Semaphore};
+use tokio_tungstenite::{
+    connect_async, tungstenite::Message as WsMessage, MaybeTlsStream, WebSocketStream,
+};
+use tracing::{debug, error, warn};
+
+use crate::{
+    dispatch::Delivery,
+    error::Result,
+    lifecycle::{Listener, Shutdown},
+    node::DIDCommNode,
+    routing::Route,
+};
+
+/// The status reported for deliveries over transports without status codes
+const ACCEPTED: u16 = 202;
+
+/// The default for the largest synchronous reply read from an endpoint, in
+/// bytes
+pub const DEFAULT_MAX_REPLY_SIZE: usize = 1024 * 1024;
+
+/// The default for the most HTTP deliveries in flight to one host
+pub const DEFAULT_MAX_CONNECTIONS_PER_HOST: usize = 32;
+
+/// The default timeout for opening a WebSocket connection or sending over it
+pub const DEFAULT_WEBSOCKET_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A failure to deliver an envelope over a transport.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportError {
+    /// The HTTP status the endpoint responded with, if it responded
+    pub status: Option<u16>,
+    /// The delay in seconds the endpoint asked for with `Retry-After`
+    pub retry_after: Option<u64>,
+    /// A description of the failure
+    pub message: String,
+}
+
+impl TransportError {
+    /// Creates an error for an endpoint that could not be reached
+    #[must_use]
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            status: None,
+            retry_after: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for TransportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Carries packed messages to endpoints of one or more URI schemes.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Makes a single attempt to deliver the envelope of a route.
+    ///
+    /// # Returns
+    ///
+    /// The delivery, with the synchronous reply if the transport has one
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the endpoint could not be reached or did not
+    /// accept the envelope
+    async fn deliver(&self, route: &Route) -> std::result::Result<Delivery, TransportError>;
+}
+
+/// Returns the URI scheme of an endpoint, in lowercase.
+#[must_use]
+pub fn scheme(endpoint: &str) -> Option<String> {
+    endpoint
+        .split_once("://")
+        .map(|(scheme, _)| scheme.to_ascii_lowercase())
+}
+
+/// Delivers envelopes with an HTTP POST.
+///
+/// A synchronous reply larger than the reply size limit is dropped, without
+/// failing the delivery, since the endpoint has already accepted the
+/// envelope.
+///
+/// At most `max_connections_per_host` deliveries to one host are in flight at
+/// a time; further deliveries wait for one to finish, for up to the
+/// connection wait timeout. Clones share these limits.
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    client: std::result::Result<Client, String>,
+    max_reply_size: usize,
+    max_connections_per_host: usize,
+    connection_wait: Duration,
+    hosts: Arc<std::sync::Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+impl HttpTransport {
+    /// Creates a transport that delivers with `client`.
+    #[must_use]
+    pub fn new(client: Client) -> Self {
+        Self::with_client(Ok(client))
+    }
+
+    /// Creates a transport whose client could not be built.
+    ///
+    /// Every delivery fails with `error`.
+    #[must_use]
+    pub fn unavailable(error: impl Into<String>) -> Self {
+        Self::with_client(Err(error.into()))
+    }
+
+    fn with_client(client: std::result::Result<Client, String>) -> Self {
+        Self {
+            client,
+            max_reply_size: DEFAULT_MAX_REPLY_SIZE,
+            max_connections_per_host: DEFAULT_MAX_CONNECTIONS_PER_HOST,
+            connection_wait: DEFAULT_WEBSOCKET_TIMEOUT,
+            hosts: Arc::default(),
+        }
+    }
+
+    /// Sets the largest synchronous reply read from an endpoint, in bytes
+    #[must_use]
+    pub fn with_max_reply_size(mut self, max_reply_size: usize) -> Self {
+        self.max_reply_size = max_reply_size;
+        self
+    }
+
+    /// Sets the most deliveries in flight to one host, and how long a
+    /// delivery waits for one of them to finish
+    #[must_use]
+    pub fn with_max_connections_per_host(mut self, max: usize, wait: Duration) -> Self {
+        self.max_connections_per_host = max.max(1);
+        self.connection_wait = wait;
+        self.hosts = Arc::default();
+        self
+    }
+
+    /// Waits until fewer than `max_connections_per_host` deliveries to the
+    /// endpoint's host are in flight.
+    async fn acquire(
+        &self,
+        endpoint: &str,
+    ) -> std::result::Result<OwnedSemaphorePermit, TransportError> {
+        let host = reqwest::Url::parse(endpoint)
+            .ok()
+            .and_then(|url| {
+                url.host_str()
+                    .map(|host| format!("{host}:{}", url.port_or_known_default().unwrap_or(0)))
+            })
+            .unwrap_or_else(|| endpoint.to_string());
+        let semaphore = {
+            let mut hosts = self
+                .hosts
+                .lock()
+                .map_err(|_| TransportError::new("Connection limits are poisoned"))?;
+            if !hosts.contains_key(&host) {
+                // A host without deliveries in flight holds no state worth keeping
+                hosts.retain(|_, semaphore| Arc::strong_count(semaphore) > 1);
+            }
+            Arc::clone(
+                hosts
+                    .entry(host.clone())
+                    .or_insert_with(|| Arc::new(Semaphore::new(self.max_connections_per_host))),
+            )
+        };
+        tokio::time::timeout(self.connection_wait, semaphore.acquire_owned())
+            .await
+            .map_err(|_| {
+                TransportError::new(format!("Timed out waiting for a connection to {host}"))
+            })?
+            .map_err(|e| TransportError::new(e.to_string()))
+    }
+
+    /// Reads the body of a response, or `None` if it is larger than the
+    /// reply size limit.
+    async fn read_reply(
+        &self,
+        endpoint: &str,
+        mut response: reqwest::Response,
+    ) -> std::result::Result<Option<String>, TransportError> {
+        let status = response.status().as_u16();
+        let error = |message: String| TransportError {
+            status: Some(status),
+            retry_after: None,
+            message,
+        };
+        let too_large = || {
+            warn!(
+                "Dropping reply from {endpoint} larger than {} bytes",
+                self.max_reply_size
+            );
+            Ok(None)
+        };
+        let declared = response.content_length().unwrap_or(0);
+        if usize::try_from(declared).map_or(true, |declared| declared > self.max_reply_size) {
+            return too_large();
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| error(e.to_string()))? {
+            if body.len() + chunk.len() > self.max_reply_size {
+                return too_large();
+            }
+            body.extend_from_slice(&chunk);
+        }
+        String::from_utf8(body)
+            .map(Some)
+            .map_err(|e| error(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl Transport for HttpTransport {
+    async fn deliver(&self, route: &Route) -> std::result::Result<Delivery, TransportError> {
+        let client = self
+            .client
+            .as_ref()
+            .map_err(|e| TransportError::new(format!("HTTP client unavailable: {e}")))?;
+        let _permit = self.acquire(&route.endpoint).await?;
+
+        let response = client
+            .post(&route.endpoint)
+            .header(reqwest::header::CONTENT_TYPE, &route.media_type)
+            .body(route.envelope.clone())
+            .send()
+            .await
+            .map_err(|e| TransportError::new(e.to_string()))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok());
+            return Err(TransportError {
+                status: Some(status.as_u16()),
+                retry_after,
+                message: format!("Failed to deliver envelope: {status}"),
+            });
+        }
+
+        let reply = self.read_reply(&route.endpoint, response).await?;
+        Ok(Delivery {
+            endpoint: route.endpoint.clone(),
+            status: status.as_u16(),
+            reply: reply.filter(|reply| !reply.trim().is_empty()),
+        })
+    }
+}
+
+/// Named inboxes that the in-memory transport delivers to.
+///
+/// The endpoint `mem://bob` delivers to the inbox named `bob`.
+#[derive(Debug, Default)]
+pub struct MemoryNetwork {
+    inboxes: RwLock<HashMap<String, mpsc::UnboundedSender<String>>>,
+}
+
+impl MemoryNetwork {
+    /// Creates a network without inboxes
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the inbox `name`, replacing any previous one.
+    ///
+    /// # Returns
+    ///
+    /// The receiver of the envelopes delivered to the inbox
+    pub fn listen(&self, name: impl Into<String>) -> mpsc::UnboundedReceiver<String> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.inboxes
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(name.into(), tx);
+        rx
+    }
+
+    /// Opens the inbox `name` and has `node` receive what is delivered to it.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn attach(&self, name: impl Into<String>, node: Arc<DIDCommNode>) {
+        let name = name.into();
+        let mut inbox = self.listen(name.clone());
+        tokio::spawn(async move {
+            while let Some(envelope) = inbox.recv().await {
+                if let Err(e) = node.receive(envelope.as_bytes()).await {
+                    error!("Node {name} failed to receive message: {e}");
+                }
+            }
+        });
+    }
+
+    /// Closes the inbox `name`.
+    pub fn close(&self, name: &str) {
+        self.inboxes
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .remove(name);
+    }
+
+    fn send(&self, name: &str, envelope: String) -> std::result::Result<(), TransportError> {
+        self.inboxes
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(name)
+            .ok_or_else(|| TransportError::new(format!("No inbox named {name}")))?
+            .send(envelope)
+            .map_err(|_| TransportError::new(format!("Inbox {name} is closed")))
+    }
+}
+
+/// Receives the envelopes delivered to an inbox of a [`MemoryNetwork`]
+/// while the node is running.
+#[derive(Debug, Clone)]
+pub struct MemoryListener {
+    network: Arc<MemoryNetwork>,
+    name: String,
+}
+
+impl MemoryListener {
+    /// Creates a listener for the inbox `name`
+    #[must_use]
+    pub fn new(network: Arc<MemoryNetwork>, name: impl Into<String>) -> Self {
+        Self {
+            network,
+            name: name.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl Listener for MemoryListener {
+    async fn listen(&self, node: Arc<DIDCommNode>, mut shutdown: Shutdown) -> Result<()> {
+        let mut inbox = self.network.listen(self.name.clone());
+        loop {
+            tokio::select! {
+                () = shutdown.wait() => break,
+                envelope = inbox.recv() => {
+                    let Some(envelope) = envelope else { break };
+                    if let Err(e) = node.receive(envelope.as_bytes()).await {
+                        error!("Node {} failed to receive message: {e}", self.name);
+                    }
+                }
+            }
+        }
+        self.network.close(&self.name);
+        Ok(())
+    }
+}
+
+/// Delivers envelopes to the inboxes of a [`MemoryNetwork`].
+#[derive(Debug, Clone)]
+pub struct MemoryTransport {
+    network: Arc<MemoryNetwork>,
+}
+
+impl MemoryTransport {
+    /// Creates a transport delivering to `network`
+    #[must_use]
+    pub fn new(network: Arc<MemoryNetwork>) -> Self {
+        Self { network }
+    }
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    async fn deliver(&self, route: &Route) -> std::result::Result<Delivery, TransportError> {
+        let name = route
+            .endpoint
+            .split_once("://")
+            .map_or(route.endpoint.as_str(), |(_, name)| name)
+            .trim_end_matches('/');
+        self.network.send(name, route.envelope.clone())?;
+        Ok(Delivery {
+            endpoint: route.endpoint.clone(),
+            status: ACCEPTED,
+            reply: None,
+        })
+    }
+}
+
+type WsSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, WsMessage>;
+
+/// The connection to one endpoint, if it is open.
+///
+/// Each endpoint has its own lock, so a slow endpoint does not hold up
+/// deliveries to the others.
+type WsConnection = Arc<Mutex<Option<WsSink>>>;
+
+/// Delivers envelopes as text frames over persistent WebSocket connections.
+///
+/// One connection is kept open per endpoint and reopened when sending over it
+/// fails. Opening a connection and sending over it each time out after the
+/// transport's timeout. Frames the endpoint sends back, such as messages
+/// delivered live by a mediator, are passed to the receiver given to
+/// [`WebSocketTransport::with_incoming`], or dropped.
+#[derive(Debug)]
+pub struct WebSocketTransport {
+    connections: std::sync::Mutex<HashMap<String, WsConnection>>,
+    incoming: Option<mpsc::UnboundedSender<String>>,
+    timeout: Duration,
+}
+
+impl Default for WebSocketTransport {
+    fn default() -> Self {
+        Self {
+            connections: std::sync::Mutex::default(),
+            incoming: None,
+            timeout: DEFAULT_WEBSOCKET_TIMEOUT,
+        }
+    }
+}
+
+impl WebSocketTransport {
+    /// Creates a transport that drops frames sent back by endpoints
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a transport that passes frames sent back by endpoints to
+    /// `incoming`.
+    #[must_use]
+    pub fn with_incoming(incoming: mpsc::UnboundedSender<String>) -> Self {
+        Self {
+            incoming: Some(incoming),
+            ..Self::default()
+        }
+    }
+
+    /// Sets how long opening a connection, or sending over it, may take
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Gets the connection slot of an endpoint, creating it if needed
+    fn connection(&self, endpoint: &str) -> WsConnection {
+        let mut connections = self
+            .connections
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        Arc::clone(connections.entry(endpoint.to_string()).or_default())
+    }
+
+    async fn connect(&self, endpoint: &str) -> std::result::Result<WsSink, TransportError> {
+        debug!("Opening WebSocket connection to {endpoint}");
+        let (stream, _) = tokio::time::timeout(self.timeout, connect_async(endpoint))
+            .await
+            .map_err(|_| TransportError::new(format!("Timed out connecting to {endpoint}")))?
+            .map_err(|e| TransportError::new(e.to_string()))?;
+        let (sink, mut stream) = stream.split();
+
+        let incoming = self.incoming.clone();
+        let endpoint = endpoint.to_string();
+        tokio::spawn(async move {
+            while let Some(Ok(frame)) = stream.next().await {
+                if let (Some(incoming), WsMessage::Text(text)) = (&incoming, frame) {
+                    if incoming.send(text).is_err() {
+                        break;
+                    }
+                }
+            }
+            debug!("WebSocket connection to {endpoint} closed");
+        });
+        Ok(sink)
+    }
+
+    async fn send(
+        &self,
+        endpoint: &str,
+        sink: &mut WsSink,
+        frame: WsMessage,
+    ) -> std::result::Result<(), TransportError> {
+        tokio::time::timeout(self.timeout, sink.send(frame))
+            .await
+            .map_err(|_| TransportError::new(format!("Timed out sending to {endpoint}")))?
+            .map_err(|e| TransportError::new(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl Transport for WebSocketTransport {
+    async fn deliver(&self, route: &Route) -> std::result::Result<Delivery, TransportError> {
+        let connection = self.connection(&route.endpoint);
+        let mut connection = connection.lock().await;
+        let frame = WsMessage::Text(route.envelope.clone());
+        let accepted = || Delivery {
+            endpoint: route.endpoint.clone(),
+            status: ACCEPTED,
+            reply: None,
+        };
+
+        if let Some(sink) = connection.as_mut() {
+            if self
+                .send(&route.endpoint, sink, frame.clone())
+                .await
+                .is_ok()
+            {
+                return Ok(accepted());
+            }
+            *connection = None;
+        }
+
+        let mut sink = self.connect(&route.endpoint).await?;
+        self.send(&route.endpoint, &mut sink, frame).await?;
+        *connection = Some(sink);
+        Ok(accepted())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dispatch::{DispatchConfig, RetryPolicy},
+        mock::MockPlugin,
+        NodeConfig,
+    };
+    use serde_json::json;
+    use tap_didcomm_core::{Message, PackingType};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_memory_transport_between_nodes() {
+        use base64::Engine;
+
+        let network = Arc::new(MemoryNetwork::new());
+        let config = NodeConfig {
+            dispatch: DispatchConfig {
+                base_url: Some("mem://bob".to_string()),
+                endpoint: String::new(),
+                retry: RetryPolicy::none(),
+                ..DispatchConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let mut alice = DIDCommNode::new(config, MockPlugin::new());
+        alice
+            .set_transport("mem", MemoryTransport::new(Arc::clone(&network)))
+            .unwrap();
+        let mut inbox = network.listen("bob");
+
+        let message = Message::new("test", json!({"hello": "bob"}))
+            .unwrap()
+            .from("did:example:alice")
+            .to(["did:example:bob"]);
+        let results = alice.send(&message, PackingType::Signed).await.unwrap();
+        assert_eq!(results[0].endpoint, "mem://bob");
+        assert_eq!(results[0].status, ACCEPTED);
+        assert_eq!(
+            inbox.recv().await.unwrap(),
+            tap_didcomm_core::pack_message(&message, &MockPlugin::new(), PackingType::Signed)
+                .await
+                .unwrap()
+        );
+
+        // An attached node receives what is delivered to its inbox
+        let bob = Arc::new(DIDCommNode::new(NodeConfig::default(), MockPlugin::new()));
+        network.attach("bob", Arc::clone(&bob));
+        let route = Route {
+            endpoint: "mem://bob".to_string(),
+            envelope: base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .encode(serde_json::to_vec(&message).unwrap()),
+            media_type: "application/didcomm-plain+json".to_string(),
+        };
+        MemoryTransport::new(Arc::clone(&network))
+            .deliver(&route)
+            .await
+            .unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while bob.threads().get(message.id.as_str()).is_empty() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+
+        network.close("bob");
+        assert!(alice.send(&message, PackingType::Signed).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_websocket_transport_reuses_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            // Only one connection is accepted; echo every frame back
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            while let Some(Ok(frame)) = socket.next().await {
+                if frame.is_text() {
+                    socket.send(frame).await.unwrap();
+                }
+            }
+        });
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let transport = WebSocketTransport::with_incoming(tx);
+        for envelope in ["one", "two"] {
+            let route = Route {
+                endpoint: endpoint.clone(),
+                envelope: envelope.to_string(),
+                media_type: PackingType::AnonV2.media_type().to_string(),
+            };
+            let delivery = transport.deliver(&route).await.unwrap();
+            assert_eq!(delivery.status, ACCEPTED);
+            assert_eq!(rx.recv().await.unwrap(), envelope);
+        }
+        server.abort();
+        assert_eq!(scheme("WSS://example.com").as_deref(), Some("wss"));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_transport_times_out_per_endpoint() {
+        // Accepts connections but never completes the handshake
+        let stalled = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stalled_endpoint = format!("ws://{}", stalled.local_addr().unwrap());
+        let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let echo_endpoint = format!("ws://{}", echo.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let _held = stalled.accept().await.unwrap();
+            let (stream, _) = echo.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            while socket.next().await.is_some() {}
+        });
+
+        let transport = WebSocketTransport::new().with_timeout(Duration::from_secs(2));
+        let route = |endpoint: &str| Route {
+            endpoint: endpoint.to_string(),
+            envelope: "envelope".to_string(),
+            media_type: PackingType::AnonV2.media_type().to_string(),
+        };
+        let (stalled_route, echo_route) = (route(&stalled_endpoint), route(&echo_endpoint));
+        let (stalled, echoed) = tokio::join!(transport.deliver(&stalled_route), async {
+            // Delivered while the other endpoint is still connecting
+            tokio::time::timeout(Duration::from_secs(1), transport.deliver(&echo_route)).await
+        });
+        assert!(stalled.unwrap_err().message.contains("Timed out"));
+        assert_eq!(echoed.unwrap().unwrap().status, ACCEPTED);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_http_transport_limits_reply_size() {
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("x".repeat(64)))
+            .mount(&mock_server)
+            .await;
+
+        let route = Route {
+            endpoint: mock_server.uri(),
+            envelope: "envelope".to_string(),
+            media_type: PackingType::AnonV2.media_type().to_string(),
+        };
+        let transport = HttpTransport::new(Client::new());
+        let delivery = transport.deliver(&route).await.unwrap();
+        assert_eq!(delivery.reply.as_deref(), Some("x".repeat(64).as_str()));
+
+        // The envelope was accepted, so only the reply is dropped
+        let delivery = transport
+            .with_max_reply_size(32)
+            .deliver(&route)
+            .await
+            .unwrap();
+        assert_eq!(delivery.status, 200);
+        assert_eq!(delivery.reply, None);
+    }
+
+    #[tokio::test]
+    async fn test_http_transport_limits_connections_per_host() {
+        use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(300)))
+            .mount(&mock_server)
+            .await;
+
+        let route = Route {
+            endpoint: mock_server.uri(),
+            envelope: "envelope".to_string(),
+            media_type: PackingType::AnonV2.media_type().to_string(),
+        };
+        let transport = HttpTransport::new(Client::new())
+            .with_max_connections_per_host(1, Duration::from_secs(5));
+        let first = tokio::spawn({
+            let (transport, route) = (transport.clone(), route.clone());
+            async move { transport.deliver(&route).await }
+        });
+        let second = tokio::spawn({
+            let (transport, route) = (transport.clone(), route.clone());
+            async move { transport.deliver(&route).await }
+        });
+
+        // The second delivery waits for the first to finish
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 1);
+        assert!(first.await.unwrap().is_ok());
+        assert!(second.await.unwrap().is_ok());
+        assert_eq!(mock_server.received_requests().await.unwrap().len(), 2);
+
+        // A delivery that cannot get a connection in time fails
+        let transport = transport.with_max_connections_per_host(1, Duration::from_millis(50));
+        let busy = tokio::spawn({
+            let (transport, route) = (transport.clone(), route.clone());
+            async move { transport.deliver(&route).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let error = transport.deliver(&route).await.unwrap_err();
+        assert!(error.message.contains("Timed out waiting"));
+        assert!(busy.await.unwrap().is_ok());
+    }
+}
@@ -99,8 +99,18 @@ export interface Message {
 export interface Attachment {
   /** Unique identifier for this attachment */
   id: string;
+  /** Optional human-readable description */
+  description?: string;
+  /** Optional filename for the attachment */
+  filename?: string;
   /** Optional MIME type of the attachment content */
   media_type?: string;
+  /** Optional format identifier */
+  format?: string;
+  /** Optional Unix timestamp of when the content was last modified */
+  lastmod_time?: number;
+  /** Optional size of the content in bytes */
+  byte_count?: number;
   /** The actual attachment data */
   data: AttachmentData;
 }
@@ -116,8 +126,8 @@ export interface AttachmentData {
   base64?: string;
   /** Array of URLs pointing to the data */
   links?: string[];
-  /** JWS data for signed attachments */
-  jws?: string;
+  /** Detached JWS over the attachment content */
+  jws?: Record<string, unknown>;
   /** Hash of the data for integrity verification */
   hash?: string;
 }